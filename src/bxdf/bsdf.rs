@@ -1,5 +1,8 @@
 //! Defines the BSDF which acts as a container for composing the various BRDFs
-//! and BTDFs that describe the surface's properties
+//! and BTDFs that describe the surface's properties. The colors and pdfs returned
+//! by the BxDFs are sanitized here so a NaN or Inf from one BxDF is replaced with
+//! black instead of spreading through the path, in debug builds the BxDF and hit
+//! point are logged when this happens.
 
 use std::cmp;
 use enum_set::EnumSet;
@@ -7,7 +10,7 @@ use enum_set::EnumSet;
 use linalg::{self, Normal, Vector, Point};
 use film::Colorf;
 use geometry::DifferentialGeometry;
-use bxdf::{BxDF, BxDFType};
+use bxdf::{self, BxDF, BxDFType};
 use sampler::Sample;
 
 /// The BSDF contains the various BRDFs and BTDFs that describe the surface's properties
@@ -74,7 +77,8 @@ impl<'a> BSDF<'a> {
             flags.remove(&BxDFType::Reflection);
         }
         // Find all matching BxDFs and add their contribution to the material's color
-        self.bxdfs.iter().filter_map(|x| if x.matches(flags) { Some(x.eval(&w_o, &w_i)) } else { None })
+        self.bxdfs.iter()
+            .filter_map(|x| if x.matches(flags) { Some(self.sanitize(x.eval(&w_o, &w_i), *x)) } else { None })
             .fold(Colorf::broadcast(0.0), |x, y| x + y)
     }
     /// Sample a component of the BSDF to get an incident light direction for light
@@ -92,8 +96,10 @@ impl<'a> BSDF<'a> {
         let comp = cmp::min((samples.one_d * n_matching as f32) as usize, n_matching - 1);
         let bxdf = self.matching_at(comp, flags);
         let w_o = self.to_shading(wo_world).normalized();
-        let (mut f, w_i, mut pdf) = bxdf.sample(&w_o, &samples.two_d);
-        if w_i.length_sqr() == 0.0 {
+        let (f, w_i, pdf) = bxdf.sample(&w_o, &samples.two_d);
+        let mut f = self.sanitize(f, bxdf);
+        let mut pdf = self.sanitize_pdf(pdf, bxdf);
+        if w_i.length_sqr() == 0.0 || !w_i.x.is_finite() || !w_i.y.is_finite() || !w_i.z.is_finite() {
             return (Colorf::broadcast(0.0), Vector::broadcast(0.0), 0.0, EnumSet::new());
         }
         let wi_world = self.from_shading(&w_i).normalized();
@@ -115,7 +121,7 @@ impl<'a> BSDF<'a> {
         let w_o = self.to_shading(wo_world).normalized();
         let w_i = self.to_shading(wi_world).normalized();
         let (pdf_val, n_comps) = self.bxdfs.iter()
-            .filter_map(|x| if x.matches(flags) { Some(x.pdf(&w_o, &w_i)) } else { None })
+            .filter_map(|x| if x.matches(flags) { Some(self.sanitize_pdf(x.pdf(&w_o, &w_i), *x)) } else { None })
             .fold((0.0, 0), |(p, n), y| (p + y, n + 1));
         if n_comps > 0 {
             pdf_val / n_comps as f32
@@ -123,6 +129,22 @@ impl<'a> BSDF<'a> {
             0.0
         }
     }
+    /// Replace a NaN or Inf color returned by `bxdf` with black, see `bxdf::sanitize`
+    fn sanitize(&self, f: Colorf, bxdf: &BxDF) -> Colorf {
+        if f.is_finite() {
+            f
+        } else {
+            bxdf::sanitize(f, &format!("{} BxDF at {:?}", bxdf.name(), self.p))
+        }
+    }
+    /// Replace a NaN or Inf pdf returned by `bxdf` with 0, see `bxdf::sanitize_pdf`
+    fn sanitize_pdf(&self, pdf: f32, bxdf: &BxDF) -> f32 {
+        if pdf.is_finite() {
+            pdf
+        } else {
+            bxdf::sanitize_pdf(pdf, &format!("{} BxDF at {:?}", bxdf.name(), self.p))
+        }
+    }
     /// Get the `i`th BxDF that matches the flags passed. There should not be fewer than i
     /// BxDFs that match the flags
     fn matching_at(&self, i: usize, flags: EnumSet<BxDFType>) -> &BxDF {
@@ -4,6 +4,7 @@ use std::f32;
 
 use film::Colorf;
 use linalg;
+use bxdf;
 
 /// Compute the Fresnel term for a dielectric material given the incident and transmission
 /// angles and refractive indices
@@ -63,7 +64,7 @@ impl Fresnel for Dielectric {
             Colorf::broadcast(1.0)
         } else {
             let ct = f32::sqrt(f32::max(0.0, 1.0 - sin_t * sin_t));
-            dielectric(f32::abs(ci), ct, ei, et)
+            bxdf::sanitize(dielectric(f32::abs(ci), ct, ei, et), "Dielectric Fresnel")
         }
     }
 }
@@ -85,6 +86,8 @@ impl Conductor {
 }
 
 impl Fresnel for Conductor {
-    fn fresnel(&self, cos_i: f32) -> Colorf { conductor(f32::abs(cos_i), &self.eta, &self.k) }
+    fn fresnel(&self, cos_i: f32) -> Colorf {
+        bxdf::sanitize(conductor(f32::abs(cos_i), &self.eta, &self.k), "Conductor Fresnel")
+    }
 }
 
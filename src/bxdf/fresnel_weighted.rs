@@ -0,0 +1,48 @@
+//! Provides a BxDF which scales a diffuse BxDF by the light transmitted through a
+//! dielectric coating on top of it, so the coating's reflection and the diffuse
+//! reflection together don't reflect more light than arrives at the surface.
+
+use enum_set::EnumSet;
+
+use linalg::Vector;
+use film::Colorf;
+use bxdf::{self, BxDF, BxDFType};
+use bxdf::fresnel::Fresnel;
+
+/// Scales the wrapped diffuse BxDF by `1 - weight * F` in both the incident and outgoing
+/// directions, where `F` is the Fresnel reflectance of the coating and `weight` is the
+/// weight of the coating's reflection lobe. Scaling both directions keeps the BxDF reciprocal
+#[derive(Copy, Clone)]
+pub struct FresnelWeighted<'a> {
+    diffuse: &'a BxDF,
+    fresnel: &'a Fresnel,
+    weight: f32,
+}
+
+impl<'a> FresnelWeighted<'a> {
+    /// Create a new BxDF scaling `diffuse` by the light not reflected by the coating
+    pub fn new(diffuse: &'a BxDF, fresnel: &'a Fresnel, weight: f32) -> FresnelWeighted<'a> {
+        FresnelWeighted { diffuse: diffuse, fresnel: fresnel, weight: weight }
+    }
+    /// Compute the fraction of light transmitted through the coating along `w`
+    fn transmitted(&self, w: &Vector) -> Colorf {
+        Colorf::broadcast(1.0) - self.fresnel.fresnel(f32::abs(bxdf::cos_theta(w))) * self.weight
+    }
+}
+
+impl<'a> BxDF for FresnelWeighted<'a> {
+    fn name(&self) -> &'static str { "FresnelWeighted" }
+    fn bxdf_type(&self) -> EnumSet<BxDFType> {
+        self.diffuse.bxdf_type()
+    }
+    fn eval(&self, w_o: &Vector, w_i: &Vector) -> Colorf {
+        self.diffuse.eval(w_o, w_i) * self.transmitted(w_o) * self.transmitted(w_i)
+    }
+    fn sample(&self, w_o: &Vector, samples: &(f32, f32)) -> (Colorf, Vector, f32) {
+        let (f, w_i, pdf) = self.diffuse.sample(w_o, samples);
+        (f * self.transmitted(w_o) * self.transmitted(&w_i), w_i, pdf)
+    }
+    fn pdf(&self, w_o: &Vector, w_i: &Vector) -> f32 {
+        self.diffuse.pdf(w_o, w_i)
+    }
+}
@@ -23,6 +23,7 @@ impl Lambertian {
 }
 
 impl BxDF for Lambertian {
+    fn name(&self) -> &'static str { "Lambertian" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Diffuse);
@@ -38,6 +38,7 @@ impl<'a> Merl<'a> {
 }
 
 impl<'a> BxDF for Merl<'a> {
+    fn name(&self) -> &'static str { "Merl" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Glossy);
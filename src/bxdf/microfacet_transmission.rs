@@ -50,12 +50,20 @@ impl<'a> MicrofacetTransmission<'a> {
             0.0
         }
     }
-    fn half_vector(w_o: &Vector, w_i: &Vector, eta: (f32, f32)) -> Vector {
-        (-eta.1 * *w_i - eta.0 * *w_o).normalized()
+    /// Compute the half vector for the pair of directions, returns None if it's degenerate,
+    /// which happens when the indices of refraction match and `w_i` is `-w_o`
+    fn half_vector(w_o: &Vector, w_i: &Vector, eta: (f32, f32)) -> Option<Vector> {
+        let w_h = -eta.1 * *w_i - eta.0 * *w_o;
+        if w_h.length_sqr() > 0.0 {
+            Some(w_h.normalized())
+        } else {
+            None
+        }
     }
 }
 
 impl<'a> BxDF for MicrofacetTransmission<'a> {
+    fn name(&self) -> &'static str { "MicrofacetTransmission" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Glossy);
@@ -72,14 +80,18 @@ impl<'a> BxDF for MicrofacetTransmission<'a> {
             return Colorf::black();
         }
         let eta = self.eta_for_interaction(w_o);
-        let w_h = MicrofacetTransmission::half_vector(w_o, w_i, eta);
+        let w_h = match MicrofacetTransmission::half_vector(w_o, w_i, eta) {
+            Some(w) => w,
+            None => return Colorf::black(),
+        };
         let d = self.microfacet.normal_distribution(&w_h);
         let f = Colorf::broadcast(1.0) - self.fresnel.fresnel(linalg::dot(w_i, &w_h));
         let g = self.microfacet.shadowing_masking(w_i, w_o, &w_h);
         let wi_dot_h = linalg::dot(w_i, &w_h);
         let jacobian = MicrofacetTransmission::jacobian(w_o, w_i, &w_h, eta);
-        self.reflectance * (f32::abs(wi_dot_h) / (f32::abs(w_i.z) * f32::abs(w_o.z)))
-            * (f * g * d) * jacobian
+        // The cosines are non-zero but may be small enough to overflow the result
+        bxdf::sanitize(self.reflectance * (f32::abs(wi_dot_h) / (f32::abs(w_i.z) * f32::abs(w_o.z)))
+                       * (f * g * d) * jacobian, self.name())
     }
     fn sample(&self, w_o: &Vector, samples: &(f32, f32)) -> (Colorf, Vector, f32) {
         let mut w_h = self.microfacet.sample(w_o, samples);
@@ -102,10 +114,29 @@ impl<'a> BxDF for MicrofacetTransmission<'a> {
             0.0
         } else {
             let eta = self.eta_for_interaction(w_o);
-            let w_h = MicrofacetTransmission::half_vector(w_o, w_i, eta);
-            self.microfacet.pdf(&w_h) * MicrofacetTransmission::jacobian(w_o, w_i, &w_h, eta)
+            match MicrofacetTransmission::half_vector(w_o, w_i, eta) {
+                Some(w_h) => bxdf::sanitize_pdf(self.microfacet.pdf(&w_h)
+                                                * MicrofacetTransmission::jacobian(w_o, w_i, &w_h, eta),
+                                                self.name()),
+                None => 0.0,
+            }
         }
     }
 }
 
-
+#[test]
+fn test_degenerate_half_vector() {
+    use bxdf::microfacet::GGX;
+    // With matching indices of refraction light passing straight through has no half vector
+    let fresnel = Dielectric::new(1.0, 1.0);
+    let ggx = GGX::new(0.3);
+    let btdf = MicrofacetTransmission::new(&Colorf::broadcast(1.0), &fresnel, &ggx);
+    let w_o = Vector::new(0.3, 0.2, 0.8).normalized();
+    let f = btdf.eval(&w_o, &-w_o);
+    assert!(f.is_finite() && f.is_black());
+    assert_eq!(btdf.pdf(&w_o, &-w_o), 0.0);
+    // Directions grazing the surface give finite values
+    let w_i = Vector::new(1.0, 0.0, -1e-20).normalized();
+    assert!(btdf.eval(&w_o, &w_i).is_finite());
+    assert!(btdf.pdf(&w_o, &w_i).is_finite());
+}
@@ -18,6 +18,8 @@ pub use self::specular_transmission::SpecularTransmission;
 pub use self::merl::Merl;
 pub use self::torrance_sparrow::TorranceSparrow;
 pub use self::microfacet_transmission::MicrofacetTransmission;
+pub use self::multiple_scattering::MultipleScattering;
+pub use self::fresnel_weighted::FresnelWeighted;
 
 pub mod bsdf;
 pub mod lambertian;
@@ -29,6 +31,8 @@ pub mod merl;
 pub mod microfacet;
 pub mod torrance_sparrow;
 pub mod microfacet_transmission;
+pub mod multiple_scattering;
+pub mod fresnel_weighted;
 
 /// Various types of BxDFs that can be selected to specify which
 /// types of surface functions should be evaluated
@@ -90,6 +94,9 @@ impl CLike for BxDFType {
 /// Trait implemented by BRDF/BTDFs in `tray_rust`. Provides methods for
 /// evaluating and sampling the function
 pub trait BxDF {
+    /// Get the name of this BxDF, used when reporting problems it caused. BxDFs
+    /// defined outside `tray_rust` which don't override this are reported as "unknown"
+    fn name(&self) -> &'static str { "unknown" }
     /// Get the type of this BxDF
     fn bxdf_type(&self) -> EnumSet<BxDFType>;
     /// Evaluate the BxDF for the pair of incident and outgoing light directions,
@@ -121,6 +128,31 @@ pub trait BxDF {
     }
 }
 
+/// Return black in place of the color `f` computed by `source` if it has NaNs or Infs,
+/// so numerical problems in a BxDF don't propagate into the image. In debug builds
+/// the bad value and where it came from are logged to help track down the problem
+pub fn sanitize(f: Colorf, source: &str) -> Colorf {
+    if f.is_finite() {
+        f
+    } else {
+        if cfg!(debug_assertions) {
+            warn!("{} produced an invalid color {:?}, replacing with black", source, f);
+        }
+        Colorf::black()
+    }
+}
+/// Return 0 in place of the pdf computed by `source` if it's NaN or Inf, see `sanitize`
+pub fn sanitize_pdf(pdf: f32, source: &str) -> f32 {
+    if f32::is_finite(pdf) {
+        pdf
+    } else {
+        if cfg!(debug_assertions) {
+            warn!("{} produced an invalid pdf {}, replacing with 0", source, pdf);
+        }
+        0.0
+    }
+}
+
 /// Compute the value of cosine theta for a vector in shading space
 pub fn cos_theta(v: &Vector) -> f32 { v.z }
 /// Compute the value of cosine^2 theta for a vector in shading space
@@ -0,0 +1,212 @@
+//! This module provides an energy compensation term for microfacet BRDFs, which only
+//! model a single scattering event on the microfacets and lose energy at high roughness.
+//! The light lost is added back with the multiple scattering lobe described by
+//! [Kulla and Conty 17](https://blog.selfshadow.com/publications/s2017-shading-course/imageworks/s2017_pbs_imageworks_slides_v2.pdf)
+//! using a precomputed table of the directional albedo of the single scattering BRDF.
+
+use std::{f32, cmp};
+use enum_set::EnumSet;
+
+use linalg::{self, Vector};
+use film::Colorf;
+use bxdf::{self, BxDF, BxDFType, TorranceSparrow};
+use bxdf::fresnel::Fresnel;
+use bxdf::microfacet::MicrofacetDistribution;
+
+/// Number of roughness values the albedo is tabulated at over [0, 1]
+const ROUGHNESS_SAMPLES: usize = 32;
+/// Number of cos theta values the albedo is tabulated at over [0, 1]
+const COS_THETA_SAMPLES: usize = 32;
+/// Number of strata along each axis used to integrate the albedo
+const INTEGRATION_STRATA: usize = 16;
+
+/// Perfectly reflective Fresnel term used to compute the albedo of the microfacet
+/// distribution alone
+struct FresnelOne;
+
+impl Fresnel for FresnelOne {
+    fn fresnel(&self, _: f32) -> Colorf { Colorf::broadcast(1.0) }
+}
+
+/// Table of the directional albedo `E(mu)` of the single scattering Torrance Sparrow BRDF
+/// with a perfectly reflective Fresnel term, along with its cosine weighted average `E_avg`.
+/// The table is computed once when the material is loaded and is indexed by roughness.
+pub struct AlbedoTable {
+    /// Directional albedo, stored as `ROUGHNESS_SAMPLES` rows of `COS_THETA_SAMPLES`
+    albedo: Vec<f32>,
+    /// Average albedo for each roughness value
+    average: Vec<f32>,
+}
+
+impl AlbedoTable {
+    /// Compute the albedo table for the microfacet distribution created by `distribution`
+    /// for each roughness value.
+    pub fn new<D, F>(distribution: F) -> AlbedoTable
+        where D: MicrofacetDistribution, F: Fn(f32) -> D
+    {
+        let mut albedo = Vec::with_capacity(ROUGHNESS_SAMPLES * COS_THETA_SAMPLES);
+        let mut average = Vec::with_capacity(ROUGHNESS_SAMPLES);
+        for r in 0..ROUGHNESS_SAMPLES {
+            let microfacet = distribution(r as f32 / (ROUGHNESS_SAMPLES - 1) as f32);
+            let brdf = TorranceSparrow::new(&Colorf::broadcast(1.0), &FresnelOne, &microfacet);
+            for c in 0..COS_THETA_SAMPLES {
+                // Avoid evaluating exactly at grazing angles where the BRDF is undefined
+                let cos_theta = f32::max(c as f32 / (COS_THETA_SAMPLES - 1) as f32, 0.001);
+                albedo.push(directional_albedo(&brdf, cos_theta));
+            }
+            // E_avg = 2 * integral of E(mu) * mu, computed with the trapezoidal rule
+            let avg = {
+                let row = &albedo[r * COS_THETA_SAMPLES..];
+                let dmu = 1.0 / (COS_THETA_SAMPLES - 1) as f32;
+                let mut sum = 0.0;
+                for c in 0..COS_THETA_SAMPLES - 1 {
+                    let mu_a = c as f32 * dmu;
+                    let mu_b = (c + 1) as f32 * dmu;
+                    sum += 0.5 * (row[c] * mu_a + row[c + 1] * mu_b) * dmu;
+                }
+                2.0 * sum
+            };
+            average.push(linalg::clamp(avg, 0.0, 1.0));
+        }
+        AlbedoTable { albedo: albedo, average: average }
+    }
+    /// Get the directional albedo for each tabulated cos theta value of a surface
+    /// with `roughness`, interpolated between the tabulated roughness values
+    pub fn albedo_row(&self, roughness: f32) -> [f32; COS_THETA_SAMPLES] {
+        let (i, t) = roughness_index(roughness);
+        let mut row = [0.0; COS_THETA_SAMPLES];
+        for (c, x) in row.iter_mut().enumerate() {
+            *x = linalg::lerp(t, &self.albedo[i * COS_THETA_SAMPLES + c],
+                              &self.albedo[(i + 1) * COS_THETA_SAMPLES + c]);
+        }
+        row
+    }
+    /// Look up the directional albedo for light leaving at angle `cos_theta`
+    /// from a surface with `roughness`
+    pub fn albedo(&self, roughness: f32, cos_theta: f32) -> f32 {
+        lookup_albedo(&self.albedo_row(roughness), cos_theta)
+    }
+    /// Look up the average albedo of a surface with `roughness`
+    pub fn average_albedo(&self, roughness: f32) -> f32 {
+        let (i, t) = roughness_index(roughness);
+        linalg::lerp(t, &self.average[i], &self.average[i + 1])
+    }
+}
+
+/// Find the tabulated roughness value below `roughness` and the offset from it
+/// to interpolate with
+fn roughness_index(roughness: f32) -> (usize, f32) {
+    let r = linalg::clamp(roughness, 0.0, 1.0) * (ROUGHNESS_SAMPLES - 1) as f32;
+    let i = cmp::min(r as usize, ROUGHNESS_SAMPLES - 2);
+    (i, r - i as f32)
+}
+
+/// Linearly interpolate the albedo in the row for light leaving at angle `cos_theta`
+fn lookup_albedo(row: &[f32; COS_THETA_SAMPLES], cos_theta: f32) -> f32 {
+    let c = linalg::clamp(f32::abs(cos_theta), 0.0, 1.0) * (COS_THETA_SAMPLES - 1) as f32;
+    let i = cmp::min(c as usize, COS_THETA_SAMPLES - 2);
+    linalg::lerp(c - i as f32, &row[i], &row[i + 1])
+}
+
+/// Integrate the albedo of the BRDF for light leaving at angle `cos_theta` by importance
+/// sampling it with stratified samples
+fn directional_albedo(brdf: &BxDF, cos_theta: f32) -> f32 {
+    let w_o = Vector::new(f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta)), 0.0, cos_theta);
+    let mut sum = 0.0;
+    for i in 0..INTEGRATION_STRATA {
+        for j in 0..INTEGRATION_STRATA {
+            let samples = ((i as f32 + 0.5) / INTEGRATION_STRATA as f32,
+                           (j as f32 + 0.5) / INTEGRATION_STRATA as f32);
+            let (f, w_i, pdf) = brdf.sample(&w_o, &samples);
+            if pdf > 0.0 {
+                sum += f.r * f32::abs(bxdf::cos_theta(&w_i)) / pdf;
+            }
+        }
+    }
+    linalg::clamp(sum / (INTEGRATION_STRATA * INTEGRATION_STRATA) as f32, 0.0, 1.0)
+}
+
+/// Compute the cosine weighted average of the Fresnel term over the hemisphere
+pub fn average_fresnel(fresnel: &Fresnel) -> Colorf {
+    let n = 16;
+    let mut avg = Colorf::black();
+    for i in 0..n {
+        let mu = (i as f32 + 0.5) / n as f32;
+        avg = avg + fresnel.fresnel(mu) * mu;
+    }
+    avg * 2.0 / n as f32
+}
+
+/// The multiple scattering lobe added alongside a single scattering microfacet BRDF
+/// to restore the energy lost at high roughness
+#[derive(Copy, Clone)]
+pub struct MultipleScattering {
+    /// Directional albedo of the single scattering BRDF for the surface's roughness
+    albedo: [f32; COS_THETA_SAMPLES],
+    /// Average albedo of the single scattering BRDF
+    avg_albedo: f32,
+    /// Scaling of the lobe to account for light absorbed by the Fresnel term
+    /// over multiple bounces
+    scale: Colorf,
+}
+
+impl MultipleScattering {
+    /// Create the multiple scattering lobe for a surface with `roughness` whose
+    /// single scattering BRDF has the cosine weighted average Fresnel term `avg_fresnel`
+    pub fn new(table: &AlbedoTable, roughness: f32, avg_fresnel: &Colorf) -> MultipleScattering {
+        let e_avg = table.average_albedo(roughness);
+        let mut scale = Colorf::black();
+        for i in 0..3 {
+            let f = avg_fresnel[i];
+            scale[i] = f * f * e_avg / (1.0 - f * (1.0 - e_avg));
+        }
+        MultipleScattering { albedo: table.albedo_row(roughness), avg_albedo: e_avg, scale: scale }
+    }
+}
+
+impl BxDF for MultipleScattering {
+    fn name(&self) -> &'static str { "MultipleScattering" }
+    fn bxdf_type(&self) -> EnumSet<BxDFType> {
+        let mut e = EnumSet::new();
+        e.insert(BxDFType::Glossy);
+        e.insert(BxDFType::Reflection);
+        e
+    }
+    fn eval(&self, w_o: &Vector, w_i: &Vector) -> Colorf {
+        if !bxdf::same_hemisphere(w_o, w_i) || self.avg_albedo >= 1.0 {
+            return Colorf::black();
+        }
+        let e_o = lookup_albedo(&self.albedo, bxdf::cos_theta(w_o));
+        let e_i = lookup_albedo(&self.albedo, bxdf::cos_theta(w_i));
+        self.scale * (1.0 - e_o) * (1.0 - e_i) / (f32::consts::PI * (1.0 - self.avg_albedo))
+    }
+}
+
+#[test]
+fn test_albedo_table() {
+    use bxdf::microfacet::Beckmann;
+    let table = AlbedoTable::new(Beckmann::new);
+    // Smooth surfaces reflect almost all light while rough ones lose energy
+    assert!(table.average_albedo(0.0) > 0.95);
+    assert!(table.average_albedo(1.0) < table.average_albedo(0.2));
+    for &r in &[0.1, 0.5, 0.9] {
+        for &c in &[0.1, 0.5, 1.0] {
+            let e = table.albedo(r, c);
+            assert!(e > 0.0 && e <= 1.0);
+        }
+    }
+    // With a perfect reflector the single and multiple scattering lobes together should
+    // conserve energy, integrate the multiple scattering lobe's albedo numerically
+    let roughness = 0.8;
+    let ms = MultipleScattering::new(&table, roughness, &Colorf::broadcast(1.0));
+    let w_o = Vector::new(f32::sqrt(1.0 - 0.5 * 0.5), 0.0, 0.5);
+    let n = 256;
+    let mut ms_albedo = 0.0;
+    for i in 0..n {
+        let mu = (i as f32 + 0.5) / n as f32;
+        let w_i = Vector::new(f32::sqrt(1.0 - mu * mu), 0.0, mu);
+        ms_albedo += ms.eval(&w_o, &w_i).r * mu * 2.0 * f32::consts::PI / n as f32;
+    }
+    let total = table.albedo(roughness, 0.5) + ms_albedo;
+    assert!(f32::abs(total - 1.0) < 0.05);
+}
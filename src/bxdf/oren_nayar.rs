@@ -21,19 +21,20 @@ pub struct OrenNayar {
 
 impl OrenNayar {
     /// Create a new Oren-Nayar BRDF with the desired color and roughness
-    /// `roughness` should be the variance of the Gaussian describing the
-    /// microfacet distribution
-    pub fn new(c: &Colorf, roughness: f32) -> OrenNayar {
-        let mut sigma = linalg::to_radians(roughness);
-        sigma *= sigma;
+    /// `sigma` is the standard deviation in degrees of the Gaussian distribution
+    /// of the microfacet slope angles. A sigma of 0 is a smooth Lambertian surface
+    pub fn new(c: &Colorf, sigma: f32) -> OrenNayar {
+        let sigma = linalg::to_radians(sigma);
+        let sigma_sqr = sigma * sigma;
         OrenNayar { albedo: *c,
-                    a: 1.0 - 0.5 * sigma / (sigma + 0.33),
-                    b: 0.45 * sigma / (sigma + 0.09),
+                    a: 1.0 - 0.5 * sigma_sqr / (sigma_sqr + 0.33),
+                    b: 0.45 * sigma_sqr / (sigma_sqr + 0.09),
         }
     }
 }
 
 impl BxDF for OrenNayar {
+    fn name(&self) -> &'static str { "OrenNayar" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Diffuse);
@@ -60,3 +61,26 @@ impl BxDF for OrenNayar {
     }
 }
 
+
+#[test]
+fn test_lambertian_limit() {
+    use bxdf::Lambertian;
+    let c = Colorf::new(0.8, 0.5, 0.2);
+    let smooth = OrenNayar::new(&c, 0.0);
+    let rough = OrenNayar::new(&c, 20.0);
+    let lambertian = Lambertian::new(&c);
+    let dirs = [Vector::new(0.0, 0.0, 1.0), Vector::new(0.6, 0.0, 0.8), Vector::new(-0.48, 0.36, 0.8),
+                Vector::new(0.0, 0.96, 0.28)];
+    let mut differs = false;
+    for w_o in &dirs {
+        for w_i in &dirs {
+            // With sigma = 0 Oren-Nayar is exactly Lambertian
+            let f = smooth.eval(w_o, w_i);
+            let l = lambertian.eval(w_o, w_i);
+            assert!((0..3).all(|i| f32::abs(f[i] - l[i]) < 1e-6));
+            let r = rough.eval(w_o, w_i);
+            differs = differs || (0..3).any(|i| f32::abs(r[i] - l[i]) > 1e-3);
+        }
+    }
+    assert!(differs);
+}
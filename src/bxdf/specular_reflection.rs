@@ -25,6 +25,7 @@ impl<'a> SpecularReflection<'a> {
 }
 
 impl<'a> BxDF for SpecularReflection<'a> {
+    fn name(&self) -> &'static str { "SpecularReflection" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Specular);
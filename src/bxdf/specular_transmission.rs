@@ -25,6 +25,7 @@ impl<'a> SpecularTransmission<'a> {
 }
 
 impl<'a> BxDF for SpecularTransmission<'a> {
+    fn name(&self) -> &'static str { "SpecularTransmission" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Specular);
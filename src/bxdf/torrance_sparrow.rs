@@ -31,6 +31,7 @@ impl<'a> TorranceSparrow<'a> {
 }
 
 impl<'a> BxDF for TorranceSparrow<'a> {
+    fn name(&self) -> &'static str { "TorranceSparrow" }
     fn bxdf_type(&self) -> EnumSet<BxDFType> {
         let mut e = EnumSet::new();
         e.insert(BxDFType::Glossy);
@@ -51,7 +52,7 @@ impl<'a> BxDF for TorranceSparrow<'a> {
         let d = self.microfacet.normal_distribution(&w_h);
         let f = self.fresnel.fresnel(linalg::dot(w_i, &w_h));
         let g = self.microfacet.shadowing_masking(w_i, w_o, &w_h);
-        (self.reflectance * f * d * g / (4.0 * cos_ti * cos_to))
+        bxdf::sanitize(self.reflectance * f * d * g / (4.0 * cos_ti * cos_to), self.name())
     }
     fn sample(&self, w_o: &Vector, samples: &(f32, f32)) -> (Colorf, Vector, f32) {
         if w_o.z == 0.0 {
@@ -76,7 +77,7 @@ impl<'a> BxDF for TorranceSparrow<'a> {
             // This term is p_o(o) in eq. 38 of Walter et al's 07 paper and is for reflection so
             // we use the Jacobian for reflection, eq. 14
             let jacobian = 1.0 / (4.0 * f32::abs(linalg::dot(w_o, &w_h)));
-            self.microfacet.pdf(&w_h) * jacobian
+            bxdf::sanitize_pdf(self.microfacet.pdf(&w_h) * jacobian, self.name())
         }
     }
 }
@@ -0,0 +1,98 @@
+//! Provides baking of the lighting on an object in the scene into a texture, e.g. to
+//! produce lightmaps for game assets. Instead of tracing rays from the camera the object's
+//! surface is rasterized in texture space, for each texel covered by the surface we look
+//! back down at the surface along its normal and compute the radiance leaving it with the
+//! scene's integrator. The texture is indexed by the surface's texture coordinates so it
+//! can be mapped back onto the object, currently only meshes can be baked since the other
+//! geometry has no texture space rasterizer.
+//!
+//! Baking is driven by passing `--bake <instance> <resolution>`, see the usage of tray_rust.
+
+use std::sync::Mutex;
+
+use scoped_threadpool::Pool;
+use rand::StdRng;
+use light_arena::{self, Allocator};
+
+use film::Colorf;
+use geometry::{Instance, Emitter};
+use linalg::{Ray, Vector, Point, Normal};
+use sampler::{self, Sampler};
+use scene::Scene;
+
+/// Distance above the surface the rays looking back at each texel start from
+const BAKE_OFFSET: f32 = 1e-3;
+
+/// Bake the lighting on the receiver tagged `tag` into a texture with `dim` texels, taking
+/// `spp` samples of the lighting at each texel at the time passed. Returns the RGBW f32
+/// pixels of the texture, texels not covered by the surface have a weight of 0. Panics if
+/// there is no receiver with the tag in the scene
+pub fn bake(pool: &mut Pool, scene: &Scene, tag: &str, dim: (usize, usize), spp: usize, time: f32) -> Vec<f32> {
+    let receiver = scene.accel.iter().filter_map(|x| {
+        match *x {
+            Instance::Receiver(ref r) if r.tag == tag => Some(r),
+            _ => None,
+        }
+    }).next().expect(&format!("No receiver tagged '{}' to bake in the scene", tag)[..]);
+    let light_list: Vec<_> = scene.accel.iter().filter_map(|x| {
+        match *x {
+            Instance::Emitter(ref e) => Some(e),
+            _ => None,
+        }
+    }).collect();
+    assert!(!light_list.is_empty(), "At least one light is required");
+    let texture = Mutex::new(vec![0.0; dim.0 * dim.1 * 4]);
+    let n = pool.thread_count() as usize;
+    pool.scoped(|scope| {
+        for i in 0..n {
+            let t = &texture;
+            let l = &light_list;
+            // The differential geometry can't be sent between threads, so each thread
+            // rasterizes the surface and bakes its share of the texture's rows
+            scope.execute(move || {
+                let mut rng = match StdRng::new() {
+                    Ok(r) => r,
+                    Err(e) => { error!("Failed to get StdRng, {}", e); return }
+                };
+                let mut sampler = sampler::Uniform::new((1, 1));
+                let mut arena = light_arena::MemoryArena::new(8);
+                let mut texels = Vec::new();
+                receiver.rasterize_uv(dim, time, &mut |texel, dg| {
+                    if texel.1 % n != i {
+                        return;
+                    }
+                    let mut illum = Colorf::black();
+                    for _ in 0..spp {
+                        let alloc = arena.allocator();
+                        illum = illum + bake_sample(scene, l, &dg.p, &dg.n, time, &mut sampler,
+                                                    &mut rng, &alloc);
+                    }
+                    texels.push((texel, illum / spp as f32));
+                });
+                let mut texture = t.lock().unwrap();
+                for &((x, y), c) in &texels {
+                    let px = (y * dim.0 + x) * 4;
+                    texture[px] = c.r;
+                    texture[px + 1] = c.g;
+                    texture[px + 2] = c.b;
+                    texture[px + 3] = 1.0;
+                }
+            });
+        }
+    });
+    texture.into_inner().unwrap()
+}
+
+/// Compute the radiance leaving the surface at `p` along its normal `n`
+fn bake_sample(scene: &Scene, light_list: &[&Emitter], p: &Point, n: &Normal, time: f32,
+               sampler: &mut Sampler, rng: &mut StdRng, alloc: &Allocator) -> Colorf {
+    // Look back down at the surface from just above it so the hit is found with the
+    // scene's own intersection code and the integrators see a regular camera ray hit
+    let n = n.normalized();
+    let d = -Vector::new(n.x, n.y, n.z);
+    let mut ray = Ray::segment(&(*p - d * BAKE_OFFSET), &d, 0.0, 2.0 * BAKE_OFFSET, time);
+    match scene.intersect(&mut ray) {
+        Some(hit) => scene.integrator.illumination(scene, light_list, &ray, &hit, sampler, rng, alloc).clamp(),
+        None => Colorf::black(),
+    }
+}
@@ -2,21 +2,21 @@
 //! portions of the image they should render and collects their results to combine
 //! into the final image.
 
-use std::path::PathBuf;
+use std::io;
 use std::io::prelude::*;
 use std::collections::HashMap;
 use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
 use std::iter;
 use std::time::SystemTime;
 
 use bincode::{Infinite, serialize, deserialize};
-use image;
 use mio::tcp::{TcpStream, Shutdown};
 use mio::*;
 
-use film::Image;
+use film::{output, Image, AovImage};
 use exec::Config;
-use exec::distrib::{worker, Instructions, Frame};
+use exec::distrib::{worker, Instructions, Frame, Asset, AssetRequest, AssetData};
 use sampler::BlockQueue;
 
 /// Stores distributed rendering status. The frame is either `InProgress` and contains
@@ -29,6 +29,8 @@ enum DistributedFrame {
         // frame so far
         num_reporting: usize,
         render: Image,
+        // The AOVs reported for this frame, if the scene saves any
+        aovs: Option<AovImage>,
         // Start time of this frame, when we got the first tiles in from a worker
         first_tile_recv: SystemTime,
     },
@@ -36,10 +38,11 @@ enum DistributedFrame {
 }
 
 impl DistributedFrame {
-    pub fn start(img_dim: (usize, usize)) -> DistributedFrame {
+    pub fn start(img_dim: (usize, usize), aovs: bool) -> DistributedFrame {
         DistributedFrame::InProgress {
             num_reporting: 0,
             render: Image::new(img_dim),
+            aovs: if aovs { Some(AovImage::new(img_dim)) } else { None },
             first_tile_recv: SystemTime::now(),
         }
     }
@@ -61,6 +64,31 @@ impl WorkerBuffer {
     }
 }
 
+/// Buffer of data waiting to be sent to a worker. Large messages like the scene files
+/// can't be written all at once so they're written as the connection becomes writable
+#[derive(Clone, Debug)]
+struct SendBuffer {
+    pub buf: Vec<u8>,
+    pub written: usize,
+}
+
+impl SendBuffer {
+    pub fn new() -> SendBuffer {
+        SendBuffer { buf: Vec::new(), written: 0 }
+    }
+}
+
+/// Tracks each worker through the handshake before it starts rendering
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum WorkerStatus {
+    /// The worker is waiting for its instructions
+    Connected,
+    /// The worker has its instructions and will reply with the scene files it needs
+    Instructed,
+    /// The worker has been sent its scene files and is rendering
+    Rendering,
+}
+
 /// The Master organizes the set of Worker processes and instructions them what parts
 /// of the scene to render. As workers report results the master collects them and
 /// saves out the PNG once all workers have reported the frame.
@@ -71,10 +99,16 @@ pub struct Master {
     /// Temporary buffers to store worker results in as they're
     /// read in over TCP
     worker_buffers: Vec<WorkerBuffer>,
+    /// Data waiting to be sent to each worker
+    send_buffers: Vec<SendBuffer>,
+    worker_status: Vec<WorkerStatus>,
     config: Config,
     /// List of the frames we're collecting or have completed
     frames: HashMap<usize, DistributedFrame>,
     img_dim: (usize, usize),
+    /// The scene file and the files it references, sent to the workers that don't have them
+    scene: Asset,
+    assets: Vec<Asset>,
     /// Number of 8x8 blocks we're assigning per worker
     blocks_per_worker: usize,
     /// Remainder of blocks that will be tacked on to the last
@@ -84,13 +118,16 @@ pub struct Master {
 
 impl Master {
     /// Create a new master that will contact the worker nodes passed and
-    /// send instructions on what parts of the scene to start rendering
-    pub fn start_workers(workers: Vec<String>, config: Config, img_dim: (usize, usize))
+    /// send instructions on what parts of the scene to start rendering. `assets` are
+    /// the files referenced by the scene, which are sent along with it to the workers
+    pub fn start_workers(workers: Vec<String>, config: Config, img_dim: (usize, usize), assets: Vec<PathBuf>)
                          -> (Master, EventLoop<Master>) {
         // Figure out how many blocks we have for this image and assign them to our workers
         let queue = BlockQueue::new((img_dim.0 as u32, img_dim.1 as u32), (8, 8), (0, 0));
         let blocks_per_worker = queue.len() / workers.len();
         let blocks_remainder = queue.len() % workers.len();
+        let scene = Asset::load(Path::new(&config.scene_file));
+        let assets: Vec<_> = assets.iter().map(|a| Asset::load(a)).collect();
 
         let mut event_loop = EventLoop::<Master>::new().unwrap();
         let mut connections = Vec::new();
@@ -110,10 +147,14 @@ impl Master {
             }
         }
         let worker_buffers: Vec<_> = iter::repeat(WorkerBuffer::new()).take(workers.len()).collect();
+        let send_buffers: Vec<_> = iter::repeat(SendBuffer::new()).take(workers.len()).collect();
+        let worker_status = vec![WorkerStatus::Connected; workers.len()];
         let master = Master { workers: workers, connections: connections,
-                              worker_buffers: worker_buffers, config: config,
+                              worker_buffers: worker_buffers, send_buffers: send_buffers,
+                              worker_status: worker_status, config: config,
                               frames: HashMap::new(),
                               img_dim: img_dim,
+                              scene: scene, assets: assets,
                               blocks_per_worker: blocks_per_worker,
                               blocks_remainder: blocks_remainder };
         (master, event_loop)
@@ -124,37 +165,43 @@ impl Master {
     fn save_results(&mut self, frame: Frame) {
         let frame_num = frame.frame as usize;
         let img_dim = self.img_dim;
+        let aovs = output::uses_aovs(&self.config.outputs);
         // Find the frame being reported and create it if we haven't received parts of this frame yet
-        let mut df = self.frames.entry(frame_num).or_insert_with(|| DistributedFrame::start(img_dim));
+        let mut df = self.frames.entry(frame_num).or_insert_with(|| DistributedFrame::start(img_dim, aovs));
 
         let mut finished = false;
         match *df {
-            DistributedFrame::InProgress { ref mut num_reporting, ref mut render, ref first_tile_recv } => {
+            DistributedFrame::InProgress { ref mut num_reporting, ref mut render, ref mut aovs,
+                                           ref first_tile_recv } => {
                 // Collect results from the worker and see if we've finished the frame and can save
                 // it out
                 render.add_blocks(frame.block_size, &frame.blocks, &frame.pixels);
+                if let Some(ref mut a) = *aovs {
+                    if !frame.aovs.is_empty() {
+                        a.set_blocks(frame.block_size, &frame.blocks, &frame.aovs);
+                    }
+                }
                 *num_reporting += 1;
                 if *num_reporting == self.workers.len() {
                     let render_time = first_tile_recv.elapsed().expect("Failed to get rendering time?");
-                    let out_file = match self.config.out_path.extension() {
-                        Some(_) => self.config.out_path.clone(),
-                        None => self.config.out_path.join(
-                            PathBuf::from(format!("frame{:05}.png", frame_num))),
-                    };
-                    let img = render.get_srgb8();
-                    let dim = render.dimensions();
-                    match image::save_buffer(&out_file.as_path(), &img[..], dim.0 as u32,
-                    dim.1 as u32, image::RGB(8)) {
-                        Ok(_) => {},
-                        Err(e) => println!("Error saving image, {}", e),
-                    };
-                    println!("Frame {}: time between receiving first and last tile {:4}s",
+                    let mut pixels = render.get_rgbw_f32();
+                    if let Some(ref mut exposure) = self.config.auto_exposure {
+                        exposure.apply(&mut pixels[..]);
+                    }
+                    let aov_pixels = aovs.as_ref().map_or(&[][..], |a| a.pixels());
+                    let out_files = output::save_frame(&self.config.outputs, &self.config.out_path, frame_num,
+                                                       render.dimensions(), &pixels, aov_pixels, self.config.grade,
+                                                       self.config.tonemap);
+                    info!("Frame {}: time between receiving first and last tile {:4}s",
                              frame_num, render_time.as_secs() as f64 + render_time.subsec_nanos() as f64 * 1e-9);
-                    println!("Frame {}: rendered to '{}'\n--------------------", frame_num, out_file.display());
+                    for f in &out_files {
+                        info!("Frame {}: rendered to '{}'", frame_num, f.display());
+                    }
+                    info!("--------------------");
                     finished = true;
                 }
             },
-            DistributedFrame::Completed => println!("Worker reporting on completed frame {}?", frame_num),
+            DistributedFrame::Completed => warn!("Worker reporting on completed frame {}?", frame_num),
         }
         // This is a bit awkward, since we borrow df in the match we can't mark it finished in there
         if finished {
@@ -171,7 +218,7 @@ impl Master {
             buf.buf.extend(iter::repeat(0u8).take(8));
             match self.connections[worker].read(&mut buf.buf[buf.currently_read..]) {
                 Ok(n) => buf.currently_read += n,
-                Err(e) => println!("Error reading results from worker {}: {}", self.workers[worker], e),
+                Err(e) => error!("Failed to read results from worker {}: {}", self.workers[worker], e),
             }
             if buf.currently_read == buf.expected_size {
                 // How many bytes we expect to get from the worker for a frame
@@ -185,11 +232,53 @@ impl Master {
         if buf.currently_read >= 8 {
             match self.connections[worker].read(&mut buf.buf[buf.currently_read..]) {
                 Ok(n) => buf.currently_read += n,
-                Err(e) => println!("Error reading results from worker {}: {}", self.workers[worker], e),
+                Err(e) => error!("Failed to read results from worker {}: {}", self.workers[worker], e),
             }
         }
         buf.currently_read == buf.expected_size
     }
+    /// Queue the message to be sent to the worker, it's written out as the worker becomes writable
+    fn send(&mut self, event_loop: &mut EventLoop<Master>, worker: usize, bytes: Vec<u8>) {
+        self.send_buffers[worker].buf.extend(bytes);
+        event_loop.reregister(&self.connections[worker], Token(worker), EventSet::all(), PollOpt::level())
+            .expect("Re-registering failed");
+    }
+    /// Write as much of the data waiting to be sent to the worker as we can without blocking.
+    /// Once everything is sent we stop listening for writable events from the worker
+    fn write_pending(&mut self, event_loop: &mut EventLoop<Master>, worker: usize) {
+        let out = &mut self.send_buffers[worker];
+        while out.written < out.buf.len() {
+            match self.connections[worker].write(&out.buf[out.written..]) {
+                Ok(n) => out.written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("Failed to send data to {}: {:?}", self.workers[worker], e);
+                    break;
+                },
+            }
+        }
+        out.buf.clear();
+        out.written = 0;
+        event_loop.reregister(&self.connections[worker], Token(worker),
+                              EventSet::readable() | EventSet::error() | EventSet::hup(),
+                              PollOpt::level()).expect("Re-registering failed");
+    }
+    /// Send the worker the scene files it requested. Requests for files we don't know about,
+    /// e.g. from a stale worker, are logged and skipped so they don't take down the render
+    fn send_assets(&mut self, event_loop: &mut EventLoop<Master>, worker: usize, request: AssetRequest) {
+        let files: Vec<_> = request.hashes.iter().filter_map(|h| {
+            match self.assets.iter().chain(iter::once(&self.scene)).find(|a| a.hash == *h) {
+                Some(a) => Some((a.hash, a.read())),
+                None => {
+                    error!("Worker {} requested unknown scene file {:016x}, skipping it", self.workers[worker], h);
+                    None
+                },
+            }
+        }).collect();
+        info!("Sending {} scene files to {}", files.len(), self.workers[worker]);
+        let bytes = serialize(&AssetData::new(files), Infinite).unwrap();
+        self.send(event_loop, worker, bytes);
+    }
 }
 
 impl Handler for Master {
@@ -206,15 +295,15 @@ impl Handler for Master {
         // If the worker has terminated, shutdown the read end of the connection
         if event.is_hup() {
             if let Err(e) = self.connections[worker].shutdown(Shutdown::Both) {
-                println!("Error shutting down worker {}: {}", worker, e);
+                error!("Failed to shut down worker {}: {}", worker, e);
             }
             // Remove the connection from the event loop
             if let Err(e) = event_loop.deregister(&self.connections[worker]) {
-                println!("Error deregistering worker {}: {}", worker, e);
+                error!("Failed to deregister worker {}: {}", worker, e);
             }
         }
         // A worker is ready to receive instructions from us
-        if event.is_writable() {
+        if event.is_writable() && self.worker_status[worker] == WorkerStatus::Connected {
             let b_start = worker * self.blocks_per_worker;
             let b_count =
                 if worker == self.workers.len() - 1 {
@@ -222,25 +311,34 @@ impl Handler for Master {
                 } else {
                     self.blocks_per_worker
                 };
-            let instr = Instructions::new(&self.config.scene_file,
+            let instr = Instructions::new(&self.scene, &self.assets,
                                           (self.config.frame_info.start, self.config.frame_info.end),
-                                          b_start, b_count);
+                                          b_start, b_count, self.config.spp, self.img_dim);
             // Encode and send our instructions to the worker
             let bytes = serialize(&instr, Infinite).unwrap();
-            if let Err(e) = self.connections[worker].write_all(&bytes[..]) {
-                println!("Failed to send instructions to {}: {:?}", self.workers[worker], e);
-            }
-            // Register that we no longer care about writable events on this connection
-            event_loop.reregister(&self.connections[worker], token,
-                                  EventSet::readable() | EventSet::error() | EventSet::hup(),
-                                  PollOpt::level()).expect("Re-registering failed");
+            self.send(event_loop, worker, bytes);
+            self.worker_status[worker] = WorkerStatus::Instructed;
+        }
+        if event.is_writable() {
+            self.write_pending(event_loop, worker);
         }
-        // Some results are available from a worker
-        // Read results from the worker, if we've accumulated all the data being sent
-        // decode and accumulate the frame
+        // Some data is available from a worker, if we've accumulated all the data being sent
+        // decode it. After its instructions the worker requests the scene files it needs,
+        // after which it reports the results of each frame
         if event.is_readable() && self.read_worker_buffer(worker) {
-            let frame = deserialize(&self.worker_buffers[worker].buf[..]).unwrap();
-            self.save_results(frame);
+            match self.worker_status[worker] {
+                WorkerStatus::Instructed => {
+                    let request = deserialize(&self.worker_buffers[worker].buf[..]).unwrap();
+                    self.send_assets(event_loop, worker, request);
+                    self.worker_status[worker] = WorkerStatus::Rendering;
+                },
+                WorkerStatus::Rendering => {
+                    let frame = deserialize(&self.worker_buffers[worker].buf[..]).unwrap();
+                    self.save_results(frame);
+                },
+                WorkerStatus::Connected => warn!("Worker {} sent data before its instructions",
+                                                 self.workers[worker]),
+            }
             // Clean up the worker buffer for the next frame
             self.worker_buffers[worker].buf.clear();
             self.worker_buffers[worker].expected_size = 8;
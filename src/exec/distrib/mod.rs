@@ -26,8 +26,23 @@
 //! ./tray_rust cornell_box.json --master worker1 worker2 192.168.32.129
 //! ```
 //!
-//! The master will send the workers the location of the scene file which is assumed to
-//! be on some shared filesystem or otherwise available at the same path on all the workers.
+//! The master sends the workers the scene file along with the meshes, textures and other
+//! files it references by relative paths, so the workers don't need a shared filesystem.
+//! The transfer is content-addressed to avoid re-sending megabytes of mesh data for each job:
+//! the master sends the path and FNV-1a hash of each file with the instructions and the worker
+//! replies with the hashes it doesn't have in its cache, which the master then sends. The worker
+//! cache is kept in `tray_rust_cache` under the system's temporary directory, files are stored
+//! by their hash and linked into a copy of the master's directory layout to load the scene from.
+//! Files the scene references by absolute paths aren't sent and must be at the same path on
+//! all the workers.
+//!
+//! For quick previews the master can override the samples per pixel with `--spp` and scale
+//! the image resolution with `--resolution-scale`, the overrides are sent to the workers so
+//! the shared scene file doesn't need to be edited.
+//!
+//! ```text
+//! ./tray_rust cornell_box.json --master worker1 worker2 --spp 16 --resolution-scale 0.5
+//! ```
 //!
 //! # Running on GCE or EC2
 //!
@@ -38,6 +53,10 @@
 //! these IP addresses instead of the public IPs of the worker nodes.
 //!
 
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
 use bincode::serialized_size;
 
 pub use self::worker::Worker;
@@ -53,7 +72,9 @@ struct Instructions {
     /// Size header for binary I/O with bincode
     pub encoded_size: u64,
     /// Scene file for the worker to load
-    pub scene: String,
+    pub scene: Asset,
+    /// Files referenced by the scene which the worker needs to load it
+    pub assets: Vec<Asset>,
     /// Frames to be rendered (inclusive)
     pub frames: (usize, usize),
     /// Block in the z-order queue of blocks this worker will
@@ -61,18 +82,99 @@ struct Instructions {
     pub block_start: usize,
     /// Number of blocks this worker will render
     pub block_count: usize,
+    /// Samples per pixel to render with, which may override the scene file's
+    pub spp: usize,
+    /// Dimensions of the image to render, which may override the scene file's
+    pub dimensions: (usize, usize),
 }
 
 impl Instructions {
-    pub fn new(scene: &str, frames: (usize, usize), block_start: usize,
-               block_count: usize) -> Instructions {
-        let mut instr = Instructions { encoded_size: 0, scene: scene.to_owned(), frames: frames,
-                       block_start: block_start, block_count: block_count };
+    pub fn new(scene: &Asset, assets: &[Asset], frames: (usize, usize), block_start: usize,
+               block_count: usize, spp: usize, dimensions: (usize, usize)) -> Instructions {
+        let mut instr = Instructions { encoded_size: 0, scene: scene.clone(), assets: assets.to_vec(),
+                       frames: frames, block_start: block_start, block_count: block_count, spp: spp,
+                       dimensions: dimensions };
         instr.encoded_size = serialized_size(&instr);
         instr
     }
 }
 
+/// A file the scene needs to be rendered, identified by the hash of its contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Asset {
+    /// Absolute path of the file on the master
+    pub path: String,
+    /// FNV-1a hash of the file's contents, see `hash_bytes`
+    pub hash: u64,
+}
+
+impl Asset {
+    /// Hash the file at `path`, the path is made absolute so workers can lay out
+    /// the files they're sent the same way as the master
+    pub fn load(path: &Path) -> Asset {
+        let path = match fs::canonicalize(path) {
+            Ok(p) => p,
+            Err(e) => panic!("Failed to find scene file {}: {}", path.display(), e),
+        };
+        let mut asset = Asset { path: path.to_string_lossy().into_owned(), hash: 0 };
+        asset.hash = hash_bytes(&asset.read());
+        asset
+    }
+    /// Read the contents of the file
+    pub fn read(&self) -> Vec<u8> {
+        let mut f = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => panic!("Failed to open scene file {}: {}", self.path, e),
+        };
+        let mut contents = Vec::new();
+        if let Err(e) = f.read_to_end(&mut contents) {
+            panic!("Failed to read scene file {}: {}", self.path, e);
+        }
+        contents
+    }
+}
+
+/// Sent by the worker in reply to its instructions to request the scene files it
+/// doesn't have cached, identified by their hashes
+#[derive(Debug, Serialize, Deserialize)]
+struct AssetRequest {
+    /// Size header for binary I/O with bincode
+    pub encoded_size: u64,
+    /// Hashes of the files the worker needs
+    pub hashes: Vec<u64>,
+}
+
+impl AssetRequest {
+    pub fn new(hashes: Vec<u64>) -> AssetRequest {
+        let mut request = AssetRequest { encoded_size: 0, hashes: hashes };
+        request.encoded_size = serialized_size(&request);
+        request
+    }
+}
+
+/// Sent by the master in reply to an `AssetRequest` with the contents of the requested files
+#[derive(Serialize, Deserialize)]
+struct AssetData {
+    /// Size header for binary I/O with bincode
+    pub encoded_size: u64,
+    /// The hash and contents of each file
+    pub files: Vec<(u64, Vec<u8>)>,
+}
+
+impl AssetData {
+    pub fn new(files: Vec<(u64, Vec<u8>)>) -> AssetData {
+        let mut data = AssetData { encoded_size: 0, files: files };
+        data.encoded_size = serialized_size(&data);
+        data
+    }
+}
+
+/// Compute the 64 bit FNV-1a hash of the data. Unlike the standard library's hasher
+/// this is stable across builds, so masters and workers can compare their hashes
+fn hash_bytes(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
 /// Frame is used by the worker to send its results back to the master. Sends information
 /// about which frame is being sent, which blocks were rendered and the data for the blocks
 #[derive(Serialize, Deserialize)]
@@ -87,15 +189,25 @@ struct Frame {
     pub blocks: Vec<(usize, usize)>,
     /// Sample data for each block, RGBW_F32 (W = weight)
     pub pixels: Vec<f32>,
+    /// AOVs for each block as returned by `RenderTarget::get_aov_blocks`, empty if the
+    /// scene doesn't save any AOVs
+    pub aovs: Vec<f32>,
 }
 
 impl Frame {
     pub fn new(frame: usize, block_size: (usize, usize), blocks: Vec<(usize, usize)>,
-               pixels: Vec<f32>) -> Frame {
+               pixels: Vec<f32>, aovs: Vec<f32>) -> Frame {
         let mut frame = Frame { encoded_size: 0, frame: frame, block_size: block_size,
-                            blocks: blocks, pixels: pixels };
+                            blocks: blocks, pixels: pixels, aovs: aovs };
         frame.encoded_size = serialized_size(&frame);
         frame
     }
 }
 
+#[test]
+fn test_hash_bytes() {
+    // Reference values for 64 bit FNV-1a
+    assert_eq!(hash_bytes(b""), 0xcbf29ce484222325);
+    assert_eq!(hash_bytes(b"a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(hash_bytes(b"foobar"), 0x85944171f73967e8);
+}
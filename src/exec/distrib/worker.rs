@@ -1,17 +1,18 @@
 //! The worker module provides the Worker struct which receives instructions from
 //! the master, renders and reports back its results
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf, Component};
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
-use std::iter;
+use std::fs::{self, File};
+use std::{env, iter};
 
 use bincode::{Infinite, serialize, deserialize};
 
 use scene::Scene;
-use film::RenderTarget;
+use film::{output, RenderTarget};
 use exec::Config;
-use exec::distrib::{Instructions, Frame};
+use exec::distrib::{self, Instructions, Frame, AssetRequest, AssetData};
 
 /// Port that the workers listen for the master on
 pub static PORT: u16 = 63234;
@@ -35,11 +36,19 @@ impl Worker {
     /// and send us instructions about the scene we should render and
     /// what parts of it we've been assigned
     pub fn listen_for_master(num_threads: u32) -> Worker {
-        let (instructions, master) = get_instructions();
-        let (scene, rt, spp, mut frame_info) = Scene::load_file(&instructions.scene);
+        let (instructions, mut master) = get_instructions();
+        let scene_file = fetch_assets(&instructions, &mut master).to_string_lossy().into_owned();
+        let (mut scene, mut rt, _, mut frame_info) = Scene::load_file(&scene_file);
         frame_info.start = instructions.frames.0;
         frame_info.end = instructions.frames.1;
-        let config = Config::new(PathBuf::from("/tmp"), instructions.scene.clone(), spp,
+        // The master may override the resolution to render a quick preview
+        if rt.dimensions() != instructions.dimensions {
+            rt.resize(instructions.dimensions);
+            scene.set_image_dimensions(instructions.dimensions);
+        }
+        // Collect the AOVs for the master if the scene saves any
+        rt.set_aovs(output::uses_aovs(&scene.outputs));
+        let config = Config::new(PathBuf::from("/tmp"), scene_file, instructions.spp,
                                  num_threads, frame_info,
                                  (instructions.block_start, instructions.block_count));
         Worker { instructions: instructions, render_target: rt, scene: scene,
@@ -48,7 +57,8 @@ impl Worker {
     /// Send our blocks back to the master
     pub fn send_results(&mut self) {
         let (block_size, blocks, pixels) = self.render_target.get_rendered_blocks();
-        let frame = Frame::new(self.config.current_frame, block_size, blocks, pixels);
+        let aovs = self.render_target.get_aov_blocks(&blocks);
+        let frame = Frame::new(self.config.current_frame, block_size, blocks, pixels, aovs);
         let bytes = serialize(&frame, Infinite).unwrap();
         if let Err(e) = self.master.write_all(&bytes[..]) {
             panic!("Failed to send frame to {:?}: {}", self.master, e);
@@ -58,34 +68,100 @@ impl Worker {
 
 fn get_instructions() -> (Instructions, TcpStream) {
     let listener = TcpListener::bind(("0.0.0.0", PORT)).expect("Worker failed to get port");
-    println!("Worker listening for master on {}", PORT);
+    info!("Worker listening for master on {}", PORT);
     match listener.accept() {
         Ok((mut stream, _)) => {
-            let mut buf: Vec<_> = iter::repeat(0u8).take(8).collect();
-            let mut expected_size = 8;
-            let mut currently_read = 0;
-            // Read the size header
-            while currently_read < expected_size {
-                match stream.read(&mut buf[currently_read..]) {
-                    Ok(n) => currently_read += n,
-                    Err(e) => panic!("Failed to read from master, {:?}", e),
-                }
-            }
-            // How many bytes we expect to get from the worker for a frame
-            expected_size = deserialize(&buf[..]).unwrap();
-            buf.extend(iter::repeat(0u8).take(expected_size - 8));
-            // Now read the rest
-            while currently_read < expected_size {
-                match stream.read(&mut buf[currently_read..]) {
-                    Ok(n) => currently_read += n,
-                    Err(e) => panic!("Failed to read from master, {:?}", e),
-                }
-            }
-            let instr = deserialize(&buf[..]).unwrap();
-            println!("Received instructions: {:?}", instr);
+            let instr = deserialize(&read_message(&mut stream)[..]).unwrap();
+            debug!("Received instructions: {:?}", instr);
             (instr, stream)
         },
         Err(e) => panic!("Error accepting: {:?}", e),
     }
 }
 
+/// Read a message from the master, which starts with its size in bytes
+fn read_message(stream: &mut TcpStream) -> Vec<u8> {
+    let mut buf: Vec<_> = iter::repeat(0u8).take(8).collect();
+    let mut expected_size = 8;
+    let mut currently_read = 0;
+    // Read the size header
+    while currently_read < expected_size {
+        match stream.read(&mut buf[currently_read..]) {
+            Ok(n) => currently_read += n,
+            Err(e) => panic!("Failed to read from master, {:?}", e),
+        }
+    }
+    // How many bytes we expect to get from the master for the message
+    expected_size = deserialize(&buf[..]).unwrap();
+    buf.extend(iter::repeat(0u8).take(expected_size - 8));
+    // Now read the rest
+    while currently_read < expected_size {
+        match stream.read(&mut buf[currently_read..]) {
+            Ok(n) => currently_read += n,
+            Err(e) => panic!("Failed to read from master, {:?}", e),
+        }
+    }
+    buf
+}
+
+/// Make sure the cache has all the files needed to render the scene, requesting the missing
+/// ones from the master, then link them into a copy of the master's directory layout so the
+/// scene's relative paths find them. Returns the path of the scene file to load
+fn fetch_assets(instructions: &Instructions, master: &mut TcpStream) -> PathBuf {
+    let cache = env::temp_dir().join("tray_rust_cache");
+    let objects = cache.join("objects");
+    if let Err(e) = fs::create_dir_all(&objects) {
+        panic!("Failed to create cache directory {}: {}", objects.display(), e);
+    }
+    let object_path = |hash: u64| objects.join(format!("{:016x}", hash));
+    let files: Vec<_> = iter::once(&instructions.scene).chain(instructions.assets.iter()).collect();
+    let mut missing: Vec<_> = files.iter().map(|a| a.hash).filter(|h| !object_path(*h).exists()).collect();
+    missing.sort();
+    missing.dedup();
+    info!("Requesting {} of the scene's {} files from the master", missing.len(), files.len());
+    let bytes = serialize(&AssetRequest::new(missing), Infinite).unwrap();
+    if let Err(e) = master.write_all(&bytes[..]) {
+        panic!("Failed to request scene files from {:?}: {}", master, e);
+    }
+    let data: AssetData = deserialize(&read_message(master)[..]).unwrap();
+    for (hash, contents) in data.files {
+        if distrib::hash_bytes(&contents) != hash {
+            panic!("Scene file received from the master is corrupt, hash {:016x} vs. {:016x}",
+                   distrib::hash_bytes(&contents), hash);
+        }
+        // Write to a temporary file first so an interrupted write doesn't leave a bad file in the cache
+        let tmp = objects.join(format!("{:016x}.part", hash));
+        let written = File::create(&tmp).and_then(|mut f| f.write_all(&contents))
+            .and_then(|_| fs::rename(&tmp, object_path(hash)));
+        if let Err(e) = written {
+            panic!("Failed to write scene file to the cache: {}", e);
+        }
+    }
+    let root = cache.join("scenes").join(format!("{:016x}", instructions.scene.hash));
+    for a in files {
+        let path = mirror_path(&root, &a.path);
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                panic!("Failed to create scene directory {}: {}", dir.display(), e);
+            }
+        }
+        // The file may be left from an earlier job with a different version of it
+        let _ = fs::remove_file(&path);
+        if fs::hard_link(object_path(a.hash), &path).is_err() {
+            if let Err(e) = fs::copy(object_path(a.hash), &path) {
+                panic!("Failed to copy {} from the cache: {}", path.display(), e);
+            }
+        }
+    }
+    mirror_path(&root, &instructions.scene.path)
+}
+
+/// Get the path under `root` for the master's absolute `path`
+fn mirror_path(root: &Path, path: &str) -> PathBuf {
+    Path::new(path).components().fold(root.to_path_buf(), |p, c| {
+        match c {
+            Component::Normal(c) => p.join(c),
+            _ => p,
+        }
+    })
+}
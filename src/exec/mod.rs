@@ -3,13 +3,14 @@
 
 use std::path::PathBuf;
 
-use film::{FrameInfo, RenderTarget};
+use film::{FrameInfo, RenderTarget, Output, Grade, Tonemap, AutoExposure};
 use scene::Scene;
 
-pub use self::multithreaded::MultiThreaded;
+pub use self::multithreaded::{MultiThreaded, RenderedBlock};
 
 pub mod multithreaded;
 pub mod distrib;
+pub mod bake;
 
 /// Config passed to set up the execution environment with information
 /// on what it should be rendering and where to put the results
@@ -24,7 +25,16 @@ pub struct Config {
     pub current_frame: usize,
     /// Which blocks the executor should render, stored
     /// as (start, count) of the block indices
-    pub select_blocks: (usize, usize)
+    pub select_blocks: (usize, usize),
+    /// The image files to save each frame to, if empty a single PNG is saved to `out_path`
+    pub outputs: Vec<Output>,
+    /// The color grade to apply to the frames when they're saved as 8-bit images
+    pub grade: Grade,
+    /// The tonemapping operator used when saving frames as 8-bit images
+    pub tonemap: Tonemap,
+    /// The auto exposure applied to the frames when they're saved, it's updated with each
+    /// frame saved to smooth the exposure over the animation
+    pub auto_exposure: Option<AutoExposure>,
 }
 
 impl Config {
@@ -32,7 +42,9 @@ impl Config {
                frame_info: FrameInfo, select_blocks: (usize, usize)) -> Config {
         Config { out_path: out_path, scene_file: scene_file, spp: spp,
                  num_threads: num_threads, frame_info: frame_info,
-                 current_frame: frame_info.start, select_blocks: select_blocks }
+                 current_frame: frame_info.start, select_blocks: select_blocks,
+                 outputs: Vec::new(), grade: Grade::identity(), tonemap: Tonemap::Clamp,
+                 auto_exposure: None }
     }
 }
 
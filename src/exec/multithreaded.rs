@@ -1,36 +1,93 @@
 //! The multithreaded module provides a multithreaded execution for rendering
-//! the image.
+//! the image. A callback can be set to be notified as each block of the image
+//! is completed, e.g. to stream the image to a viewer while it's rendering.
+//! A cancel token can also be set to stop a render in progress, e.g. when the
+//! user hits cancel in an application embedding the renderer.
 
-use std::iter;
+use std::{iter, cmp, f32};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::SystemTime;
 
 use scoped_threadpool::Pool;
-use rand::StdRng;
-use light_arena;
+use rand::{StdRng, Rng};
+use light_arena::{self, Allocator};
 
 use sampler::BlockQueue;
-use film::{RenderTarget, ImageSample, Colorf};
-use geometry::{Instance, Emitter};
-use sampler::{self, Sampler};
+use film::{RenderTarget, ImageSample, AovSample, IdSample, Colorf};
+use film::object_id::object_id;
+use geometry::{Instance, Emitter, Intersection, TraversalStats};
+use linalg::{self, Ray, Normal};
+use bxdf::BxDFType;
+use sampler::{self, Sampler, SamplerType, Sample, GeometrySample};
 use scene::Scene;
-use exec::{Config, Exec};
+use exec::{bake, Config, Exec};
+
+/// A block of the image which has finished rendering, passed to the block callback
+#[derive(Debug, Clone)]
+pub struct RenderedBlock {
+    /// The frame being rendered
+    pub frame: usize,
+    /// The position of the block's upper left pixel in the image
+    pub start: (usize, usize),
+    /// The dimensions of the block, blocks on the edges of the image may be smaller
+    pub dim: (usize, usize),
+    /// The RGBW f32 pixels of the block, the weight of each pixel has not been divided out.
+    /// Since samples are filtered into neighboring pixels the block's pixels may still change
+    /// as its neighbors are rendered
+    pub pixels: Vec<f32>,
+}
+
+/// Callback called by the render threads with each block as it's completed
+pub type BlockCallback = Box<Fn(&RenderedBlock) + Send + Sync>;
 
 /// The `MultiThreaded` execution uses a configurable number of threads in
 /// a threadpool to render each frame
 pub struct MultiThreaded {
     pool: Pool,
+    block_callback: Option<BlockCallback>,
+    cancel: Option<Arc<AtomicBool>>,
 }
 
 impl MultiThreaded {
     /// Create a new multithreaded renderer which will use `num_threads` to render the image
     pub fn new(num_threads: u32) -> MultiThreaded {
-        MultiThreaded { pool: Pool::new(num_threads) }
+        MultiThreaded { pool: Pool::new(num_threads), block_callback: None, cancel: None }
+    }
+    /// Set a callback to be called with each block of the image as it's completed. The
+    /// callback is called from the render threads so it should return quickly
+    pub fn set_block_callback<F>(&mut self, f: F) where F: Fn(&RenderedBlock) + Send + Sync + 'static {
+        self.block_callback = Some(Box::new(f));
+    }
+    /// Set a token to cancel rendering. When the token is set to true the render threads
+    /// stop after finishing the blocks they're working on, leaving the blocks rendered so
+    /// far in the render target. The token isn't reset, so it must be cleared before
+    /// rendering again
+    pub fn set_cancel_token(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+    /// Check if rendering has been cancelled through the cancel token
+    pub fn cancelled(&self) -> bool {
+        match self.cancel {
+            Some(ref c) => c.load(Ordering::Relaxed),
+            None => false,
+        }
+    }
+    /// Read back the rendered image from the render target in parallel across the threads,
+    /// see `RenderTarget::get_renderf32_parallel`
+    pub fn get_renderf32(&mut self, rt: &RenderTarget) -> Vec<f32> {
+        rt.get_renderf32_parallel(&mut self.pool)
+    }
+    /// Bake the lighting on the instance tagged `tag` into a texture with `dim` texels
+    /// using the render threads, see `bake::bake`
+    pub fn bake(&mut self, scene: &Scene, tag: &str, dim: (usize, usize), spp: usize, time: f32) -> Vec<f32> {
+        bake::bake(&mut self.pool, scene, tag, dim, spp, time)
     }
     /// Launch a rendering job in parallel across the threads and wait for it to finish
     fn render_parallel(&mut self, scene: &Scene, rt: &RenderTarget, config: &Config) {
         let dim = rt.dimensions();
         let block_queue = BlockQueue::new((dim.0 as u32, dim.1 as u32), (8, 8), config.select_blocks);
-        let light_list: Vec<_> = scene.bvh.iter().filter_map(|x| {
+        let light_list: Vec<_> = scene.accel.iter().filter_map(|x| {
             match *x {
                 Instance::Emitter(ref e) => Some(e),
                 _ => None,
@@ -38,13 +95,15 @@ impl MultiThreaded {
         }).collect();
         assert!(!light_list.is_empty(), "At least one light is required");
         let n = self.pool.thread_count();
+        let callback = self.block_callback.as_ref();
+        let cancel = self.cancel.as_ref().map(|c| &**c);
         self.pool.scoped(|scope| {
             for _ in 0..n {
                 let b = &block_queue;
                 let r = &rt;
                 let l = &light_list;
                 scope.execute(move || {
-                    thread_work(config.spp, b, scene, r, l);
+                    thread_work(config, b, scene, r, l, callback, cancel);
                 });
             }
         });
@@ -53,38 +112,67 @@ impl MultiThreaded {
 
 impl Exec for MultiThreaded {
     fn render(&mut self, scene: &mut Scene, rt: &mut RenderTarget, config: &Config) {
-        println!("Rendering using {} threads\n--------------------", self.pool.thread_count());
+        info!("Rendering using {} threads\n--------------------", self.pool.thread_count());
         let time_step = config.frame_info.time / config.frame_info.frames as f32;
         let frame_start_time = config.current_frame as f32 * time_step;
         let frame_end_time = (config.current_frame as f32 + 1.0) * time_step;
         scene.update_frame(config.current_frame, frame_start_time, frame_end_time);
 
-        println!("Frame {}: rendering for {} to {}", config.current_frame,
+        info!("Frame {}: rendering for {} to {}", config.current_frame,
                  frame_start_time, frame_end_time);
         let scene_start = SystemTime::now();
+        TraversalStats::reset();
         self.render_parallel(scene, rt, config);
+        if self.cancelled() {
+            info!("Frame {}: rendering cancelled", config.current_frame);
+            return;
+        }
         let time = scene_start.elapsed().expect("Failed to get render time?");
-        println!("Frame {}: rendering took {:4}s", config.current_frame,
+        info!("Frame {}: rendering took {:4}s", config.current_frame,
                  time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9);
+        if TraversalStats::enabled() {
+            let stats = TraversalStats::collected();
+            let traversals = cmp::max(stats.traversals, 1) as f64;
+            info!("Frame {}: {} BVH traversals, {:.2} nodes visited and {:.2} geometry tests per traversal",
+                     config.current_frame, stats.traversals, stats.nodes_visited as f64 / traversals,
+                     stats.geometry_tests as f64 / traversals);
+        }
     }
 }
 
-fn thread_work(spp: usize, queue: &BlockQueue, scene: &Scene,
-               target: &RenderTarget, light_list: &[&Emitter]) {
-    let mut sampler = sampler::LowDiscrepancy::new(queue.block_dim(), spp);
+fn thread_work(config: &Config, queue: &BlockQueue, scene: &Scene, target: &RenderTarget,
+               light_list: &[&Emitter], callback: Option<&BlockCallback>, cancel: Option<&AtomicBool>) {
+    let mut sampler: Box<Sampler> = match scene.sampler {
+        SamplerType::LowDiscrepancy => Box::new(sampler::LowDiscrepancy::new(queue.block_dim(), config.spp)),
+        SamplerType::Adaptive { min_spp, max_spp, edges: Some(e) } => {
+            Box::new(sampler::Adaptive::with_edges(queue.block_dim(), min_spp, max_spp, e))
+        },
+        SamplerType::Adaptive { min_spp, max_spp, edges: None } => {
+            Box::new(sampler::Adaptive::new(queue.block_dim(), min_spp, max_spp))
+        },
+    };
+    let uses_geometry = sampler.uses_geometry();
     let mut sample_pos = Vec::with_capacity(sampler.max_spp());
     let mut time_samples: Vec<_> = iter::repeat(0.0).take(sampler.max_spp()).collect();
     let block_dim = queue.block_dim();
     let mut block_samples = Vec::with_capacity(sampler.max_spp() * (block_dim.0 * block_dim.1) as usize);
+    let mut aov_samples = Vec::new();
+    let mut id_samples = Vec::new();
+    let mut geometry_samples = Vec::new();
     let mut rng = match StdRng::new() {
         Ok(r) => r,
-        Err(e) => { println!("Failed to get StdRng, {}", e); return }
+        Err(e) => { error!("Failed to get StdRng, {}", e); return }
     };
     let mut arena = light_arena::MemoryArena::new(8);
     let camera = scene.active_camera();
+    let img_dim = target.dimensions();
+    let img_dim = (img_dim.0 as f32, img_dim.1 as f32);
     // Grab a block from the queue and start working on it, submitting samples
     // to the render target thread after each pixel
     for b in queue.iter() {
+        if cancel.map_or(false, |c| c.load(Ordering::Relaxed)) {
+            break;
+        }
         sampler.select_block(b);
         let mut pixel_samples = 0;
         while sampler.has_samples() {
@@ -92,24 +180,81 @@ fn thread_work(spp: usize, queue: &BlockQueue, scene: &Scene,
             sampler.get_samples(&mut sample_pos, &mut rng);
             sampler.get_samples_1d(&mut time_samples[..], &mut rng);
             for (s, t) in sample_pos.iter().zip(time_samples.iter()) {
+                // Blocks on the edge of the image may extend past it, skip pixels outside the image
+                if s.0 >= img_dim.0 || s.1 >= img_dim.1 {
+                    continue;
+                }
                 let alloc = arena.allocator();
                 let mut ray = camera.generate_ray(s, *t);
-                if let Some(hit) = scene.intersect(&mut ray) {
-                    let c = scene.integrator.illumination(scene, light_list, &ray, &hit,
-                                                          &mut sampler, &mut rng, &alloc).clamp();
-                    block_samples.push(ImageSample::new(s.0, s.1, c));
-                } else {
-                    block_samples.push(ImageSample::new(s.0, s.1, Colorf::black()));
+                let hit = scene.intersect(&mut ray);
+                if target.has_aovs() {
+                    aov_samples.push(first_hit_aovs(s, &ray, hit.as_ref(), &mut rng, &alloc));
                 }
+                if target.has_id_mattes() {
+                    let id = hit.as_ref().map(|h| object_id(h.instance.tag()));
+                    id_samples.push(IdSample::new(s.0, s.1, id));
+                }
+                if uses_geometry {
+                    geometry_samples.push(match hit {
+                        Some(ref h) => GeometrySample::new(ray.max_t, h.dg.n.normalized(),
+                                                           object_id(h.instance.tag())),
+                        None => GeometrySample::miss(),
+                    });
+                }
+                let c = match hit {
+                    Some(ref hit) => scene.integrator.illumination(scene, light_list, &ray, hit,
+                                                                   &mut *sampler, &mut rng, &alloc),
+                    None => scene.integrator.escaped(light_list, &ray),
+                };
+                block_samples.push(ImageSample::new(s.0, s.1, scene.apply_fog(&ray, &c).clamp()));
             }
             // If the samples are ok the samples for the next pixel start at the end of the current
             // pixel's samples
-            if sampler.report_results(&block_samples[pixel_samples..]) {
+            if sampler.report_results(&block_samples[pixel_samples..], &geometry_samples) {
                 pixel_samples = block_samples.len();
+                geometry_samples.clear();
             }
         }
         target.write(&block_samples, sampler.get_region());
+        target.write_aovs(&aov_samples);
+        target.write_ids(&id_samples);
         block_samples.clear();
+        aov_samples.clear();
+        id_samples.clear();
+        if let Some(f) = callback {
+            let region = sampler.get_region();
+            let start = (region.start.0 as usize, region.start.1 as usize);
+            let (dim, pixels) = target.get_region(start, (block_dim.0 as usize, block_dim.1 as usize));
+            if !pixels.is_empty() {
+                f(&RenderedBlock { frame: config.current_frame, start: start, dim: dim, pixels: pixels });
+            }
+        }
     }
 }
 
+/// Compute the albedo, normal and depth of the first surface hit by the camera ray through `px`,
+/// the normal faces back along the ray. The albedo is estimated by sampling the surface's
+/// BSDF once, lights are given a white albedo
+fn first_hit_aovs(px: &(f32, f32), ray: &Ray, hit: Option<&Intersection>, rng: &mut StdRng,
+                  alloc: &Allocator) -> AovSample {
+    let hit = match hit {
+        Some(h) => h,
+        None => return AovSample::new(px.0, px.1, Colorf::black(), Normal::broadcast(0.0), f32::INFINITY),
+    };
+    let bsdf = hit.material.bsdf(hit, alloc);
+    let w_o = -ray.d.normalized();
+    let n = if linalg::dot(&bsdf.n, &w_o) < 0.0 { -bsdf.n } else { bsdf.n };
+    let albedo = match *hit.instance {
+        Instance::Emitter(_) => Colorf::broadcast(1.0),
+        Instance::Receiver(_) => {
+            let sample = Sample::new(&(rng.next_f32(), rng.next_f32()), rng.next_f32());
+            let (f, w_i, pdf, _) = bsdf.sample(&w_o, BxDFType::all(), &sample);
+            if pdf > 0.0 {
+                (f * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf).clamp()
+            } else {
+                Colorf::black()
+            }
+        },
+    };
+    AovSample::new(px.0, px.1, albedo, n, ray.max_t * ray.d.length())
+}
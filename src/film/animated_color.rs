@@ -2,7 +2,6 @@
 
 use std::cmp::{Eq, Ord, PartialOrd, PartialEq, Ordering};
 
-use linalg;
 use film::Colorf;
 
 /// `ColorKeyframe` is a color associated with a specific time
@@ -48,6 +47,16 @@ impl AnimatedColor {
         keyframes.sort();
         AnimatedColor { keyframes: keyframes }
     }
+    /// Scale the color of each keyframe by `s`
+    pub fn scale(&mut self, s: f32) {
+        for k in &mut self.keyframes {
+            k.color = k.color * s;
+        }
+    }
+    /// Check if the color is black at all times
+    pub fn is_black(&self) -> bool {
+        self.keyframes.iter().all(|k| k.color.is_black())
+    }
     /// Compute the color at the desired time
     pub fn color(&self, time: f32) -> Colorf {
         if self.keyframes.is_empty() {
@@ -63,15 +72,10 @@ impl AnimatedColor {
             } else if second.is_none() {
                 self.keyframes.last().unwrap().color
             } else {
-                let mut color = Colorf::black();
                 let fk = first.unwrap();
                 let sk = second.unwrap();
                 let t = (time - fk.time) / (sk.time - fk.time);
-                color.r = linalg::lerp(t, &fk.color.r, &sk.color.r);
-                color.g = linalg::lerp(t, &fk.color.g, &sk.color.g);
-                color.b = linalg::lerp(t, &fk.color.b, &sk.color.b);
-                color.a = linalg::lerp(t, &fk.color.a, &sk.color.a);
-                color
+                Colorf::lerp(t, &fk.color, &sk.color)
             }
         }
     }
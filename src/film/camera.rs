@@ -19,10 +19,154 @@
 //!     ]
 //! }
 //! ```
+//!
+//! ## Look At Keyframes
+//! Camera moves can also be specified as a list of look at keyframes, each giving the
+//! time, eye position, target and up vector of the camera. The camera is linearly
+//! interpolated between the keyframes, which must be listed in increasing time order.
+//!
+//! ```json
+//! "camera": {
+//!     "fov": 50.0,
+//!     "look_at_keyframes": [
+//!         {
+//!             "time": 0.0,
+//!             "eye": [0, 12, -60],
+//!             "target": [0, 12, 0],
+//!             "up": [0, 1, 0]
+//!         },
+//!         {
+//!             "time": 2.0,
+//!             "eye": [30, 12, -50],
+//!             "target": [0, 10, 0],
+//!             "up": [0.1, 1, 0]
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! ## Stereo
+//! For VR content the camera can render a stereo pair by specifying the interocular distance
+//! in world units. Each frame is then rendered once for each eye, with the camera shifted half
+//! the distance along its local x axis to the left and right, and the images are saved with
+//! `_L` and `_R` appended to their file names. Stereo pairs are only rendered when rendering
+//! on a single machine.
+//!
+//! ```json
+//! "camera": {
+//!     "fov": 50.0,
+//!     "transform": [...],
+//!     "stereo": {
+//!         "ipd": 0.065
+//!     }
+//! }
+//! ```
+//!
+//! ## Shutter Curve
+//! By default the shutter is open uniformly over its interval, so moving objects leave an even
+//! motion blur streak. A shutter curve remaps the time samples to weight parts of the interval
+//! more heavily. The curve is a B-spline mapping the uniform time sample in [0, 1] to the
+//! fraction of the shutter interval, given like the animated field of view by its control
+//! points, knots and degree. Where the curve is flat more time samples are taken, e.g. the
+//! curve below rises quickly then flattens out so objects appear mostly at their position when
+//! the shutter closes, with the streak trailing behind them. The curve should increase from 0
+//! to 1, its values are clamped to [0, 1].
+//!
+//! ```json
+//! "camera": {
+//!     "fov": 50.0,
+//!     "transform": [...],
+//!     "shutter_size": 1.0,
+//!     "shutter_curve": [0, 0.8, 1, 1],
+//!     "shutter_curve_knots": [0, 0, 0, 0, 1, 1, 1, 1],
+//!     "shutter_curve_degree": 3
+//! }
+//! ```
+//!
+//! ## Turntable
+//! For turntable previews the camera can orbit a target point instead of being keyframed by
+//! hand. The camera starts at the position given by its `transform` and rotates about the
+//! `axis` through the `target`, making a full revolution every `frames` frames of the film.
+//! The orbit continues at the same speed if the film has more frames than the turntable.
+//!
+//! ```json
+//! "camera": {
+//!     "fov": 50.0,
+//!     "transform": [...],
+//!     "turntable": {
+//!         "target": [0, 2, 0],
+//!         "axis": [0, 1, 0],
+//!         "frames": 120
+//!     }
+//! }
+//! ```
+//!
+//! ## Orthographic Projection
+//! Instead of a field of view the camera can use an orthographic projection, where all rays
+//! travel parallel along the camera's view axis, e.g. for technical or scientific images
+//! where distant objects shouldn't appear smaller. The `size` is half the extent of the
+//! view in world units along the image's shorter side.
+//!
+//! ```json
+//! "camera": {
+//!     "orthographic": {
+//!         "size": 5.0
+//!     },
+//!     "transform": [...]
+//! }
+//! ```
+//!
+//! ## Clip Range
+//! The camera can clip the scene to a range of depths along its view axis, objects closer
+//! than `near` or farther than `far` are ignored by the camera rays, though they still cast
+//! shadows and show up in reflections. Rendering with `--slices <n>` splits the clip range
+//! into `n` slices of equal depth and renders an image of each, which combined with the
+//! orthographic projection is useful for inspecting the interior of a model. The clip range
+//! of each camera used by the frames rendered must be finite to render slices. Like the
+//! clip range, slices only clip the camera rays so the geometry outside a slice still casts
+//! shadows onto it.
+//!
+//! ```json
+//! "camera": {
+//!     "fov": 50.0,
+//!     "transform": [...],
+//!     "clip": [1.0, 20.0]
+//! }
+//! ```
+
+use std::f32;
 
 use bspline::BSpline;
 use linalg::{self, Transform, Vector, Point, Ray, AnimatedTransform, Matrix4};
 
+/// Which eye the camera renders the image for
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Eye {
+    /// The camera's own position, used for mono rendering
+    Center,
+    Left,
+    Right,
+}
+
+impl Eye {
+    /// Get the suffix appended to the image file names for this eye
+    pub fn suffix(&self) -> &'static str {
+        match *self {
+            Eye::Center => "",
+            Eye::Left => "_L",
+            Eye::Right => "_R",
+        }
+    }
+}
+
+/// The projection used to generate rays through the image
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Projection {
+    Perspective,
+    /// Parallel rays covering half the size passed along the image's shorter side
+    Orthographic(f32),
+}
+
 #[derive(Clone, Debug)]
 enum CameraFov {
     Unanimated(f32),
@@ -36,6 +180,8 @@ pub struct Camera {
     cam_world: AnimatedTransform,
     /// Transformation from raster space to screen space
     raster_screen: Transform,
+    /// Dimensions of the image the camera generates rays for
+    dims: (usize, usize),
     /// The projective division matrix, the perspective matrix is changing in the
     /// case of animated FOV so we deconstruct it some to reduce creating a new
     /// transform each time
@@ -53,6 +199,17 @@ pub struct Camera {
     scaling: Vector,
     /// The frame this camera becomes active on
     pub active_at: usize,
+    /// Interocular distance for stereo rendering, 0 if the camera isn't stereo
+    ipd: f32,
+    /// The eye currently being rendered
+    eye: Eye,
+    /// Optional curve remapping the time samples across the shutter interval
+    shutter_curve: Option<BSpline<f32>>,
+    projection: Projection,
+    /// Near and far depths along the view axis the camera rays are clipped to
+    clip: (f32, f32),
+    /// The slice of the clip range being rendered as (slice, number of slices), if any
+    slice: Option<(usize, usize)>,
 }
 
 impl Camera {
@@ -63,17 +220,7 @@ impl Camera {
     /// where the camera is at the origin looking down the -z axis
     pub fn new(cam_world: AnimatedTransform, fov: f32, dims: (usize, usize), shutter_size: f32, active_at: usize)
         -> Camera {
-        let aspect_ratio = (dims.0 as f32) / (dims.1 as f32);
-        let screen =
-            if aspect_ratio > 1.0 {
-                [-aspect_ratio, aspect_ratio, -1.0, 1.0]
-            } else {
-                [-1.0, 1.0, -1.0 / aspect_ratio, 1.0 / aspect_ratio]
-            };
-        let screen_raster = Transform::scale(&Vector::new(dims.0 as f32, dims.1 as f32, 1.0))
-            * Transform::scale(&Vector::new(1.0 / (screen[1] - screen[0]), 1.0 / (screen[2] - screen[3]), 1.0))
-            * Transform::translate(&Vector::new(-screen[0], -screen[3], 0.0));
-        let raster_screen = screen_raster.inverse();
+        let raster_screen = raster_screen_transform(dims);
         let far = 1.0;
         let near = 1000.0;
         let proj_div = Matrix4::new(
@@ -83,12 +230,24 @@ impl Camera {
              0.0, 0.0, 1.0, 0.0]);
         let tan_fov = f32::tan(linalg::to_radians(fov) / 2.0);
         let scaling = Vector::new(tan_fov, tan_fov, 1.0);
-        Camera { cam_world: cam_world, raster_screen: raster_screen,
+        Camera { cam_world: cam_world, raster_screen: raster_screen, dims: dims,
                  proj_div_inv: Transform::from_mat(&proj_div).inverse(),
                  shutter_open: 0.0, shutter_close: 0.0, shutter_size: shutter_size,
-                 fov: CameraFov::Unanimated(fov), scaling: scaling, active_at: active_at
+                 fov: CameraFov::Unanimated(fov), scaling: scaling, active_at: active_at,
+                 ipd: 0.0, eye: Eye::Center, shutter_curve: None, projection: Projection::Perspective,
+                 clip: (0.0, f32::INFINITY), slice: None
         }
     }
+    /// Create a camera with some orientation in the world specified by `cam_world` and an
+    /// orthographic projection covering `size` world units on either side of the view axis
+    /// along the image's shorter side
+    pub fn orthographic(cam_world: AnimatedTransform, size: f32, dims: (usize, usize), shutter_size: f32,
+                        active_at: usize) -> Camera {
+        assert!(size > 0.0, "Orthographic camera size must be greater than 0");
+        let mut camera = Camera::new(cam_world, 90.0, dims, shutter_size, active_at);
+        camera.projection = Projection::Orthographic(size);
+        camera
+    }
     /// Create a camera with some orientation in the world specified by `cam_world`
     /// and an animated perspective projection with `fov`. The render target dimensions `dims`
     /// are needed to construct the raster -> camera transform
@@ -96,17 +255,7 @@ impl Camera {
     /// where the camera is at the origin looking down the -z axis
     pub fn animated_fov(cam_world: AnimatedTransform, fovs: Vec<f32>, fov_knots: Vec<f32>, fov_spline_degree: usize,
                         dims: (usize, usize), shutter_size: f32, active_at: usize) -> Camera {
-        let aspect_ratio = (dims.0 as f32) / (dims.1 as f32);
-        let screen =
-            if aspect_ratio > 1.0 {
-                [-aspect_ratio, aspect_ratio, -1.0, 1.0]
-            } else {
-                [-1.0, 1.0, -1.0 / aspect_ratio, 1.0 / aspect_ratio]
-            };
-        let screen_raster = Transform::scale(&Vector::new(dims.0 as f32, dims.1 as f32, 1.0))
-            * Transform::scale(&Vector::new(1.0 / (screen[1] - screen[0]), 1.0 / (screen[2] - screen[3]), 1.0))
-            * Transform::translate(&Vector::new(-screen[0], -screen[3], 0.0));
-        let raster_screen = screen_raster.inverse();
+        let raster_screen = raster_screen_transform(dims);
         let far = 1.0;
         let near = 1000.0;
         let proj_div = Matrix4::new(
@@ -116,14 +265,88 @@ impl Camera {
              0.0, 0.0, 1.0, 0.0]);
         let tan_fov = f32::tan(linalg::to_radians(fovs[0]) / 2.0);
         let scaling = Vector::new(tan_fov, tan_fov, 1.0);
-        Camera { cam_world: cam_world, raster_screen: raster_screen,
+        Camera { cam_world: cam_world, raster_screen: raster_screen, dims: dims,
                  proj_div_inv: Transform::from_mat(&proj_div).inverse(),
                  shutter_open: 0.0, shutter_close: 0.0, shutter_size: shutter_size,
                  fov: CameraFov::Animated(BSpline::new(fov_spline_degree, fovs, fov_knots)),
-                 scaling: scaling, active_at: active_at
+                 scaling: scaling, active_at: active_at, ipd: 0.0, eye: Eye::Center,
+                 shutter_curve: None, projection: Projection::Perspective, clip: (0.0, f32::INFINITY),
+                 slice: None
+        }
+    }
+    /// Remap the time samples across the shutter interval through the B-spline curve with
+    /// the control points, knots and degree passed, see the module docs
+    pub fn set_shutter_curve(&mut self, points: Vec<f32>, knots: Vec<f32>, degree: usize) {
+        self.shutter_curve = Some(BSpline::new(degree, points, knots));
+    }
+    /// Make the camera render stereo pairs with the interocular distance `ipd`
+    pub fn set_stereo(&mut self, ipd: f32) {
+        assert!(ipd > 0.0, "Stereo interocular distance must be greater than 0");
+        self.ipd = ipd;
+    }
+    /// Check if the camera uses an orthographic projection
+    pub fn is_orthographic(&self) -> bool {
+        self.projection != Projection::Perspective
+    }
+    /// Clip the camera rays to the range of depths `[near, far]` along the view axis
+    pub fn set_clip(&mut self, near: f32, far: f32) {
+        assert!(near >= 0.0 && far > near, "Camera clip range must have 0 <= near < far");
+        self.clip = (near, far);
+    }
+    /// Get the range of depths along the view axis the camera rays are clipped to
+    pub fn clip(&self) -> (f32, f32) {
+        self.clip
+    }
+    /// Set the slice of the clip range to render, passed as (slice, number of slices), or
+    /// None to render the whole clip range. The clip range must be finite to render slices
+    pub fn set_slice(&mut self, slice: Option<(usize, usize)>) {
+        if let Some((i, n)) = slice {
+            assert!(i < n, "Slice {} is out of range of the {} slices", i, n);
+            assert!(self.clip.1.is_finite(), "The camera's clip range must be finite to render slices");
         }
+        self.slice = slice;
+    }
+    /// Get the range of depths currently being rendered, the slice's depths if
+    /// rendering slices or the whole clip range
+    pub fn slice_range(&self) -> (f32, f32) {
+        match self.slice {
+            Some((i, n)) => {
+                let depth = (self.clip.1 - self.clip.0) / n as f32;
+                (self.clip.0 + i as f32 * depth, self.clip.0 + (i + 1) as f32 * depth)
+            },
+            None => self.clip,
+        }
+    }
+    /// Check if the camera renders stereo pairs
+    pub fn is_stereo(&self) -> bool {
+        self.ipd > 0.0
     }
-    /// Update the camera's shutter open/close time for this new frame
+    /// Set which eye the camera should generate rays for, for a mono camera
+    /// all eyes are at the camera's position
+    pub fn set_eye(&mut self, eye: Eye) {
+        self.eye = eye;
+    }
+    /// Get the transform placing the camera in the world
+    pub fn get_transform(&self) -> &AnimatedTransform {
+        &self.cam_world
+    }
+    /// Set the transform placing the camera in the world
+    pub fn set_transform(&mut self, cam_world: AnimatedTransform) {
+        self.cam_world = cam_world;
+    }
+    /// Change the image dimensions the camera generates rays for, e.g. to render
+    /// a preview of the scene at a lower resolution
+    pub fn set_dimensions(&mut self, dims: (usize, usize)) {
+        self.raster_screen = raster_screen_transform(dims);
+        self.dims = dims;
+    }
+    /// Get the dimensions of the image the camera generates rays for
+    pub fn dimensions(&self) -> (usize, usize) {
+        self.dims
+    }
+    /// Update the camera's shutter open/close time for this new frame. The shutter opens
+    /// at the start of the frame, so it never samples times before the scene starts and the
+    /// first frame is blurred over the same length of time as the others
     pub fn update_frame(&mut self, start: f32, end: f32) {
         self.shutter_open = start;
         self.shutter_close = start + self.shutter_size * (end - start);
@@ -140,7 +363,7 @@ impl Camera {
         };
         let tan_fov = f32::tan(linalg::to_radians(fov) / 2.0);
         self.scaling = Vector::new(tan_fov, tan_fov, 1.0);
-        println!("Shutter open from {} to {}", self.shutter_open, self.shutter_close);
+        debug!("Shutter open from {} to {}", self.shutter_open, self.shutter_close);
     }
     /// Get the time that the shutter opens and closes at
     pub fn shutter_time(&self) -> (f32, f32) {
@@ -148,12 +371,107 @@ impl Camera {
     }
     /// Generate a ray from the camera through the pixel `px`
     pub fn generate_ray(&self, px: &(f32, f32), time: f32) -> Ray {
+        // The eyes are offset along the camera's x axis and look in parallel
+        let eye_offset = match self.eye {
+            Eye::Center => 0.0,
+            Eye::Left => -0.5 * self.ipd,
+            Eye::Right => 0.5 * self.ipd,
+        };
         // Take the raster space position -> camera space
-        let px_pos = self.scaling * (self.proj_div_inv * self.raster_screen * Point::new(px.0, px.1, 0.0));
-        let d = Vector::new(px_pos.x, px_pos.y, px_pos.z).normalized();
+        let (o, d) = match self.projection {
+            Projection::Perspective => {
+                let px_pos = self.scaling * (self.proj_div_inv * self.raster_screen * Point::new(px.0, px.1, 0.0));
+                (Point::new(eye_offset, 0.0, 0.0), Vector::new(px_pos.x, px_pos.y, px_pos.z).normalized())
+            },
+            Projection::Orthographic(size) => {
+                let screen = self.raster_screen * Point::new(px.0, px.1, 0.0);
+                (Point::new(screen.x * size + eye_offset, screen.y * size, 0.0), Vector::new(0.0, 0.0, 1.0))
+            },
+        };
         // Compute the time being sampled for this frame based on shutter open/close times
+        let time = match self.shutter_curve {
+            Some(ref curve) => {
+                let domain = curve.knot_domain();
+                linalg::clamp(curve.point(linalg::lerp(time, &domain.0, &domain.1)), 0.0, 1.0)
+            },
+            None => time,
+        };
         let frame_time = (self.shutter_close - self.shutter_open) * time + self.shutter_open;
-        self.cam_world.transform(frame_time) * Ray::new(&Point::broadcast(0.0), &d, frame_time)
+        // Clip to the depths along the view axis, which is +z in camera space
+        let (near, far) = self.slice_range();
+        let mut ray = self.cam_world.transform(frame_time)
+            * Ray::segment(&o, &d, near / d.z, far / d.z, frame_time);
+        ray.primary = true;
+        ray
+    }
+}
+
+/// Compute the raster -> screen space transform for an image with dimensions `dims`
+fn raster_screen_transform(dims: (usize, usize)) -> Transform {
+    let aspect_ratio = (dims.0 as f32) / (dims.1 as f32);
+    let screen =
+        if aspect_ratio > 1.0 {
+            [-aspect_ratio, aspect_ratio, -1.0, 1.0]
+        } else {
+            [-1.0, 1.0, -1.0 / aspect_ratio, 1.0 / aspect_ratio]
+        };
+    let screen_raster = Transform::scale(&Vector::new(dims.0 as f32, dims.1 as f32, 1.0))
+        * Transform::scale(&Vector::new(1.0 / (screen[1] - screen[0]), 1.0 / (screen[2] - screen[3]), 1.0))
+        * Transform::translate(&Vector::new(-screen[0], -screen[3], 0.0));
+    screen_raster.inverse()
+}
+
+#[test]
+fn test_shutter_curve() {
+    let mut camera = Camera::new(AnimatedTransform::unanimated(&Transform::identity()), 60.0, (4, 4), 0.5, 0);
+    camera.update_frame(2.0, 4.0);
+    // Without a curve the time samples are spread uniformly over the open shutter
+    assert!(f32::abs(camera.generate_ray(&(2.0, 2.0), 0.5).time - 2.5) < 1e-5);
+    // The quadratic curve rises quickly, pushing the sample later in the interval
+    camera.set_shutter_curve(vec![0.0, 0.9, 1.0], vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0], 2);
+    assert!(f32::abs(camera.generate_ray(&(2.0, 2.0), 0.0).time - 2.0) < 1e-5);
+    assert!(f32::abs(camera.generate_ray(&(2.0, 2.0), 0.5).time - 2.7) < 1e-5);
+    assert!(f32::abs(camera.generate_ray(&(2.0, 2.0), 1.0).time - 3.0) < 1e-5);
+}
+
+#[test]
+fn test_first_frame_motion_blur() {
+    use linalg::Keyframe;
+    // A camera moving 24 units a second, rendered at 24 frames per second with a 180 degree shutter
+    let moving = AnimatedTransform::with_keyframes(
+        vec![Keyframe::new(&Transform::identity()),
+             Keyframe::new(&Transform::translate(&Vector::new(24.0, 0.0, 0.0)))],
+        vec![0.0, 0.0, 1.0, 1.0], 1);
+    let mut camera = Camera::new(moving, 60.0, (4, 4), 0.5, 0);
+    let time_step = 1.0 / 24.0;
+    // The first frame's motion blur streak should be as long as the following frames'
+    for frame in 0..3 {
+        camera.update_frame(frame as f32 * time_step, (frame + 1) as f32 * time_step);
+        let open = camera.generate_ray(&(2.0, 2.0), 0.0);
+        let close = camera.generate_ray(&(2.0, 2.0), 1.0);
+        assert!(open.time >= 0.0);
+        assert!(f32::abs(open.o.distance(&close.o) - 0.5) < 1e-4);
     }
 }
 
+#[test]
+fn test_orthographic_slices() {
+    let mut camera = Camera::orthographic(AnimatedTransform::unanimated(&Transform::identity()), 2.0,
+                                          (4, 2), 1.0, 0);
+    camera.update_frame(0.0, 1.0);
+    // The rays are parallel, spread over twice the size along the shorter image side
+    let corner = camera.generate_ray(&(0.0, 0.0), 0.0);
+    let center = camera.generate_ray(&(2.0, 1.0), 0.0);
+    assert_eq!(corner.d, center.d);
+    assert!(f32::abs(f32::abs(corner.o.y - center.o.y) - 2.0) < 1e-5);
+    assert!(f32::abs(f32::abs(corner.o.x - center.o.x) - 4.0) < 1e-5);
+    assert_eq!((center.min_t, center.max_t), (0.0, f32::INFINITY));
+    // Each slice covers an equal part of the clip range
+    camera.set_clip(2.0, 10.0);
+    camera.set_slice(Some((1, 4)));
+    assert_eq!(camera.slice_range(), (4.0, 6.0));
+    let ray = camera.generate_ray(&(2.0, 1.0), 0.0);
+    assert_eq!((ray.min_t, ray.max_t), (4.0, 6.0));
+    camera.set_slice(None);
+    assert_eq!(camera.slice_range(), (2.0, 10.0));
+}
@@ -5,6 +5,49 @@ use std::ops::{Add, Sub, Mul, Div, Neg, Index, IndexMut};
 
 use linalg;
 
+/// The RGB color spaces colors can be converted between, each is specified by
+/// its primaries and uses a D65 white point
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The sRGB/Rec. 709 primaries, this is the renderer's working color space
+    SRGB,
+    /// The wide gamut Rec. 2020 primaries
+    Rec2020,
+}
+
+impl ColorSpace {
+    /// Get the matrix converting linear RGB in this color space to CIE XYZ
+    fn rgb_to_xyz(&self) -> [[f32; 3]; 3] {
+        match *self {
+            ColorSpace::SRGB => [[0.4124564, 0.3575761, 0.1804375],
+                                 [0.2126729, 0.7151522, 0.0721750],
+                                 [0.0193339, 0.1191920, 0.9503041]],
+            ColorSpace::Rec2020 => [[0.6369580, 0.1446169, 0.1688810],
+                                    [0.2627002, 0.6779981, 0.0593017],
+                                    [0.0000000, 0.0280727, 1.0609851]],
+        }
+    }
+    /// Get the matrix converting CIE XYZ to linear RGB in this color space
+    fn xyz_to_rgb(&self) -> [[f32; 3]; 3] {
+        match *self {
+            ColorSpace::SRGB => [[3.2404542, -1.5371385, -0.4985314],
+                                 [-0.9692660, 1.8760108, 0.0415560],
+                                 [0.0556434, -0.2040259, 1.0572252]],
+            ColorSpace::Rec2020 => [[1.7166512, -0.3556708, -0.2533663],
+                                    [-0.6666844, 1.6164812, 0.0157685],
+                                    [0.0176399, -0.0427706, 0.9421031]],
+        }
+    }
+}
+
+/// Multiply the RGB channels of the color by the 3x3 matrix, alpha is left unchanged
+fn mul_mat3(m: &[[f32; 3]; 3], c: &Colorf) -> Colorf {
+    Colorf::with_alpha(m[0][0] * c.r + m[0][1] * c.g + m[0][2] * c.b,
+                       m[1][0] * c.r + m[1][1] * c.g + m[1][2] * c.b,
+                       m[2][0] * c.r + m[2][1] * c.g + m[2][2] * c.b,
+                       c.a)
+}
+
 /// Colorf is a floating point RGBA color type
 /// `a` is typically used to store the weight of a color eg. in the
 /// render target for multisampling we need to track the weight to
@@ -39,9 +82,36 @@ impl Colorf {
                  b: linalg::clamp(self.b, 0.0, 1.0),
                  a: linalg::clamp(self.a, 0.0, 1.0) }
     }
-    /// Compute the luminance of the color
+    /// Compute the luminance of the color, this is the Y component of
+    /// the color in CIE XYZ
     pub fn luminance(&self) -> f32 {
-        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+        0.2126729 * self.r + 0.7151522 * self.g + 0.0721750 * self.b
+    }
+    /// Convert the linear RGB color in the working sRGB color space to CIE XYZ,
+    /// the X, Y and Z values are returned in the r, g and b channels respectively
+    pub fn to_xyz(&self) -> Colorf {
+        self.to_xyz_from(ColorSpace::SRGB)
+    }
+    /// Convert the CIE XYZ color, stored in the r, g and b channels, to linear RGB
+    /// in the working sRGB color space
+    pub fn from_xyz(xyz: &Colorf) -> Colorf {
+        Colorf::from_xyz_to(xyz, ColorSpace::SRGB)
+    }
+    /// Convert the linear RGB color in the color space passed to CIE XYZ
+    pub fn to_xyz_from(&self, space: ColorSpace) -> Colorf {
+        mul_mat3(&space.rgb_to_xyz(), self)
+    }
+    /// Convert the CIE XYZ color to linear RGB in the color space passed
+    pub fn from_xyz_to(xyz: &Colorf, space: ColorSpace) -> Colorf {
+        mul_mat3(&space.xyz_to_rgb(), xyz)
+    }
+    /// Convert the linear RGB color from one color space to another
+    pub fn convert(&self, from: ColorSpace, to: ColorSpace) -> Colorf {
+        if from == to {
+            *self
+        } else {
+            Colorf::from_xyz_to(&self.to_xyz_from(from), to)
+        }
     }
     /// Check if the color is black
     pub fn is_black(&self) -> bool {
@@ -55,6 +125,10 @@ impl Colorf {
     pub fn has_infs(&self) -> bool {
         f32::is_infinite(self.r) || f32::is_infinite(self.g) || f32::is_infinite(self.b) || f32::is_infinite(self.a)
     }
+    /// Check that none of the color channels are NaN or infinite
+    pub fn is_finite(&self) -> bool {
+        !self.has_nans() && !self.has_infs()
+    }
     /// Convert the linear RGB color to sRGB
     pub fn to_srgb(&self) -> Colorf {
         let a = 0.055f32;
@@ -69,6 +143,33 @@ impl Colorf {
         }
         srgb
     }
+    /// Adjust the saturation of the color by blending it with its luminance, a saturation of
+    /// 0 gives gray, 1 leaves the color unchanged and larger values boost the saturation
+    pub fn saturate(&self, s: f32) -> Colorf {
+        let l = self.luminance();
+        let mut c = *self;
+        for i in 0..3 {
+            c[i] = f32::max(l + (self[i] - l) * s, 0.0);
+        }
+        c
+    }
+    /// Convert the sRGB color to linear RGB, the alpha value is unchanged
+    pub fn to_linear(&self) -> Colorf {
+        let a = 0.055f32;
+        let mut linear = *self;
+        for i in 0..3 {
+            if self[i] <= 0.04045 {
+                linear[i] = self[i] / 12.92;
+            } else {
+                linear[i] = f32::powf((self[i] + a) / (1.0 + a), 2.4);
+            }
+        }
+        linear
+    }
+    /// Linearly interpolate between `a` and `b`, including the alpha value
+    pub fn lerp(t: f32, a: &Colorf, b: &Colorf) -> Colorf {
+        linalg::lerp(t, a, b)
+    }
     /// Return the color with values { e^r, e^g, e^b }
     pub fn exp(&self) -> Colorf {
         Colorf { r: f32::exp(self.r), g: f32::exp(self.g),
@@ -177,3 +278,27 @@ impl IndexMut<usize> for Colorf {
     }
 }
 
+#[test]
+fn test_srgb_round_trip() {
+    let c = Colorf::with_alpha(0.001, 0.2, 0.9, 0.5);
+    let back = c.to_srgb().to_linear();
+    for i in 0..3 {
+        assert!(f32::abs(back[i] - c[i]) < 1e-5);
+    }
+    assert!(f32::abs(Colorf::broadcast(0.5).to_linear().r - 0.2140411) < 1e-5);
+}
+
+#[test]
+fn test_xyz_round_trip() {
+    let c = Colorf::new(0.8, 0.3, 0.1);
+    let xyz = c.to_xyz();
+    assert!(f32::abs(xyz.g - c.luminance()) < 1e-6);
+    let white = Colorf::new(1.0, 1.0, 1.0).to_xyz();
+    assert!(f32::abs(white.r - 0.95047) < 1e-4 && f32::abs(white.b - 1.08883) < 1e-4);
+    for &space in &[ColorSpace::SRGB, ColorSpace::Rec2020] {
+        let back = Colorf::from_xyz_to(&c.to_xyz_from(space), space);
+        for i in 0..3 {
+            assert!(f32::abs(back[i] - c[i]) < 1e-4);
+        }
+    }
+}
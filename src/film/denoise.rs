@@ -0,0 +1,153 @@
+//! Provides a simple denoiser to clean up noisy low sample count renders, using the
+//! edge-avoiding à-trous wavelet filter guided by the albedo and normals of the first
+//! surface seen through each pixel. The filter repeatedly blurs the image with a 5x5
+//! B3 spline kernel whose taps are spread further apart each iteration, weighting each
+//! tap by how similar its color, normal and albedo are to the center pixel's so edges
+//! and texture details are preserved.
+//!
+//! See [Dammertz et al., Edge-Avoiding À-Trous Wavelet Transform for fast Global
+//! Illumination Filtering](https://jo.dreggn.org/home/2010_atrous.pdf)
+//!
+//! The render target must collect the AOVs used to guide the filter, see
+//! `RenderTarget::set_aovs`. Denoising is enabled by passing `--denoise`.
+
+use std::f32;
+
+use film::tonemap;
+
+/// The B3 spline kernel weights for taps 0, 1 and 2 pixel steps from the center
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Parameters for the à-trous denoising filter. Larger sigmas blur more across
+/// differences in the corresponding buffer
+#[derive(Debug, Copy, Clone)]
+pub struct Denoiser {
+    /// Number of filter iterations to run, the filter's footprint doubles each iteration
+    pub iterations: usize,
+    /// Sigma for the color difference, halved each iteration
+    pub color_sigma: f32,
+    /// Sigma for the normal difference
+    pub normal_sigma: f32,
+    /// Sigma for the albedo difference
+    pub albedo_sigma: f32,
+}
+
+impl Denoiser {
+    pub fn new(iterations: usize, color_sigma: f32, normal_sigma: f32, albedo_sigma: f32) -> Denoiser {
+        assert!(color_sigma > 0.0 && normal_sigma > 0.0 && albedo_sigma > 0.0,
+                "Denoiser sigmas must be greater than 0");
+        Denoiser { iterations: iterations, color_sigma: color_sigma, normal_sigma: normal_sigma,
+                   albedo_sigma: albedo_sigma }
+    }
+    /// Denoise the RGBW f32 `pixels` of the image with dimensions `dim`, guided by the RGB
+    /// albedo and XYZ normal buffers returned by `RenderTarget::get_aovs`. Returns the
+    /// denoised RGBW f32 pixels, where each pixel with samples has a weight of 1
+    pub fn denoise(&self, pixels: &[f32], albedo: &[f32], normals: &[f32], dim: (usize, usize)) -> Vec<f32> {
+        let n_pixels = dim.0 * dim.1;
+        assert!(pixels.len() == n_pixels * 4 && albedo.len() == n_pixels * 3 && normals.len() == n_pixels * 3,
+                "Image and AOV buffers don't match the image dimensions");
+        let valid: Vec<_> = (0..n_pixels).map(|i| pixels[i * 4 + 3] > 0.0).collect();
+        let mut color = Vec::with_capacity(n_pixels * 3);
+        for i in 0..n_pixels {
+            let c = tonemap::normalize(pixels, i);
+            color.extend_from_slice(&[c.r, c.g, c.b]);
+        }
+        let inv_normal_sigma = 1.0 / (self.normal_sigma * self.normal_sigma);
+        let inv_albedo_sigma = 1.0 / (self.albedo_sigma * self.albedo_sigma);
+        let mut filtered = color.clone();
+        for it in 0..self.iterations {
+            let step = 1 << it;
+            let color_sigma = self.color_sigma / step as f32;
+            let inv_color_sigma = 1.0 / (color_sigma * color_sigma);
+            for y in 0..dim.1 {
+                for x in 0..dim.0 {
+                    let p = y * dim.0 + x;
+                    if !valid[p] {
+                        continue;
+                    }
+                    let mut sum = [0.0; 3];
+                    let mut weight_sum = 0.0;
+                    for dy in -2i32..3 {
+                        let qy = y as i32 + dy * step;
+                        if qy < 0 || qy >= dim.1 as i32 {
+                            continue;
+                        }
+                        for dx in -2i32..3 {
+                            let qx = x as i32 + dx * step;
+                            if qx < 0 || qx >= dim.0 as i32 {
+                                continue;
+                            }
+                            let q = qy as usize * dim.0 + qx as usize;
+                            if !valid[q] {
+                                continue;
+                            }
+                            let w = KERNEL[dx.abs() as usize] * KERNEL[dy.abs() as usize]
+                                * f32::exp(-distance_sqr(&color, p, q) * inv_color_sigma
+                                           - distance_sqr(normals, p, q) * inv_normal_sigma
+                                           - distance_sqr(albedo, p, q) * inv_albedo_sigma);
+                            for i in 0..3 {
+                                sum[i] += w * color[q * 3 + i];
+                            }
+                            weight_sum += w;
+                        }
+                    }
+                    // The center pixel always has a non-zero weight
+                    for i in 0..3 {
+                        filtered[p * 3 + i] = sum[i] / weight_sum;
+                    }
+                }
+            }
+            color.copy_from_slice(&filtered);
+        }
+        let mut denoised = Vec::with_capacity(n_pixels * 4);
+        for (i, c) in color.chunks(3).enumerate() {
+            if valid[i] {
+                denoised.extend_from_slice(&[c[0], c[1], c[2], 1.0]);
+            } else {
+                denoised.extend_from_slice(&[0.0; 4]);
+            }
+        }
+        denoised
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser::new(5, 0.5, 0.2, 0.1)
+    }
+}
+
+/// Compute the squared distance between the RGB or XYZ triples of pixels `p` and `q`
+fn distance_sqr(buf: &[f32], p: usize, q: usize) -> f32 {
+    (0..3).map(|i| buf[p * 3 + i] - buf[q * 3 + i]).map(|d| d * d).sum()
+}
+
+#[test]
+fn test_denoise() {
+    // The left half of the image is a dark surface and the right a bright one facing another
+    // way, both covered in a checkerboard of noise
+    let dim = (16, 16);
+    let mut pixels = Vec::new();
+    let mut albedo = Vec::new();
+    let mut normals = Vec::new();
+    for y in 0..dim.1 {
+        for x in 0..dim.0 {
+            let (base, n) = if x < dim.0 / 2 { (0.2, [0.0, 0.0, 1.0]) } else { (0.8, [1.0, 0.0, 0.0]) };
+            let noise = if (x + y) % 2 == 0 { 0.1 } else { -0.1 };
+            // Write the pixels with a weight of 2 to check it's divided out
+            pixels.extend_from_slice(&[2.0 * (base + noise), 2.0 * (base + noise), 2.0 * (base + noise), 2.0]);
+            albedo.extend_from_slice(&[base, base, base]);
+            normals.extend_from_slice(&n);
+        }
+    }
+    let denoised = Denoiser::default().denoise(&pixels, &albedo, &normals, dim);
+    for y in 0..dim.1 {
+        for x in 0..dim.0 {
+            let px = &denoised[(y * dim.0 + x) * 4..(y * dim.0 + x + 1) * 4];
+            let expected = if x < dim.0 / 2 { 0.2 } else { 0.8 };
+            // The noise is smoothed out without blurring the two halves together
+            assert!(f32::abs(px[0] - expected) < 0.05, "Pixel ({}, {}) = {}", x, y, px[0]);
+            assert_eq!(px[3], 1.0);
+        }
+    }
+}
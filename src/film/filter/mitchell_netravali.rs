@@ -21,10 +21,10 @@ pub struct MitchellNetravali {
 impl MitchellNetravali {
     pub fn new(w: f32, h: f32, b: f32, c: f32) -> MitchellNetravali {
         if b < 0.0 || b > 1.0 {
-            println!("Warning! Mitchell-Netravali b param = {} is out of bounds, clamping in range", b);
+            warn!("Mitchell-Netravali b param = {} is out of bounds, clamping in range", b);
         }
         if c < 0.0 || c > 1.0 {
-            println!("Warning! Mitchell-Netravali c param = {} is out of bounds, clamping in range", c);
+            warn!("Mitchell-Netravali c param = {} is out of bounds, clamping in range", c);
         }
         MitchellNetravali { w: w, h: h, inv_w: 1.0 / w, inv_h: 1.0 / h,
             b: linalg::clamp(b, 0.0, 1.0), c: linalg::clamp(c, 0.0, 1.0)
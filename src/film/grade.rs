@@ -0,0 +1,107 @@
+//! Provides a simple color grading pass applied to the final image when it's saved,
+//! allowing the overall look of the image to be tweaked without re-rendering.
+//!
+//! # Scene Usage Example
+//! The grade is specified in the film and is applied to the linear color of each
+//! pixel before it's converted to sRGB, linear outputs like PFM are saved ungraded.
+//! `exposure` scales the image by 2^exposure, `contrast` scales the image's contrast
+//! around middle gray (0.18) and `saturation` blends between the grayscale image (0)
+//! and the original colors (1), values greater than 1 boost the saturation. Each
+//! parameter is optional, the defaults leave the image unchanged.
+//!
+//! ```json
+//! "film": {
+//!     ...
+//!     "grade": {
+//!         "exposure": 0.5,
+//!         "contrast": 1.1,
+//!         "saturation": 0.8
+//!     }
+//! }
+//! ```
+
+use std::f32;
+
+use film::Colorf;
+
+/// Middle gray in linear space that contrast is adjusted around
+const MIDDLE_GRAY: f32 = 0.18;
+
+/// The color grade to apply to the final image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Grade {
+    /// Exposure adjustment in stops
+    pub exposure: f32,
+    /// Contrast around middle gray, 1 leaves the contrast unchanged
+    pub contrast: f32,
+    /// Saturation, 0 gives a grayscale image and 1 leaves the colors unchanged
+    pub saturation: f32,
+}
+
+impl Grade {
+    /// Create a grade applying the exposure, contrast and saturation adjustments
+    pub fn new(exposure: f32, contrast: f32, saturation: f32) -> Grade {
+        assert!(contrast > 0.0, "Grade contrast must be greater than 0");
+        assert!(saturation >= 0.0, "Grade saturation must not be negative");
+        Grade { exposure: exposure, contrast: contrast, saturation: saturation }
+    }
+    /// Create a grade which leaves the image unchanged
+    pub fn identity() -> Grade {
+        Grade::new(0.0, 1.0, 1.0)
+    }
+    /// Check if the grade leaves the image unchanged
+    pub fn is_identity(&self) -> bool {
+        *self == Grade::identity()
+    }
+    /// Apply the grade to the linear color passed, the alpha value is unchanged
+    pub fn apply(&self, c: &Colorf) -> Colorf {
+        let mut g = *c * f32::powf(2.0, self.exposure);
+        if self.contrast != 1.0 {
+            for i in 0..3 {
+                if g[i] > 0.0 {
+                    g[i] = MIDDLE_GRAY * f32::powf(g[i] / MIDDLE_GRAY, self.contrast);
+                }
+            }
+        }
+        g = g.saturate(self.saturation);
+        g.a = c.a;
+        g
+    }
+    /// Apply the grade to a buffer of RGBW pixels whose weight hasn't been divided out yet
+    pub fn apply_rgbw(&self, pixels: &mut [f32]) {
+        if self.is_identity() {
+            return;
+        }
+        for px in pixels.chunks_mut(4) {
+            if px[3] > 0.0 {
+                let c = Colorf::new(px[0], px[1], px[2]) / px[3];
+                let g = self.apply(&c) * px[3];
+                for i in 0..3 {
+                    px[i] = g[i];
+                }
+            }
+        }
+    }
+}
+
+impl Default for Grade {
+    fn default() -> Grade {
+        Grade::identity()
+    }
+}
+
+#[test]
+fn test_grade() {
+    let c = Colorf::with_alpha(0.5, 0.2, 0.1, 0.7);
+    let same = Grade::identity().apply(&c);
+    for i in 0..4 {
+        assert!(f32::abs(same[i] - c[i]) < 1e-6);
+    }
+    let gray = Grade::new(1.0, 1.0, 0.0).apply(&c);
+    assert!(f32::abs(gray.r - gray.g) < 1e-6 && f32::abs(gray.g - gray.b) < 1e-6);
+    assert!(f32::abs(gray.r - 2.0 * c.luminance()) < 1e-5);
+    assert_eq!(gray.a, c.a);
+    // Contrast pivots around middle gray
+    let mid = Grade::new(0.0, 2.0, 1.0).apply(&Colorf::broadcast(MIDDLE_GRAY));
+    assert!(f32::abs(mid.r - MIDDLE_GRAY) < 1e-6);
+}
@@ -1,9 +1,11 @@
 //! Provides a simple RGBA_F32 image, used by the distributed master to store results
 //! from the worker processes
 
-use std::iter;
+use std::{iter, cmp, f32};
 
-use film::Colorf;
+use film::{Colorf, Tonemap};
+use film::tonemap;
+use film::render_target::AOV_CHANNELS;
 
 #[derive(Debug)]
 pub struct Image {
@@ -32,13 +34,16 @@ impl Image {
     /// Add the blocks of RGBAf32 pixels to the image. It's assumed that the block information
     /// passed is equivalent to that returned by RenderTarget::get_blocks. `block_size` specifies
     /// the size of the blocks being passed, `blocks` contains the start points of each block and
-    /// `pixels` contains `block_size.0 * block_size.1 * 4` floats for each block.
+    /// `pixels` contains `block_size.0 * block_size.1 * 4` floats for each block. Pixels in
+    /// blocks on the edge of the image which fall outside the image are ignored.
     pub fn add_blocks(&mut self, block_size: (usize, usize), blocks: &[(usize, usize)], pixels: &[f32]) {
         let block_stride = block_size.0 * block_size.1 * 4;
         for (i, b) in blocks.iter().enumerate() {
             let block_px = &pixels[block_stride * i..block_stride * (i + 1)];
-            for by in 0..block_size.1 {
-                for bx in 0..block_size.0 {
+            let block_h = cmp::min(block_size.1, self.dim.1 - b.1);
+            let block_w = cmp::min(block_size.0, self.dim.0 - b.0);
+            for by in 0..block_h {
+                for bx in 0..block_w {
                     let c = &mut self.pixels[(by + b.1) * self.dim.0 + bx + b.0];
                     let px = by * block_size.0 * 4 + bx * 4;
                     for i in 0..4 {
@@ -48,25 +53,61 @@ impl Image {
             }
         }
     }
-    /// Convert the Image to sRGB8 format and return it
-    pub fn get_srgb8(&self) -> Vec<u8> {
-        let mut render: Vec<u8> = iter::repeat(0u8).take(self.dim.0 * self.dim.1 * 3).collect();
-        for y in 0..self.dim.1 {
-            for x in 0..self.dim.0 {
-                let c = &self.pixels[y * self.dim.0 + x];
-                if c.a > 0.0 {
-                    let cn = (*c / c.a).clamp().to_srgb();
-                    let px = y  * self.dim.0 * 3 + x * 3;
-                    for i in 0..3 {
-                        render[px + i] = (cn[i] * 255.0) as u8;
-                    }
-                }
+    /// Get the RGBW f32 pixels of the image, the weight has not been divided out
+    pub fn get_rgbw_f32(&self) -> Vec<f32> {
+        let mut render = Vec::with_capacity(self.dim.0 * self.dim.1 * 4);
+        for c in &self.pixels {
+            for i in 0..4 {
+                render.push(c[i]);
             }
         }
         render
     }
+    /// Convert the Image to sRGB8 format and return it
+    pub fn get_srgb8(&self) -> Vec<u8> {
+        tonemap::encode_8bit(&tonemap::apply_tonemap(&self.get_rgbw_f32(), Tonemap::Clamp))
+    }
     pub fn dimensions(&self) -> (usize, usize) {
         self.dim
     }
 }
 
+/// The AOVs of an image stored as `AOV_CHANNELS` floats per pixel, like those returned by
+/// `RenderTarget::get_aov_image`. Used by the distributed master to collect the AOVs rendered
+/// by the workers
+#[derive(Debug)]
+pub struct AovImage {
+    dim: (usize, usize),
+    pixels: Vec<f32>,
+}
+
+impl AovImage {
+    /// Create an AOV image where no surfaces have been seen, with black albedo and normals
+    /// and infinite depth
+    pub fn new(dimensions: (usize, usize)) -> AovImage {
+        let empty = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, f32::INFINITY];
+        let pixels = (0..dimensions.0 * dimensions.1).flat_map(|_| empty.iter().cloned()).collect();
+        AovImage { dim: dimensions, pixels: pixels }
+    }
+    /// Write the blocks of AOVs returned by `RenderTarget::get_aov_blocks` for the blocks passed
+    /// into the image, replacing the image's AOVs for those pixels. Pixels in blocks on the edge
+    /// of the image which fall outside the image are ignored
+    pub fn set_blocks(&mut self, block_size: (usize, usize), blocks: &[(usize, usize)], aovs: &[f32]) {
+        let block_stride = block_size.0 * block_size.1 * AOV_CHANNELS;
+        for (i, b) in blocks.iter().enumerate() {
+            let block_px = &aovs[block_stride * i..block_stride * (i + 1)];
+            let block_h = cmp::min(block_size.1, self.dim.1 - b.1);
+            let block_w = cmp::min(block_size.0, self.dim.0 - b.0);
+            for by in 0..block_h {
+                let src = by * block_size.0 * AOV_CHANNELS;
+                let dst = ((by + b.1) * self.dim.0 + b.0) * AOV_CHANNELS;
+                self.pixels[dst..dst + block_w * AOV_CHANNELS]
+                    .copy_from_slice(&block_px[src..src + block_w * AOV_CHANNELS]);
+            }
+        }
+    }
+    /// Get the AOVs of each pixel, with `AOV_CHANNELS` floats per pixel
+    pub fn pixels(&self) -> &[f32] {
+        &self.pixels
+    }
+}
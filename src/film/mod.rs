@@ -1,12 +1,17 @@
 //! The film module provides color types and a render target that the image
 //! is written too.
 
-pub use self::color::Colorf;
+pub use self::color::{Colorf, ColorSpace};
 pub use self::render_target::RenderTarget;
-pub use self::camera::Camera;
-pub use self::render_target::ImageSample;
+pub use self::camera::{Camera, Eye};
+pub use self::render_target::{ImageSample, AovSample};
 pub use self::animated_color::{ColorKeyframe, AnimatedColor};
-pub use self::image::Image;
+pub use self::image::{Image, AovImage};
+pub use self::output::Output;
+pub use self::grade::Grade;
+pub use self::tonemap::{Tonemap, AutoExposure};
+pub use self::denoise::Denoiser;
+pub use self::object_id::IdSample;
 
 pub mod color;
 pub mod render_target;
@@ -14,6 +19,11 @@ pub mod camera;
 pub mod filter;
 pub mod animated_color;
 pub mod image;
+pub mod output;
+pub mod grade;
+pub mod tonemap;
+pub mod denoise;
+pub mod object_id;
 
 /// Struct to store various parameters for the frame timing
 #[derive(Debug, Copy, Clone)]
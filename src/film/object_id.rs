@@ -0,0 +1,70 @@
+//! Provides object ID mattes for isolating objects when compositing, a simplified take on
+//! [Cryptomatte](https://github.com/Psyop/Cryptomatte). The tag of the instance seen by each
+//! camera sample is hashed into an ID and the render target counts how many of each pixel's
+//! samples saw each ID, giving every object in the image an anti-aliased coverage matte.
+//! Objects sharing a tag share a matte. Unlike Cryptomatte the coverage isn't filtered, each
+//! sample only counts towards the pixel it was taken in, like the albedo and normal AOVs.
+//!
+//! The render target must be collecting the mattes, see `RenderTarget::set_id_mattes`.
+//! Mattes are written for each frame by passing `--mattes`, see the usage of tray_rust.
+
+/// Hash the instance tag passed into its object ID, using 32-bit FNV-1a
+pub fn object_id(tag: &str) -> u32 {
+    tag.bytes().fold(2166136261, |h, b| (h ^ b as u32).wrapping_mul(16777619))
+}
+
+/// The ID of the object seen by a camera ray fired through continuous pixel coordinates
+/// [x, y], `None` if the ray missed the scene
+pub struct IdSample {
+    pub x: f32,
+    pub y: f32,
+    pub id: Option<u32>,
+}
+
+impl IdSample {
+    pub fn new(x: f32, y: f32, id: Option<u32>) -> IdSample {
+        IdSample { x: x, y: y, id: id }
+    }
+}
+
+/// The number of samples taken in a pixel and how many of them saw each object
+#[derive(Clone, Debug, Default)]
+pub struct PixelCoverage {
+    /// Total number of samples taken in the pixel, including those that missed the scene
+    pub samples: f32,
+    /// The number of samples which saw each object ID in the pixel
+    pub ids: Vec<(u32, f32)>,
+}
+
+impl PixelCoverage {
+    /// Count a sample which saw the object passed, or missed the scene if `None`
+    pub fn add(&mut self, id: Option<u32>) {
+        self.samples += 1.0;
+        if let Some(id) = id {
+            match self.ids.iter_mut().find(|x| x.0 == id) {
+                Some(x) => x.1 += 1.0,
+                None => self.ids.push((id, 1.0)),
+            }
+        }
+    }
+    /// Get the fraction of the pixel's samples which saw the object
+    pub fn coverage(&self, id: u32) -> f32 {
+        if self.samples == 0.0 {
+            return 0.0;
+        }
+        self.ids.iter().find(|x| x.0 == id).map_or(0.0, |x| x.1 / self.samples)
+    }
+}
+
+#[test]
+fn test_pixel_coverage() {
+    let mut px = PixelCoverage::default();
+    let (a, b) = (object_id("sphere"), object_id("floor"));
+    assert!(a != b);
+    for id in &[Some(a), Some(b), Some(a), None] {
+        px.add(*id);
+    }
+    assert_eq!(px.coverage(a), 0.5);
+    assert_eq!(px.coverage(b), 0.25);
+    assert_eq!(px.coverage(object_id("light")), 0.0);
+}
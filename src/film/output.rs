@@ -0,0 +1,413 @@
+//! Defines the image files written out for each rendered frame. By default a single
+//! PNG is written for each frame, but the film can specify a list of outputs to write
+//! the frame to several files and formats at once without re-rendering.
+//!
+//! # Scene Usage Example
+//! Each output specifies the path to write to, relative paths are placed in the output
+//! directory passed with `-o` (or the directory containing the output file). The format
+//! is chosen from the file extension: PNG, JPG, BMP and PPM are saved as 8-bit sRGB images
+//! while PFM and uncompressed EXR save the linear floating point image for HDR output. Any
+//! occurrence of `{frame}` in the path is replaced with the frame number, which is required
+//! to keep the files for each frame of an animation from overwriting each other.
+//!
+//! An output can save one of the AOVs of the first surface seen through each pixel instead of
+//! the rendered image by setting `aov` to `albedo`, `normal` or `depth`, the default `beauty`
+//! saves the rendered image. The normal faces the camera and the depth is the distance along
+//! the camera ray, which is infinite for pixels where no surface was seen. AOVs aren't graded,
+//! tonemapped or exposed, 8-bit images of them are clamped to [0, 1] after mapping the normals
+//! from [-1, 1], so depth is best saved to a linear format. Depth is saved to the Z channel of
+//! EXR files and as a grayscale PFM.
+//!
+//! ```json
+//! "film": {
+//!     ...
+//!     "outputs": [
+//!         {
+//!             "path": "beauty{frame}.png"
+//!         },
+//!         {
+//!             "path": "beauty{frame}.exr"
+//!         },
+//!         {
+//!             "path": "depth{frame}.exr",
+//!             "aov": "depth"
+//!         }
+//!     ]
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use image;
+
+use film::tonemap::{self, Tonemap, normalize};
+use film::render_target::AOV_CHANNELS;
+use film::{Eye, Grade};
+
+/// Magic number at the start of OpenEXR files
+const EXR_MAGIC: u32 = 20000630;
+
+/// The image formats outputs can be saved in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// An 8-bit sRGB image saved with the image crate, e.g. PNG or JPG
+    SRGB8,
+    /// A linear 32-bit float Portable Float Map
+    PFM,
+    /// A linear 32-bit float uncompressed OpenEXR image
+    EXR,
+}
+
+/// The image an output saves, either the rendered image or one of the AOVs collected by
+/// the render target
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    /// The rendered image
+    Beauty,
+    /// The albedo of the first surface seen through each pixel
+    Albedo,
+    /// The normal of the first surface seen through each pixel, facing the camera
+    Normal,
+    /// The distance along the camera ray to the first surface seen through each pixel
+    Depth,
+}
+
+impl Aov {
+    /// Parse the AOV from its name in the scene file, returns None if the AOV is unrecognized
+    pub fn from_str(aov: &str) -> Option<Aov> {
+        match aov {
+            "beauty" => Some(Aov::Beauty),
+            "albedo" => Some(Aov::Albedo),
+            "normal" => Some(Aov::Normal),
+            "depth" => Some(Aov::Depth),
+            _ => None,
+        }
+    }
+}
+
+/// An image file to save each rendered frame to
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// The path template for the output, `{frame}` is replaced by the frame number
+    pub path: String,
+    pub format: OutputFormat,
+    /// The image saved to the output
+    pub aov: Aov,
+}
+
+impl Output {
+    /// Create an output saving the rendered image to the path passed, the format is picked
+    /// based on the file extension. Panics if the format isn't supported
+    pub fn new(path: &str) -> Output {
+        Output::with_aov(path, Aov::Beauty)
+    }
+    /// Create an output saving the AOV to the path passed, the format is picked based on
+    /// the file extension. Panics if the format isn't supported
+    pub fn with_aov(path: &str, aov: Aov) -> Output {
+        let ext = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some(e) => e.to_lowercase(),
+            None => panic!("Output '{}' must have a file extension to pick the format", path),
+        };
+        let format = match &ext[..] {
+            "png" | "jpg" | "jpeg" | "bmp" | "ppm" => OutputFormat::SRGB8,
+            "pfm" => OutputFormat::PFM,
+            "exr" => OutputFormat::EXR,
+            _ => panic!("Output '{}' has an unsupported format '{}'", path, ext),
+        };
+        Output { path: path.to_owned(), format: format, aov: aov }
+    }
+    /// Get the file this output should be written to for the frame, relative paths are
+    /// placed in `out_dir`
+    pub fn file_path(&self, out_dir: &Path, frame: usize) -> PathBuf {
+        let p = PathBuf::from(self.path.replace("{frame}", &format!("{:05}", frame)));
+        if p.is_relative() {
+            out_dir.join(p)
+        } else {
+            p
+        }
+    }
+    /// Save the image to the file passed. `pixels` contains the RGBW f32 pixels
+    /// of the frame, where the weight has not been divided out yet, and `aovs` the AOVs
+    /// returned by `RenderTarget::get_aov_image`, which can be empty if the output saves the
+    /// rendered image. The rendered image is graded with `grade` and tonemapped with `tonemap`
+    /// when saved to 8-bit formats, linear formats and AOVs are saved unchanged
+    pub fn save(&self, file: &Path, dim: (usize, usize), pixels: &[f32], aovs: &[f32], grade: Grade,
+                tonemap: Tonemap) -> io::Result<()> {
+        if self.aov != Aov::Beauty && aovs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("the {:?} AOV wasn't collected", self.aov)));
+        }
+        match self.format {
+            OutputFormat::SRGB8 => {
+                let img = match self.aov {
+                    Aov::Beauty => {
+                        let mut graded = pixels.to_vec();
+                        grade.apply_rgbw(&mut graded[..]);
+                        tonemap::encode_8bit(&tonemap::apply_tonemap(&graded, tonemap))
+                    },
+                    // The albedo is a color so it's sRGB encoded like the rendered image
+                    Aov::Albedo => tonemap::encode_8bit(&self.linear_pixels(pixels, aovs)),
+                    Aov::Normal => {
+                        let normals = self.linear_pixels(pixels, aovs);
+                        let mapped: Vec<_> = normals.iter().map(|n| n * 0.5 + 0.5).collect();
+                        encode_linear_8bit(&mapped)
+                    },
+                    Aov::Depth => {
+                        let depth = self.linear_pixels(pixels, aovs);
+                        let gray: Vec<_> = depth.iter().flat_map(|d| vec![*d; 3]).collect();
+                        encode_linear_8bit(&gray)
+                    },
+                };
+                image::save_buffer(file, &img[..], dim.0 as u32, dim.1 as u32, image::RGB(8))
+            },
+            OutputFormat::PFM => save_pfm(file, dim, self.channels(), &self.linear_pixels(pixels, aovs)),
+            OutputFormat::EXR => save_exr(file, dim, self.channels(), &self.linear_pixels(pixels, aovs)),
+        }
+    }
+    /// Get the number of channels in the image saved by the output, 1 for depth and 3 otherwise
+    fn channels(&self) -> usize {
+        match self.aov {
+            Aov::Depth => 1,
+            _ => 3,
+        }
+    }
+    /// Get the linear f32 pixels of the image saved by the output, with `channels` floats per
+    /// pixel. The weights are divided out of the rendered image's RGBW `pixels` and the AOV saved
+    /// is picked out of the `aovs`
+    fn linear_pixels(&self, pixels: &[f32], aovs: &[f32]) -> Vec<f32> {
+        let offset = match self.aov {
+            Aov::Beauty => {
+                let mut linear = Vec::with_capacity(pixels.len() / 4 * 3);
+                for i in 0..pixels.len() / 4 {
+                    let c = normalize(pixels, i);
+                    for j in 0..3 {
+                        linear.push(c[j]);
+                    }
+                }
+                return linear;
+            },
+            Aov::Albedo => 0,
+            Aov::Normal => 3,
+            Aov::Depth => 6,
+        };
+        let channels = self.channels();
+        aovs.chunks(AOV_CHANNELS).flat_map(|a| a[offset..offset + channels].iter().cloned()).collect()
+    }
+}
+
+/// Check if any of the outputs save AOVs, in which case the render target must collect them
+pub fn uses_aovs(outputs: &[Output]) -> bool {
+    outputs.iter().any(|o| o.aov != Aov::Beauty)
+}
+
+/// Save the frame to all the outputs passed, returning the files written. If no outputs
+/// are specified the frame is written as a PNG to `out_path` if it's a file or as
+/// `frame<#>.png` in the directory `out_path`. `aovs` are the frame's AOVs returned by
+/// `RenderTarget::get_aov_image`, which can be empty if no outputs save AOVs. 8-bit images
+/// are graded with `grade` and tonemapped with `tonemap`. Errors saving are printed.
+pub fn save_frame(outputs: &[Output], out_path: &Path, frame: usize, dim: (usize, usize),
+                  pixels: &[f32], aovs: &[f32], grade: Grade, tonemap: Tonemap) -> Vec<PathBuf> {
+    save_eye_frame(outputs, out_path, frame, Eye::Center, dim, pixels, aovs, grade, tonemap)
+}
+
+/// Save the frame rendered for the eye passed to all the outputs, like `save_frame`. The
+/// eye's suffix is appended to the name of each file written, e.g. `frame00001_L.png`
+pub fn save_eye_frame(outputs: &[Output], out_path: &Path, frame: usize, eye: Eye, dim: (usize, usize),
+                      pixels: &[f32], aovs: &[f32], grade: Grade, tonemap: Tonemap) -> Vec<PathBuf> {
+    save_suffixed_frame(outputs, out_path, frame, eye.suffix(), dim, pixels, aovs, grade, tonemap)
+}
+
+/// Save the frame to all the outputs, like `save_frame`, appending the suffix to the name
+/// of each file written, e.g. the eye and slice rendered in `frame00001_L_slice003.png`
+pub fn save_suffixed_frame(outputs: &[Output], out_path: &Path, frame: usize, suffix: &str, dim: (usize, usize),
+                           pixels: &[f32], aovs: &[f32], grade: Grade, tonemap: Tonemap) -> Vec<PathBuf> {
+    let files: Vec<_> = if outputs.is_empty() {
+        let file = match out_path.extension() {
+            Some(_) => out_path.to_path_buf(),
+            None => out_path.join(PathBuf::from(format!("frame{:05}.png", frame))),
+        };
+        vec![(Output::new("frame.png"), file)]
+    } else {
+        let out_dir = match out_path.extension() {
+            Some(_) => out_path.parent().unwrap_or(Path::new("./")),
+            None => out_path,
+        };
+        outputs.iter().map(|o| (o.clone(), o.file_path(out_dir, frame))).collect()
+    };
+    let files: Vec<_> = files.into_iter().map(|(o, f)| (o, with_suffix(&f, suffix))).collect();
+    for &(ref o, ref f) in &files {
+        if let Err(e) = o.save(f, dim, pixels, aovs, grade, tonemap) {
+            error!("Failed to save image '{}', {}", f.display(), e);
+        }
+    }
+    files.into_iter().map(|(_, f)| f).collect()
+}
+
+/// Save the object ID mattes returned by `RenderTarget::get_id_mattes` for the frame rendered
+/// for the eye passed as grayscale PNGs, returning the files written. Each matte is named
+/// after the tag of its object in `tags`, e.g. `sphere_matte00001.png`, and written to the
+/// directory `out_path` or the directory containing it if it's a file. Errors saving are printed
+pub fn save_eye_mattes(mattes: &[(u32, Vec<f32>)], tags: &HashMap<u32, String>, out_path: &Path,
+                       frame: usize, eye: Eye, dim: (usize, usize)) -> Vec<PathBuf> {
+    let out_dir = match out_path.extension() {
+        Some(_) => out_path.parent().unwrap_or(Path::new("./")),
+        None => out_path,
+    };
+    let output = Output::new("matte.png");
+    let mut files = Vec::with_capacity(mattes.len());
+    for &(id, ref coverage) in mattes {
+        let name = match tags.get(&id) {
+            Some(t) => t.replace('/', "_"),
+            None => format!("{:08x}", id),
+        };
+        let file = with_suffix(&out_dir.join(format!("{}_matte{:05}.png", name, frame)), eye.suffix());
+        let pixels: Vec<_> = coverage.iter().flat_map(|c| vec![*c, *c, *c, 1.0]).collect();
+        if let Err(e) = output.save(&file, dim, &pixels, &[], Grade::identity(), Tonemap::Clamp) {
+            error!("Failed to save matte '{}', {}", file.display(), e);
+        }
+        files.push(file);
+    }
+    files
+}
+
+/// Append the suffix to the file name of the path, before its extension
+fn with_suffix(file: &Path, suffix: &str) -> PathBuf {
+    if suffix.is_empty() {
+        return file.to_path_buf();
+    }
+    let stem = file.file_stem().expect("Output file must have a name").to_string_lossy();
+    match file.extension() {
+        Some(ext) => file.with_file_name(format!("{}{}.{}", stem, suffix, ext.to_string_lossy())),
+        None => file.with_file_name(format!("{}{}", stem, suffix)),
+    }
+}
+
+/// Convert the linear f32 values to 8-bit values without sRGB encoding, clamping them to [0, 1]
+fn encode_linear_8bit(values: &[f32]) -> Vec<u8> {
+    values.iter().map(|x| (f32::max(0.0, f32::min(*x, 1.0)) * 255.0).round() as u8).collect()
+}
+
+/// Write the linear f32 pixels with `channels` floats per pixel, either RGB or grayscale,
+/// to a little endian Portable Float Map file. PFM stores the scanlines from bottom to top
+fn save_pfm(file: &Path, dim: (usize, usize), channels: usize, pixels: &[f32]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file)?);
+    let magic = if channels == 1 { "Pf" } else { "PF" };
+    write!(writer, "{}\n{} {}\n-1.0\n", magic, dim.0, dim.1)?;
+    for y in (0..dim.1).rev() {
+        for x in &pixels[y * dim.0 * channels..(y + 1) * dim.0 * channels] {
+            writer.write_f32::<LittleEndian>(*x)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the linear f32 pixels with `channels` floats per pixel, either RGB or depth, to an
+/// uncompressed scanline OpenEXR file. RGB is saved to the R, G and B channels and depth to
+/// the Z channel. See [the OpenEXR file layout](https://www.openexr.com/documentation/openexrfilelayout.pdf)
+fn save_exr(file: &Path, dim: (usize, usize), channels: usize, pixels: &[f32]) -> io::Result<()> {
+    // The channels must be listed in alphabetical order, each is paired with its offset in the pixel
+    let names: &[(&str, usize)] = if channels == 1 { &[("Z", 0)] } else { &[("B", 2), ("G", 1), ("R", 0)] };
+    let mut header = Vec::new();
+    header.write_u32::<LittleEndian>(EXR_MAGIC)?;
+    // Version 2 with no flags set, for a single part scanline image
+    header.write_u32::<LittleEndian>(2)?;
+    let mut channel_list = Vec::new();
+    for &(name, _) in names {
+        // The name is followed by the FLOAT pixel type, the linear flag and reserved bytes,
+        // then the x and y sampling rates
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.write_u8(0)?;
+        channel_list.write_i32::<LittleEndian>(2)?;
+        channel_list.write_u32::<LittleEndian>(0)?;
+        channel_list.write_i32::<LittleEndian>(1)?;
+        channel_list.write_i32::<LittleEndian>(1)?;
+    }
+    channel_list.write_u8(0)?;
+    let mut window = Vec::new();
+    for x in &[0, 0, dim.0 as i32 - 1, dim.1 as i32 - 1] {
+        window.write_i32::<LittleEndian>(*x)?;
+    }
+    let mut one = Vec::new();
+    one.write_f32::<LittleEndian>(1.0)?;
+    write_exr_attribute(&mut header, "channels", "chlist", &channel_list)?;
+    // No compression
+    write_exr_attribute(&mut header, "compression", "compression", &[0])?;
+    write_exr_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_exr_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    // Scanlines are stored from top to bottom
+    write_exr_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_exr_attribute(&mut header, "pixelAspectRatio", "float", &one)?;
+    write_exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_exr_attribute(&mut header, "screenWindowWidth", "float", &one)?;
+    header.write_u8(0)?;
+
+    let mut writer = BufWriter::new(File::create(file)?);
+    writer.write_all(&header)?;
+    // The offset table holds the position in the file of each scanline, which is stored as its
+    // y coordinate and size in bytes followed by the scanline's values for each channel in turn
+    let line_size = dim.0 * channels * 4;
+    let first_line = header.len() + dim.1 * 8;
+    for y in 0..dim.1 {
+        writer.write_u64::<LittleEndian>((first_line + y * (line_size + 8)) as u64)?;
+    }
+    for y in 0..dim.1 {
+        writer.write_i32::<LittleEndian>(y as i32)?;
+        writer.write_i32::<LittleEndian>(line_size as i32)?;
+        for &(_, c) in names {
+            for x in 0..dim.0 {
+                writer.write_f32::<LittleEndian>(pixels[(y * dim.0 + x) * channels + c])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Append the OpenEXR header attribute to the header, attributes are stored as their name
+/// and type followed by the size of their value and the value
+fn write_exr_attribute(header: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) -> io::Result<()> {
+    header.extend_from_slice(name.as_bytes());
+    header.write_u8(0)?;
+    header.extend_from_slice(ty.as_bytes());
+    header.write_u8(0)?;
+    header.write_i32::<LittleEndian>(value.len() as i32)?;
+    header.extend_from_slice(value);
+    Ok(())
+}
+
+#[test]
+fn test_exr_aov_output() {
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use byteorder::{ByteOrder, LittleEndian};
+    let dir = env::temp_dir().join("tray_rust_test_exr_aov_output");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("depth.exr");
+    let output = Output::with_aov("depth.exr", Aov::Depth);
+    assert_eq!(output.format, OutputFormat::EXR);
+    assert!(uses_aovs(&[Output::new("beauty.png"), output.clone()]));
+    assert!(!uses_aovs(&[Output::new("beauty.png"), Output::new("beauty.exr")]));
+    // A 2x2 image with the depth of each pixel set to its index
+    let pixels = [0.0; 16];
+    let aovs: Vec<_> = (0..4).flat_map(|i| vec![0.5, 0.5, 0.5, 0.0, 0.0, 1.0, i as f32]).collect();
+    assert!(output.save(&file, (2, 2), &pixels, &[], Grade::identity(), Tonemap::Clamp).is_err());
+    output.save(&file, (2, 2), &pixels, &aovs, Grade::identity(), Tonemap::Clamp).unwrap();
+    let mut data = Vec::new();
+    File::open(&file).unwrap().read_to_end(&mut data).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(LittleEndian::read_u32(&data[0..4]), EXR_MAGIC);
+    // Each scanline holds its y coordinate, its size and the Z channel of the pixels in the row
+    let line_size = 8 + 2 * 4;
+    let first_line = LittleEndian::read_u64(&data[data.len() - 2 * line_size - 16..]) as usize;
+    assert_eq!(first_line, data.len() - 2 * line_size);
+    for y in 0..2 {
+        let line = &data[first_line + y * line_size..first_line + (y + 1) * line_size];
+        assert_eq!(LittleEndian::read_i32(&line[0..4]), y as i32);
+        assert_eq!(LittleEndian::read_i32(&line[4..8]), 8);
+        assert_eq!(LittleEndian::read_f32(&line[8..12]), 2.0 * y as f32);
+        assert_eq!(LittleEndian::read_f32(&line[12..16]), 2.0 * y as f32 + 1.0);
+    }
+}
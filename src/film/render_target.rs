@@ -1,15 +1,37 @@
 //! Defines the render target for tray, where our image will be written too
 //! during rendering
+//!
+//! Since the render threads add their samples to the image in whatever order they finish
+//! rendering in, floating point rounding can make the image differ in the last bits between
+//! runs. For reproducible renders, e.g. when comparing against reference images, the render
+//! target can accumulate samples in fixed point, which is exact and so doesn't depend on the
+//! order samples are written in. This is enabled by setting `"deterministic": true` in the film.
+//!
+//! The render target can also collect the albedo, normal and depth of the first surface seen
+//! through each pixel, which are used to guide the denoiser in `film::denoise` and can be saved
+//! as AOV outputs, see `film::output`. These are averaged over the samples in each pixel without
+//! filtering. Similarly it can count the fraction of each
+//! pixel's samples that saw each object for the object ID mattes, see `film::object_id`.
 
 use std::vec::Vec;
 use std::{iter, cmp, f32};
 use std::sync::Mutex;
 
-use film::Colorf;
+use scoped_threadpool::Pool;
+
+use film::{Colorf, Grade, Tonemap, AutoExposure};
+use film::tonemap;
+use film::object_id::{IdSample, PixelCoverage};
 use film::filter::Filter;
+use linalg::Normal;
 use sampler::Region;
 
 const FILTER_TABLE_SIZE: usize = 16;
+/// Scale applied to filtered sample values when accumulating them in fixed point
+const FIXED_POINT_SCALE: f64 = 4294967296.0;
+/// Number of floats stored for each pixel in the AOV images returned by `get_aov_image` and
+/// `get_aov_blocks`: the albedo RGB, normal XYZ and depth
+pub const AOV_CHANNELS: usize = 7;
 
 /// A struct containing results of an image sample where a ray was fired through
 /// continuous pixel coordinates [x, y] and color `color` was computed
@@ -25,23 +47,61 @@ impl ImageSample {
     }
 }
 
+/// The albedo, normal and depth of the first surface hit by a camera ray fired through
+/// continuous pixel coordinates [x, y], for rays which miss the albedo and normal should be
+/// black and the depth infinite
+pub struct AovSample {
+    pub x: f32,
+    pub y: f32,
+    pub albedo: Colorf,
+    pub normal: Normal,
+    /// Distance along the camera ray to the surface
+    pub depth: f32,
+}
+
+impl AovSample {
+    pub fn new(x: f32, y: f32, albedo: Colorf, normal: Normal, depth: f32) -> AovSample {
+        AovSample { x: x, y: y, albedo: albedo, normal: normal, depth: depth }
+    }
+}
+
 /// `RenderTarget` is a RGBF render target to write our image too while rendering
 pub struct RenderTarget {
     width: usize,
     height: usize,
     pixels_locked: Vec<Mutex<Vec<Colorf>>>,
+    /// Light splatted on to the image by `splat`, stored in the same blocks as the pixels.
+    /// Always locked after the block's pixels
+    splat_locked: Vec<Mutex<Vec<Colorf>>>,
     lock_size: (i32, i32),
     filter: Box<Filter + Send + Sync>,
     filter_table: Vec<f32>,
     filter_pixel_width: (i32, i32),
+    /// Color grade applied to the image when it's converted to 8-bit
+    grade: Grade,
+    /// Tonemapping operator used when saving 8-bit images
+    tonemap: Tonemap,
+    /// Auto exposure to apply to each frame before it's saved, if any
+    auto_exposure: Option<AutoExposure>,
+    /// Fixed point RGBW accumulators for each block when accumulating deterministically,
+    /// empty otherwise. Always locked after the block's pixels
+    fixed_locked: Vec<Mutex<Vec<[i64; 4]>>>,
+    /// Summed albedo RGB, normal XYZ, sample count, depth and count of samples that hit a
+    /// surface for each pixel of each block when collecting AOVs, empty otherwise
+    aov_locked: Vec<Mutex<Vec<[f32; 9]>>>,
+    /// Coverage of the objects seen in each pixel of each block when collecting object
+    /// ID mattes, empty otherwise
+    id_locked: Vec<Mutex<Vec<PixelCoverage>>>,
 }
 
 impl RenderTarget {
-    /// Create a render target with `width * height` pixels
+    /// Create a render target with `width * height` pixels. The pixels are stored in blocks of
+    /// `lock_size` pixels, if the image isn't evenly divided by the block size the block grid
+    /// is padded to cover the image and pixels outside the image are ignored
     pub fn new(image_dim: (usize, usize), lock_size: (usize, usize),
                filter: Box<Filter + Send + Sync>) -> RenderTarget {
-        if image_dim.0 % lock_size.0 != 0 || image_dim.1 % lock_size.1 != 0 {
-            panic!("Image with dimension {:?} not evenly divided by blocks of {:?}", image_dim, lock_size);
+        if lock_size.0 == 0 || lock_size.1 == 0 {
+            panic!("Render target blocks must be at least 1x1, got {:?}", lock_size);
         }
         let width = image_dim.0;
         let height = image_dim.1;
@@ -57,22 +117,128 @@ impl RenderTarget {
             }
         }
 
-        let x_blocks = width / lock_size.0;
-        let y_blocks = height / lock_size.1;
+        let x_blocks = (width + lock_size.0 - 1) / lock_size.0;
+        let y_blocks = (height + lock_size.1 - 1) / lock_size.1;
         let mut pixels_locked = Vec::with_capacity(x_blocks * y_blocks);
+        let mut splat_locked = Vec::with_capacity(x_blocks * y_blocks);
         for _ in 0..x_blocks * y_blocks {
             pixels_locked.push(Mutex::new(iter::repeat(Colorf::broadcast(0.0))
                                           .take(lock_size.0 * lock_size.1).collect()));
+            splat_locked.push(Mutex::new(iter::repeat(Colorf::broadcast(0.0))
+                                         .take(lock_size.0 * lock_size.1).collect()));
         }
 
         RenderTarget { width: width, height: height,
             pixels_locked: pixels_locked,
+            splat_locked: splat_locked,
             lock_size: (lock_size.0 as i32, lock_size.1 as i32),
             filter: filter,
             filter_table: filter_table,
             filter_pixel_width: filter_pixel_width,
+            grade: Grade::identity(),
+            tonemap: Tonemap::Clamp,
+            auto_exposure: None,
+            fixed_locked: Vec::new(),
+            aov_locked: Vec::new(),
+            id_locked: Vec::new(),
         }
     }
+    /// Change the image dimensions of the render target, keeping its filter, block size,
+    /// grade, tonemap, accumulation mode, AOVs and ID mattes. All pixels are cleared to black
+    pub fn resize(&mut self, image_dim: (usize, usize)) {
+        let deterministic = self.is_deterministic();
+        let aovs = self.has_aovs();
+        let id_mattes = self.has_id_mattes();
+        self.width = image_dim.0;
+        self.height = image_dim.1;
+        let (x_blocks, y_blocks) = self.block_grid();
+        let block_pixels = (self.lock_size.0 * self.lock_size.1) as usize;
+        self.pixels_locked = (0..x_blocks * y_blocks).map(|_| {
+            Mutex::new(iter::repeat(Colorf::broadcast(0.0)).take(block_pixels).collect())
+        }).collect();
+        self.splat_locked = (0..x_blocks * y_blocks).map(|_| {
+            Mutex::new(iter::repeat(Colorf::broadcast(0.0)).take(block_pixels).collect())
+        }).collect();
+        self.set_deterministic(deterministic);
+        self.set_aovs(aovs);
+        self.set_id_mattes(id_mattes);
+    }
+    /// Set whether samples should be accumulated in fixed point, making the image independent
+    /// of the order the render threads write their samples in. Any samples already written
+    /// are kept
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.fixed_locked.clear();
+        if deterministic {
+            let block_pixels = (self.lock_size.0 * self.lock_size.1) as usize;
+            for block in &self.pixels_locked {
+                let pixels = block.lock().unwrap();
+                let fixed = pixels.iter().take(block_pixels).map(|c| {
+                    [to_fixed(c.r), to_fixed(c.g), to_fixed(c.b), to_fixed(c.a)]
+                }).collect();
+                self.fixed_locked.push(Mutex::new(fixed));
+            }
+        }
+    }
+    /// Check if samples are accumulated deterministically
+    pub fn is_deterministic(&self) -> bool {
+        !self.fixed_locked.is_empty()
+    }
+    /// Set whether the albedo, normal and depth AOVs should be collected, the AOVs are cleared
+    pub fn set_aovs(&mut self, aovs: bool) {
+        self.aov_locked.clear();
+        if aovs {
+            let block_pixels = (self.lock_size.0 * self.lock_size.1) as usize;
+            for _ in 0..self.pixels_locked.len() {
+                self.aov_locked.push(Mutex::new(iter::repeat([0.0; 9]).take(block_pixels).collect()));
+            }
+        }
+    }
+    /// Check if the albedo, normal and depth AOVs are being collected
+    pub fn has_aovs(&self) -> bool {
+        !self.aov_locked.is_empty()
+    }
+    /// Set whether the object ID mattes should be collected, the mattes are cleared
+    pub fn set_id_mattes(&mut self, id_mattes: bool) {
+        self.id_locked.clear();
+        if id_mattes {
+            let block_pixels = (self.lock_size.0 * self.lock_size.1) as usize;
+            for _ in 0..self.pixels_locked.len() {
+                let coverage = iter::repeat(PixelCoverage::default()).take(block_pixels).collect();
+                self.id_locked.push(Mutex::new(coverage));
+            }
+        }
+    }
+    /// Check if the object ID mattes are being collected
+    pub fn has_id_mattes(&self) -> bool {
+        !self.id_locked.is_empty()
+    }
+    /// Set the color grade to apply to the image returned by `get_render` and when saving
+    /// 8-bit images. The linear image returned by `get_renderf32` is not graded
+    pub fn set_grade(&mut self, grade: Grade) {
+        self.grade = grade;
+    }
+    /// Get the color grade applied to the image
+    pub fn grade(&self) -> Grade {
+        self.grade
+    }
+    /// Set the tonemapping operator used by `get_render` and when saving 8-bit images
+    pub fn set_tonemap(&mut self, tonemap: Tonemap) {
+        self.tonemap = tonemap;
+    }
+    /// Get the tonemapping operator used for 8-bit images
+    pub fn tonemap(&self) -> Tonemap {
+        self.tonemap
+    }
+    /// Set the auto exposure to apply to each frame of the animation before it's saved. The
+    /// render target only stores it, the executor saving the frames applies it since it
+    /// carries the exposure between frames
+    pub fn set_auto_exposure(&mut self, auto_exposure: Option<AutoExposure>) {
+        self.auto_exposure = auto_exposure;
+    }
+    /// Get the auto exposure to apply to each frame, if any
+    pub fn auto_exposure(&self) -> Option<AutoExposure> {
+        self.auto_exposure
+    }
     /// Write all the image samples to the render target
     pub fn write(&self, samples: &[ImageSample], region: &Region) {
         // Determine which blocks we touch with our set of samples
@@ -91,7 +257,7 @@ impl RenderTarget {
         let mut filtered_samples: Vec<_> = iter::repeat(Colorf::broadcast(0.0))
             .take((self.lock_size.0 * self.lock_size.1) as usize).collect();
 
-        let blocks_per_row = self.width as i32 / self.lock_size.0;
+        let blocks_per_row = self.block_grid().0 as i32;
         for y in block_y_range.0..block_y_range.1 + 1 {
             for x in block_x_range.0..block_x_range.1 + 1 {
                 let block_x_start = x * self.lock_size.0;
@@ -150,23 +316,228 @@ impl RenderTarget {
                 // Acquire lock for the block and write the filtered samples
                 let block_idx = (y * blocks_per_row + x) as usize;
                 let mut pixels = self.pixels_locked[block_idx].lock().unwrap();
-                for iy in y_write_range.0..y_write_range.1 {
-                    for ix in x_write_range.0..x_write_range.1 {
-                        let px = ((iy - block_y_start) * self.lock_size.0 + ix - block_x_start) as usize;
-                        let c = &filtered_samples[px];
-                        pixels[px].r += c.r;
-                        pixels[px].g += c.g;
-                        pixels[px].b += c.b;
-                        pixels[px].a += c.a;
+                if self.is_deterministic() {
+                    let mut fixed = self.fixed_locked[block_idx].lock().unwrap();
+                    for iy in y_write_range.0..y_write_range.1 {
+                        for ix in x_write_range.0..x_write_range.1 {
+                            let px = ((iy - block_y_start) * self.lock_size.0 + ix - block_x_start) as usize;
+                            for i in 0..4 {
+                                fixed[px][i] += to_fixed(filtered_samples[px][i]);
+                                pixels[px][i] = from_fixed(fixed[px][i]);
+                            }
+                        }
                     }
+                } else {
+                    for iy in y_write_range.0..y_write_range.1 {
+                        for ix in x_write_range.0..x_write_range.1 {
+                            let px = ((iy - block_y_start) * self.lock_size.0 + ix - block_x_start) as usize;
+                            let c = &filtered_samples[px];
+                            pixels[px].r += c.r;
+                            pixels[px].g += c.g;
+                            pixels[px].b += c.b;
+                            pixels[px].a += c.a;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Add the AOV samples to the pixels they were taken in. Does nothing if the render
+    /// target isn't collecting AOVs
+    pub fn write_aovs(&self, samples: &[AovSample]) {
+        if !self.has_aovs() {
+            return;
+        }
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        for s in samples {
+            if s.x < 0.0 || s.y < 0.0 || s.x >= self.width as f32 || s.y >= self.height as f32 {
+                continue;
+            }
+            let (ix, iy) = (s.x as usize, s.y as usize);
+            let block_idx = (iy / lock_size.1) * blocks_per_row + ix / lock_size.0;
+            let px = (iy % lock_size.1) * lock_size.0 + ix % lock_size.0;
+            let mut aovs = self.aov_locked[block_idx].lock().unwrap();
+            let a = &mut aovs[px];
+            for i in 0..3 {
+                a[i] += s.albedo[i];
+                a[i + 3] += s.normal[i];
+            }
+            a[6] += 1.0;
+            if s.depth.is_finite() {
+                a[7] += s.depth;
+                a[8] += 1.0;
+            }
+        }
+    }
+    /// Get the average albedo RGB and normal XYZ of each pixel, the normals are normalized.
+    /// Both are black if the render target isn't collecting AOVs
+    pub fn get_aovs(&self) -> (Vec<f32>, Vec<f32>) {
+        let mut albedo: Vec<f32> = iter::repeat(0.0).take(self.width * self.height * 3).collect();
+        let mut normals = albedo.clone();
+        if !self.has_aovs() {
+            return (albedo, normals);
+        }
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block_idx = (y / lock_size.1) * blocks_per_row + x / lock_size.0;
+                let aovs = self.aov_locked[block_idx].lock().unwrap();
+                let a = resolve_aovs(&aovs[(y % lock_size.1) * lock_size.0 + x % lock_size.0]);
+                let px = (y * self.width + x) * 3;
+                albedo[px..px + 3].copy_from_slice(&a[0..3]);
+                normals[px..px + 3].copy_from_slice(&a[3..6]);
+            }
+        }
+        (albedo, normals)
+    }
+    /// Get the average albedo RGB, normal XYZ and depth of each pixel, stored as `AOV_CHANNELS`
+    /// floats per pixel. The depth is infinite for pixels where no surface was hit, or if the
+    /// render target isn't collecting AOVs
+    pub fn get_aov_image(&self) -> Vec<f32> {
+        let mut image = Vec::with_capacity(self.width * self.height * AOV_CHANNELS);
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        let empty = resolve_aovs(&[0.0; 9]);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block_idx = (y / lock_size.1) * blocks_per_row + x / lock_size.0;
+                match self.aov_locked.get(block_idx) {
+                    Some(aovs) => {
+                        let aovs = aovs.lock().unwrap();
+                        let px = (y % lock_size.1) * lock_size.0 + x % lock_size.0;
+                        image.extend_from_slice(&resolve_aovs(&aovs[px]));
+                    },
+                    None => image.extend_from_slice(&empty),
+                }
+            }
+        }
+        image
+    }
+    /// Get the AOVs of the blocks starting at the pixels passed, e.g. the blocks returned by
+    /// `get_rendered_blocks`, with `AOV_CHANNELS` floats per pixel like `get_aov_image`. Each
+    /// block is stored like the blocks of `get_rendered_blocks`. Returns an empty vec if the
+    /// render target isn't collecting AOVs
+    pub fn get_aov_blocks(&self, blocks: &[(usize, usize)]) -> Vec<f32> {
+        if !self.has_aovs() {
+            return Vec::new();
+        }
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        let mut image = Vec::with_capacity(blocks.len() * lock_size.0 * lock_size.1 * AOV_CHANNELS);
+        for b in blocks {
+            let block_idx = (b.1 / lock_size.1) * blocks_per_row + b.0 / lock_size.0;
+            for a in self.aov_locked[block_idx].lock().unwrap().iter() {
+                image.extend_from_slice(&resolve_aovs(a));
+            }
+        }
+        image
+    }
+    /// Count the objects seen by the ID samples in the pixels they were taken in. Does nothing
+    /// if the render target isn't collecting object ID mattes
+    pub fn write_ids(&self, samples: &[IdSample]) {
+        if !self.has_id_mattes() {
+            return;
+        }
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        for s in samples {
+            if s.x < 0.0 || s.y < 0.0 || s.x >= self.width as f32 || s.y >= self.height as f32 {
+                continue;
+            }
+            let (ix, iy) = (s.x as usize, s.y as usize);
+            let block_idx = (iy / lock_size.1) * blocks_per_row + ix / lock_size.0;
+            let px = (iy % lock_size.1) * lock_size.0 + ix % lock_size.0;
+            self.id_locked[block_idx].lock().unwrap()[px].add(s.id);
+        }
+    }
+    /// Get the coverage matte of each object seen in the image, sorted by object ID. Each
+    /// matte has a coverage value in [0, 1] for each pixel of the image. Empty if the render
+    /// target isn't collecting object ID mattes
+    pub fn get_id_mattes(&self) -> Vec<(u32, Vec<f32>)> {
+        let mut mattes: Vec<(u32, Vec<f32>)> = Vec::new();
+        if !self.has_id_mattes() {
+            return mattes;
+        }
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block_idx = (y / lock_size.1) * blocks_per_row + x / lock_size.0;
+                let coverage = self.id_locked[block_idx].lock().unwrap();
+                let c = &coverage[(y % lock_size.1) * lock_size.0 + x % lock_size.0];
+                for &(id, _) in &c.ids {
+                    let m = match mattes.binary_search_by_key(&id, |m| m.0) {
+                        Ok(m) => m,
+                        Err(m) => {
+                            mattes.insert(m, (id, iter::repeat(0.0).take(self.width * self.height).collect()));
+                            m
+                        },
+                    };
+                    mattes[m].1[y * self.width + x] = c.coverage(id);
+                }
+            }
+        }
+        mattes
+    }
+    /// Splat the sample's color on to the image at its continuous pixel position, spreading
+    /// it over the nearby pixels with the reconstruction filter. Unlike `write` the splatted
+    /// light isn't weighted against the other samples of the pixels, it's added directly to
+    /// the final pixel value. This is used by light transport algorithms which connect paths
+    /// to the camera at arbitrary points on the image, the caller is responsible for scaling
+    /// the splatted color by the number of paths traced.
+    pub fn splat(&self, sample: &ImageSample) {
+        let img_x = sample.x - 0.5;
+        let img_y = sample.y - 0.5;
+        let x_range = (cmp::max(f32::ceil(img_x) as i32 - self.filter_pixel_width.0, 0),
+                       cmp::min(f32::floor(img_x) as i32 + self.filter_pixel_width.0, self.width as i32 - 1));
+        let y_range = (cmp::max(f32::ceil(img_y) as i32 - self.filter_pixel_width.1, 0),
+                       cmp::min(f32::floor(img_y) as i32 + self.filter_pixel_width.1, self.height as i32 - 1));
+        if x_range.1 < x_range.0 || y_range.1 < y_range.0 {
+            return;
+        }
+        // Normalize the filter weights so the splat deposits exactly the sample's color
+        let mut weights = Vec::new();
+        for iy in y_range.0..y_range.1 + 1 {
+            for ix in x_range.0..x_range.1 + 1 {
+                weights.push(self.filter_weight(ix as f32 - img_x, iy as f32 - img_y));
+            }
+        }
+        let weight_sum: f32 = weights.iter().sum();
+        if weight_sum == 0.0 {
+            return;
+        }
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let blocks_per_row = self.block_grid().0;
+        let mut w = weights.iter();
+        for iy in y_range.0 as usize..y_range.1 as usize + 1 {
+            for ix in x_range.0 as usize..x_range.1 as usize + 1 {
+                let weight = *w.next().unwrap() / weight_sum;
+                if weight == 0.0 {
+                    continue;
                 }
+                let block_idx = (iy / lock_size.1) * blocks_per_row + ix / lock_size.0;
+                let px = (iy % lock_size.1) * lock_size.0 + ix % lock_size.0;
+                let mut splats = self.splat_locked[block_idx].lock().unwrap();
+                splats[px] = splats[px] + sample.color * weight;
             }
         }
     }
+    /// Look up the filter weight for a pixel offset `(dx, dy)` from the sample position
+    fn filter_weight(&self, dx: f32, dy: f32) -> f32 {
+        let fx = f32::abs(dx) * self.filter.inv_width();
+        let fy = f32::abs(dy) * self.filter.inv_height();
+        if fx >= 1.0 || fy >= 1.0 {
+            return 0.0;
+        }
+        let fx_idx = cmp::min((fx * FILTER_TABLE_SIZE as f32) as usize, FILTER_TABLE_SIZE - 1);
+        let fy_idx = cmp::min((fy * FILTER_TABLE_SIZE as f32) as usize, FILTER_TABLE_SIZE - 1);
+        self.filter_table[fy_idx * FILTER_TABLE_SIZE + fx_idx]
+    }
     /// Clear the render target to black
     pub fn clear(&mut self) {
-        let x_blocks = self.width / self.lock_size.0 as usize;
-        let y_blocks = self.height / self.lock_size.1 as usize;
+        let (x_blocks, y_blocks) = self.block_grid();
         for by in 0..y_blocks {
             for bx in 0..x_blocks {
                 let block_idx = (by * x_blocks + bx) as usize;
@@ -174,6 +545,24 @@ impl RenderTarget {
                 for p in pixels.iter_mut() {
                     *p = Colorf::broadcast(0.0);
                 }
+                for p in self.splat_locked[block_idx].lock().unwrap().iter_mut() {
+                    *p = Colorf::broadcast(0.0);
+                }
+                if let Some(fixed) = self.fixed_locked.get(block_idx) {
+                    for p in fixed.lock().unwrap().iter_mut() {
+                        *p = [0; 4];
+                    }
+                }
+                if let Some(aovs) = self.aov_locked.get(block_idx) {
+                    for p in aovs.lock().unwrap().iter_mut() {
+                        *p = [0.0; 9];
+                    }
+                }
+                if let Some(coverage) = self.id_locked.get(block_idx) {
+                    for p in coverage.lock().unwrap().iter_mut() {
+                        *p = PixelCoverage::default();
+                    }
+                }
             }
         }
     }
@@ -181,32 +570,13 @@ impl RenderTarget {
     pub fn dimensions(&self) -> (usize, usize) {
         (self.width, self.height)
     }
-    /// Convert the floating point color buffer to 24bpp sRGB for output to an image
+    /// Convert the floating point color buffer to 24bpp sRGB for output to an image. This
+    /// grades the linear image and tonemaps it with the render target's tonemap, see
+    /// `film::tonemap` to tonemap or encode it separately
     pub fn get_render(&self) -> Vec<u8> {
-        let mut render: Vec<u8> = iter::repeat(0u8).take(self.width * self.height * 3).collect();
-        let x_blocks = self.width / self.lock_size.0 as usize;
-        let y_blocks = self.height / self.lock_size.1 as usize;
-        for by in 0..y_blocks {
-            for bx in 0..x_blocks {
-                let block_x_start = bx * self.lock_size.0 as usize;
-                let block_y_start = by * self.lock_size.1 as usize;
-                let block_idx = (by * x_blocks + bx) as usize;
-                let pixels = self.pixels_locked[block_idx].lock().unwrap();
-                for y in 0..self.lock_size.1 as usize {
-                    for x in 0..self.lock_size.0 as usize {
-                        let c = &pixels[y * self.lock_size.0 as usize + x];
-                        if c.a > 0.0 {
-                            let cn = (*c / c.a).clamp().to_srgb();
-                            let px = (y + block_y_start) * self.width * 3 + (x + block_x_start) * 3;
-                            for i in 0..3 {
-                                render[px + i] = (cn[i] * 255.0) as u8;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        render
+        let mut render = self.get_renderf32();
+        self.grade.apply_rgbw(&mut render[..]);
+        tonemap::encode_8bit(&tonemap::apply_tonemap(&render, self.tonemap))
     }
     /// Get the blocks that have had pixels written too them. Returns the size of each block,
     /// a list of block positions in pixels and then pixels for the blocks (in a single f32 vec).
@@ -216,19 +586,22 @@ impl RenderTarget {
         let block_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
         let mut blocks = Vec::new();
         let mut render = Vec::new();
-        let x_blocks = self.width / block_size.0;
-        let y_blocks = self.height / block_size.1;
+        let (x_blocks, y_blocks) = self.block_grid();
         for by in 0..y_blocks {
             for bx in 0..x_blocks {
                 let block_x_start = bx * block_size.0;
                 let block_y_start = by * block_size.1;
                 let block_idx = by * x_blocks + bx;
                 let pixels = self.pixels_locked[block_idx].lock().unwrap();
-                if pixels.iter().fold(true, |acc, px| acc && px.a != 0.0) {
+                let splats = self.splat_locked[block_idx].lock().unwrap();
+                // Only the pixels inside the image will be written in blocks on the edge of the image
+                let (block_w, block_h) = self.block_extent(block_x_start, block_y_start);
+                let written = (0..block_h).all(|y| (0..block_w).all(|x| pixels[y * block_size.0 + x].a != 0.0));
+                if written {
                     blocks.push((block_x_start, block_y_start));
                     for y in 0..block_size.1 {
                         for x in 0..block_size.0 {
-                            let c = &pixels[y * block_size.0 + x];
+                            let c = resolve(&pixels[y * block_size.0 + x], &splats[y * block_size.0 + x]);
                             for i in 0..4 {
                                 render.push(c[i]);
                             }
@@ -242,26 +615,267 @@ impl RenderTarget {
     /// Get the raw floating point framebuffer
     pub fn get_renderf32(&self) -> Vec<f32> {
         let mut render: Vec<f32> = iter::repeat(0.0).take(self.width * self.height * 4).collect();
-        let x_blocks = self.width / self.lock_size.0 as usize;
-        let y_blocks = self.height / self.lock_size.1 as usize;
-        for by in 0..y_blocks {
-            for bx in 0..x_blocks {
-                let block_x_start = bx * self.lock_size.0 as usize;
-                let block_y_start = by * self.lock_size.1 as usize;
-                let block_idx = (by * x_blocks + bx) as usize;
-                let pixels = self.pixels_locked[block_idx].lock().unwrap();
-                for y in 0..self.lock_size.1 as usize {
-                    for x in 0..self.lock_size.0 as usize {
-                        let c = &pixels[y * self.lock_size.0 as usize + x];
-                        let px = (y + block_y_start) * self.width * 4 + (x + block_x_start) * 4;
-                        for i in 0..4 {
-                            render[px + i] = c[i];
-                        }
+        let row_size = self.block_row_size();
+        for (by, row) in render.chunks_mut(row_size).enumerate() {
+            self.read_block_row(by, row);
+        }
+        render
+    }
+    /// Get the raw floating point framebuffer, reading it back in parallel on the threads
+    /// of the pool. Each thread reads out a row of blocks at a time
+    pub fn get_renderf32_parallel(&self, pool: &mut Pool) -> Vec<f32> {
+        let mut render: Vec<f32> = iter::repeat(0.0).take(self.width * self.height * 4).collect();
+        let row_size = self.block_row_size();
+        pool.scoped(|scope| {
+            for (by, row) in render.chunks_mut(row_size).enumerate() {
+                scope.execute(move || self.read_block_row(by, row));
+            }
+        });
+        render
+    }
+    /// Get the number of f32's in the image for a row of blocks
+    fn block_row_size(&self) -> usize {
+        self.width * self.lock_size.1 as usize * 4
+    }
+    /// Read the RGBW pixels of the row of blocks `by` into `row`, which is the slice
+    /// of the image's pixels covered by the row
+    fn read_block_row(&self, by: usize, row: &mut [f32]) {
+        let x_blocks = self.block_grid().0;
+        let block_y_start = by * self.lock_size.1 as usize;
+        for bx in 0..x_blocks {
+            let block_x_start = bx * self.lock_size.0 as usize;
+            let block_idx = by * x_blocks + bx;
+            let pixels = self.pixels_locked[block_idx].lock().unwrap();
+            let splats = self.splat_locked[block_idx].lock().unwrap();
+            let (block_w, block_h) = self.block_extent(block_x_start, block_y_start);
+            for y in 0..block_h {
+                for x in 0..block_w {
+                    let i = y * self.lock_size.0 as usize + x;
+                    let c = resolve(&pixels[i], &splats[i]);
+                    let px = y * self.width * 4 + (x + block_x_start) * 4;
+                    for i in 0..4 {
+                        row[px + i] = c[i];
                     }
                 }
             }
         }
-        render
     }
+    /// Get the RGBW f32 pixels of the `dim` pixels starting at `start`, clipped to the image.
+    /// Returns the dimensions of the clipped region and its pixels, the weight of each pixel
+    /// has not been divided out, like `get_renderf32`
+    pub fn get_region(&self, start: (usize, usize), dim: (usize, usize)) -> ((usize, usize), Vec<f32>) {
+        let end = (cmp::min(start.0 + dim.0, self.width), cmp::min(start.1 + dim.1, self.height));
+        if start.0 >= end.0 || start.1 >= end.1 {
+            return ((0, 0), Vec::new());
+        }
+        let region_dim = (end.0 - start.0, end.1 - start.1);
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        let x_blocks = self.block_grid().0;
+        let mut render = Vec::with_capacity(region_dim.0 * region_dim.1 * 4);
+        for y in start.1..end.1 {
+            for x in start.0..end.0 {
+                let block_idx = (y / lock_size.1) * x_blocks + x / lock_size.0;
+                let pixels = self.pixels_locked[block_idx].lock().unwrap();
+                let splats = self.splat_locked[block_idx].lock().unwrap();
+                let i = (y % lock_size.1) * lock_size.0 + x % lock_size.0;
+                let c = resolve(&pixels[i], &splats[i]);
+                for i in 0..4 {
+                    render.push(c[i]);
+                }
+            }
+        }
+        (region_dim, render)
+    }
+    /// Get the number of blocks along x and y in the block grid covering the image
+    fn block_grid(&self) -> (usize, usize) {
+        let lock_size = (self.lock_size.0 as usize, self.lock_size.1 as usize);
+        ((self.width + lock_size.0 - 1) / lock_size.0, (self.height + lock_size.1 - 1) / lock_size.1)
+    }
+    /// Get the width and height of the part of the block starting at the pixel passed
+    /// which is inside the image. This is smaller than the block size for blocks on the
+    /// right and bottom edges of images that aren't evenly divided into blocks
+    fn block_extent(&self, block_x_start: usize, block_y_start: usize) -> (usize, usize) {
+        (cmp::min(self.lock_size.0 as usize, self.width - block_x_start),
+         cmp::min(self.lock_size.1 as usize, self.height - block_y_start))
+    }
+}
+
+/// Add the light splatted on to a pixel to its RGBW value, the splat is scaled by the
+/// pixel's weight so it's added directly to the pixel's value once the weight is divided out
+fn resolve(c: &Colorf, splat: &Colorf) -> Colorf {
+    Colorf::with_alpha(c.r + splat.r * c.a, c.g + splat.g * c.a, c.b + splat.b * c.a, c.a)
+}
+
+/// Average the summed AOVs of a pixel, returning the albedo RGB, normalized normal XYZ and depth
+fn resolve_aovs(a: &[f32; 9]) -> [f32; AOV_CHANNELS] {
+    let mut px = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, f32::INFINITY];
+    if a[6] > 0.0 {
+        let n = Normal::new(a[3], a[4], a[5]);
+        let n = if n.length_sqr() > 0.0 { n.normalized() } else { n };
+        for i in 0..3 {
+            px[i] = a[i] / a[6];
+            px[i + 3] = n[i];
+        }
+    }
+    if a[8] > 0.0 {
+        px[6] = a[7] / a[8];
+    }
+    px
+}
+
+/// Convert a filtered sample value to fixed point for deterministic accumulation
+fn to_fixed(x: f32) -> i64 {
+    (x as f64 * FIXED_POINT_SCALE).round() as i64
+}
+
+/// Convert a fixed point accumulated value back to floating point
+fn from_fixed(x: i64) -> f32 {
+    (x as f64 / FIXED_POINT_SCALE) as f32
+}
+
+#[test]
+fn test_deterministic_accumulation() {
+    use film::filter::MitchellNetravali;
+    // Write the same blocks of samples in opposite orders, the images should match exactly
+    let render = |reverse: bool| {
+        let filter = Box::new(MitchellNetravali::new(2.0, 2.0, 1.0 / 3.0, 1.0 / 3.0));
+        let mut rt = RenderTarget::new((8, 8), (2, 2), filter);
+        rt.set_deterministic(true);
+        let mut blocks: Vec<_> = (0..4).map(|b| {
+            let start = ((b % 2) * 4, (b / 2) * 4);
+            let samples: Vec<_> = (0..64).map(|i| {
+                let x = start.0 as f32 + (i % 8) as f32 * 0.5 + 0.1;
+                let y = start.1 as f32 + (i / 8) as f32 * 0.5 + 0.3;
+                ImageSample::new(x, y, Colorf::new(0.1 * i as f32, 0.37, 1.0 / (i + 1) as f32))
+            }).collect();
+            (Region::new(start, (4, 4)), samples)
+        }).collect();
+        if reverse {
+            blocks.reverse();
+        }
+        for &(ref region, ref samples) in &blocks {
+            rt.write(samples, region);
+        }
+        rt.get_renderf32()
+    };
+    let a = render(false);
+    let b = render(true);
+    assert!(a.iter().zip(b.iter()).all(|(x, y)| x.to_bits() == y.to_bits()));
+}
+
+#[test]
+fn test_splat() {
+    use film::filter::MitchellNetravali;
+    let filter = Box::new(MitchellNetravali::new(2.0, 2.0, 1.0 / 3.0, 1.0 / 3.0));
+    let mut rt = RenderTarget::new((8, 8), (2, 2), filter);
+    // Give every pixel some black samples so they have weight
+    let samples: Vec<_> = (0..64).map(|i| {
+        ImageSample::new((i % 8) as f32 + 0.5, (i / 8) as f32 + 0.5, Colorf::black())
+    }).collect();
+    rt.write(&samples, &Region::new((0, 0), (8, 8)));
+    // The splat should deposit exactly its color over the pixels around it
+    rt.splat(&ImageSample::new(4.2, 3.7, Colorf::new(1.0, 2.0, 3.0)));
+    let render = rt.get_renderf32();
+    let mut total = Colorf::black();
+    for px in render.chunks(4) {
+        assert!(px[3] > 0.0);
+        total = total + Colorf::new(px[0], px[1], px[2]) / px[3];
+    }
+    assert!(f32::abs(total.r - 1.0) < 1e-4 && f32::abs(total.g - 2.0) < 1e-4 && f32::abs(total.b - 3.0) < 1e-4);
+    rt.clear();
+    rt.write(&samples, &Region::new((0, 0), (8, 8)));
+    assert!(rt.get_renderf32().chunks(4).all(|px| px[0] == 0.0));
+}
+
+#[test]
+fn test_parallel_readout() {
+    use film::filter::MitchellNetravali;
+    // An image which isn't evenly divided into blocks, so the last row and column are partial
+    let filter = Box::new(MitchellNetravali::new(2.0, 2.0, 1.0 / 3.0, 1.0 / 3.0));
+    let rt = RenderTarget::new((13, 11), (4, 4), filter);
+    let samples: Vec<_> = (0..13 * 11).map(|i| {
+        let (x, y) = (i % 13, i / 13);
+        ImageSample::new(x as f32 + 0.5, y as f32 + 0.5, Colorf::new(x as f32, y as f32, 1.0))
+    }).collect();
+    rt.write(&samples, &Region::new((0, 0), (13, 11)));
+    let mut pool = Pool::new(3);
+    assert_eq!(rt.get_renderf32(), rt.get_renderf32_parallel(&mut pool));
+}
+
+#[test]
+fn test_grade_ldr_only() {
+    use film::filter::MitchellNetravali;
+    use film::Grade;
+    let filter = Box::new(MitchellNetravali::new(2.0, 2.0, 1.0 / 3.0, 1.0 / 3.0));
+    let mut rt = RenderTarget::new((4, 4), (2, 2), filter);
+    let samples: Vec<_> = (0..16).map(|i| {
+        ImageSample::new((i % 4) as f32 + 0.5, (i / 4) as f32 + 0.5, Colorf::broadcast(0.1))
+    }).collect();
+    rt.write(&samples, &Region::new((0, 0), (4, 4)));
+    let ungraded = rt.get_render();
+    rt.set_grade(Grade::new(1.0, 1.0, 1.0));
+    // The linear image is left as rendered while the 8-bit image is graded
+    for px in rt.get_renderf32().chunks(4) {
+        assert!(f32::abs(px[0] / px[3] - 0.1) < 1e-4);
+    }
+    assert!(rt.get_render().iter().zip(ungraded.iter()).all(|(g, u)| g > u));
 }
 
+#[test]
+fn test_id_mattes() {
+    use film::filter::MitchellNetravali;
+    use film::object_id::object_id;
+    let filter = Box::new(MitchellNetravali::new(2.0, 2.0, 1.0 / 3.0, 1.0 / 3.0));
+    let mut rt = RenderTarget::new((3, 2), (2, 2), filter);
+    rt.set_id_mattes(true);
+    let (a, b) = (object_id("sphere"), object_id("floor"));
+    // The sphere covers the left column, the floor the right and the middle column is split
+    // between the sphere, floor and background
+    let mut samples = Vec::new();
+    for y in 0..2 {
+        let y = y as f32 + 0.5;
+        for &id in &[Some(a), Some(a)] {
+            samples.push(IdSample::new(0.5, y, id));
+        }
+        for &id in &[Some(a), Some(b), None, None] {
+            samples.push(IdSample::new(1.5, y, id));
+        }
+        samples.push(IdSample::new(2.5, y, Some(b)));
+    }
+    rt.write_ids(&samples);
+    let mattes = rt.get_id_mattes();
+    assert_eq!(mattes.len(), 2);
+    let matte = |id| &mattes.iter().find(|m| m.0 == id).unwrap().1;
+    assert_eq!(matte(a), &vec![1.0, 0.25, 0.0, 1.0, 0.25, 0.0]);
+    assert_eq!(matte(b), &vec![0.0, 0.25, 1.0, 0.0, 0.25, 1.0]);
+    rt.clear();
+    assert!(rt.get_id_mattes().is_empty());
+}
+
+#[test]
+fn test_aov_readback() {
+    use film::filter::MitchellNetravali;
+    let filter = Box::new(MitchellNetravali::new(2.0, 2.0, 1.0 / 3.0, 1.0 / 3.0));
+    let mut rt = RenderTarget::new((3, 1), (2, 2), filter);
+    rt.set_aovs(true);
+    let up = Normal::new(0.0, 1.0, 0.0);
+    // The first pixel sees a surface at two depths, the second sees the surface once and misses
+    // once and the third sees nothing
+    rt.write_aovs(&[AovSample::new(0.5, 0.5, Colorf::broadcast(1.0), up, 2.0),
+                    AovSample::new(0.5, 0.5, Colorf::broadcast(1.0), up, 4.0),
+                    AovSample::new(1.5, 0.5, Colorf::broadcast(1.0), up, 5.0),
+                    AovSample::new(1.5, 0.5, Colorf::black(), Normal::broadcast(0.0), f32::INFINITY),
+                    AovSample::new(2.5, 0.5, Colorf::black(), Normal::broadcast(0.0), f32::INFINITY)]);
+    let image = rt.get_aov_image();
+    assert_eq!(image.len(), 3 * AOV_CHANNELS);
+    assert_eq!(&image[0..7], &[1.0, 1.0, 1.0, 0.0, 1.0, 0.0, 3.0]);
+    assert_eq!(&image[7..14], &[0.5, 0.5, 0.5, 0.0, 1.0, 0.0, 5.0]);
+    assert_eq!(&image[14..20], &[0.0; 6]);
+    assert_eq!(image[20], f32::INFINITY);
+    // Blocks are returned whole, including the pixels outside the image
+    let blocks = rt.get_aov_blocks(&[(2, 0)]);
+    assert_eq!(blocks.len(), 4 * AOV_CHANNELS);
+    assert_eq!(&blocks[0..7], &image[14..21]);
+    rt.set_aovs(false);
+    assert!(rt.get_aov_blocks(&[(0, 0)]).is_empty());
+}
@@ -0,0 +1,229 @@
+//! Provides the steps to turn the linear floating point image from the render target into
+//! a display ready 8-bit image. The steps are kept separate so callers can choose which
+//! they need, e.g. saving the linear image for HDR output while also showing a preview:
+//!
+//! 1. `RenderTarget::get_renderf32` returns the linear RGBW image.
+//! 2. `apply_tonemap` normalizes the pixels by their weight and maps them into [0, 1]
+//!    with the tonemapping operator chosen.
+//! 3. `encode_8bit` converts the tonemapped linear image to 24bpp sRGB.
+//!
+//! For animations the frames can also be automatically exposed before tonemapping with
+//! `AutoExposure`, which scales each frame so its log-average luminance matches a target key.
+//!
+//! # Scene Usage Example
+//! The film can pick the tonemapping operator applied when saving 8-bit images, by default
+//! the image is clamped. The `reinhard` operator compresses highlights instead, mapping the
+//! luminance `white` to 1 and leaving brighter pixels clipped, if no white point is given
+//! highlights are compressed but never reach 1.
+//!
+//! The film can also enable `auto_exposure`, which scales each frame so its log-average
+//! luminance is `key` (default 0.18, middle gray) before tonemapping. To avoid flickering in
+//! animations the exposure is smoothed across frames, `smoothing` is the fraction of the
+//! previous frame's exposure kept, in [0, 1), and defaults to 0.8. The exposure scales all
+//! outputs, including linear PFM images. Frames rendered out of order, e.g. with `--frame-step`
+//! or by distributed workers finishing frames in a different order, are smoothed in the order
+//! they're saved.
+//!
+//! ```json
+//! "film": {
+//!     ...
+//!     "tonemap": {
+//!         "type": "reinhard",
+//!         "white": 4.0
+//!     },
+//!     "auto_exposure": {
+//!         "key": 0.18,
+//!         "smoothing": 0.8
+//!     }
+//! }
+//! ```
+
+use std::f32;
+
+use film::Colorf;
+
+/// Small offset added to the luminance of each pixel when computing the log-average luminance,
+/// so black pixels don't send it to zero
+const LOG_AVERAGE_DELTA: f32 = 1e-4;
+/// Frames with a log-average luminance below this are treated as black by auto exposure
+const MIN_EXPOSED_LUMINANCE: f32 = 1e-3;
+
+/// The tonemapping operators available to map the linear image into [0, 1]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tonemap {
+    /// Clamp each channel to [0, 1]
+    Clamp,
+    /// The extended Reinhard operator `c (1 + c / white^2) / (1 + c)` applied to the luminance
+    /// of each pixel, which compresses highlights instead of clipping them. `white` is the
+    /// luminance mapped to 1, with an infinite white point this is the simple `c / (1 + c)`
+    Reinhard { white: f32 },
+}
+
+impl Tonemap {
+    /// Apply the tonemapping operator to the linear color
+    pub fn map(&self, c: &Colorf) -> Colorf {
+        match *self {
+            Tonemap::Clamp => c.clamp(),
+            Tonemap::Reinhard { white } => {
+                let l = c.luminance();
+                if l <= 0.0 {
+                    return Colorf::black();
+                }
+                let l_mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+                (*c * (l_mapped / l)).clamp()
+            },
+        }
+    }
+}
+
+impl Default for Tonemap {
+    fn default() -> Tonemap {
+        Tonemap::Clamp
+    }
+}
+
+/// Normalize the pixel by its weight, returning black if no samples were written to it
+pub fn normalize(pixels: &[f32], i: usize) -> Colorf {
+    let c = Colorf::with_alpha(pixels[i * 4], pixels[i * 4 + 1], pixels[i * 4 + 2], pixels[i * 4 + 3]);
+    if c.a > 0.0 {
+        c / c.a
+    } else {
+        Colorf::black()
+    }
+}
+
+/// Tonemap the RGBW f32 pixels, whose weights have not been divided out yet. Returns
+/// the linear RGB f32 pixels mapped into [0, 1]
+pub fn apply_tonemap(pixels: &[f32], tonemap: Tonemap) -> Vec<f32> {
+    let mut mapped = Vec::with_capacity(pixels.len() / 4 * 3);
+    for i in 0..pixels.len() / 4 {
+        let c = tonemap.map(&normalize(pixels, i));
+        for j in 0..3 {
+            mapped.push(c[j]);
+        }
+    }
+    mapped
+}
+
+/// Compute the log-average luminance of the RGBW f32 pixels, whose weights have not been divided
+/// out yet. Pixels with no samples are ignored, returns 0 if no pixels have samples
+pub fn log_average_luminance(pixels: &[f32]) -> f32 {
+    let mut log_sum = 0.0;
+    let mut count = 0;
+    for i in 0..pixels.len() / 4 {
+        if pixels[i * 4 + 3] > 0.0 {
+            log_sum += f32::ln(LOG_AVERAGE_DELTA + f32::max(normalize(pixels, i).luminance(), 0.0));
+            count += 1;
+        }
+    }
+    if count == 0 {
+        0.0
+    } else {
+        f32::exp(log_sum / count as f32)
+    }
+}
+
+/// Automatic exposure for animations, scaling each frame so its log-average luminance is the
+/// target key. The exposure is smoothed across frames to avoid flickering, so the same
+/// `AutoExposure` should be used for each frame of the animation in order
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoExposure {
+    /// The log-average luminance frames are scaled to
+    pub key: f32,
+    /// The fraction of the previous frame's exposure kept when computing the next one
+    pub smoothing: f32,
+    /// The log of the exposure scale applied to the previous frame, None before the first frame
+    log_scale: Option<f32>,
+}
+
+impl AutoExposure {
+    /// Create an auto exposure scaling frames to the log-average luminance `key`, keeping
+    /// `smoothing` of the previous frame's exposure for each frame. `smoothing` must be in [0, 1)
+    pub fn new(key: f32, smoothing: f32) -> AutoExposure {
+        assert!(key > 0.0, "Auto exposure key must be greater than 0");
+        assert!(smoothing >= 0.0 && smoothing < 1.0, "Auto exposure smoothing must be in [0, 1)");
+        AutoExposure { key: key, smoothing: smoothing, log_scale: None }
+    }
+    /// Compute the exposure scale for the next frame from its RGBW f32 pixels, smoothing it
+    /// with the previous frames. Frames with no light keep the previous exposure
+    pub fn update(&mut self, pixels: &[f32]) -> f32 {
+        let l_avg = log_average_luminance(pixels);
+        if l_avg < MIN_EXPOSED_LUMINANCE {
+            return self.log_scale.map_or(1.0, f32::exp);
+        }
+        let target = f32::ln(self.key / l_avg);
+        // Smooth in log space so brightening and darkening adapt at the same rate
+        let log_scale = match self.log_scale {
+            Some(prev) => self.smoothing * prev + (1.0 - self.smoothing) * target,
+            None => target,
+        };
+        self.log_scale = Some(log_scale);
+        f32::exp(log_scale)
+    }
+    /// Expose the next frame's RGBW f32 pixels, whose weights have not been divided out yet.
+    /// Returns the exposure scale applied
+    pub fn apply(&mut self, pixels: &mut [f32]) -> f32 {
+        let scale = self.update(pixels);
+        expose(pixels, scale);
+        scale
+    }
+}
+
+/// Scale the color of the RGBW f32 pixels by the exposure `scale`, leaving their weights unchanged
+pub fn expose(pixels: &mut [f32], scale: f32) {
+    for px in pixels.chunks_mut(4) {
+        for c in px.iter_mut().take(3) {
+            *c *= scale;
+        }
+    }
+}
+
+/// Convert the linear RGB f32 pixels in [0, 1] to 24bpp sRGB
+pub fn encode_8bit(pixels: &[f32]) -> Vec<u8> {
+    let mut img = Vec::with_capacity(pixels.len());
+    for px in pixels.chunks(3) {
+        let c = Colorf::new(px[0], px[1], px[2]).clamp().to_srgb();
+        for j in 0..3 {
+            img.push((c[j] * 255.0).round() as u8);
+        }
+    }
+    img
+}
+
+#[test]
+fn test_tonemap_pipeline() {
+    // One bright pixel written with weight 2 and one pixel with no samples
+    let pixels = [4.0, 1.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0];
+    let clamped = apply_tonemap(&pixels, Tonemap::Clamp);
+    assert_eq!(clamped, vec![1.0, 0.5, 0.0, 0.0, 0.0, 0.0]);
+    let reinhard = apply_tonemap(&pixels, Tonemap::Reinhard { white: 1000.0 });
+    assert!(reinhard.iter().all(|&x| x >= 0.0 && x <= 1.0));
+    // Highlights are compressed instead of clipped
+    assert!(reinhard[0] > reinhard[1] && reinhard[1] < clamped[1]);
+    let img = encode_8bit(&clamped);
+    assert_eq!(img.len(), 6);
+    assert_eq!(img[0], 255);
+    assert_eq!(&img[3..], &[0, 0, 0]);
+}
+
+#[test]
+fn test_auto_exposure() {
+    let frame = |l: f32| vec![l, l, l, 1.0, 2.0 * l, 2.0 * l, 2.0 * l, 2.0, 0.0, 0.0, 0.0, 0.0];
+    // The pixel with no samples is ignored
+    assert!(f32::abs(log_average_luminance(&frame(0.5)) - 0.5) < 1e-3);
+    assert_eq!(log_average_luminance(&[0.0; 8]), 0.0);
+    // Without smoothing each frame is exposed to the key
+    let mut exposure = AutoExposure::new(0.18, 0.0);
+    let mut pixels = frame(2.0);
+    exposure.apply(&mut pixels);
+    assert!(f32::abs(log_average_luminance(&pixels) - 0.18) < 1e-3);
+    assert_eq!(pixels[7], 2.0);
+    // With smoothing a sudden change in brightness is only partly compensated for
+    let mut smoothed = AutoExposure::new(0.18, 0.5);
+    let first = smoothed.update(&frame(1.0));
+    let second = smoothed.update(&frame(4.0));
+    assert!(f32::abs(first - 0.18) < 1e-3);
+    assert!(second < first && second > 0.18 / 4.0);
+    // Black frames keep the previous exposure
+    assert_eq!(smoothed.update(&frame(0.0)), second);
+}
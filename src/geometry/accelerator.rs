@@ -0,0 +1,79 @@
+//! Defines the `Accelerator` used to find the objects in the scene hit by rays, which is
+//! either a BVH, a uniform grid or a KD-tree. The BVH handles any scene well and is the default,
+//! the grid can be faster for scenes of many similar objects spread evenly through the scene
+//! and the KD-tree can be faster for large static meshes, though it's slower to build.
+//! See the Acceleration Structure section of the scene docs for how to pick one.
+
+use std::slice::{Iter, IterMut};
+
+use geometry::{BBox, Boundable, BVH, Grid, KdTree};
+use linalg::Ray;
+
+/// The types of acceleration structure the objects in the scene can be stored in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AcceleratorType {
+    BVH,
+    Grid,
+    KdTree,
+}
+
+/// An acceleration structure storing the objects in the scene
+pub enum Accelerator<T: Boundable> {
+    BVH(BVH<T>),
+    Grid(Grid<T>),
+    KdTree(KdTree<T>),
+}
+
+impl<T: Boundable> Accelerator<T> {
+    /// Re-build the acceleration structure for the time range passed
+    pub fn rebuild(&mut self, start: f32, end: f32) {
+        match *self {
+            Accelerator::BVH(ref mut b) => b.rebuild(start, end),
+            Accelerator::Grid(ref mut g) => g.rebuild(start, end),
+            Accelerator::KdTree(ref mut k) => k.rebuild(start, end),
+        }
+    }
+    /// Call the function passed on the objects the ray may hit, returning the value
+    /// returned by the function after traversal completes. See `BVH::intersect`
+    pub fn intersect<'a, F, R>(&'a self, ray: &mut Ray, f: F) -> Option<R>
+            where F: Fn(&mut Ray, &'a T) -> Option<R> {
+        match *self {
+            Accelerator::BVH(ref b) => b.intersect(ray, f),
+            Accelerator::Grid(ref g) => g.intersect(ray, f),
+            Accelerator::KdTree(ref k) => k.intersect(ray, f),
+        }
+    }
+    pub fn iter(&self) -> Iter<T> {
+        match *self {
+            Accelerator::BVH(ref b) => b.iter(),
+            Accelerator::Grid(ref g) => g.iter(),
+            Accelerator::KdTree(ref k) => k.iter(),
+        }
+    }
+    /// Get a mutable iterator over the objects, the acceleration structure must be
+    /// rebuilt if their bounds are changed
+    pub fn iter_mut(&mut self) -> IterMut<T> {
+        match *self {
+            Accelerator::BVH(ref mut b) => b.iter_mut(),
+            Accelerator::Grid(ref mut g) => g.iter_mut(),
+            Accelerator::KdTree(ref mut k) => k.iter_mut(),
+        }
+    }
+    /// Get the BVH if the objects are stored in one
+    pub fn bvh(&self) -> Option<&BVH<T>> {
+        match *self {
+            Accelerator::BVH(ref b) => Some(b),
+            Accelerator::Grid(_) | Accelerator::KdTree(_) => None,
+        }
+    }
+}
+
+impl<T: Boundable> Boundable for Accelerator<T> {
+    fn bounds(&self, start: f32, end: f32) -> BBox {
+        match *self {
+            Accelerator::BVH(ref b) => b.bounds(start, end),
+            Accelerator::Grid(ref g) => g.bounds(start, end),
+            Accelerator::KdTree(ref k) => k.bounds(start, end),
+        }
+    }
+}
@@ -26,6 +26,28 @@
 //!     ]
 //! }
 //! ```
+//!
+//! ## OBJ Sequence Example
+//! Simulation caches (cloth, fluids, etc.) are typically exported as a sequence of
+//! numbered OBJ files, one per frame. Instead of listing each keyframe the mesh can
+//! specify a `sequence` giving the file name pattern, where the run of `#` characters
+//! is replaced with the zero padded frame number, and the frame rate the sequence was
+//! exported at. The files numbered from `start` (default 0) to `end` are loaded, if
+//! no `end` is given files are loaded until the next one in the sequence doesn't exist.
+//! The frame numbered `start` is placed at time 0, or `start_time` if specified.
+//!
+//! ```json
+//! "geometry": {
+//!     "type": "animated_mesh",
+//!     "model": "Suzanne"
+//!     "sequence": {
+//!         "file": "./suzanne_####.obj",
+//!         "fps": 24,
+//!         "start": 1,
+//!         "end": 48
+//!     }
+//! }
+//! ```
 
 extern crate tobj;
 
@@ -104,6 +126,36 @@ impl AnimatedMeshData {
     }
 }
 
+/// Check if the meshes have the same topology, i.e. the same vertex attribute counts
+/// and triangles. Returns a description of the first difference found if they don't
+pub fn topology_mismatch(a: &Mesh, b: &Mesh) -> Option<String> {
+    let (ta, tb) = (a.accel.iter().next().unwrap(), b.accel.iter().next().unwrap());
+    if ta.positions.len() != tb.positions.len() {
+        return Some(format!("expected {} vertices, found {}", ta.positions.len(), tb.positions.len()));
+    }
+    if ta.normals.len() != tb.normals.len() {
+        return Some(format!("expected {} normals, found {}", ta.normals.len(), tb.normals.len()));
+    }
+    if ta.texcoords.len() != tb.texcoords.len() {
+        return Some(format!("expected {} texture coordinates, found {}", ta.texcoords.len(),
+                            tb.texcoords.len()));
+    }
+    // The BVH may order the triangles differently so compare the sorted indices
+    let indices = |m: &Mesh| {
+        let mut idx: Vec<_> = m.accel.iter().map(|t| (t.a, t.b, t.c)).collect();
+        idx.sort();
+        idx
+    };
+    let (ia, ib) = (indices(a), indices(b));
+    if ia.len() != ib.len() {
+        Some(format!("expected {} triangles, found {}", ia.len(), ib.len()))
+    } else if ia != ib {
+        Some("the triangle indices differ".to_owned())
+    } else {
+        None
+    }
+}
+
 /// An animated mesh composed of a series of meshes linearly interpolated between
 /// over time. It's assumed the mesh topology does not change.
 pub struct AnimatedMesh {
@@ -111,18 +163,21 @@ pub struct AnimatedMesh {
 }
 
 impl AnimatedMesh {
-    /// Create a new AnimatedMesh from the meshes passed. It's assumed the meshes
-    /// are sorted in ascending time
+    /// Create an animated mesh interpolating between the keyframe meshes at each time.
+    /// It's assumed the meshes are sorted in ascending time and share the same topology,
+    /// see `topology_mismatch` for validating them
     pub fn new(meshes: Vec<Arc<Mesh>>, times: Vec<f32>) -> AnimatedMesh {
-        let pos = meshes.iter().map(|m| m.bvh.iter().next().unwrap().positions.clone()).collect();
-        let normals = meshes.iter().map(|m| m.bvh.iter().next().unwrap().normals.clone()).collect();
-        let tex = meshes.iter().map(|m| m.bvh.iter().next().unwrap().texcoords.clone()).collect();
+        let pos = meshes.iter().map(|m| m.accel.iter().next().unwrap().positions.clone()).collect();
+        let normals = meshes.iter().map(|m| m.accel.iter().next().unwrap().normals.clone()).collect();
+        let tex = meshes.iter().map(|m| m.accel.iter().next().unwrap().texcoords.clone()).collect();
         let data = Arc::new(AnimatedMeshData::new(pos, normals, tex, times));
-        let tris = meshes[0].bvh.iter().map(|t| {
+        let tris = meshes[0].accel.iter().map(|t| {
             AnimatedTriangle::new(t.a, t.b, t.c, data.clone())
         }).collect();
+        // Build the BVH over the entire animation so it's valid at any time without re-building
+        let (start, end) = (data.times[0], *data.times.last().unwrap());
         AnimatedMesh {
-            bvh: BVH::new(16, tris, data.times[0], data.times[1]),
+            bvh: BVH::new(16, tris, start, end),
         }
     }
 }
@@ -175,12 +230,42 @@ impl Geometry for AnimatedTriangle {
 
 impl Boundable for AnimatedTriangle {
     fn bounds(&self, start: f32, end: f32) -> BBox {
-        BBox::singular(self.data.position(self.a, start))
+        let mut b = BBox::singular(self.data.position(self.a, start))
             .point_union(&self.data.position(self.b, start))
             .point_union(&self.data.position(self.c, start))
             .point_union(&self.data.position(self.a, end))
             .point_union(&self.data.position(self.b, end))
-            .point_union(&self.data.position(self.c, end))
+            .point_union(&self.data.position(self.c, end));
+        // The triangle moves linearly between keyframes so also include the keyframes
+        // we pass through during the interval
+        for (k, _) in self.data.times.iter().enumerate().filter(|&(_, t)| *t > start && *t < end) {
+            b = b.point_union(&(*self.data.positions[k])[self.a])
+                .point_union(&(*self.data.positions[k])[self.b])
+                .point_union(&(*self.data.positions[k])[self.c]);
+        }
+        b
     }
 }
 
+
+#[test]
+fn test_bounds_cover_keyframes() {
+    // A triangle that rests then moves up and back down, so the third keyframe is
+    // outside the bounds of the others
+    let keyframe = |y: f32| {
+        let pos = vec![Point::new(0.0, y, 0.0), Point::new(1.0, y, 0.0), Point::new(0.0, y + 1.0, 0.0)];
+        let normals = vec![Normal::new(0.0, 0.0, 1.0); 3];
+        let uvs = vec![Point::new(0.0, 0.0, 0.0); 3];
+        Arc::new(Mesh::new(Arc::new(pos), Arc::new(normals), Arc::new(uvs), vec![0, 1, 2]))
+    };
+    let mesh = AnimatedMesh::new(vec![keyframe(0.0), keyframe(0.0), keyframe(4.0), keyframe(0.0)],
+                                 vec![0.0, 1.0, 2.0, 3.0]);
+    let b = mesh.bounds(0.0, 3.0);
+    assert_eq!(b.min.y, 0.0);
+    assert_eq!(b.max.y, 5.0);
+    // The mesh can be hit at the raised keyframe without re-building its BVH
+    let mut ray = Ray::new(&Point::new(0.25, 4.25, 1.0), &Vector::new(0.0, 0.0, -1.0), 2.0);
+    assert!(mesh.intersect(&mut ray).is_some());
+    let mut ray = Ray::new(&Point::new(0.25, 4.25, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0);
+    assert!(mesh.intersect(&mut ray).is_none());
+}
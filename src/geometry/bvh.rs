@@ -1,13 +1,81 @@
 //! Provides a simple SAH split based BVH2 that stores types implementing the Boundable trait
 
-use std::f32;
+use std::{f32, cmp};
 use std::iter::repeat;
-use std::slice::Iter;
+use std::io::{self, Write};
+use std::slice::{Iter, IterMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use partition::partition;
 use geometry::{BBox, Boundable};
 use linalg::{Point, Ray, Axis, Vector};
 
+/// Count of BVH traversals performed, only tracked when built with the `bvh_stats` feature
+static TRAVERSALS: AtomicUsize = AtomicUsize::new(0);
+/// Count of BVH nodes visited during traversal
+static NODES_VISITED: AtomicUsize = AtomicUsize::new(0);
+/// Count of geometry in leaf nodes tested for intersection during traversal
+static GEOMETRY_TESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Aggregate statistics about BVH traversals, summed over all BVHs and threads. Nested
+/// BVHs (e.g. of a mesh's triangles) count as their own traversals. The statistics are
+/// only collected when tray_rust is built with the `bvh_stats` feature and will be zero otherwise.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TraversalStats {
+    /// Number of traversals of a BVH
+    pub traversals: usize,
+    /// Number of nodes visited during the traversals
+    pub nodes_visited: usize,
+    /// Number of pieces of geometry tested for intersection in the leaves
+    pub geometry_tests: usize,
+}
+
+impl TraversalStats {
+    /// Get the statistics collected since they were last reset
+    pub fn collected() -> TraversalStats {
+        TraversalStats { traversals: TRAVERSALS.load(Ordering::Relaxed),
+                         nodes_visited: NODES_VISITED.load(Ordering::Relaxed),
+                         geometry_tests: GEOMETRY_TESTS.load(Ordering::Relaxed) }
+    }
+    /// Reset the collected statistics to zero
+    pub fn reset() {
+        TRAVERSALS.store(0, Ordering::Relaxed);
+        NODES_VISITED.store(0, Ordering::Relaxed);
+        GEOMETRY_TESTS.store(0, Ordering::Relaxed);
+    }
+    /// Check if statistics are being collected
+    pub fn enabled() -> bool {
+        cfg!(feature = "bvh_stats")
+    }
+}
+
+/// Parameters controlling the SAH split search used when building the BVH
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SAHParams {
+    /// Number of buckets the geometry centroids are binned into along the split
+    /// axis, the split cost is evaluated between each pair of adjacent buckets
+    pub buckets: usize,
+    /// Relative cost of traversing an interior node
+    pub traversal_cost: f32,
+    /// Relative cost of intersecting a piece of geometry
+    pub intersection_cost: f32,
+}
+
+impl SAHParams {
+    /// Create SAH parameters using the bucket count and costs passed
+    pub fn new(buckets: usize, traversal_cost: f32, intersection_cost: f32) -> SAHParams {
+        assert!(buckets >= 2, "At least 2 SAH buckets are required to find a split");
+        SAHParams { buckets: buckets, traversal_cost: traversal_cost, intersection_cost: intersection_cost }
+    }
+}
+
+impl Default for SAHParams {
+    /// The default parameters bin into 12 buckets with a traversal cost of 1/8 that of an intersection
+    fn default() -> SAHParams {
+        SAHParams::new(12, 0.125, 1.0)
+    }
+}
+
 /// A standard BVH2 that stores objects that can report their bounds in some space
 /// via the `Boundable` trait. The BVH is constructed using a SAH partitioning scheme
 pub struct BVH<T: Boundable> {
@@ -22,6 +90,8 @@ pub struct BVH<T: Boundable> {
     tree: Vec<FlatNode>,
     /// Maximum amount of geometry we want to store per node
     max_geom: usize,
+    /// Parameters for the SAH split search
+    params: SAHParams,
 }
 
 impl<T: Boundable> BVH<T> {
@@ -32,6 +102,11 @@ impl<T: Boundable> BVH<T> {
     /// Create a new BVH using a SAH construction algorithm which holds the scene
     /// geometry for some time period.
     pub fn new(max_geom: usize, geometry: Vec<T>, start: f32, end: f32) -> BVH<T> {
+        BVH::with_params(max_geom, SAHParams::default(), geometry, start, end)
+    }
+    /// Create a new BVH holding the geometry for some time period, using the SAH
+    /// parameters passed to control the split search during construction
+    pub fn with_params(max_geom: usize, params: SAHParams, geometry: Vec<T>, start: f32, end: f32) -> BVH<T> {
         assert!(!geometry.is_empty());
         let mut flat_tree = Vec::new();
         let mut ordered_geom = Vec::with_capacity(geometry.len());
@@ -47,7 +122,7 @@ impl<T: Boundable> BVH<T> {
             // it knows the index of the items
             let mut total_nodes = 0;
             let root = Box::new(BVH::build(&mut build_geom[..], &mut ordered_geom, &mut total_nodes,
-                                  max_geom, start, end));
+                                  max_geom, &params, start, end));
             flat_tree.reserve(total_nodes);
             BVH::<T>::flatten_tree(&root, &mut flat_tree);
             assert_eq!(flat_tree.len(), total_nodes);
@@ -55,7 +130,8 @@ impl<T: Boundable> BVH<T> {
             // TODO: I'm not sure if there's a better way that we can re-sort the geometry by the
             // indices in ordered geom
         }
-        BVH { geometry: geometry, ordered_geom: ordered_geom, tree: flat_tree, max_geom: max_geom }
+        BVH { geometry: geometry, ordered_geom: ordered_geom, tree: flat_tree, max_geom: max_geom,
+              params: params }
     }
     /// Re-build the BVH for the time range passed
     pub fn rebuild(&mut self, start: f32, end: f32) {
@@ -72,7 +148,7 @@ impl<T: Boundable> BVH<T> {
         // it knows the index of the items
         let mut total_nodes = 0;
         let root = Box::new(BVH::build(&mut build_geom[..], &mut self.ordered_geom, &mut total_nodes,
-                              self.max_geom, start, end));
+                              self.max_geom, &self.params, start, end));
         self.tree.reserve(total_nodes);
         BVH::<T>::flatten_tree(&root, &mut self.tree);
     }
@@ -86,11 +162,15 @@ impl<T: Boundable> BVH<T> {
         let mut stack = [0; 64];
         let mut stack_ptr = 0;
         let mut current = 0;
+        let mut nodes_visited = 0;
+        let mut geometry_tests = 0;
         loop {
             let node = &self.tree[current];
+            nodes_visited += 1;
             if node.bounds.fast_intersect(ray, &inv_dir, &neg_dir) {
                 match node.node {
                     FlatNodeData::Leaf { ref geom_offset, ref ngeom } => {
+                        geometry_tests += *ngeom;
                         // Call function on all geometry in this leaf
                         for i in &self.ordered_geom[*geom_offset..*geom_offset + *ngeom] {
                             let o = &self.geometry[*i];
@@ -126,18 +206,64 @@ impl<T: Boundable> BVH<T> {
                 current = stack[stack_ptr];
             }
         }
+        if cfg!(feature = "bvh_stats") {
+            TRAVERSALS.fetch_add(1, Ordering::Relaxed);
+            NODES_VISITED.fetch_add(nodes_visited, Ordering::Relaxed);
+            GEOMETRY_TESTS.fetch_add(geometry_tests, Ordering::Relaxed);
+        }
         result
     }
     pub fn iter(&self) -> Iter<T> {
         self.geometry.iter()
     }
+    /// Get a mutable iterator over the geometry in the BVH. The BVH must be rebuilt
+    /// if the bounds of the geometry are changed
+    pub fn iter_mut(&mut self) -> IterMut<T> {
+        self.geometry.iter_mut()
+    }
+    /// Write the nodes of the BVH to `out` in a simple text format for debugging and visualization.
+    /// Each node is written on its own line in the order they're stored in the flattened tree,
+    /// the first child of an interior node is the node following it. Interior nodes are written as
+    /// `interior <depth> <min> <max> <second child> <split axis>` and leaves as
+    /// `leaf <depth> <min> <max> <geometry offset> <geometry count>`, where min and max are the
+    /// three components of the node's bounds.
+    pub fn write_nodes<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# tray_rust BVH with {} nodes and {} objects", self.tree.len(), self.geometry.len())?;
+        // The tree is flattened depth first, so after finishing a subtree the next node is
+        // the second child of the most recent interior node we haven't finished yet
+        let mut depth = 0;
+        let mut second_children = Vec::new();
+        for (i, node) in self.tree.iter().enumerate() {
+            if let Some(&(child, child_depth)) = second_children.last() {
+                if child == i {
+                    depth = child_depth;
+                    second_children.pop();
+                }
+            }
+            let b = &node.bounds;
+            match node.node {
+                FlatNodeData::Interior { second_child, axis } => {
+                    writeln!(out, "interior {} {} {} {} {} {} {} {} {:?}", depth, b.min.x, b.min.y, b.min.z,
+                             b.max.x, b.max.y, b.max.z, second_child, axis)?;
+                    second_children.push((second_child, depth + 1));
+                    depth += 1;
+                },
+                FlatNodeData::Leaf { geom_offset, ngeom } => {
+                    writeln!(out, "leaf {} {} {} {} {} {} {} {} {}", depth, b.min.x, b.min.y, b.min.z,
+                             b.max.x, b.max.y, b.max.z, geom_offset, ngeom)?;
+                },
+            }
+        }
+        Ok(())
+    }
     /// Construct the BVH tree using SAH splitting heuristic to determine split locations
     /// returns the root node of the subtree constructed over the slice of geom info passed
     /// and will increment `total_nodes` by the number of nodes in this subtree
     /// `ordered_geom` will be filled out with the indices of the geometry in the flattened
     /// tree ordering for more efficient access
     fn build(build_info: &mut [GeomInfo<T>], ordered_geom: &mut Vec<usize>,
-             total_nodes: &mut usize, max_geom: usize, start: f32, end: f32) -> BuildNode {
+             total_nodes: &mut usize, max_geom: usize, params: &SAHParams, start: f32, end: f32)
+             -> BuildNode {
         *total_nodes += 1;
         // Find bounding box for all geometry we're trying to store at this level
         let bounds = build_info.iter().fold(BBox::new(), |b, g| b.box_union(&g.geom.bounds(start, end)));
@@ -158,9 +284,9 @@ impl<T: Boundable> BVH<T> {
                 return BVH::build_leaf(&mut build_info[..], ordered_geom, bounds);
             } else {
                 let l = Box::new(BVH::build(&mut build_info[..mid], ordered_geom,
-                                            total_nodes, max_geom, start, end));
+                                            total_nodes, max_geom, params, start, end));
                 let r = Box::new(BVH::build(&mut build_info[mid..], ordered_geom,
-                                            total_nodes, max_geom, start, end));
+                                            total_nodes, max_geom, params, start, end));
                 return BuildNode::interior([l, r], split_axis);
             }
         }
@@ -177,19 +303,25 @@ impl<T: Boundable> BVH<T> {
                 }
             });
         } else {
-            // We only consider binning into 12 buckets
-            let mut buckets = [SAHBucket::new(); 12];
+            let mut buckets = vec![SAHBucket::new(); params.buckets];
+            // The same bucketing must be used when partitioning as when computing the split
+            // costs. Since the min and max centroids always land in the first and last buckets
+            // splitting between any pair of buckets gives two non-empty children
+            let nbuckets = buckets.len();
+            let extent = centroids.max[split_axis] - centroids.min[split_axis];
+            let bucket_index = |g: &GeomInfo<T>| {
+                let b = ((g.center[split_axis] - centroids.min[split_axis]) / extent
+                         * nbuckets as f32) as usize;
+                cmp::min(b, nbuckets - 1)
+            };
             // Place geometry into nearest bucket
             for g in build_info.iter() {
-                let b = ((g.center[split_axis] - centroids.min[split_axis])
-                         / (centroids.max[split_axis] - centroids.min[split_axis])
-                        * buckets.len() as f32) as usize;
-                let b = if b == buckets.len() { b - 1 } else { b };
+                let b = bucket_index(g);
                 buckets[b].count += 1;
                 buckets[b].bounds = buckets[b].bounds.box_union(&g.bounds);
             }
             // Compute cost of each bucket but the last using the surface area heuristic
-            let mut cost = [0.0; 11];
+            let mut cost = vec![0.0; buckets.len() - 1];
             for (i, c) in cost.iter_mut().enumerate() {
                 let left = buckets.iter().take(i + 1).fold(SAHBucket::new(), |mut s, b| {
                     s.bounds = s.bounds.box_union(&b.bounds);
@@ -201,33 +333,30 @@ impl<T: Boundable> BVH<T> {
                     s.count += b.count;
                     s
                 });
-                *c = 0.125 + (left.count as f32 * left.bounds.surface_area()
-                             + right.count as f32 * right.bounds.surface_area()) / bounds.surface_area();
+                *c = params.traversal_cost + params.intersection_cost
+                    * (left.count as f32 * left.bounds.surface_area()
+                       + right.count as f32 * right.bounds.surface_area()) / bounds.surface_area();
             }
             let (min_bucket, min_cost) = cost.iter().enumerate().fold((0, f32::INFINITY),
                 |(pi, pc), (i, c)| {
                     if *c < pc { (i, *c) } else { (pi, pc) }
                 });
             // If we're forced to split by the amount of geometry or it's cheaper to split, do so
-            if ngeom > max_geom || min_cost < ngeom as f32 {
-                mid = partition(build_info.iter_mut(),
-                    |g| {
-                        let b = ((g.center[split_axis] - centroids.min[split_axis])
-                                 / (centroids.max[split_axis] - centroids.min[split_axis])
-                                * buckets.len() as f32) as usize;
-                        let b = if b == buckets.len() { b - 1 } else { b };
-                        b <= min_bucket
-                    });
+            if ngeom > max_geom || min_cost < params.intersection_cost * ngeom as f32 {
+                // The partition index is the number of objects in buckets up to the split,
+                // which all go in the left child
+                mid = partition(build_info.iter_mut(), |g| bucket_index(g) <= min_bucket);
             }
             else {
                 return BVH::build_leaf(build_info, ordered_geom, bounds);
             }
         }
-        assert!(mid != 0 && mid != build_info.len());
+        assert!(mid != 0 && mid != build_info.len(), "BVH split produced an empty child, mid = {} of {}",
+                mid, build_info.len());
         let l = Box::new(BVH::build(&mut build_info[..mid], ordered_geom,
-                                    total_nodes, max_geom, start, end));
+                                    total_nodes, max_geom, params, start, end));
         let r = Box::new(BVH::build(&mut build_info[mid..], ordered_geom,
-                                    total_nodes, max_geom, start, end));
+                                    total_nodes, max_geom, params, start, end));
         BuildNode::interior([l, r], split_axis)
     }
     /// Construct a new leaf node containing the passed geometry. Indices will be
@@ -400,3 +529,64 @@ impl SAHBucket {
     }
 }
 
+
+#[test]
+fn test_adversarial_splits() {
+    use geometry::test_util::TestBox;
+    /// Check that every interior node in the subtree rooted at `node` has geometry in both
+    /// of its children, returns the amount of geometry in the subtree
+    fn check_subtree(tree: &[FlatNode], node: usize) -> usize {
+        match tree[node].node {
+            FlatNodeData::Leaf { ngeom, .. } => ngeom,
+            FlatNodeData::Interior { second_child, .. } => {
+                let left = check_subtree(tree, node + 1);
+                let right = check_subtree(tree, second_child);
+                assert!(left > 0 && right > 0, "Interior node {} has an empty child", node);
+                left + right
+            },
+        }
+    }
+    let make_box = |c: Point, r: f32| TestBox::new(c, r, 0);
+    let mut scenes = Vec::new();
+    // All coplanar
+    scenes.push((0..400).map(|i| make_box(Point::new((i % 20) as f32, (i / 20) as f32, 0.0), 0.5))
+                .collect::<Vec<_>>());
+    // Two tight clusters far apart
+    scenes.push((0..300).map(|i| {
+            let offset = if i % 2 == 0 { 0.0 } else { 1000.0 };
+            make_box(Point::broadcast(offset + i as f32 * 1e-5), 0.1)
+        }).collect());
+    // Identical centers with different sizes
+    scenes.push((0..50).map(|i| make_box(Point::broadcast(3.0), 1.0 + i as f32)).collect());
+    // Tiny spacing far from the origin
+    scenes.push((0..200).map(|i| make_box(Point::new(1e5 + i as f32 * 1e-2, 0.0, 0.0), 1e-3)).collect());
+    for geom in scenes {
+        let n = geom.len();
+        let bvh = BVH::unanimated(4, geom);
+        assert_eq!(check_subtree(&bvh.tree, 0), n);
+        let mut ordered = bvh.ordered_geom.clone();
+        ordered.sort();
+        assert!(ordered.iter().enumerate().all(|(i, g)| i == *g));
+    }
+}
+
+#[test]
+fn test_write_nodes() {
+    use geometry::test_util::make_boxes;
+    let geom = make_boxes((16, 1, 1), Vector::broadcast(1.0), &|_| 0.25);
+    let bvh = BVH::unanimated(2, geom);
+    let mut out = Vec::new();
+    bvh.write_nodes(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let nodes: Vec<_> = text.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(nodes.len(), bvh.tree.len());
+    assert!(nodes[0].starts_with("interior 0 "));
+    // Every object is referenced by exactly one leaf, and leaves are below the root
+    let mut total = 0;
+    for l in nodes.iter().filter(|l| l.starts_with("leaf")) {
+        let fields: Vec<_> = l.split_whitespace().collect();
+        assert!(fields[1].parse::<usize>().unwrap() > 0);
+        total += fields[9].parse::<usize>().unwrap();
+    }
+    assert_eq!(total, 16);
+}
@@ -0,0 +1,141 @@
+//! Defines a Cone type which implements the Geometry and Boundable traits.
+//! The cone's base is a circle in the XY plane centered at the origin and the
+//! apex is on the +Z axis at the cone's height. The base of the cone is open.
+//!
+//! The normal at the apex is undefined so hits there use the limiting normal
+//! of the slant approaching the apex from the hit's side of the cone, keeping
+//! the normal and shading frame valid right up to the tip.
+//!
+//! # Scene Usage Example
+//! The cone takes the radius of its base and its height.
+//!
+//! ```json
+//! "geometry": {
+//!     "type": "cone",
+//!     "radius": 1.0,
+//!     "height": 2.0
+//! }
+//! ```
+
+use std::f32;
+
+use geometry::{Geometry, DifferentialGeometry, Boundable, BBox};
+use linalg::{self, Normal, Vector, Ray, Point};
+
+/// Smallest radius of the ring around the cone used to compute the surface derivatives,
+/// relative to the cone's radius, so they don't vanish at the apex
+const MIN_RING_RADIUS: f32 = 1e-4;
+
+/// A cone with its base in the XY plane and apex along +Z
+#[derive(Clone, Copy)]
+pub struct Cone {
+    radius: f32,
+    height: f32,
+}
+
+impl Cone {
+    /// Create a cone with the base radius and height passed
+    pub fn new(radius: f32, height: f32) -> Cone {
+        assert!(radius > 0.0 && height > 0.0, "Cone radius and height must be greater than 0");
+        Cone { radius: radius, height: height }
+    }
+    /// Check that the point on the infinite cone is on the part of the cone we have,
+    /// allowing for some rounding error in hits on the apex
+    fn in_range(&self, p: &Point) -> bool {
+        p.z >= 0.0 && p.z <= self.height * (1.0 + 1e-5)
+    }
+}
+
+impl Geometry for Cone {
+    fn intersect(&self, ray: &mut Ray) -> Option<DifferentialGeometry> {
+        // Intersect the ray with the infinite double cone x^2 + y^2 = k * (z - h)^2
+        let k = (self.radius / self.height) * (self.radius / self.height);
+        let oz = ray.o.z - self.height;
+        let a = ray.d.x * ray.d.x + ray.d.y * ray.d.y - k * ray.d.z * ray.d.z;
+        let b = 2.0 * (ray.d.x * ray.o.x + ray.d.y * ray.o.y - k * ray.d.z * oz);
+        let c = ray.o.x * ray.o.x + ray.o.y * ray.o.y - k * oz * oz;
+        // Rays parallel to the slant of the cone only cross it once
+        let t = if f32::abs(a) < 1e-8 {
+            if b == 0.0 {
+                return None;
+            }
+            (-c / b, f32::INFINITY)
+        } else {
+            // Rays through the apex touch the cone at a double root, allow for some
+            // rounding error in the discriminant so we don't miss them
+            let discrim_sqr = b * b - 4.0 * a * c;
+            if discrim_sqr < -1e-5 * b * b {
+                return None;
+            }
+            let discrim = f32::sqrt(f32::max(discrim_sqr, 0.0));
+            let q = if b < 0.0 { -0.5 * (b - discrim) } else { -0.5 * (b + discrim) };
+            if q == 0.0 {
+                return None;
+            }
+            let (t0, t1) = (q / a, c / q);
+            if t0 < t1 { (t0, t1) } else { (t1, t0) }
+        };
+        // Find the first t value within the ray's range hitting the part of the cone we have
+        let t_hit = if t.0 >= ray.min_t && t.0 <= ray.max_t && self.in_range(&ray.at(t.0)) {
+            t.0
+        } else if t.1 >= ray.min_t && t.1 <= ray.max_t && self.in_range(&ray.at(t.1)) {
+            t.1
+        } else {
+            return None;
+        };
+        ray.max_t = t_hit;
+        let p = ray.at(t_hit);
+        // At the apex phi is undefined, atan2 returns 0 there which picks a valid limiting normal
+        let mut phi = f32::atan2(p.y, p.x);
+        if phi < 0.0 {
+            phi += f32::consts::PI * 2.0;
+        }
+        let (sin_phi, cos_phi) = (f32::sin(phi), f32::cos(phi));
+        let u = phi / (2.0 * f32::consts::PI);
+        let v = linalg::clamp(p.z / self.height, 0.0, 1.0);
+        let ring = f32::max(self.radius * (1.0 - v), MIN_RING_RADIUS * self.radius);
+        let dp_du = Vector::new(-sin_phi, cos_phi, 0.0) * (2.0 * f32::consts::PI * ring);
+        let dp_dv = Vector::new(-self.radius * cos_phi, -self.radius * sin_phi, self.height);
+        // The outward normal cross(dp_du, dp_dv) depends only on phi
+        let n = Normal::new(self.height * cos_phi, self.height * sin_phi, self.radius);
+        Some(DifferentialGeometry::new(&p, &n, u, v, ray.time, &dp_du, &dp_dv, self))
+    }
+}
+
+impl Boundable for Cone {
+    fn bounds(&self, _: f32, _: f32) -> BBox {
+        BBox::span(Point::new(-self.radius, -self.radius, 0.0),
+                   Point::new(self.radius, self.radius, self.height))
+    }
+}
+
+#[test]
+fn test_cone_normals() {
+    let cone = Cone::new(1.0, 2.0);
+    let expected = Normal::new(2.0, 0.0, 1.0).normalized();
+    // Hit the slant from the +X side, the normal points outwards and up the slant
+    let mut ray = Ray::new(&Point::new(4.0, 0.0, 1.0), &Vector::new(-1.0, 0.0, 0.0), 0.0);
+    let dg = cone.intersect(&mut ray).expect("Ray should hit the cone's slant");
+    assert!(f32::abs(ray.max_t - 3.5) < 1e-5);
+    assert!(f32::abs(linalg::dot(&dg.n, &expected) - 1.0) < 1e-4);
+    assert!(f32::abs(linalg::dot(&dg.ng, &expected) - 1.0) < 1e-4);
+    assert!(f32::abs(dg.v - 0.5) < 1e-5);
+    // Rays hitting exactly at the apex get a valid unit normal facing up the cone
+    for d in &[Vector::new(0.0, 0.0, -1.0), Vector::new(-1.0, 0.0, -1.0).normalized()] {
+        let o = Point::new(0.0, 0.0, 2.0) - *d * 3.0;
+        let mut ray = Ray::new(&o, d, 0.0);
+        let dg = cone.intersect(&mut ray).expect("Ray should hit the cone's apex");
+        assert!(dg.p.distance(&Point::new(0.0, 0.0, 2.0)) < 1e-4);
+        for n in &[dg.n, dg.ng] {
+            assert!(n.x.is_finite() && n.y.is_finite() && n.z.is_finite());
+            assert!(f32::abs(n.length() - 1.0) < 1e-4 && n.z > 0.0);
+        }
+        assert!(dg.dp_du.length() > 0.0);
+    }
+    // Rays passing above the apex or through the open base miss or hit the inside
+    let mut ray = Ray::new(&Point::new(4.0, 0.0, 2.5), &Vector::new(-1.0, 0.0, 0.0), 0.0);
+    assert!(cone.intersect(&mut ray).is_none());
+    let mut ray = Ray::new(&Point::new(0.0, 0.0, -1.0), &Vector::new(0.0, 0.0, 1.0), 0.0);
+    let dg = cone.intersect(&mut ray).expect("Ray through the base should hit the apex from inside");
+    assert!(f32::abs(ray.max_t - 3.0) < 1e-4 && dg.n.z > 0.0);
+}
@@ -13,6 +13,10 @@ pub struct DifferentialGeometry<'a> {
     pub n: Normal,
     /// The geometry normal
     pub ng: Normal,
+    /// The normal of the face that was hit. For triangle meshes this follows the triangle's
+    /// counter-clockwise winding regardless of the normals in the mesh, for other geometry
+    /// it's the geometry normal
+    pub face_normal: Normal,
     /// Surface parameterization u, v for texture mapping
     pub u: f32,
     pub v: f32,
@@ -33,10 +37,12 @@ impl<'a> DifferentialGeometry<'a> {
                dp_du: &Vector, dp_dv: &Vector, geom: &'a (Geometry + 'a)) -> DifferentialGeometry<'a>
     {
         let n = linalg::cross(dp_du, dp_dv).normalized();
+        let ng = ng.normalized();
         DifferentialGeometry {
             p: *p,
             n: Normal::new(n.x, n.y, n.z),
-            ng: ng.normalized(),
+            ng: ng,
+            face_normal: ng,
             u: u,
             v: v,
             time: time,
@@ -54,6 +60,7 @@ impl<'a> DifferentialGeometry<'a> {
             p: *p,
             n: nn,
             ng: nn,
+            face_normal: nn,
             u: u,
             v: v,
             time: time,
@@ -84,7 +84,16 @@ impl Boundable for Disk {
 impl Sampleable for Disk {
     fn sample_uniform(&self, samples: &(f32, f32)) -> (Point, Normal) {
         let disk_pos = mc::concentric_sample_disk(samples);
-        let p = Point::new(disk_pos.0 * self.radius, disk_pos.1 * self.radius, 0.0);
+        // Remap the radius of the sample on the unit disk so points are
+        // uniformly distributed over the ring between the inner and outer radius
+        let r_sqr = disk_pos.0 * disk_pos.0 + disk_pos.1 * disk_pos.1;
+        let scale = if r_sqr > 0.0 {
+            let inner_sqr = self.inner_radius * self.inner_radius;
+            f32::sqrt((inner_sqr + r_sqr * (self.radius * self.radius - inner_sqr)) / r_sqr)
+        } else {
+            self.radius
+        };
+        let p = Point::new(disk_pos.0 * scale, disk_pos.1 * scale, 0.0);
         let n = Normal::new(0.0, 0.0, 1.0);
         (p, n)
     }
@@ -110,3 +119,9 @@ impl Sampleable for Disk {
     }
 }
 
+#[test]
+fn test_pdf_integrates_to_one() {
+    use geometry::test_util::assert_pdf_integrates_to_one;
+    let points = [Point::new(0.3, -0.2, 1.5), Point::new(0.0, 0.0, -2.0)];
+    assert_pdf_integrates_to_one(&Disk::new(1.0, 0.25), &points);
+}
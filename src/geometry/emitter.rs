@@ -7,8 +7,10 @@
 //! to set the color and strength of emitted light.
 //!
 //! ## Point Light Example
-//! The point light has no geometry, material or transformation since it's not a
-//! physical object. Instead it simply takes a position to place the light at in the scene.
+//! The point light has no geometry or material since it's not a physical object. It's placed
+//! at the origin and moved into the scene by the standard `transform` or `keyframes` block
+//! used by other objects, so point lights can follow animated paths as well. Any rotation
+//! or scaling in the transform has no effect on the light.
 //!
 //! ```json
 //! "objects": [
@@ -56,14 +58,148 @@
 //!     ...
 //! ]
 //! ```
+//!
+//! ## Dome Light Example
+//! The dome light is infinitely far away and emits light from the hemisphere above the
+//! scene, a simple procedural sky for lighting studio style renders. The radiance blends
+//! from the `top` color at the zenith to the `bottom` color at the horizon, if no bottom
+//! color is given the dome is uniform. Both colors are multiplied by the optional `strength`.
+//! Directions below the horizon receive no light. The dome's up direction is +Y and can be
+//! rotated by an optional `transform` or `keyframes`, translation and scaling have no
+//! effect. Rays escaping the scene see the dome, and it's sampled like any other light
+//! so it doesn't need a material or geometry. Dome lights only support radiance emission.
+//! For interiors lit through windows, portals can be placed over the openings to sample
+//! the dome through them, see geometry/portal.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "sky",
+//!         "type": "emitter",
+//!         "emitter": "dome",
+//!         "top": [0.6, 0.75, 1.0],
+//!         "bottom": [1.0, 1.0, 1.0],
+//!         "strength": 2
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! ## Sky Light Example
+//! The sky light is an analytic daylight sky, Preetham et al.'s model, computed from the
+//! direction of the sun and the `turbidity` of the atmosphere for quick outdoor lighting
+//! without an HDR environment map. The turbidity must be in [2, 10], from a clear sky at 2 to
+//! a hazy one at 10, and defaults to 3. The sun direction points towards the sun in the sky's
+//! space, which like the dome has +Y up, and must not be below the horizon. The sky itself
+//! is fairly bright, its radiance is the sky's luminance in kcd/m^2, and can be scaled by
+//! an optional `strength`. The sky is importance sampled, favoring the bright regions around
+//! the sun and the horizon. The sky light otherwise behaves like the dome light, it can be
+//! rotated and sampled through portals. The sun itself isn't part of the sky and should be
+//! added as another light if needed. Giving the dome or sky `keyframes` instead of a
+//! `transform` rotates it over time, eg. to spin the sky or move the sun across the frames
+//! of a time lapse. Rays escaping the scene and light samples see it as rotated at the ray's time.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "sky",
+//!         "type": "emitter",
+//!         "emitter": "sky",
+//!         "sun_direction": [0.5, 0.4, -0.3],
+//!         "turbidity": 3,
+//!         "strength": 0.5
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! ## Emission Units
+//! By default the emission is the radiance emitted by the light (or intensity for point
+//! lights), so an area light's brightness doesn't depend on its size. An `emission_unit` can
+//! be given to specify the strength of the emission as the total power of the light instead,
+//! which is converted to radiance when the scene is loaded using the area of the light's
+//! geometry and its spread. The supported units are `radiance` (the default), `watts` and
+//! `lumens`, which are converted to watts using a luminous efficacy of 683 lm/W. Note that
+//! the area used is that of the geometry before it's transformed into the scene.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "my_panel",
+//!         "type": "emitter",
+//!         "emitter": "area",
+//!         "emission": [1, 1, 1, 100],
+//!         "emission_unit": "watts",
+//!         ...
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! ## Spread Example
+//! Area lights can optionally limit the directions they emit light in with a `spread`,
+//! similar to barn doors or louvers on a softbox. The spread is the half-angle in degrees of
+//! the cone around the surface normal that light is emitted in, and must be in (0, 90]. The
+//! default of 90 emits over the full hemisphere. This is mainly useful with rectangle lights.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "my_softbox",
+//!         "type": "emitter",
+//!         "emitter": "area",
+//!         "emission": [1, 1, 1, 100],
+//!         "spread": 45,
+//!         "material": "white_matte",
+//!         "geometry": {
+//!             "type": "rectangle",
+//!             "width": 4,
+//!             "height": 2
+//!         },
+//!         "transform": [
+//!             {
+//!                 "type": "translate",
+//!                 "translation": [0, 0, 22]
+//!             }
+//!         ]
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! ## Front and Back Emission
+//! Area lights only emit from the front of their surface, the side their normal faces. A
+//! different color can be emitted from the back by giving the `emission` as an object with
+//! `front` and `back` colors, e.g. for a sign glowing one color on each side. If no `back`
+//! is given the back doesn't emit, as before. The back emits in the same `spread` around the
+//! flipped normal and each side's emission is converted from its `emission_unit` separately.
+//! Point lights don't have a back and only accept a single emission color.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "my_sign",
+//!         "type": "emitter",
+//!         "emitter": "area",
+//!         "emission": {
+//!             "front": [1, 0.2, 0.2, 20],
+//!             "back": [0.2, 0.2, 1, 10]
+//!         },
+//!         ...
+//!     },
+//!     ...
+//! ]
+//! ```
 
+use std::{cmp, f32};
 use std::sync::Arc;
 
-use geometry::{Boundable, BBox, SampleableGeom, DifferentialGeometry};
+use geometry::{Boundable, BBox, SampleableGeom, DifferentialGeometry, Portal};
 use material::Material;
 use linalg::{self, AnimatedTransform, Point, Ray, Vector, Normal};
-use film::{AnimatedColor, Colorf};
-use light::{Light, OcclusionTester};
+use film::{AnimatedColor, ColorKeyframe, Colorf};
+use light::{Light, OcclusionTester, Sky};
+use mc;
 
 /// The type of emitter, either a point light or an area light
 /// in which case the emitter has associated geometry and a material
@@ -73,6 +209,37 @@ enum EmitterType {
     /// The area light holds the geometry that is emitting the light
     /// and the material for the geometry
     Area(Arc<SampleableGeom + Send + Sync>, Arc<Material + Send + Sync>),
+    /// The dome light emits the emitter's emission at the zenith, blending to
+    /// the color held here at the horizon. If it has portals they're used to
+    /// sample the dome
+    Dome(AnimatedColor, Vec<Portal>),
+    /// The sky light emits the analytic sky scaled by the emitter's emission. If it has
+    /// portals they're used to sample the sky
+    Sky(Sky, Vec<Portal>),
+}
+
+/// The units the strength of an emitter's emission can be specified in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EmissionUnit {
+    /// The emission is the radiance (or intensity for point lights) emitted
+    Radiance,
+    /// The emission is the total power emitted by the light in watts
+    Watts,
+    /// The emission is the total luminous flux emitted by the light in lumens
+    Lumens,
+}
+
+impl EmissionUnit {
+    /// Parse the emission unit from its name in the scene file, returns None if the
+    /// unit is unrecognized
+    pub fn from_str(unit: &str) -> Option<EmissionUnit> {
+        match unit {
+            "radiance" => Some(EmissionUnit::Radiance),
+            "watts" => Some(EmissionUnit::Watts),
+            "lumens" => Some(EmissionUnit::Lumens),
+            _ => None,
+        }
+    }
 }
 
 /// An instance of geometry in the scene that receives and emits light.
@@ -80,8 +247,15 @@ pub struct Emitter {
     emitter: EmitterType,
     /// The light intensity emitted
     pub emission: AnimatedColor,
+    /// The radiance emitted from the back of an area light, if None the back doesn't emit
+    back_emission: Option<AnimatedColor>,
+    /// Name of the area light's material in the scene file, empty for point lights or
+    /// if the material wasn't loaded from one
+    pub material_name: String,
     /// The transform to world space
     transform: AnimatedTransform,
+    /// Cosine of the half-angle of the cone around the normal that light is emitted in
+    cos_spread: f32,
     /// Tag to identify the instance
     pub tag: String,
 }
@@ -96,12 +270,15 @@ impl Emitter {
         // TODO: How to change this transform to handle scaling within the animation?
         /*
         if transform.has_scale() {
-            println!("Warning: scaling detected in area light transform, this may give incorrect results");
+            warn!("Scaling detected in area light transform, this may give incorrect results");
         }
         */
         Emitter { emitter: EmitterType::Area(geom, material),
                   emission: emission,
+                  back_emission: None,
+                  material_name: String::new(),
                   transform: transform,
+                  cos_spread: 0.0,
                   tag: tag }
     }
     /// Create a point light at the origin that is transformed by `transform` to its location
@@ -109,15 +286,55 @@ impl Emitter {
     pub fn point(transform: AnimatedTransform, emission: AnimatedColor, tag: String) -> Emitter {
         Emitter { emitter: EmitterType::Point,
                   emission: emission,
+                  back_emission: None,
+                  material_name: String::new(),
                   transform: transform,
+                  cos_spread: 0.0,
                   tag: tag }
     }
+    /// Create a dome light surrounding the scene, emitting `top` at the zenith and
+    /// blending to `bottom` at the horizon. The dome's up direction is +Y rotated by `transform`
+    pub fn dome(transform: AnimatedTransform, top: AnimatedColor, bottom: AnimatedColor, tag: String) -> Emitter {
+        Emitter { emitter: EmitterType::Dome(bottom, Vec::new()),
+                  emission: top,
+                  back_emission: None,
+                  material_name: String::new(),
+                  transform: transform,
+                  cos_spread: 0.0,
+                  tag: tag }
+    }
+    /// Create a sky light surrounding the scene emitting the analytic `sky` scaled by `strength`.
+    /// The sky's up direction is +Y rotated by `transform`
+    pub fn sky(transform: AnimatedTransform, sky: Sky, strength: f32, tag: String) -> Emitter {
+        let emission = AnimatedColor::with_keyframes(vec![ColorKeyframe::new(&Colorf::broadcast(strength), 0.0)]);
+        Emitter { emitter: EmitterType::Sky(sky, Vec::new()),
+                  emission: emission,
+                  back_emission: None,
+                  material_name: String::new(),
+                  transform: transform,
+                  cos_spread: 0.0,
+                  tag: tag }
+    }
+    /// Check if the emitter is a dome light, or a sky light which is a dome showing the sky
+    pub fn is_dome(&self) -> bool {
+        match self.emitter {
+            EmitterType::Dome(..) | EmitterType::Sky(..) => true,
+            _ => false,
+        }
+    }
+    /// Set the portals to sample the dome light through, panics if the emitter isn't a dome light
+    pub fn set_portals(&mut self, portals: Vec<Portal>) {
+        match self.emitter {
+            EmitterType::Dome(_, ref mut p) | EmitterType::Sky(_, ref mut p) => *p = portals,
+            _ => panic!("Only dome lights can be sampled through portals, {} is not a dome light", self.tag),
+        }
+    }
     /// Test the ray for intersection against this insance of geometry.
     /// returns Some(Intersection) if an intersection was found and None if not.
     /// If an intersection is found `ray.max_t` will be set accordingly
     pub fn intersect(&self, ray: &mut Ray) -> Option<(DifferentialGeometry, &Material)> {
         match self.emitter {
-            EmitterType::Point => None,
+            EmitterType::Point | EmitterType::Dome(..) | EmitterType::Sky(..) => None,
             EmitterType::Area(ref geom, ref mat) => {
                 let transform = self.transform.transform(ray.time);
                 let mut local = transform.inv_mul_ray(ray);
@@ -129,16 +346,117 @@ impl Emitter {
                 dg.p = transform * dg.p;
                 dg.n = transform * dg.n;
                 dg.ng = transform * dg.ng;
+                dg.face_normal = transform * dg.face_normal;
                 dg.dp_du = transform * dg.dp_du;
                 dg.dp_dv = transform * dg.dp_dv;
                 Some((dg, &**mat))
             },
         }
     }
+    /// Check if the emitter emits no light at any time from either side
+    pub fn is_black(&self) -> bool {
+        self.emission.is_black() && self.back_emission.as_ref().map_or(true, |b| b.is_black())
+    }
+    /// Set the radiance emitted from the back of the area light, panics if the emitter
+    /// isn't an area light
+    pub fn set_back_emission(&mut self, back: AnimatedColor) {
+        match self.emitter {
+            EmitterType::Area(..) => self.back_emission = Some(back),
+            _ => panic!("Only area lights can emit from their back, {} is not an area light", self.tag),
+        }
+    }
+    /// Limit the light emitted to a cone with half-angle `spread` in degrees around the
+    /// surface normal. A spread of 90 emits over the entire hemisphere
+    pub fn set_spread(&mut self, spread: f32) {
+        if spread <= 0.0 || spread > 90.0 {
+            panic!("Emitter spread must be in (0, 90] degrees, got {}", spread);
+        }
+        self.cos_spread = if spread == 90.0 { 0.0 } else { f32::cos(linalg::to_radians(spread)) };
+    }
+    /// Convert the emission from the unit passed to radiance (or intensity for point lights)
+    /// based on the area and spread of the light. This should be called after setting the
+    /// spread, since the spread changes the power emitted for some radiance
+    pub fn convert_emission(&mut self, unit: EmissionUnit) {
+        let watts = match unit {
+            EmissionUnit::Radiance => return,
+            EmissionUnit::Watts => 1.0,
+            EmissionUnit::Lumens => 1.0 / 683.0,
+        };
+        let power_per_radiance = match self.emitter {
+            // A point light emits its intensity uniformly over the sphere of directions
+            EmitterType::Point => 4.0 * f32::consts::PI,
+            // An area light emits radiance from each point in the cone around the normal,
+            // the projected solid angle of the cone is pi * sin^2 of the spread
+            EmitterType::Area(ref g, _) => {
+                let sin_spread_sqr = 1.0 - self.cos_spread * self.cos_spread;
+                f32::consts::PI * sin_spread_sqr * g.surface_area()
+            },
+            // The dome is infinitely large so its power isn't meaningful
+            EmitterType::Dome(..) | EmitterType::Sky(..) => {
+                panic!("Dome light {} only supports radiance emission", self.tag)
+            },
+        };
+        self.emission.scale(watts / power_per_radiance);
+        if let Some(ref mut b) = self.back_emission {
+            b.scale(watts / power_per_radiance);
+        }
+    }
     /// Return the radiance emitted by the light in the direction `w`
-    /// from point `p` on the light's surface with normal `n`
+    /// from point `p` on the light's surface with normal `n`. The front emission is
+    /// seen from the side the normal faces and the back emission, if any, from the other.
+    /// No light is emitted in directions outside the spread of the emitter
     pub fn radiance(&self, w: &Vector, _: &Point, n: &Normal, time: f32) -> Colorf {
-        if linalg::dot(w, n) > 0.0 { self.emission.color(time) } else { Colorf::black() }
+        let cos_theta = linalg::dot(&w.normalized(), &n.normalized());
+        if cos_theta > self.cos_spread {
+            self.emission.color(time)
+        } else if -cos_theta > self.cos_spread {
+            match self.back_emission {
+                Some(ref b) => b.color(time),
+                None => Colorf::black(),
+            }
+        } else {
+            Colorf::black()
+        }
+    }
+    /// Return the radiance emitted by the dome or sky light towards the scene from the direction
+    /// `w` pointing out of the scene, black for other lights
+    fn dome_radiance(&self, w: &Vector, time: f32) -> Colorf {
+        match self.emitter {
+            EmitterType::Dome(ref bottom, _) => {
+                let cos_theta = self.transform.transform(time).inv_mul_vector(w).normalized().y;
+                if cos_theta > 0.0 {
+                    Colorf::lerp(cos_theta, &bottom.color(time), &self.emission.color(time))
+                } else {
+                    Colorf::black()
+                }
+            },
+            EmitterType::Sky(ref sky, _) => {
+                sky.radiance(&self.transform.transform(time).inv_mul_vector(w)) * self.emission.color(time)
+            },
+            _ => Colorf::black(),
+        }
+    }
+    /// Return a rough estimate of the luminance of the irradiance the light could deliver to the
+    /// point `p`, ignoring the light's orientation, spread and anything blocking it. This is only
+    /// 0 if the light emits nothing at `time`
+    pub fn approx_irradiance(&self, p: &Point, time: f32) -> f32 {
+        let front = self.emission.color(time).luminance();
+        let l = self.back_emission.as_ref().map_or(front, |b| f32::max(front, b.color(time).luminance()));
+        match self.emitter {
+            EmitterType::Point => {
+                let pos = self.transform.transform(time) * Point::broadcast(0.0);
+                l / pos.distance_sqr(p)
+            },
+            // Treat the light as a disk of the same area facing the point, points inside
+            // the light's bounds see at most the irradiance of the light covering the hemisphere
+            EmitterType::Area(ref g, _) => {
+                let area = g.surface_area();
+                let center = self.bounds(time, time).lerp(0.5, 0.5, 0.5);
+                f32::consts::PI * l * area / f32::max(center.distance_sqr(p), area)
+            },
+            EmitterType::Dome(ref bottom, _) => f32::consts::PI * f32::max(l, bottom.color(time).luminance()),
+            EmitterType::Sky(..) => f32::consts::PI * l,
+        }
     }
     /// Get the transform to place the emitter into world space
     pub fn get_transform(&self) -> &AnimatedTransform {
@@ -153,7 +471,10 @@ impl Emitter {
 impl Boundable for Emitter {
     fn bounds(&self, start: f32, end: f32) -> BBox {
         match self.emitter {
-            EmitterType::Point => self.transform.animation_bounds(&BBox::singular(Point::broadcast(0.0)), start, end),
+            // The dome has no position, it's just placed at the origin to keep the BVH finite
+            EmitterType::Point | EmitterType::Dome(..) | EmitterType::Sky(..) => {
+                self.transform.animation_bounds(&BBox::singular(Point::broadcast(0.0)), start, end)
+            },
             EmitterType::Area(ref g, _) => {
                 self.transform.animation_bounds(&g.bounds(start, end), start, end)
             },
@@ -182,6 +503,27 @@ impl Light for Emitter {
                 let p_w = transform * p_sampled;
                 (radiance, transform * w_il, pdf, OcclusionTester::test_points(p, &p_w, time))
             },
+            EmitterType::Dome(_, ref portals) | EmitterType::Sky(_, ref portals) if !portals.is_empty() => {
+                // Pick a portal to sample a direction through using the first sample
+                let n = portals.len();
+                let i = cmp::min((samples.0 * n as f32) as usize, n - 1);
+                let remapped = (samples.0 * n as f32 - i as f32, samples.1);
+                let (w_i, _) = portals[i].sample(p, &remapped, time);
+                (self.dome_radiance(&w_i, time), w_i, self.pdf(p, &w_i, time),
+                 OcclusionTester::test_ray(p, &w_i, time))
+            },
+            EmitterType::Dome(..) => {
+                // Cosine weighted sampling of the dome's hemisphere, with +Y up
+                let d = mc::cos_sample_hemisphere(samples);
+                let w_i = (self.transform.transform(time) * Vector::new(d.x, d.z, d.y)).normalized();
+                (self.dome_radiance(&w_i, time), w_i, mc::cos_hemisphere_pdf(d.z),
+                 OcclusionTester::test_ray(p, &w_i, time))
+            },
+            EmitterType::Sky(ref sky, _) => {
+                let (w, pdf) = sky.sample(samples);
+                let w_i = (self.transform.transform(time) * w).normalized();
+                (self.dome_radiance(&w_i, time), w_i, pdf, OcclusionTester::test_ray(p, &w_i, time))
+            },
         }
     }
     fn delta_light(&self) -> bool {
@@ -198,8 +540,141 @@ impl Light for Emitter {
                 let p_l = transform.inv_mul_point(p);
                 let w = (transform.inv_mul_vector(w_i)).normalized();
                 g.pdf(&p_l, &w)
-            }
+            },
+            EmitterType::Dome(_, ref portals) | EmitterType::Sky(_, ref portals) if !portals.is_empty() => {
+                // The direction may pass through several portals
+                portals.iter().map(|x| x.pdf(p, w_i, time)).sum::<f32>() / portals.len() as f32
+            },
+            EmitterType::Dome(..) => {
+                let cos_theta = self.transform.transform(time).inv_mul_vector(w_i).normalized().y;
+                mc::cos_hemisphere_pdf(f32::max(cos_theta, 0.0))
+            },
+            EmitterType::Sky(ref sky, _) => sky.pdf(&self.transform.transform(time).inv_mul_vector(w_i)),
         }
     }
+    fn escaped_radiance(&self, d: &Vector, time: f32) -> Colorf {
+        self.dome_radiance(d, time)
+    }
 }
 
+
+#[test]
+fn test_dome_light() {
+    use linalg::Transform;
+    use film::ColorKeyframe;
+    let color = |c: Colorf| AnimatedColor::with_keyframes(vec![ColorKeyframe::new(&c, 0.0)]);
+    let top = Colorf::new(0.5, 0.5, 1.0);
+    let bottom = Colorf::broadcast(1.0);
+    let dome = Emitter::dome(AnimatedTransform::unanimated(&Transform::identity()), color(top),
+                             color(bottom), "dome".to_owned());
+    // Rays escaping upwards see the gradient, rays below the horizon see nothing
+    let zenith = dome.escaped_radiance(&Vector::new(0.0, 1.0, 0.0), 0.0);
+    assert!((0..3).all(|i| f32::abs(zenith[i] - top[i]) < 1e-5));
+    let horizon = dome.escaped_radiance(&Vector::new(1.0, 1e-4, 0.0), 0.0);
+    assert!((0..3).all(|i| f32::abs(horizon[i] - bottom[i]) < 1e-3));
+    assert!(dome.escaped_radiance(&Vector::new(0.0, -1.0, 0.0), 0.0).is_black());
+    // Sampled directions are above the horizon and their pdf matches the light's pdf
+    let p = Point::broadcast(0.0);
+    for &u in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+        let (li, w_i, pdf, _) = dome.sample_incident(&p, &u, 0.0);
+        assert!(w_i.y > 0.0 && !li.is_black());
+        assert!(f32::abs(pdf - dome.pdf(&p, &w_i, 0.0)) < 1e-4);
+    }
+    assert_eq!(dome.pdf(&p, &Vector::new(0.0, -1.0, 0.0), 0.0), 0.0);
+}
+
+#[test]
+fn test_dome_portals() {
+    use linalg::Transform;
+    use film::ColorKeyframe;
+    let color = AnimatedColor::with_keyframes(vec![ColorKeyframe::new(&Colorf::broadcast(1.0), 0.0)]);
+    let mut dome = Emitter::dome(AnimatedTransform::unanimated(&Transform::identity()), color.clone(),
+                                 color, "dome".to_owned());
+    // A window in the ceiling above the point and another off to the side
+    let portals = vec![
+        Portal::new(1.0, 1.0, AnimatedTransform::unanimated(&(Transform::translate(&Vector::new(0.0, 2.0, 0.0))
+                                                               * Transform::rotate_x(90.0)))),
+        Portal::new(1.0, 1.0, AnimatedTransform::unanimated(&(Transform::translate(&Vector::new(3.0, 2.0, 0.0))
+                                                               * Transform::rotate_x(90.0)))),
+    ];
+    dome.set_portals(portals);
+    assert!(dome.is_dome());
+    // Sampled directions go through one of the portals and their pdf matches the light's pdf
+    let p = Point::broadcast(0.0);
+    for &u in &[(0.1, 0.2), (0.4, 0.5), (0.6, 0.3), (0.9, 0.7)] {
+        let (li, w_i, pdf, _) = dome.sample_incident(&p, &u, 0.0);
+        assert!(w_i.y > 0.0 && !li.is_black() && pdf > 0.0);
+        assert!(f32::abs(pdf - dome.pdf(&p, &w_i, 0.0)) < 1e-3 * pdf);
+    }
+    // Directions missing every portal are never sampled
+    assert_eq!(dome.pdf(&p, &Vector::new(-1.0, 1.0, 0.0).normalized(), 0.0), 0.0);
+}
+
+#[test]
+fn test_sky_light() {
+    use linalg::Transform;
+    // A sky with the sun towards +X, rotated so its zenith points along +Z
+    let sky = Emitter::sky(AnimatedTransform::unanimated(&Transform::rotate_x(90.0)),
+                           Sky::new(&Vector::new(1.0, 1.0, 0.0), 3.0), 2.0, "sky".to_owned());
+    assert!(sky.is_dome() && !sky.delta_light());
+    let zenith = sky.escaped_radiance(&Vector::new(0.0, 0.0, 1.0), 0.0);
+    assert!(!zenith.is_black() && zenith.is_finite());
+    assert!(sky.escaped_radiance(&Vector::new(0.0, 0.0, -1.0), 0.0).is_black());
+    // Sampled directions are above the rotated horizon and their pdf matches the light's pdf
+    let p = Point::broadcast(0.0);
+    for &u in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+        let (li, w_i, pdf, _) = sky.sample_incident(&p, &u, 0.0);
+        assert!(w_i.z > 0.0 && !li.is_black() && pdf > 0.0);
+        assert!(f32::abs(pdf - sky.pdf(&p, &w_i, 0.0)) < 1e-2 * pdf);
+    }
+}
+
+#[test]
+fn test_back_emission() {
+    use geometry::Rectangle;
+    use test_util::{gray_matte, translate, constant_color};
+    let front = Colorf::new(1.0, 0.0, 0.0);
+    let back = Colorf::new(0.0, 0.0, 1.0);
+    let mut light = Emitter::area(Arc::new(Rectangle::new(1.0, 1.0)), gray_matte(), constant_color(front),
+                                  translate(0.0, 0.0, 0.0), "sign".to_owned());
+    let p = Point::broadcast(0.0);
+    let n = Normal::new(0.0, 0.0, 1.0);
+    let w_front = Vector::new(0.0, 0.5, 1.0);
+    let w_back = Vector::new(0.0, 0.5, -1.0);
+    // By default only the front emits
+    assert_eq!(light.radiance(&w_front, &p, &n, 0.0), front);
+    assert!(light.radiance(&w_back, &p, &n, 0.0).is_black());
+    light.set_back_emission(constant_color(back));
+    assert_eq!(light.radiance(&w_front, &p, &n, 0.0), front);
+    assert_eq!(light.radiance(&w_back, &p, &n, 0.0), back);
+    // The back emits in the same spread as the front
+    light.set_spread(30.0);
+    assert!(light.radiance(&Vector::new(0.0, 1.0, -1.0), &p, &n, 0.0).is_black());
+    assert_eq!(light.radiance(&Vector::new(0.0, 0.1, -1.0), &p, &n, 0.0), back);
+}
+
+#[test]
+fn test_rotating_sky() {
+    use linalg::{Transform, Keyframe};
+    use test_util::constant_color;
+    // The dome and sky tip over from +Y up to +X up over the first second
+    let keyframes = vec![Keyframe::new(&Transform::identity()), Keyframe::new(&Transform::rotate_z(-90.0))];
+    let transform = AnimatedTransform::with_keyframes(keyframes, vec![0.0, 0.0, 1.0, 1.0], 1);
+    let dome = Emitter::dome(transform.clone(), constant_color(Colorf::broadcast(1.0)),
+                             constant_color(Colorf::broadcast(1.0)), "dome".to_owned());
+    let sky = Emitter::sky(transform, Sky::new(&Vector::new(1.0, 1.0, 0.0), 3.0), 2.0, "sky".to_owned());
+    let p = Point::broadcast(0.0);
+    for light in &[dome, sky] {
+        for &(time, up) in &[(0.0, Vector::new(0.0, 1.0, 0.0)), (1.0, Vector::new(1.0, 0.0, 0.0))] {
+            assert!(!light.escaped_radiance(&up, time).is_black());
+            assert!(light.escaped_radiance(&-up, time).is_black());
+            // Samples are taken from the light as rotated at their time
+            for &u in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+                let (li, w_i, pdf, _) = light.sample_incident(&p, &u, time);
+                assert!(linalg::dot(&w_i, &up) > 0.0 && pdf > 0.0);
+                assert_eq!(li, light.escaped_radiance(&w_i, time));
+                assert!(f32::abs(pdf - light.pdf(&p, &w_i, time)) < 1e-2 * pdf);
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Provides a uniform grid that stores types implementing the Boundable trait, as an
+//! alternative to the BVH. The bounds of the geometry are split into equally sized cells
+//! and each object is referenced by every cell its bounds overlap, rays then walk through
+//! the cells they pass through front to back with a 3D DDA. The grid builds almost instantly
+//! and traverses quickly when the objects are of similar size and spread evenly through the
+//! scene, e.g. particles or voxels, but performs poorly when they're clustered together or
+//! vary greatly in size, e.g. a small detailed model sitting on a large ground plane.
+
+use std::{f32, cmp};
+use std::slice::{Iter, IterMut};
+
+use geometry::{BBox, Boundable};
+use linalg::{Point, Ray, Vector};
+
+/// Number of cells placed along the longest axis of the grid per cube root of the
+/// number of objects, the other axes get cells of about the same size
+const GRID_DENSITY: f32 = 3.0;
+/// Maximum number of cells along each axis of the grid
+const MAX_CELLS: usize = 64;
+
+/// A uniform grid storing objects that can report their bounds in some space
+/// via the `Boundable` trait
+pub struct Grid<T: Boundable> {
+    /// The geometry stored in this grid
+    geometry: Vec<T>,
+    /// Indices into `geometry` of the objects overlapping each cell, stored in x, y, z order
+    cells: Vec<Vec<usize>>,
+    /// Number of cells along each axis
+    n_cells: [usize; 3],
+    /// Bounds of all the geometry in the grid
+    bounds: BBox,
+    /// Size of the cells along each axis
+    cell_size: Vector,
+}
+
+impl<T: Boundable> Grid<T> {
+    /// Create a new non-animated grid holding the geometry
+    pub fn unanimated(geometry: Vec<T>) -> Grid<T> {
+        Grid::new(geometry, 0.0, 0.0)
+    }
+    /// Create a new grid holding the scene geometry for some time period
+    pub fn new(geometry: Vec<T>, start: f32, end: f32) -> Grid<T> {
+        assert!(!geometry.is_empty());
+        let mut grid = Grid { geometry: geometry, cells: Vec::new(), n_cells: [1; 3], bounds: BBox::new(),
+                              cell_size: Vector::broadcast(0.0) };
+        grid.rebuild(start, end);
+        grid
+    }
+    /// Re-build the grid for the time range passed
+    pub fn rebuild(&mut self, start: f32, end: f32) {
+        let geom_bounds: Vec<_> = self.geometry.iter().map(|g| g.bounds(start, end)).collect();
+        self.bounds = geom_bounds.iter().fold(BBox::new(), |b, g| b.box_union(g));
+        let extent = self.bounds.max - self.bounds.min;
+        let max_extent = f32::max(extent.x, f32::max(extent.y, extent.z));
+        let cells_per_unit = GRID_DENSITY * f32::cbrt(self.geometry.len() as f32) / max_extent;
+        for a in 0..3 {
+            self.n_cells[a] = if max_extent > 0.0 {
+                cmp::max(cmp::min(f32::round(extent[a] * cells_per_unit) as usize, MAX_CELLS), 1)
+            } else {
+                1
+            };
+            self.cell_size[a] = extent[a] / self.n_cells[a] as f32;
+        }
+        self.cells = vec![Vec::new(); self.n_cells[0] * self.n_cells[1] * self.n_cells[2]];
+        for (i, b) in geom_bounds.iter().enumerate() {
+            let lo = self.cell_of(&b.min);
+            let hi = self.cell_of(&b.max);
+            for z in lo[2]..hi[2] + 1 {
+                for y in lo[1]..hi[1] + 1 {
+                    for x in lo[0]..hi[0] + 1 {
+                        let c = self.cell_index(&[x, y, z]);
+                        self.cells[c].push(i);
+                    }
+                }
+            }
+        }
+    }
+    /// Walk the cells of the grid along the ray and call the function passed on the objects
+    /// in them, returning the value returned by the function after traversal completes.
+    /// Objects overlapping several cells may be passed to the function more than once
+    pub fn intersect<'a, F, R>(&'a self, ray: &mut Ray, f: F) -> Option<R>
+            where F: Fn(&mut Ray, &'a T) -> Option<R> {
+        let t_enter = match self.enter_grid(ray) {
+            Some(t) => t,
+            None => return None,
+        };
+        let p = ray.at(t_enter);
+        let mut cell = self.cell_of(&p);
+        // The t values the ray crosses into the next cell along each axis, the change in t
+        // to cross a cell along each axis and the cell past the edge of the grid the ray exits at
+        let mut next_crossing = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        let mut exit = [0; 3];
+        for a in 0..3 {
+            let cell_min = self.bounds.min[a] + cell[a] as f32 * self.cell_size[a];
+            if ray.d[a] > 0.0 {
+                next_crossing[a] = t_enter + (cell_min + self.cell_size[a] - p[a]) / ray.d[a];
+                delta[a] = self.cell_size[a] / ray.d[a];
+                exit[a] = self.n_cells[a];
+            } else if ray.d[a] < 0.0 {
+                next_crossing[a] = t_enter + (cell_min - p[a]) / ray.d[a];
+                delta[a] = -self.cell_size[a] / ray.d[a];
+                exit[a] = usize::max_value();
+            }
+        }
+        let mut result = None;
+        loop {
+            for i in &self.cells[self.cell_index(&cell)] {
+                result = f(ray, &self.geometry[*i]).or(result);
+            }
+            let mut a = if next_crossing[0] < next_crossing[1] { 0 } else { 1 };
+            if next_crossing[2] < next_crossing[a] {
+                a = 2;
+            }
+            // Any hit found before the ray leaves the cell is the closest one
+            if ray.max_t < next_crossing[a] {
+                break;
+            }
+            cell[a] = if ray.d[a] > 0.0 { cell[a] + 1 } else { cell[a].wrapping_sub(1) };
+            if cell[a] == exit[a] {
+                break;
+            }
+            next_crossing[a] += delta[a];
+        }
+        result
+    }
+    pub fn iter(&self) -> Iter<T> {
+        self.geometry.iter()
+    }
+    /// Get a mutable iterator over the geometry in the grid. The grid must be rebuilt
+    /// if the bounds of the geometry are changed
+    pub fn iter_mut(&mut self) -> IterMut<T> {
+        self.geometry.iter_mut()
+    }
+    /// Get the number of cells along each axis of the grid
+    pub fn resolution(&self) -> [usize; 3] {
+        self.n_cells
+    }
+    /// Find the t value the ray enters the grid's bounds at, or starts at if its origin
+    /// is inside the grid. Returns None if the ray misses the grid
+    fn enter_grid(&self, ray: &Ray) -> Option<f32> {
+        let mut t0 = ray.min_t;
+        let mut t1 = ray.max_t;
+        for a in 0..3 {
+            let inv_d = 1.0 / ray.d[a];
+            let mut t_near = (self.bounds.min[a] - ray.o[a]) * inv_d;
+            let mut t_far = (self.bounds.max[a] - ray.o[a]) * inv_d;
+            if t_near > t_far {
+                ::std::mem::swap(&mut t_near, &mut t_far);
+            }
+            // NaNs from rays parallel to and on a slab's edge fail these tests and are ignored
+            if t_near > t0 {
+                t0 = t_near;
+            }
+            if t_far < t1 {
+                t1 = t_far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some(t0)
+    }
+    /// Find the cell containing the point, points outside the grid are clamped to its edge
+    fn cell_of(&self, p: &Point) -> [usize; 3] {
+        let mut c = [0; 3];
+        for a in 0..3 {
+            if self.cell_size[a] > 0.0 {
+                let i = f32::max((p[a] - self.bounds.min[a]) / self.cell_size[a], 0.0) as usize;
+                c[a] = cmp::min(i, self.n_cells[a] - 1);
+            }
+        }
+        c
+    }
+    /// Get the index of the cell in `cells`
+    fn cell_index(&self, c: &[usize; 3]) -> usize {
+        (c[2] * self.n_cells[1] + c[1]) * self.n_cells[0] + c[0]
+    }
+}
+
+impl<T: Boundable> Boundable for Grid<T> {
+    fn bounds(&self, _: f32, _: f32) -> BBox {
+        self.bounds
+    }
+}
+
+#[test]
+fn test_grid_traversal() {
+    use geometry::BVH;
+    use geometry::test_util::make_boxes;
+    let boxes = || make_boxes((8, 8, 8), Vector::broadcast(2.0), &|_| 0.4);
+    let grid = Grid::unanimated(boxes());
+    let bvh = BVH::unanimated(4, boxes());
+    assert!(grid.resolution().iter().all(|n| *n > 1));
+    // Rays through the grid from all directions should hit the same boxes the BVH finds
+    let dirs = [Vector::new(1.0, 0.3, 0.2), Vector::new(-0.5, 1.0, -0.1), Vector::new(0.0, 0.0, -1.0),
+                Vector::new(-1.0, -1.0, 1.0), Vector::new(0.2, -1.0, 0.0)];
+    for d in &dirs {
+        for i in 0..64 {
+            let o = Point::new((i % 8) as f32 * 2.0, (i / 8) as f32 * 2.0, 7.0) - *d * 20.0;
+            let mut grid_ray = Ray::new(&o, d, 0.0);
+            let mut bvh_ray = grid_ray;
+            let g = grid.intersect(&mut grid_ray, |r, b| b.intersect(r));
+            let b = bvh.intersect(&mut bvh_ray, |r, b| b.intersect(r));
+            assert_eq!(g, b);
+        }
+    }
+    // Rays starting inside the grid and missing it entirely
+    let mut inside = Ray::new(&Point::new(6.0, 6.0, 5.0), &Vector::new(0.0, 0.0, 1.0), 0.0);
+    assert_eq!(grid.intersect(&mut inside, |r, b| b.intersect(r)), Some(3 * 64 + 3 * 8 + 3));
+    let mut miss = Ray::new(&Point::new(-5.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0), 0.0);
+    assert!(grid.intersect(&mut miss, |r, b| b.intersect(r)).is_none());
+}
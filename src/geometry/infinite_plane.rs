@@ -0,0 +1,69 @@
+//! Defines an infinite plane at some height along the y axis, useful as a
+//! ground plane for quick studio setups without modeling a huge rectangle
+//!
+//! # Scene Usage Example
+//! The infinite plane optionally takes the height of the plane along y, which defaults
+//! to 0, and a scale for the texture coordinates, which defaults to 1. The texture
+//! coordinates repeat every 1 / `uv_scale` units along the x and z axes. The plane's
+//! normal faces along [0, 1, 0].
+//!
+//! ```json
+//! "geometry": {
+//!     "type": "infinite_plane",
+//!     "height": 0.0,
+//!     "uv_scale": 0.5
+//! }
+//! ```
+
+use std::f32;
+
+use geometry::{Geometry, DifferentialGeometry, Boundable, BBox};
+use linalg::{Normal, Vector, Ray, Point};
+
+/// The extent used for the bounds of the plane, since the BVH requires finite bounds
+const PLANE_EXTENT: f32 = 1.0e7;
+
+/// A plane at `y = height` with unbounded extent and a normal along [0, 1, 0]
+#[derive(Clone, Copy)]
+pub struct InfinitePlane {
+    height: f32,
+    uv_scale: f32,
+}
+
+impl InfinitePlane {
+    /// Create a new infinite plane at `y = height` with texture coordinates scaled by `uv_scale`
+    pub fn new(height: f32, uv_scale: f32) -> InfinitePlane {
+        InfinitePlane { height: height, uv_scale: uv_scale }
+    }
+}
+
+impl Geometry for InfinitePlane {
+    fn intersect(&self, ray: &mut Ray) -> Option<DifferentialGeometry> {
+        // If the ray is parallel to the plane it can't intersect
+        if f32::abs(ray.d.y) < 1e-8 {
+            return None;
+        }
+        let t = (self.height - ray.o.y) / ray.d.y;
+        if t < ray.min_t || t > ray.max_t {
+            return None;
+        }
+        ray.max_t = t;
+        let p = ray.at(t);
+        let n = Normal::new(0.0, 1.0, 0.0);
+        // The texture coordinates repeat across the plane, u runs along z and v along x
+        // so that cross(dp_du, dp_dv) gives the plane's normal
+        let u = p.z * self.uv_scale;
+        let v = p.x * self.uv_scale;
+        let dp_du = Vector::new(0.0, 0.0, 1.0 / self.uv_scale);
+        let dp_dv = Vector::new(1.0 / self.uv_scale, 0.0, 0.0);
+        Some(DifferentialGeometry::new(&p, &n, u - f32::floor(u), v - f32::floor(v), ray.time,
+                                       &dp_du, &dp_dv, self))
+    }
+}
+
+impl Boundable for InfinitePlane {
+    fn bounds(&self, _: f32, _: f32) -> BBox {
+        BBox::span(Point::new(-PLANE_EXTENT, self.height, -PLANE_EXTENT),
+                   Point::new(PLANE_EXTENT, self.height, PLANE_EXTENT))
+    }
+}
@@ -22,7 +22,7 @@
 //!         },
 //!         "transform": [
 //!             {
-//!                 "type": "scaling",
+//!                 "type": "scale",
 //!                 "scaling": 15.0
 //!             },
 //!             {
@@ -59,6 +59,116 @@
 //! ]
 //! ```
 //!
+//! # Shared Geometry Example
+//! To place the same geometry in the scene multiple times with different materials a receiver
+//! can list its instances. The geometry is only loaded once and shared by each instance, which
+//! can specify its own name, material and transform or keyframes. The receiver's material is
+//! used for instances which don't specify one and the receiver's transform is applied to all
+//! of its instances. Instances without a name are named after the receiver and their index.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "bunnies",
+//!         "type": "receiver",
+//!         "material": "white_wall",
+//!         "geometry": {
+//!             "type": "mesh",
+//!             "file": "./bunny.obj",
+//!             "model": "bunny"
+//!         },
+//!         "transform": [],
+//!         "instances": [
+//!             {
+//!                 "name": "red_bunny",
+//!                 "material": "red_wall",
+//!                 "transform": [
+//!                     {
+//!                         "type": "translate",
+//!                         "translation": [-5.0, 0.0, 0.0]
+//!                     }
+//!                 ]
+//!             },
+//!             {
+//!                 "transform": [
+//!                     {
+//!                         "type": "translate",
+//!                         "translation": [5.0, 0.0, 0.0]
+//!                     }
+//!                 ]
+//!             }
+//!         ]
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! # Primary Visibility
+//! Receivers can be hidden from the camera while still appearing in reflections, refractions
+//! and shadows by setting `"primary_visible": false`, e.g. to composite a matte object into
+//! a photo. Instances of shared geometry inherit the receiver's setting unless they specify
+//! their own.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "hidden_bunny",
+//!         "type": "receiver",
+//!         "material": "white_wall",
+//!         "primary_visible": false,
+//!         ...
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! # Clipping Planes
+//! For cutaway and section renders receivers can list planes clipping away their geometry.
+//! Each plane is given by a point on it and its normal in world space, the part of the object
+//! on the side the normal points towards is kept and the rest is removed. Clipped objects are
+//! left open at the cut and the removed parts don't cast shadows. Instances of shared geometry
+//! are all clipped by the receiver's planes.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "building",
+//!         "type": "receiver",
+//!         "material": "white_wall",
+//!         "clip_planes": [
+//!             {
+//!                 "point": [0, 4, 0],
+//!                 "normal": [0, -1, 0]
+//!             }
+//!         ],
+//!         ...
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! # Fixing Normals
+//! Imported meshes sometimes have inverted normals, making them shade black or refract the
+//! wrong way. Setting `"flip_normals": true` on a receiver flips its shading and geometric
+//! normals, and `"recompute_normals": true` ignores the normals in the mesh file and shades
+//! with the geometric normal given by the triangles' counter-clockwise winding instead. The
+//! normals are recomputed before they're flipped, so both can be set to shade with the normal
+//! opposite the winding. Instances of shared geometry inherit the receiver's settings unless
+//! they specify their own.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "imported_car",
+//!         "type": "receiver",
+//!         "material": "car_paint",
+//!         "flip_normals": true,
+//!         ...
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
 
 use std::sync::Arc;
 
@@ -110,6 +220,15 @@ impl Instance {
             Instance::Receiver(ref r) => &r.tag[..],
         }
     }
+    /// Get the name of the instance's material in the scene file, returns None for
+    /// point lights and instances whose material wasn't loaded from a scene file
+    pub fn material_name(&self) -> Option<&str> {
+        let name = match *self {
+            Instance::Emitter(ref e) => &e.material_name[..],
+            Instance::Receiver(ref r) => &r.material_name[..],
+        };
+        if name.is_empty() { None } else { Some(name) }
+    }
     /// Get the transform for this instance
     pub fn get_transform(&self) -> &AnimatedTransform {
         match *self {
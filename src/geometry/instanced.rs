@@ -0,0 +1,153 @@
+//! Defines geometry placed many times in the scene with different transforms, such as a forest
+//! of identical trees. Each receiver in the scene holds its own transform, material, name and
+//! so on, which adds up when placing thousands of copies of an object. Instanced geometry
+//! instead stores the placements of one piece of geometry in a BVH of its own, forming a
+//! two-level BVH: rays find the placements they may hit in the placement BVH and then traverse
+//! the shared geometry, e.g. the mesh's BVH, in the placement's space. The geometry is only
+//! loaded and built once no matter how many times it's placed.
+//!
+//! All placements share the receiver's material and are moved together by the receiver's
+//! transform. The placement transforms are static and the shared geometry can't be an animated
+//! mesh. Instanced geometry doesn't support baking lighting since the placements would overlap
+//! in texture space.
+//!
+//! # Scene Usage Example
+//! The instanced geometry takes the `geometry` to place and a list of `transforms` for each
+//! placement, which are transform stacks like the one used to place objects.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "forest",
+//!         "type": "receiver",
+//!         "material": "bark",
+//!         "geometry": {
+//!             "type": "instanced",
+//!             "geometry": {
+//!                 "type": "mesh",
+//!                 "file": "./tree.obj",
+//!                 "model": "Tree"
+//!             },
+//!             "transforms": [
+//!                 [
+//!                     {
+//!                         "type": "translate",
+//!                         "translation": [0, 0, 0]
+//!                     }
+//!                 ],
+//!                 [
+//!                     {
+//!                         "type": "rotate_y",
+//!                         "angle": 45
+//!                     },
+//!                     {
+//!                         "type": "translate",
+//!                         "translation": [10, 0, 4]
+//!                     }
+//!                 ],
+//!                 ...
+//!             ]
+//!         },
+//!         "transform": [
+//!             {
+//!                 "type": "translate",
+//!                 "translation": [0, 0, 0]
+//!             }
+//!         ]
+//!     },
+//!     ...
+//! ]
+//! ```
+
+use std::sync::Arc;
+
+use geometry::{Geometry, DifferentialGeometry, Boundable, BoundableGeom, BBox, BVH};
+use linalg::{Ray, Transform};
+
+/// A placement of the shared geometry
+struct Placement {
+    /// Transform from the geometry's space into the instanced geometry's space
+    transform: Transform,
+    /// Bounds of the placed geometry in the instanced geometry's space
+    bounds: BBox,
+}
+
+impl Boundable for Placement {
+    fn bounds(&self, _: f32, _: f32) -> BBox {
+        self.bounds
+    }
+}
+
+/// Geometry placed many times with different transforms, sharing a single copy of the
+/// geometry and its acceleration structure between the placements
+pub struct Instanced {
+    /// The geometry shared by all placements
+    geometry: Arc<BoundableGeom + Send + Sync>,
+    /// BVH over the placements of the geometry
+    placements: BVH<Placement>,
+}
+
+impl Instanced {
+    /// Create instanced geometry placing `geometry` with each of the transforms passed
+    pub fn new(geometry: Arc<BoundableGeom + Send + Sync>, transforms: Vec<Transform>) -> Instanced {
+        assert!(!transforms.is_empty(), "Instanced geometry must have at least one placement");
+        let geom_bounds = geometry.bounds(0.0, 0.0);
+        let placements = transforms.into_iter().map(|t| Placement { transform: t, bounds: t * geom_bounds })
+            .collect();
+        Instanced { geometry: geometry, placements: BVH::unanimated(4, placements) }
+    }
+    /// Get the number of times the geometry is placed
+    pub fn num_placements(&self) -> usize {
+        self.placements.iter().count()
+    }
+}
+
+impl Geometry for Instanced {
+    fn intersect(&self, ray: &mut Ray) -> Option<DifferentialGeometry> {
+        let geometry = &self.geometry;
+        self.placements.intersect(ray, |r, p| {
+            let mut local = p.transform.inv_mul_ray(r);
+            let mut dg = match geometry.intersect(&mut local) {
+                Some(dg) => dg,
+                None => return None,
+            };
+            r.max_t = local.max_t;
+            dg.p = p.transform * dg.p;
+            dg.n = p.transform * dg.n;
+            dg.ng = p.transform * dg.ng;
+            dg.face_normal = p.transform * dg.face_normal;
+            dg.dp_du = p.transform * dg.dp_du;
+            dg.dp_dv = p.transform * dg.dp_dv;
+            Some(dg)
+        })
+    }
+}
+
+impl Boundable for Instanced {
+    fn bounds(&self, start: f32, end: f32) -> BBox {
+        self.placements.bounds(start, end)
+    }
+}
+
+#[test]
+fn test_instanced() {
+    use geometry::Sphere;
+    use linalg::{Point, Vector};
+    let transforms = (0..100).map(|i| {
+        Transform::translate(&Vector::new((i % 10) as f32 * 3.0, 0.0, (i / 10) as f32 * 3.0))
+    }).collect();
+    let sphere = Arc::new(Sphere::new(1.0));
+    let instanced = Instanced::new(sphere, transforms);
+    assert_eq!(instanced.num_placements(), 100);
+    let b = instanced.bounds(0.0, 0.0);
+    assert_eq!((b.min.x, b.max.x, b.max.z), (-1.0, 28.0, 28.0));
+    // A ray down a row of spheres hits the first one in front of it
+    let mut ray = Ray::new(&Point::new(6.0, 0.0, -5.0), &Vector::new(0.0, 0.0, 1.0), 0.0);
+    let dg = instanced.intersect(&mut ray).expect("Ray should hit a sphere");
+    assert!(f32::abs(dg.p.z + 1.0) < 1e-4 && f32::abs(dg.p.x - 6.0) < 1e-4);
+    assert!(f32::abs(dg.n.normalized().z + 1.0) < 1e-4);
+    assert!(f32::abs(ray.max_t - 4.0) < 1e-4);
+    // A ray between the rows misses them all
+    let mut miss = Ray::new(&Point::new(1.5, 0.0, -5.0), &Vector::new(0.0, 0.0, 1.0), 0.0);
+    assert!(instanced.intersect(&mut miss).is_none());
+}
@@ -0,0 +1,314 @@
+//! Provides a KD-tree that stores types implementing the Boundable trait, as an alternative
+//! to the BVH, following the SAH KD-tree construction and traversal described in PBRT.
+//! The tree recursively splits space with axis aligned planes placed to minimize the
+//! surface area heuristic's cost and stores references to the objects overlapping each
+//! leaf, so objects straddling a split are referenced by both sides. Rays visit the leaves
+//! they pass through front to back and can stop at the first leaf containing a hit, which
+//! can make the KD-tree faster than the BVH for large static meshes. It takes longer to
+//! build than the BVH so isn't well suited to animated objects that are rebuilt each frame.
+//! The depth of the tree is bounded by the number of objects, see `KdTree::new`.
+
+use std::{f32, cmp};
+use std::cmp::Ordering;
+use std::slice::{Iter, IterMut};
+
+use geometry::{BBox, Boundable};
+use linalg::{Ray, Vector};
+
+/// Estimated cost of testing an object for intersection, relative to the traversal cost
+const INTERSECTION_COST: f32 = 80.0;
+/// Estimated cost of traversing an interior node
+const TRAVERSAL_COST: f32 = 1.0;
+/// Fraction of the cost saved by splits which leave one side empty
+const EMPTY_BONUS: f32 = 0.5;
+/// Number of objects below which we stop splitting and make a leaf
+const MAX_LEAF_GEOM: usize = 1;
+/// Maximum number of nodes to remember visiting during traversal
+const MAX_TODO: usize = 64;
+
+/// A node in the flattened tree, the child below an interior node's split
+/// immediately follows it in the node list
+#[derive(Debug, Copy, Clone)]
+enum KdNode {
+    /// An interior node splitting its space at `split` along `axis`, with the index
+    /// of the child above the split
+    Interior { axis: usize, split: f32, above: usize },
+    /// A leaf referencing `count` objects starting at `start` in the tree's index list
+    Leaf { start: usize, count: usize },
+}
+
+/// An edge of an object's bounds along the axis being split, used to find the best split
+#[derive(Debug, Copy, Clone)]
+struct BoundEdge {
+    t: f32,
+    starting: bool,
+}
+
+/// A KD-tree storing objects that can report their bounds in some space
+/// via the `Boundable` trait
+pub struct KdTree<T: Boundable> {
+    /// The geometry stored in this tree
+    geometry: Vec<T>,
+    /// The flattened nodes of the tree, the root is the first node
+    nodes: Vec<KdNode>,
+    /// Indices into `geometry` of the objects referenced by each leaf
+    indices: Vec<usize>,
+    /// Bounds of all the geometry in the tree
+    bounds: BBox,
+}
+
+impl<T: Boundable> KdTree<T> {
+    /// Create a new non-animated KD-tree holding the geometry
+    pub fn unanimated(geometry: Vec<T>) -> KdTree<T> {
+        KdTree::new(geometry, 0.0, 0.0)
+    }
+    /// Create a new KD-tree holding the scene geometry for some time period. The tree is at
+    /// most 8 + 1.3 log2(N) levels deep for N objects, as suggested by PBRT
+    pub fn new(geometry: Vec<T>, start: f32, end: f32) -> KdTree<T> {
+        assert!(!geometry.is_empty());
+        let mut tree = KdTree { geometry: geometry, nodes: Vec::new(), indices: Vec::new(), bounds: BBox::new() };
+        tree.rebuild(start, end);
+        tree
+    }
+    /// Re-build the KD-tree for the time range passed
+    pub fn rebuild(&mut self, start: f32, end: f32) {
+        let geom_bounds: Vec<_> = self.geometry.iter().map(|g| g.bounds(start, end)).collect();
+        self.bounds = geom_bounds.iter().fold(BBox::new(), |b, g| b.box_union(g));
+        self.nodes.clear();
+        self.indices.clear();
+        let max_depth = f32::round(8.0 + 1.3 * f32::log2(self.geometry.len() as f32)) as usize;
+        let geom = (0..self.geometry.len()).collect();
+        let bounds = self.bounds;
+        self.build(&bounds, &geom_bounds, geom, max_depth, 0);
+    }
+    /// Build the node for the objects in `geom` within `node_bounds`, appending it and its children
+    /// to the node list. `bad_refines` counts the splits above this node which didn't reduce the cost
+    fn build(&mut self, node_bounds: &BBox, geom_bounds: &[BBox], geom: Vec<usize>, depth: usize,
+             mut bad_refines: usize) {
+        if geom.len() <= MAX_LEAF_GEOM || depth == 0 {
+            self.push_leaf(&geom);
+            return;
+        }
+        let leaf_cost = INTERSECTION_COST * geom.len() as f32;
+        let (axis, split, cost) = match find_split(node_bounds, geom_bounds, &geom) {
+            Some(s) => s,
+            None => {
+                self.push_leaf(&geom);
+                return;
+            },
+        };
+        if cost > leaf_cost {
+            bad_refines += 1;
+        }
+        if (cost > 4.0 * leaf_cost && geom.len() < 16) || bad_refines == 3 {
+            self.push_leaf(&geom);
+            return;
+        }
+        // Objects lying flat in the split plane are placed below it
+        let below: Vec<_> = geom.iter().cloned().filter(|g| {
+            geom_bounds[*g].min[axis] < split || geom_bounds[*g].max[axis] <= split
+        }).collect();
+        let above: Vec<_> = geom.into_iter().filter(|g| geom_bounds[*g].max[axis] > split).collect();
+        let mut below_bounds = *node_bounds;
+        below_bounds.max[axis] = split;
+        let mut above_bounds = *node_bounds;
+        above_bounds.min[axis] = split;
+
+        let node = self.nodes.len();
+        self.nodes.push(KdNode::Interior { axis: axis, split: split, above: 0 });
+        self.build(&below_bounds, geom_bounds, below, depth - 1, bad_refines);
+        let above_idx = self.nodes.len();
+        if let KdNode::Interior { ref mut above, .. } = self.nodes[node] {
+            *above = above_idx;
+        }
+        self.build(&above_bounds, geom_bounds, above, depth - 1, bad_refines);
+    }
+    fn push_leaf(&mut self, geom: &[usize]) {
+        self.nodes.push(KdNode::Leaf { start: self.indices.len(), count: geom.len() });
+        self.indices.extend_from_slice(geom);
+    }
+    /// Traverse the KD-tree and call the function passed on the objects in the leaves the ray
+    /// passes through, returning the value returned by the function after traversal completes.
+    /// Objects referenced by several leaves may be passed to the function more than once
+    pub fn intersect<'a, F, R>(&'a self, ray: &mut Ray, f: F) -> Option<R>
+            where F: Fn(&mut Ray, &'a T) -> Option<R> {
+        let (mut t_min, mut t_max) = match self.clip_ray(ray) {
+            Some(t) => t,
+            None => return None,
+        };
+        let inv_dir = Vector::new(1.0 / ray.d.x, 1.0 / ray.d.y, 1.0 / ray.d.z);
+        let mut todo = [(0, 0.0, 0.0); MAX_TODO];
+        let mut todo_pos = 0;
+        let mut node = 0;
+        let mut result = None;
+        loop {
+            // Stop once we've found a hit closer than the space left to visit
+            if ray.max_t < t_min {
+                break;
+            }
+            match self.nodes[node] {
+                KdNode::Interior { axis, split, above } => {
+                    let t_split = (split - ray.o[axis]) * inv_dir[axis];
+                    let below_first = ray.o[axis] < split || (ray.o[axis] == split && ray.d[axis] <= 0.0);
+                    let (first, second) = if below_first { (node + 1, above) } else { (above, node + 1) };
+                    if t_split > t_max || t_split <= 0.0 {
+                        node = first;
+                    } else if t_split < t_min {
+                        node = second;
+                    } else {
+                        todo[todo_pos] = (second, t_split, t_max);
+                        todo_pos += 1;
+                        node = first;
+                        t_max = t_split;
+                    }
+                },
+                KdNode::Leaf { start, count } => {
+                    for i in &self.indices[start..start + count] {
+                        result = f(ray, &self.geometry[*i]).or(result);
+                    }
+                    if todo_pos == 0 {
+                        break;
+                    }
+                    todo_pos -= 1;
+                    let next = todo[todo_pos];
+                    node = next.0;
+                    t_min = next.1;
+                    t_max = next.2;
+                },
+            }
+        }
+        result
+    }
+    pub fn iter(&self) -> Iter<T> {
+        self.geometry.iter()
+    }
+    /// Get a mutable iterator over the geometry in the KD-tree. The tree must be rebuilt
+    /// if the bounds of the geometry are changed
+    pub fn iter_mut(&mut self) -> IterMut<T> {
+        self.geometry.iter_mut()
+    }
+    /// Get the depth of the deepest leaf in the tree, the root is at depth 0
+    pub fn depth(&self) -> usize {
+        self.node_depth(0)
+    }
+    fn node_depth(&self, node: usize) -> usize {
+        match self.nodes[node] {
+            KdNode::Interior { above, .. } => 1 + cmp::max(self.node_depth(node + 1), self.node_depth(above)),
+            KdNode::Leaf { .. } => 0,
+        }
+    }
+    /// Find the range of t values along the ray within the tree's bounds, returns None
+    /// if the ray misses the tree
+    fn clip_ray(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let mut t0 = ray.min_t;
+        let mut t1 = ray.max_t;
+        for a in 0..3 {
+            let inv_d = 1.0 / ray.d[a];
+            let mut t_near = (self.bounds.min[a] - ray.o[a]) * inv_d;
+            let mut t_far = (self.bounds.max[a] - ray.o[a]) * inv_d;
+            if t_near > t_far {
+                ::std::mem::swap(&mut t_near, &mut t_far);
+            }
+            // NaNs from rays parallel to and on a slab's edge fail these tests and are ignored
+            if t_near > t0 {
+                t0 = t_near;
+            }
+            if t_far < t1 {
+                t1 = t_far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+impl<T: Boundable> Boundable for KdTree<T> {
+    fn bounds(&self, _: f32, _: f32) -> BBox {
+        self.bounds
+    }
+}
+
+/// Find the split of the node with the lowest SAH cost, trying the axis the node is longest
+/// along first. Returns the axis and position of the split and its cost, or None if no
+/// split could be found
+fn find_split(node_bounds: &BBox, geom_bounds: &[BBox], geom: &[usize]) -> Option<(usize, f32, f32)> {
+    let extent = node_bounds.max - node_bounds.min;
+    // Degenerate nodes can't be split usefully since the SAH costs aren't defined for them
+    if node_bounds.surface_area() <= 0.0 {
+        return None;
+    }
+    let inv_area = 1.0 / node_bounds.surface_area();
+    let mut axis = node_bounds.max_extent() as usize;
+    let mut edges = Vec::with_capacity(2 * geom.len());
+    for _ in 0..3 {
+        edges.clear();
+        for g in geom {
+            edges.push(BoundEdge { t: geom_bounds[*g].min[axis], starting: true });
+            edges.push(BoundEdge { t: geom_bounds[*g].max[axis], starting: false });
+        }
+        // Edges at the same position are ordered with those starting an object first
+        edges.sort_by(|a, b| {
+            match a.t.partial_cmp(&b.t) {
+                Some(Ordering::Equal) | None => b.starting.cmp(&a.starting),
+                Some(o) => o,
+            }
+        });
+        let (o0, o1) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut best: Option<(usize, f32, f32)> = None;
+        let (mut n_below, mut n_above) = (0, geom.len());
+        for e in &edges {
+            if !e.starting {
+                n_above -= 1;
+            }
+            if e.t > node_bounds.min[axis] && e.t < node_bounds.max[axis] {
+                let below_area = 2.0 * (extent[o0] * extent[o1] + (e.t - node_bounds.min[axis])
+                                        * (extent[o0] + extent[o1]));
+                let above_area = 2.0 * (extent[o0] * extent[o1] + (node_bounds.max[axis] - e.t)
+                                        * (extent[o0] + extent[o1]));
+                let bonus = if n_below == 0 || n_above == 0 { EMPTY_BONUS } else { 0.0 };
+                let cost = TRAVERSAL_COST + INTERSECTION_COST * (1.0 - bonus)
+                    * (below_area * inv_area * n_below as f32 + above_area * inv_area * n_above as f32);
+                if best.map_or(true, |b| cost < b.2) {
+                    best = Some((axis, e.t, cost));
+                }
+            }
+            if e.starting {
+                n_below += 1;
+            }
+        }
+        if best.is_some() {
+            return best;
+        }
+        axis = (axis + 1) % 3;
+    }
+    None
+}
+
+#[test]
+fn test_kdtree_traversal() {
+    use geometry::BVH;
+    use geometry::test_util::make_boxes;
+    use linalg::Point;
+    // Boxes of varying sizes, some overlapping, so splits have to reference objects on both sides
+    let boxes = || make_boxes((10, 6, 5), Vector::new(1.5, 2.0, 3.0), &|i| 0.3 + (i % 7) as f32 * 0.15);
+    let kdtree = KdTree::unanimated(boxes());
+    let bvh = BVH::unanimated(4, boxes());
+    assert!(kdtree.depth() > 1 && kdtree.depth() <= 19);
+    // Rays through the tree from all directions should hit the same boxes the BVH finds
+    let dirs = [Vector::new(1.0, 0.3, 0.2), Vector::new(-0.5, 1.0, -0.1), Vector::new(0.0, 0.0, -1.0),
+                Vector::new(-1.0, -1.0, 1.0), Vector::new(0.2, -1.0, 0.0)];
+    for d in &dirs {
+        for i in 0..60 {
+            let o = Point::new((i % 10) as f32 * 1.5, (i / 10) as f32 * 2.0, 6.0) - *d * 30.0;
+            let mut kd_ray = Ray::new(&o, d, 0.0);
+            let mut bvh_ray = kd_ray;
+            let k = kdtree.intersect(&mut kd_ray, |r, b| b.intersect(r));
+            let b = bvh.intersect(&mut bvh_ray, |r, b| b.intersect(r));
+            assert_eq!(k, b);
+        }
+    }
+    let mut miss = Ray::new(&Point::new(-5.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0), 0.0);
+    assert!(kdtree.intersect(&mut miss, |r, b| b.intersect(r)).is_none());
+}
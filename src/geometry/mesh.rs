@@ -1,5 +1,5 @@
 //! Defines a triangle mesh geometry. Intersection tests are accelerated internally
-//! by storing the triangles of the mesh in a BVH
+//! by storing the triangles of the mesh in a BVH, or optionally a uniform grid or KD-tree
 //!
 //! # Scene Usage Example
 //! The mesh is specified by the OBJ file to load and the name of the specific
@@ -15,78 +15,236 @@
 //!     "model": "Suzanne"
 //! }
 //! ```
+//!
+//! # Disk Cache
+//! Parsing large OBJ files can take a while, if `"cache": true` is set on the mesh the
+//! parsed buffers are saved next to the OBJ file (e.g. `suzanne.obj.cache`) and loaded
+//! from there on later runs. The cache is rebuilt if the OBJ file has been modified
+//! since the cache was written.
+//!
+//! ```json
+//! "geometry": {
+//!     "type": "mesh",
+//!     "file": "./suzanne.obj",
+//!     "model": "Suzanne",
+//!     "cache": true
+//! }
+//! ```
+//!
+//! # Acceleration Structure
+//! The triangles are stored in a BVH by default, the `"accelerator"` key can be set to
+//! `"kdtree"` or `"grid"` to use a different structure. The KD-tree takes longer to build
+//! but can be faster to render for large, densely tessellated meshes. See `geometry::KdTree`.
+//!
+//! ```json
+//! "geometry": {
+//!     "type": "mesh",
+//!     "file": "./dragon.obj",
+//!     "model": "Dragon",
+//!     "accelerator": "kdtree"
+//! }
+//! ```
 
 extern crate tobj;
 
 use std::sync::Arc;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use std::collections::HashMap;
 
-use geometry::{Geometry, DifferentialGeometry, Boundable, BBox, BVH};
+use bincode::{self, Infinite};
+
+use geometry::{Geometry, DifferentialGeometry, Boundable, BBox, BVH, Grid, KdTree, Accelerator, AcceleratorType};
 use linalg::{self, Normal, Vector, Ray, Point};
 
 /// A mesh composed of triangles, specified by directly passing the position,
 /// normal and index buffers for the triangles making up the mesh
 pub struct Mesh {
-    pub bvh: BVH<Triangle>,
+    pub accel: Accelerator<Triangle>,
 }
 
 impl Mesh {
     /// Create a new Mesh from the triangles described in the buffers passed
     /// This data could come from an OBJ file via [tobj](https://github.com/Twinklebear/tobj)
-    /// for example.
+    /// for example. The triangles are stored in a BVH
     pub fn new(positions: Arc<Vec<Point>>, normals: Arc<Vec<Normal>>, texcoords: Arc<Vec<Point>>,
                indices: Vec<u32>) -> Mesh {
+        Mesh::with_accelerator(positions, normals, texcoords, indices, AcceleratorType::BVH)
+    }
+    /// Create a new Mesh from the triangles described in the buffers passed, storing
+    /// the triangles in the type of acceleration structure requested
+    pub fn with_accelerator(positions: Arc<Vec<Point>>, normals: Arc<Vec<Normal>>, texcoords: Arc<Vec<Point>>,
+                            indices: Vec<u32>, accelerator: AcceleratorType) -> Mesh {
         let triangles = indices.chunks(3).map(|i| {
             Triangle::new(i[0] as usize, i[1] as usize, i[2] as usize, positions.clone(),
                           normals.clone(), texcoords.clone())
             }).collect();
-        Mesh { bvh: BVH::unanimated(16, triangles) }
+        let accel = match accelerator {
+            AcceleratorType::BVH => Accelerator::BVH(BVH::unanimated(16, triangles)),
+            AcceleratorType::Grid => Accelerator::Grid(Grid::unanimated(triangles)),
+            AcceleratorType::KdTree => Accelerator::KdTree(KdTree::unanimated(triangles)),
+        };
+        Mesh { accel: accel }
     }
     /// Load all the meshes defined in an OBJ file and return them in a hashmap that maps the
-    /// model's name in the file to its loaded mesh. TODO: Don't build the BVH until we actually
-    /// use the mesh in the scene, will reduce scene load time.
+    /// model's name in the file to its loaded mesh. The triangles of each mesh are stored in
+    /// a BVH. TODO: Don't build the BVH until we actually use the mesh in the scene, will
+    /// reduce scene load time.
     /// TODO: Currently materials are ignored
     pub fn load_obj(file_name: &Path) -> HashMap<String, Arc<Mesh>> {
-        match tobj::load_obj(file_name) {
-            Ok((models, _)) => {
-                let mut meshes = HashMap::new();
-                for m in models {
-                    println!("Loading model {}", m.name);
-                    let mesh = m.mesh;
-                    if mesh.normals.is_empty() || mesh.texcoords.is_empty() {
-                        print!("Mesh::load_obj error! Normals and texture coordinates are required!");
-                        println!("Skipping {}", m.name);
-                        continue;
-                    }
-                    println!("{} has {} triangles", m.name, mesh.indices.len() / 3);
-                    let positions = Arc::new(mesh.positions.chunks(3).map(|i| Point::new(i[0], i[1], i[2]))
-                                             .collect());
-                    let normals = Arc::new(mesh.normals.chunks(3).map(|i| Normal::new(i[0], i[1], i[2]))
-                                           .collect());
-                    let texcoords = Arc::new(mesh.texcoords.chunks(2).map(|i| Point::new(i[0], i[1], 0.0))
-                                             .collect());
-                    meshes.insert(m.name, Arc::new(Mesh::new(positions, normals, texcoords, mesh.indices)));
-                }
-                meshes
-            },
-            Err(e) => {
-                println!("Failed to load {:?} due to {:?}", file_name, e);
-                HashMap::new()
+        Mesh::load_obj_with_accelerator(file_name, AcceleratorType::BVH)
+    }
+    /// Load all the meshes defined in an OBJ file like `load_obj`, storing the triangles of
+    /// each mesh in the type of acceleration structure passed
+    pub fn load_obj_with_accelerator(file_name: &Path, accelerator: AcceleratorType) -> HashMap<String, Arc<Mesh>> {
+        build_meshes(load_obj_buffers(file_name), accelerator)
+    }
+    /// Load all the meshes defined in an OBJ file like `load_obj`, but read the parsed buffers
+    /// from the disk cache next to the file if it's up to date. If the cache is missing or out
+    /// of date the OBJ is parsed and a new cache is written for the next run.
+    pub fn load_obj_cached(file_name: &Path) -> HashMap<String, Arc<Mesh>> {
+        Mesh::load_obj_cached_with_accelerator(file_name, AcceleratorType::BVH)
+    }
+    /// Load all the meshes defined in an OBJ file like `load_obj_cached`, storing the triangles
+    /// of each mesh in the type of acceleration structure passed
+    pub fn load_obj_cached_with_accelerator(file_name: &Path, accelerator: AcceleratorType)
+                                            -> HashMap<String, Arc<Mesh>> {
+        let mtime = match modified_time(file_name) {
+            Some(t) => t,
+            None => {
+                warn!("Can't read the modification time of {:?}, not caching its meshes", file_name);
+                return Mesh::load_obj_with_accelerator(file_name, accelerator);
             },
+        };
+        let cache_file = cache_file_name(file_name);
+        if let Ok(f) = File::open(&cache_file) {
+            let mut reader = BufReader::new(f);
+            match bincode::deserialize_from::<_, MeshCache, _>(&mut reader, Infinite) {
+                Ok(c) => {
+                    if c.obj_modified == mtime {
+                        info!("Loading cached meshes from {:?}", cache_file);
+                        return build_meshes(c.models, accelerator);
+                    }
+                    info!("Mesh cache {:?} is out of date, reloading {:?}", cache_file, file_name);
+                },
+                Err(e) => warn!("Failed to read mesh cache {:?} due to {:?}", cache_file, e),
+            }
         }
+        let models = load_obj_buffers(file_name);
+        if !models.is_empty() {
+            let cache = MeshCache { obj_modified: mtime, models: models };
+            match File::create(&cache_file) {
+                Ok(f) => {
+                    let mut writer = BufWriter::new(f);
+                    if let Err(e) = bincode::serialize_into(&mut writer, &cache, Infinite) {
+                        warn!("Failed to write mesh cache {:?} due to {:?}", cache_file, e);
+                    }
+                },
+                Err(e) => warn!("Failed to create mesh cache {:?} due to {:?}", cache_file, e),
+            }
+            build_meshes(cache.models, accelerator)
+        } else {
+            HashMap::new()
+        }
+    }
+}
+
+/// The raw buffers for a model loaded from an OBJ file, as stored in the disk cache
+#[derive(Serialize, Deserialize)]
+struct ModelBuffers {
+    name: String,
+    positions: Vec<f32>,
+    normals: Vec<f32>,
+    texcoords: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+/// The disk cache of an OBJ file's models, tagged with the modification time of
+/// the OBJ file when it was parsed
+#[derive(Serialize, Deserialize)]
+struct MeshCache {
+    /// Seconds and nanoseconds since the Unix epoch that the OBJ was last modified
+    obj_modified: (u64, u32),
+    models: Vec<ModelBuffers>,
+}
+
+/// Parse the models in the OBJ file, skipping any that are missing normals or texture coordinates
+fn load_obj_buffers(file_name: &Path) -> Vec<ModelBuffers> {
+    match tobj::load_obj(file_name) {
+        Ok((models, _)) => {
+            let mut buffers = Vec::with_capacity(models.len());
+            for m in models {
+                let mesh = m.mesh;
+                if mesh.normals.is_empty() || mesh.texcoords.is_empty() {
+                    warn!("Normals and texture coordinates are required for meshes, skipping {}", m.name);
+                    continue;
+                }
+                buffers.push(ModelBuffers { name: m.name, positions: mesh.positions, normals: mesh.normals,
+                                            texcoords: mesh.texcoords, indices: mesh.indices });
+            }
+            buffers
+        },
+        Err(e) => {
+            error!("Failed to load {:?} due to {:?}", file_name, e);
+            Vec::new()
+        },
+    }
+}
+
+/// Build the meshes for the model buffers passed, returning a map of model name to mesh
+fn build_meshes(models: Vec<ModelBuffers>, accelerator: AcceleratorType) -> HashMap<String, Arc<Mesh>> {
+    let mut meshes = HashMap::new();
+    for m in models {
+        info!("Loading model {}", m.name);
+        debug!("{} has {} triangles", m.name, m.indices.len() / 3);
+        let positions = Arc::new(m.positions.chunks(3).map(|i| Point::new(i[0], i[1], i[2]))
+                                 .collect());
+        let normals = Arc::new(m.normals.chunks(3).map(|i| Normal::new(i[0], i[1], i[2]))
+                               .collect());
+        let texcoords = Arc::new(m.texcoords.chunks(2).map(|i| Point::new(i[0], i[1], 0.0))
+                                 .collect());
+        meshes.insert(m.name, Arc::new(Mesh::with_accelerator(positions, normals, texcoords, m.indices,
+                                                              accelerator)));
+    }
+    meshes
+}
+
+/// Get the path of the disk cache for the OBJ file, e.g. `bunny.obj` -> `bunny.obj.cache`
+fn cache_file_name(file_name: &Path) -> PathBuf {
+    let mut name = file_name.as_os_str().to_owned();
+    name.push(".cache");
+    PathBuf::from(name)
+}
+
+/// Get the time the file was last modified as seconds and nanoseconds since the Unix epoch
+fn modified_time(file_name: &Path) -> Option<(u64, u32)> {
+    let modified = match fs::metadata(file_name).and_then(|m| m.modified()) {
+        Ok(t) => t,
+        Err(_) => return None,
+    };
+    match modified.duration_since(UNIX_EPOCH) {
+        Ok(d) => Some((d.as_secs(), d.subsec_nanos())),
+        Err(_) => None,
     }
 }
 
 impl Geometry for Mesh {
     fn intersect(&self, ray: &mut linalg::Ray) -> Option<DifferentialGeometry> {
-        self.bvh.intersect(ray, |r, i| i.intersect(r))
+        self.accel.intersect(ray, |r, i| i.intersect(r))
+    }
+    fn rasterize_uv<'a>(&'a self, dim: (usize, usize),
+                        f: &mut FnMut((usize, usize), DifferentialGeometry<'a>)) {
+        for t in self.accel.iter() {
+            t.rasterize_uv(dim, f);
+        }
     }
 }
 
 impl Boundable for Mesh {
     fn bounds(&self, start: f32, end: f32) -> BBox {
-        self.bvh.bounds(start, end)
+        self.accel.bounds(start, end)
     }
 }
 
@@ -123,6 +281,46 @@ impl Geometry for Triangle {
         let tc = &self.texcoords[self.c];
         intersect_triangle(self, ray, pa, pb, pc, na, nb, nc, ta, tb, tc)
     }
+    fn rasterize_uv<'a>(&'a self, dim: (usize, usize),
+                        f: &mut FnMut((usize, usize), DifferentialGeometry<'a>)) {
+        let pa = &self.positions[self.a];
+        let pb = &self.positions[self.b];
+        let pc = &self.positions[self.c];
+        let na = &self.normals[self.a];
+        let nb = &self.normals[self.b];
+        let nc = &self.normals[self.c];
+        let ta = &self.texcoords[self.a];
+        let tb = &self.texcoords[self.b];
+        let tc = &self.texcoords[self.c];
+        // Texel centers inside the triangle in texture space are covered by it
+        let denom = (tb.y - tc.y) * (ta.x - tc.x) + (tc.x - tb.x) * (ta.y - tc.y);
+        if denom == 0.0 || dim.0 == 0 || dim.1 == 0 {
+            return;
+        }
+        // Find the range of texels whose centers may be inside the triangle
+        let texel_range = |a: f32, b: f32, c: f32, n: usize| {
+            let lo = f32::floor(f32::min(a, f32::min(b, c)) * n as f32 - 0.5);
+            let hi = f32::ceil(f32::max(a, f32::max(b, c)) * n as f32 - 0.5);
+            (f32::max(lo, 0.0) as usize, f32::min(f32::max(hi, 0.0), (n - 1) as f32) as usize)
+        };
+        let x_range = texel_range(ta.x, tb.x, tc.x, dim.0);
+        let y_range = texel_range(ta.y, tb.y, tc.y, dim.1);
+        for y in y_range.0..y_range.1 + 1 {
+            let v = (y as f32 + 0.5) / dim.1 as f32;
+            for x in x_range.0..x_range.1 + 1 {
+                let u = (x as f32 + 0.5) / dim.0 as f32;
+                let mut bary = [0.0; 3];
+                bary[0] = ((tb.y - tc.y) * (u - tc.x) + (tc.x - tb.x) * (v - tc.y)) / denom;
+                bary[1] = ((tc.y - ta.y) * (u - tc.x) + (ta.x - tc.x) * (v - tc.y)) / denom;
+                bary[2] = 1.0 - bary[0] - bary[1];
+                if bary.iter().any(|b| *b < 0.0) {
+                    continue;
+                }
+                let p = bary[0] * *pa + bary[1] * *pb + bary[2] * *pc;
+                f((x, y), triangle_dg(self, &p, &bary, pa, pb, pc, na, nb, nc, ta, tb, tc, 0.0));
+            }
+        }
+    }
 }
 
 impl Boundable for Triangle {
@@ -169,7 +367,16 @@ pub fn intersect_triangle<'a, G: Geometry>(geom: &'a G, ray: &mut Ray,
     bary[0] = 1.0 - bary[1] - bary[2];
     ray.max_t = t;
     let p = ray.at(t);
+    Some(triangle_dg(geom, &p, &bary, pa, pb, pc, na, nb, nc, ta, tb, tc, ray.time))
+}
 
+/// Compute the differential geometry at the point `p` on the triangle with barycentric
+/// coordinates `bary`
+fn triangle_dg<'a, G: Geometry>(geom: &'a G, p: &Point, bary: &[f32; 3],
+                                pa: &Point, pb: &Point, pc: &Point,
+                                na: &Normal, nb: &Normal, nc: &Normal,
+                                ta: &Point, tb: &Point, tc: &Point, time: f32) -> DifferentialGeometry<'a> {
+    let e = [*pb - *pa, *pc - *pa];
     // Now compute normal at this location on the triangle
     let n = (bary[0] * *na + bary[1] * *nb + bary[2] * *nc).normalized();
 
@@ -194,6 +401,41 @@ pub fn intersect_triangle<'a, G: Geometry>(geom: &'a G, ray: &mut Ray,
             let dp_dv = (-du[1] * dp[0] + du[0] * dp[1]) * det;
             (dp_du, dp_dv)
         };
-    Some(DifferentialGeometry::with_normal(&p, &n, texcoord.x, texcoord.y, ray.time, &dp_du, &dp_dv, geom))
+    let mut dg = DifferentialGeometry::with_normal(p, &n, texcoord.x, texcoord.y, time, &dp_du, &dp_dv, geom);
+    // The face normal follows the counter-clockwise winding of the triangle
+    let nf = linalg::cross(&e[0], &e[1]);
+    if nf.length_sqr() > 0.0 {
+        let nf = nf.normalized();
+        dg.face_normal = Normal::new(nf.x, nf.y, nf.z);
+    }
+    dg
 }
 
+
+#[test]
+fn test_rasterize_uv() {
+    // A triangle covering the lower left half of texture space, placed so its positions
+    // match its texture coordinates
+    let uvs = vec![Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0), Point::new(0.0, 1.0, 0.0)];
+    let normals = vec![Normal::new(0.0, 0.0, 1.0); 3];
+    let tri = Triangle::new(0, 1, 2, Arc::new(uvs.clone()), Arc::new(normals), Arc::new(uvs));
+    let mut covered = Vec::new();
+    tri.rasterize_uv((4, 4), &mut |texel, dg| {
+        let center = Point::new((texel.0 as f32 + 0.5) / 4.0, (texel.1 as f32 + 0.5) / 4.0, 0.0);
+        assert!(dg.p.distance(&center) < 1e-5);
+        assert!(f32::abs(dg.u - center.x) < 1e-5 && f32::abs(dg.v - center.y) < 1e-5);
+        assert!(f32::abs(dg.n.z - 1.0) < 1e-5);
+        covered.push(texel);
+    });
+    // Only the texels whose centers are inside the triangle are covered
+    covered.sort();
+    let mut expected = Vec::new();
+    for x in 0..4 {
+        for y in 0..4 {
+            if x + y <= 3 {
+                expected.push((x, y));
+            }
+        }
+    }
+    assert_eq!(covered, expected);
+}
@@ -30,26 +30,42 @@ pub use self::intersection::Intersection;
 pub use self::instance::Instance;
 pub use self::sphere::Sphere;
 pub use self::disk::Disk;
+pub use self::cone::Cone;
 pub use self::rectangle::Rectangle;
+pub use self::infinite_plane::InfinitePlane;
 pub use self::bbox::BBox;
-pub use self::bvh::BVH;
+pub use self::bvh::{BVH, SAHParams, TraversalStats};
+pub use self::grid::Grid;
+pub use self::kdtree::KdTree;
+pub use self::accelerator::{Accelerator, AcceleratorType};
 pub use self::mesh::Mesh;
 pub use self::animated_mesh::AnimatedMesh;
-pub use self::receiver::Receiver;
-pub use self::emitter::Emitter;
+pub use self::instanced::Instanced;
+pub use self::receiver::{Receiver, ClipPlane};
+pub use self::emitter::{Emitter, EmissionUnit};
+pub use self::portal::Portal;
 
 pub mod differential_geometry;
 pub mod intersection;
 pub mod instance;
 pub mod sphere;
 pub mod disk;
+pub mod cone;
 pub mod rectangle;
+pub mod infinite_plane;
 pub mod bbox;
 pub mod bvh;
+pub mod grid;
+pub mod kdtree;
+pub mod accelerator;
 pub mod mesh;
 pub mod animated_mesh;
+pub mod instanced;
 pub mod receiver;
 pub mod emitter;
+pub mod portal;
+#[cfg(test)]
+pub mod test_util;
 
 /// Trait implemented by geometric primitives
 pub trait Geometry {
@@ -59,6 +75,13 @@ pub trait Geometry {
     /// Returns the differential geometry containing the hit information if the
     /// ray hit the object and set's the ray's `max_t` member accordingly
     fn intersect(&self, ray: &mut Ray) -> Option<DifferentialGeometry>;
+    /// Rasterize the surface in texture space into an image with `dim` texels, calling `f` with
+    /// each texel covered by the surface and the differential geometry of the surface at the
+    /// texel's center, in the geometry's object space. This is used to bake lighting into
+    /// textures, the default implementation is for geometry which doesn't support it and
+    /// doesn't cover any texels.
+    fn rasterize_uv<'a>(&'a self, _dim: (usize, usize),
+                        _f: &mut FnMut((usize, usize), DifferentialGeometry<'a>)) {}
 }
 
 /// Trait implemented by scene objects that can report an AABB describing their bounds
@@ -73,18 +96,24 @@ pub trait Boundable {
     fn update_deformation(&mut self, start: f32, end: f32) {}
 }
 
-/// Trait implemented by geometry that can sample a point on its surface
+/// Trait implemented by geometry that can sample a point on its surface. Sampling
+/// is done in the object's space and the PDFs returned are always with respect to
+/// solid angle as seen from the point `p` being illuminated. Shapes which sample points
+/// by area must convert the area PDF to solid angle by the geometry term `d^2 / |cos theta|`,
+/// where `d` is the distance from `p` to the sampled point and `theta` the angle between
+/// the surface normal and the direction back to `p`.
 pub trait Sampleable {
     /// Uniformly sample a position and normal on the surface using the samples passed
     fn sample_uniform(&self, samples: &(f32, f32)) -> (Point, Normal);
-    /// Sample the object using the probability density of the solid angle
-    /// from `p` to the sampled point on the surface.
+    /// Sample a point on the object as seen from `p`, the density of the direction
+    /// from `p` to the sampled point is given by `pdf`.
     /// Returns the sampled point and the surface normal at that point
     fn sample(&self, p: &Point, samples: &(f32, f32)) -> (Point, Normal);
     /// Return the surface area of the shape
     fn surface_area(&self) -> f32;
-    /// Compute the PDF that the ray from `p` with direction `w_i` intersects
-    /// the shape
+    /// Compute the PDF with respect to solid angle of sampling the direction `w_i`
+    /// from `p` with `sample`. `w_i` must be normalized and the PDF is 0 if the ray from
+    /// `p` along `w_i` doesn't hit the shape
     fn pdf(&self, p: &Point, w_i: &Vector) -> f32;
 }
 
@@ -0,0 +1,77 @@
+//! Defines a portal, a rectangle placed over an opening like a window that light from
+//! the dome light enters the scene through. Interiors lit through small openings converge
+//! very slowly when the dome is sampled over its whole hemisphere since most of the
+//! shadow rays hit the walls, when the scene has portals the dome light is instead sampled
+//! by picking points on the portals, aiming the shadow rays through the openings.
+//!
+//! Only light passing through the portals is sampled when lighting the scene, so they must
+//! cover every opening light should enter through. Portals aren't visible and don't block
+//! any rays. Like area lights the portal's transform should not contain any scaling, the
+//! size of the portal should be set with its `width` and `height`.
+//!
+//! # Scene Usage Example
+//! A portal is a rectangle centered at the origin facing along [0, 0, 1], and is placed
+//! over the opening with a `transform` or `keyframes` like other objects.
+//!
+//! ```json
+//! "objects": [
+//!     {
+//!         "name": "window",
+//!         "type": "portal",
+//!         "width": 2.0,
+//!         "height": 1.5,
+//!         "transform": [
+//!             {
+//!                 "type": "translate",
+//!                 "translation": [0, 4, 10]
+//!             }
+//!         ]
+//!     },
+//!     ...
+//! ]
+//! ```
+
+use geometry::{Rectangle, Sampleable};
+use linalg::{AnimatedTransform, Point, Vector};
+
+/// A rectangular opening that light from the dome light enters the scene through
+#[derive(Clone)]
+pub struct Portal {
+    rect: Rectangle,
+    /// The transform to world space
+    transform: AnimatedTransform,
+}
+
+impl Portal {
+    /// Create a portal with the width and height passed placed in the world by `transform`
+    pub fn new(width: f32, height: f32, transform: AnimatedTransform) -> Portal {
+        assert!(width > 0.0 && height > 0.0, "Portal width and height must be greater than 0");
+        Portal { rect: Rectangle::new(width, height), transform: transform }
+    }
+    /// Sample a direction from `p` through the portal, returns the direction and its pdf
+    /// with respect to solid angle
+    pub fn sample(&self, p: &Point, samples: &(f32, f32), time: f32) -> (Vector, f32) {
+        let transform = self.transform.transform(time);
+        let p_l = transform.inv_mul_point(p);
+        let (p_sampled, _) = self.rect.sample_uniform(samples);
+        let w_il = (p_sampled - p_l).normalized();
+        let pdf = self.rect.pdf(&p_l, &w_il);
+        ((transform * w_il).normalized(), pdf)
+    }
+    /// Compute the pdf of sampling the direction `w_i` from `p` through the portal,
+    /// 0 if the direction doesn't pass through the portal
+    pub fn pdf(&self, p: &Point, w_i: &Vector, time: f32) -> f32 {
+        let transform = self.transform.transform(time);
+        let p_l = transform.inv_mul_point(p);
+        let w = transform.inv_mul_vector(w_i).normalized();
+        self.rect.pdf(&p_l, &w)
+    }
+    /// Get the transform to place the portal into world space
+    pub fn get_transform(&self) -> &AnimatedTransform {
+        &self.transform
+    }
+    /// Set the transform to place the portal into world space
+    pub fn set_transform(&mut self, transform: AnimatedTransform) {
+        self.transform = transform;
+    }
+}
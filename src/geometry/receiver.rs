@@ -3,7 +3,29 @@
 use std::sync::Arc;
 use geometry::{Boundable, BBox, BoundableGeom, DifferentialGeometry};
 use material::Material;
-use linalg::{Ray, AnimatedTransform};
+use linalg::{self, Ray, AnimatedTransform, Point, Normal};
+use integrator;
+
+/// A plane clipping away the part of a receiver behind it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    /// A point on the plane in world space
+    pub point: Point,
+    /// The normal of the plane, pointing towards the side of the receiver that's kept
+    pub normal: Normal,
+}
+
+impl ClipPlane {
+    /// Create a clip plane through `point` keeping the side `normal` points towards
+    pub fn new(point: Point, normal: Normal) -> ClipPlane {
+        assert!(normal.length_sqr() > 0.0, "Clip plane normals can't be zero");
+        ClipPlane { point: point, normal: normal.normalized() }
+    }
+    /// Check if the world space point is clipped away by the plane
+    pub fn clips(&self, p: &Point) -> bool {
+        linalg::dot(&(*p - self.point), &self.normal) < 0.0
+    }
+}
 
 /// An instance of geometry in the scene that only receives light
 pub struct Receiver {
@@ -11,8 +33,21 @@ pub struct Receiver {
     geom: Arc<BoundableGeom + Send + Sync>,
     /// The material being used by this instance.
     pub material: Arc<Material + Send + Sync>,
+    /// Name of the material in the scene file, empty if the material wasn't loaded from one
+    pub material_name: String,
     /// The transform to world space
     transform: AnimatedTransform,
+    /// Whether the instance is hit by primary camera rays, if false the instance
+    /// only shows up indirectly, e.g. in reflections, refractions or shadows
+    pub primary_visible: bool,
+    /// Planes clipping away parts of the instance, hits on the clipped side of any
+    /// of the planes are ignored
+    pub clip_planes: Vec<ClipPlane>,
+    /// Whether to flip the shading and geometric normals of the geometry
+    pub flip_normals: bool,
+    /// Whether to shade with the normal of the face hit instead of the geometry's shading and
+    /// geometric normals, e.g. to ignore the normals in a mesh file
+    pub recompute_normals: bool,
     /// Tag to identify the instance
     pub tag: String,
 }
@@ -21,26 +56,80 @@ impl Receiver {
     /// Create a new instance of some geometry in the scene
     pub fn new(geom: Arc<BoundableGeom + Send + Sync>, material: Arc<Material + Send + Sync>,
                transform: AnimatedTransform, tag: String) -> Receiver {
-        Receiver { geom: geom, material: material, transform: transform, tag: tag }
+        Receiver { geom: geom, material: material, material_name: String::new(), transform: transform,
+                   primary_visible: true, clip_planes: Vec::new(), flip_normals: false,
+                   recompute_normals: false, tag: tag }
     }
     /// Test the ray for intersection against this insance of geometry.
     /// returns Some(Intersection) if an intersection was found and None if not.
     /// If an intersection is found `ray.max_t` will be set accordingly
     pub fn intersect(&self, ray: &mut Ray) -> Option<(DifferentialGeometry, &Material)> {
+        if ray.primary && !self.primary_visible {
+            return None;
+        }
         let transform = self.transform.transform(ray.time);
         let mut local = transform.inv_mul_ray(ray);
-        let mut dg = match self.geom.intersect(&mut local) {
-            Some(dg) => dg,
-            None => return None,
+        // Geometry with many clipped surfaces along the ray is treated as missed after stepping
+        // past as many surfaces as the default ray limit
+        let mut hits = 0;
+        let mut dg = loop {
+            if hits == integrator::DEFAULT_RAY_LIMIT {
+                integrator::ray_limit_reached(hits);
+                return None;
+            }
+            hits += 1;
+            let dg = match self.geom.intersect(&mut local) {
+                Some(dg) => dg,
+                None => return None,
+            };
+            let p = transform * dg.p;
+            if !self.clip_planes.iter().any(|c| c.clips(&p)) {
+                break dg;
+            }
+            // Keep looking for a hit on the geometry past the clipped one
+            local.min_t = local.max_t + f32::max(1e-4, local.max_t * 1e-5);
+            local.max_t = ray.max_t;
         };
         ray.max_t = local.max_t;
+        self.fix_normals(&mut dg);
         dg.p = transform * dg.p;
         dg.n = transform * dg.n;
         dg.ng = transform * dg.ng;
+        dg.face_normal = transform * dg.face_normal;
         dg.dp_du = transform * dg.dp_du;
         dg.dp_dv = transform * dg.dp_dv;
         Some((dg, &*self.material))
     }
+    /// Rasterize the receiver's geometry in texture space into an image with `dim` texels,
+    /// calling `f` with each covered texel and the world space differential geometry at its
+    /// center at the time passed. See `Geometry::rasterize_uv`
+    pub fn rasterize_uv<'a>(&'a self, dim: (usize, usize), time: f32,
+                            f: &mut FnMut((usize, usize), DifferentialGeometry<'a>)) {
+        let transform = self.transform.transform(time);
+        self.geom.rasterize_uv(dim, &mut |texel, mut dg| {
+            self.fix_normals(&mut dg);
+            dg.p = transform * dg.p;
+            dg.n = transform * dg.n;
+            dg.ng = transform * dg.ng;
+            dg.face_normal = transform * dg.face_normal;
+            dg.dp_du = transform * dg.dp_du;
+            dg.dp_dv = transform * dg.dp_dv;
+            dg.time = time;
+            f(texel, dg);
+        });
+    }
+    /// Apply the instance's normal fix-ups to the differential geometry of a hit on its geometry
+    fn fix_normals(&self, dg: &mut DifferentialGeometry) {
+        if self.recompute_normals {
+            dg.n = dg.face_normal;
+            dg.ng = dg.face_normal;
+        }
+        if self.flip_normals {
+            dg.n = -dg.n;
+            dg.ng = -dg.ng;
+            dg.face_normal = -dg.face_normal;
+        }
+    }
     /// Get the transform to place the receiver into world space
     pub fn get_transform(&self) -> &AnimatedTransform {
         &self.transform
@@ -57,3 +146,50 @@ impl Boundable for Receiver {
     }
 }
 
+
+#[test]
+fn test_clip_planes() {
+    use geometry::Sphere;
+    use linalg::Vector;
+    use test_util::{gray_matte, translate};
+    let mut r = Receiver::new(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 5.0),
+                              "sphere".to_owned());
+    // Cut away the half of the sphere facing the ray
+    r.clip_planes.push(ClipPlane::new(Point::new(0.0, 0.0, 5.0), Normal::new(0.0, 0.0, 1.0)));
+    let mut ray = Ray::new(&Point::broadcast(0.0), &Vector::new(0.0, 0.0, 1.0), 0.0);
+    {
+        let (dg, _) = r.intersect(&mut ray).expect("Ray should hit the inside of the back half");
+        assert!(f32::abs(dg.p.z - 6.0) < 1e-4);
+    }
+    assert!(f32::abs(ray.max_t - 6.0) < 1e-4);
+    // Rays only passing through the clipped half miss
+    let mut ray = Ray::new(&Point::new(-2.0, 0.0, 4.5), &Vector::new(1.0, 0.0, 0.0), 0.0);
+    assert!(r.intersect(&mut ray).is_none());
+}
+
+#[test]
+fn test_fix_normals() {
+    use geometry::Mesh;
+    use linalg::Vector;
+    use test_util::{gray_matte, translate};
+    // A triangle wound to face -Z but whose file normals point the other way, the file
+    // normals are used unless they're recomputed
+    let positions = vec![Point::new(-1.0, -1.0, 0.0), Point::new(0.0, 1.0, 0.0), Point::new(1.0, -1.0, 0.0)];
+    let normals = vec![Normal::new(0.0, 0.0, 1.0); 3];
+    let mesh = Arc::new(Mesh::new(Arc::new(positions.clone()), Arc::new(normals), Arc::new(positions),
+                                  vec![0, 1, 2]));
+    let mut r = Receiver::new(mesh, gray_matte(), translate(0.0, 0.0, 5.0), "triangle".to_owned());
+    let normals_at_hit = |r: &Receiver| {
+        let mut ray = Ray::new(&Point::broadcast(0.0), &Vector::new(0.0, 0.0, 1.0), 0.0);
+        let (dg, _) = r.intersect(&mut ray).expect("Ray should hit the triangle");
+        (dg.n.normalized().z, dg.ng.normalized().z)
+    };
+    assert_eq!(normals_at_hit(&r), (1.0, 1.0));
+    r.flip_normals = true;
+    assert_eq!(normals_at_hit(&r), (-1.0, -1.0));
+    r.flip_normals = false;
+    r.recompute_normals = true;
+    assert_eq!(normals_at_hit(&r), (-1.0, -1.0));
+    r.flip_normals = true;
+    assert_eq!(normals_at_hit(&r), (1.0, 1.0));
+}
@@ -104,3 +104,9 @@ impl Sampleable for Rectangle {
     }
 }
 
+#[test]
+fn test_pdf_integrates_to_one() {
+    use geometry::test_util::assert_pdf_integrates_to_one;
+    let points = [Point::new(0.3, -0.2, 1.5), Point::new(0.5, 0.25, -1.0)];
+    assert_pdf_integrates_to_one(&Rectangle::new(2.0, 1.0), &points);
+}
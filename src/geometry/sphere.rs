@@ -96,6 +96,12 @@ impl Sampleable for Sphere {
     /// Sample the object using the probability density of the solid angle
     /// from `p` to the sampled point on the surface.
     /// Returns the sampled point and the surface normal at that point
+    ///
+    /// From outside the sphere directions are sampled uniformly in the cone subtended
+    /// by the sphere and the point is found analytically on the cap of the sphere visible
+    /// from `p`, instead of intersecting a ray with the sphere. Rays near the edge of the
+    /// cone would otherwise miss due to rounding error, which is common when `p` is close to
+    /// the sphere and the cone is nearly a hemisphere.
     fn sample(&self, p: &Point, samples: &(f32, f32)) -> (Point, Normal) {
         // If the point is inside the sphere just sample uniformly
         let dist_sqr = p.distance_sqr(&Point::broadcast(0.0));
@@ -107,36 +113,81 @@ impl Sampleable for Sphere {
             let w_z = (Point::broadcast(0.0) - *p).normalized();
             let (w_x, w_y) = linalg::coordinate_system(&w_z);
             // Compute theta and phi for samples in the cone of the sphere seen from `p`
-            let cos_theta_max = f32::sqrt(f32::max(0.0, 1.0 - self.radius * self.radius / dist_sqr));
-            let mut ray = Ray::new(p, &mc::uniform_sample_cone_frame(samples, cos_theta_max,
-                                                                     &w_x, &w_y, &w_z).normalized(), 0.0);
-            // Try to hit the sphere with this ray to sample it
-            match self.intersect(&mut ray) {
-                Some(dg) => (dg.p, dg.ng),
-                None => {
-                    // If we miss find where we kind of hit at the edge
-                    let t = linalg::dot(&(Point::broadcast(0.0) - *p), &ray.d);
-                    let p = ray.at(t);
-                    (p, Normal::new(p.x, p.y, p.z).normalized())
-                }
-            }
+            let sin_theta_max_sqr = self.radius * self.radius / dist_sqr;
+            let cos_theta_max = f32::sqrt(f32::max(0.0, 1.0 - sin_theta_max_sqr));
+            let cos_theta = linalg::lerp(samples.0, &cos_theta_max, &1.0);
+            let sin_theta_sqr = f32::max(0.0, 1.0 - cos_theta * cos_theta);
+            let phi = samples.1 * f32::consts::PI * 2.0;
+            // Find the angle alpha from the sphere's center between the direction to `p` and
+            // the first point hit by the ray along the sampled direction
+            let dist = f32::sqrt(dist_sqr);
+            let dist_surf = dist * cos_theta
+                - f32::sqrt(f32::max(0.0, self.radius * self.radius - dist_sqr * sin_theta_sqr));
+            let cos_alpha = linalg::clamp((dist_sqr + self.radius * self.radius - dist_surf * dist_surf)
+                                          / (2.0 * dist * self.radius), -1.0, 1.0);
+            let sin_alpha = f32::sqrt(f32::max(0.0, 1.0 - cos_alpha * cos_alpha));
+            // The normal points back towards `p`, so is along -w_z at alpha = 0
+            let n = -(f32::cos(phi) * sin_alpha * w_x + f32::sin(phi) * sin_alpha * w_y + cos_alpha * w_z);
+            let n = Normal::new(n.x, n.y, n.z).normalized();
+            (Point::new(n.x, n.y, n.z) * self.radius, n)
         }
     }
     /// Compute the sphere's surface area
     fn surface_area(&self) -> f32 {
-        4.0 * f32::consts::PI * self.radius
+        4.0 * f32::consts::PI * self.radius * self.radius
     }
     /// Compute the PDF that the ray from `p` with direction `w_i` intersects
     /// the shape
-    fn pdf(&self, p: &Point, _: &Vector) -> f32 {
+    fn pdf(&self, p: &Point, w_i: &Vector) -> f32 {
         let dist_sqr = p.distance_sqr(&Point::broadcast(0.0));
-        // The PDF is uniform if we're insidfe the sphere
+        // If we're inside the sphere points are sampled uniformly by area, so convert
+        // the area PDF to solid angle
         if dist_sqr - self.radius * self.radius < 0.0001 {
-            1.0 / self.surface_area()
+            let mut ray = Ray::segment(p, w_i, 0.001, f32::INFINITY, 0.0);
+            match self.intersect(&mut ray) {
+                Some(d) => {
+                    let w = -*w_i;
+                    let pdf = p.distance_sqr(&ray.at(ray.max_t))
+                        / (f32::abs(linalg::dot(&d.n.normalized(), &w)) * self.surface_area());
+                    if f32::is_finite(pdf) { pdf } else { 0.0 }
+                },
+                None => 0.0
+            }
         } else {
             let cos_theta_max = f32::sqrt(f32::max(0.0, 1.0 - self.radius * self.radius / dist_sqr));
-            mc::uniform_cone_pdf(cos_theta_max)
+            let w_z = (Point::broadcast(0.0) - *p).normalized();
+            if linalg::dot(w_i, &w_z) < cos_theta_max {
+                0.0
+            } else {
+                mc::uniform_cone_pdf(cos_theta_max)
+            }
         }
     }
 }
 
+#[test]
+fn test_pdf_integrates_to_one() {
+    use geometry::test_util::assert_pdf_integrates_to_one;
+    let points = [Point::new(0.0, 0.0, 3.0), Point::new(2.0, -1.5, 0.5), Point::new(0.2, 0.1, -0.3)];
+    assert_pdf_integrates_to_one(&Sphere::new(1.0), &points);
+}
+
+#[test]
+fn test_sample_visible_cap() {
+    // Points sampled from outside the sphere, even very close to it, must lie on the
+    // sphere on the cap facing the point with a direction the PDF can generate
+    let shape = Sphere::new(1.0);
+    let n = 32;
+    for p in &[Point::new(0.0, 0.0, 3.0), Point::new(0.6, -0.8, 0.02), Point::new(-0.5, 0.5, 0.71)] {
+        for i in 0..n {
+            for j in 0..n {
+                let s = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let (ps, ns) = shape.sample(p, &s);
+                let to_p = *p - ps;
+                assert!(f32::abs(ps.distance(&Point::broadcast(0.0)) - 1.0) < 1e-4);
+                assert!(linalg::dot(&ns, &to_p) >= -1e-4, "Sampled {:?} facing away from {:?}", ps, p);
+                assert!(shape.pdf(p, &-to_p.normalized()) > 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Fixtures and checks shared by the geometry tests
+
+use std::f32;
+
+use geometry::{SampleableGeom, Boundable, BBox};
+use linalg::{Point, Vector, Ray};
+use mc;
+
+/// Check that the solid angle PDF of sampling the geometry from each of the points integrates
+/// to one over the sphere of directions, estimated with a grid of uniformly distributed directions
+pub fn assert_pdf_integrates_to_one<G: SampleableGeom>(geom: &G, points: &[Point]) {
+    let n = 1024;
+    for p in points {
+        let mut integral = 0.0f64;
+        for i in 0..n {
+            for j in 0..n {
+                let s = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let w = mc::uniform_sample_sphere(&s);
+                integral += (geom.pdf(p, &w) * 4.0 * f32::consts::PI) as f64;
+            }
+        }
+        integral /= (n * n) as f64;
+        assert!(f64::abs(integral - 1.0) < 0.02, "PDF integrated to {} from {:?}", integral, p);
+    }
+}
+
+/// A solid box that returns its index when hit, used to test the acceleration structures
+pub struct TestBox(pub BBox, pub usize);
+
+impl TestBox {
+    /// Create the box with index `i` centered at `c` extending `r` along each axis
+    pub fn new(c: Point, r: f32, i: usize) -> TestBox {
+        TestBox(BBox::span(c - Vector::broadcast(r), c + Vector::broadcast(r)), i)
+    }
+    /// Intersect the ray with the box, returning its index if the ray enters it
+    pub fn intersect(&self, ray: &mut Ray) -> Option<usize> {
+        let mut t0 = ray.min_t;
+        let mut t1 = ray.max_t;
+        for a in 0..3 {
+            let ta = (self.0.min[a] - ray.o[a]) / ray.d[a];
+            let tb = (self.0.max[a] - ray.o[a]) / ray.d[a];
+            t0 = f32::max(t0, f32::min(ta, tb));
+            t1 = f32::min(t1, f32::max(ta, tb));
+        }
+        if t0 > t1 || t0 == ray.min_t {
+            return None;
+        }
+        ray.max_t = t0;
+        Some(self.1)
+    }
+}
+
+impl Boundable for TestBox {
+    fn bounds(&self, _: f32, _: f32) -> BBox {
+        self.0
+    }
+}
+
+/// Make a `dims.0 x dims.1 x dims.2` grid of boxes spaced `spacing` apart, the size of
+/// box `i` is given by `radius(i)`. The boxes are indexed along x, then y, then z
+pub fn make_boxes(dims: (usize, usize, usize), spacing: Vector, radius: &Fn(usize) -> f32) -> Vec<TestBox> {
+    (0..dims.0 * dims.1 * dims.2).map(|i| {
+        let c = Point::new((i % dims.0) as f32 * spacing.x, ((i / dims.0) % dims.1) as f32 * spacing.y,
+                           (i / (dims.0 * dims.1)) as f32 * spacing.z);
+        TestBox::new(c, radius(i), i)
+    }).collect()
+}
@@ -0,0 +1,74 @@
+//! Defines a scene wide homogeneous fog which attenuates light traveling along rays
+//! with the Beer-Lambert law and adds a constant amount of in-scattered light. This is
+//! a cheap approximation of a participating medium filling the scene, giving distant
+//! objects an atmospheric fade towards the fog color.
+//!
+//! Camera rays and the rays traced by the integrators between surfaces are fogged, shadow
+//! rays are not attenuated. Since the fog fills the entire scene rays which don't hit anything
+//! see the fog color.
+//!
+//! # Scene Usage Example
+//! The fog is specified in the root object of the scene with the color of the in-scattered
+//! light and the density of the fog, which is the fraction of light extinguished per unit distance.
+//!
+//! ```json
+//! "fog": {
+//!     "color": [0.6, 0.65, 0.7],
+//!     "density": 0.02
+//! }
+//! ```
+
+use std::f32;
+
+use linalg::Ray;
+use film::Colorf;
+
+/// A homogeneous fog filling the scene
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    /// Color of the light scattered in to rays by the fog
+    pub color: Colorf,
+    /// Fraction of light extinguished per unit distance
+    pub density: f32,
+}
+
+impl Fog {
+    /// Create a fog with the in-scattered color and density passed
+    pub fn new(color: Colorf, density: f32) -> Fog {
+        assert!(density >= 0.0, "Fog density must not be negative");
+        Fog { color: color, density: density }
+    }
+    /// Compute the fraction of light transmitted through `distance` of fog
+    pub fn transmittance(&self, distance: f32) -> f32 {
+        if distance == f32::INFINITY {
+            if self.density > 0.0 { 0.0 } else { 1.0 }
+        } else {
+            f32::exp(-self.density * distance)
+        }
+    }
+    /// Attenuate the radiance `li` arriving along the ray from its hit point and add
+    /// the light scattered in to the ray by the fog. For rays which didn't hit anything
+    /// `max_t` should be infinite
+    pub fn apply(&self, ray: &Ray, li: &Colorf) -> Colorf {
+        let tr = self.transmittance(ray_distance(ray));
+        *li * tr + self.color * (1.0 - tr)
+    }
+}
+
+/// Compute the world space distance the ray travels to its hit point
+pub fn ray_distance(ray: &Ray) -> f32 {
+    ray.max_t * ray.d.length()
+}
+
+#[test]
+fn test_fog() {
+    use linalg::{Point, Vector};
+    let fog = Fog::new(Colorf::broadcast(0.5), 0.5);
+    let li = Colorf::broadcast(1.0);
+    let mut ray = Ray::segment(&Point::broadcast(0.0), &Vector::new(0.0, 0.0, 2.0), 0.0, 1.0, 0.0);
+    let c = fog.apply(&ray, &li);
+    let tr = f32::exp(-1.0);
+    assert!(f32::abs(c.r - (tr + 0.5 * (1.0 - tr))) < 1e-6);
+    ray.max_t = f32::INFINITY;
+    assert_eq!(fog.apply(&ray, &li).r, 0.5);
+}
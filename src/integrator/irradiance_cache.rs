@@ -0,0 +1,249 @@
+//! Defines the `IrradianceCache` integrator which computes direct lighting at each hit
+//! but interpolates single bounce indirect diffuse lighting from a sparse cache of
+//! irradiance records instead of tracing new paths at every hit. Records are computed
+//! on demand by sampling the hemisphere above a point and are shared by all the render
+//! threads, the cache is cleared at the start of each frame.
+//!
+//! See [Ward et al., A Ray Tracing Solution for Diffuse Interreflection](http://dl.acm.org/citation.cfm?id=378490)
+//!
+//! # Scene Usage Example
+//! The irradiance cache takes a maximum ray depth to terminate specular reflection and
+//! transmission rays, along with optional parameters controlling the cache. `samples`
+//! is the number of hemisphere rays used to compute each record (default 64), `max_error`
+//! controls how far records are re-used (default 0.2, smaller values place records more
+//! densely). `min_spacing` and `max_spacing` clamp the radius of each record in world
+//! units and default to 0.1 and 10.
+//!
+//! ```json
+//! "integrator": {
+//!     "type": "irradiance_cache",
+//!     "max_depth": 4,
+//!     "samples": 128,
+//!     "max_error": 0.15
+//! }
+//! ```
+
+use std::f32;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use enum_set::EnumSet;
+use rand::{StdRng, Rng};
+use light_arena::Allocator;
+
+use scene::Scene;
+use linalg::{self, Ray, Point, Normal, Vector};
+use geometry::{Intersection, Emitter, Instance};
+use film::Colorf;
+use integrator::Integrator;
+use bxdf::{BSDF, BxDFType};
+use sampler::{Sampler, Sample};
+use mc;
+
+/// An irradiance value computed at some point on a surface
+#[derive(Clone, Copy, Debug)]
+struct Record {
+    p: Point,
+    n: Normal,
+    irradiance: Colorf,
+    /// Harmonic mean distance to the surfaces seen from the point
+    radius: f32,
+}
+
+/// The records in the cache, bucketed into a uniform grid to find nearby records quickly.
+/// Each record is stored in every cell its region of validity overlaps
+struct CacheGrid {
+    cell_size: f32,
+    records: Vec<Record>,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl CacheGrid {
+    fn new(cell_size: f32) -> CacheGrid {
+        CacheGrid { cell_size: cell_size, records: Vec::new(), cells: HashMap::new() }
+    }
+    fn cell(&self, p: &Point) -> (i32, i32, i32) {
+        (f32::floor(p.x / self.cell_size) as i32, f32::floor(p.y / self.cell_size) as i32,
+         f32::floor(p.z / self.cell_size) as i32)
+    }
+    /// Insert a record which will be used by points within `reach` of it
+    fn insert(&mut self, record: Record, reach: f32) {
+        let id = self.records.len();
+        let lo = self.cell(&(record.p - Vector::broadcast(reach)));
+        let hi = self.cell(&(record.p + Vector::broadcast(reach)));
+        for x in lo.0..hi.0 + 1 {
+            for y in lo.1..hi.1 + 1 {
+                for z in lo.2..hi.2 + 1 {
+                    self.cells.entry((x, y, z)).or_insert_with(Vec::new).push(id);
+                }
+            }
+        }
+        self.records.push(record);
+    }
+    fn clear(&mut self) {
+        self.records.clear();
+        self.cells.clear();
+    }
+}
+
+/// The irradiance cache integrator, see the module docs for details
+pub struct IrradianceCache {
+    max_depth: u32,
+    samples: usize,
+    max_error: f32,
+    min_spacing: f32,
+    max_spacing: f32,
+    cache: RwLock<CacheGrid>,
+}
+
+impl IrradianceCache {
+    /// Create a new irradiance cache integrator. Records are computed with `samples`
+    /// hemisphere rays and are re-used for points where the estimated error is below
+    /// `max_error`, the radius of each record is clamped to `[min_spacing, max_spacing]`.
+    pub fn new(max_depth: u32, samples: usize, max_error: f32, min_spacing: f32,
+               max_spacing: f32) -> IrradianceCache {
+        assert!(samples > 0, "The irradiance cache must take at least one sample per record");
+        assert!(max_error > 0.0, "The irradiance cache max_error must be greater than 0");
+        assert!(min_spacing > 0.0 && min_spacing <= max_spacing,
+                "The irradiance cache spacing must satisfy 0 < min_spacing <= max_spacing");
+        // A record is only used by points within max_error * radius of it, so sizing
+        // the cells to the largest reach means each record covers at most 8 cells
+        let cell_size = max_error * max_spacing;
+        IrradianceCache { max_depth: max_depth, samples: samples, max_error: max_error,
+                          min_spacing: min_spacing, max_spacing: max_spacing,
+                          cache: RwLock::new(CacheGrid::new(cell_size)) }
+    }
+    /// Get the number of records currently in the cache
+    pub fn num_records(&self) -> usize {
+        self.cache.read().unwrap().records.len()
+    }
+    /// Interpolate the irradiance at the point from the nearby cached records, returns
+    /// None if no record is close enough to be used
+    fn interpolate(&self, p: &Point, n: &Normal) -> Option<Colorf> {
+        let cache = self.cache.read().unwrap();
+        let ids = match cache.cells.get(&cache.cell(p)) {
+            Some(ids) => ids,
+            None => return None,
+        };
+        let mut irradiance = Colorf::black();
+        let mut weight_sum = 0.0;
+        for &i in ids {
+            let r = &cache.records[i];
+            // Skip records in front of the point, they may see different surfaces
+            let d = linalg::dot(&(*p - r.p), &((*n + r.n) * 0.5));
+            if d < -0.01 * r.radius {
+                continue;
+            }
+            let err = p.distance(&r.p) / r.radius
+                + f32::sqrt(f32::max(0.0, 1.0 - linalg::dot(n, &r.n)));
+            let w = 1.0 / f32::max(err, 1e-6);
+            if w > 1.0 / self.max_error {
+                irradiance = irradiance + r.irradiance * w;
+                weight_sum += w;
+            }
+        }
+        if weight_sum > 0.0 {
+            Some(irradiance / weight_sum)
+        } else {
+            None
+        }
+    }
+    /// Compute a new irradiance record at the point by sampling the hemisphere about `n`
+    /// with stratified cosine weighted rays, computing direct lighting where they hit
+    fn compute_record(&self, scene: &Scene, light_list: &[&Emitter], bsdf: &BSDF, n: &Normal,
+                      flip: bool, time: f32, rng: &mut StdRng, alloc: &Allocator) -> Record {
+        let strata = f32::ceil(f32::sqrt(self.samples as f32)) as usize;
+        let mut irradiance = Colorf::black();
+        let mut inv_dist_sum = 0.0;
+        for i in 0..strata {
+            for j in 0..strata {
+                let u = ((i as f32 + rng.next_f32()) / strata as f32,
+                         (j as f32 + rng.next_f32()) / strata as f32);
+                let mut dir = mc::cos_sample_hemisphere(&u);
+                if flip {
+                    dir.z = -dir.z;
+                }
+                let w_i = bsdf.from_shading(&dir);
+                let mut ray = Ray::segment(&bsdf.p, &w_i, 0.001, f32::INFINITY, time);
+                let mut li = Colorf::black();
+                if let Some(hit) = scene.intersect(&mut ray) {
+                    inv_dist_sum += 1.0 / ray.max_t;
+                    // Light seen directly is accounted for by the direct lighting at the
+                    // point so only light reflected off other surfaces is gathered, along
+                    // with light from emissive materials which aren't sampled as lights
+                    if let Instance::Receiver(_) = *hit.instance {
+                        let hit_bsdf = hit.material.bsdf(&hit, alloc);
+                        let light_sample = Sample::new(&(rng.next_f32(), rng.next_f32()), rng.next_f32());
+                        let bsdf_sample = Sample::new(&(rng.next_f32(), rng.next_f32()), rng.next_f32());
+                        li = self.sample_one_light(scene, light_list, &-w_i, &hit.dg.p, &hit_bsdf,
+                                                   &light_sample, &bsdf_sample, rng.next_f32(), time)
+                            + hit.material.emission(&-w_i, &hit);
+                    }
+                }
+                irradiance = irradiance + scene.apply_fog(&ray, &li);
+            }
+        }
+        // With cosine weighted sampling the cos / pdf term is just pi
+        let n_samples = (strata * strata) as f32;
+        irradiance = irradiance * f32::consts::PI / n_samples;
+        let radius = if inv_dist_sum > 0.0 {
+            linalg::clamp(n_samples / inv_dist_sum, self.min_spacing, self.max_spacing)
+        } else {
+            self.max_spacing
+        };
+        Record { p: bsdf.p, n: *n, irradiance: irradiance, radius: radius }
+    }
+}
+
+impl Integrator for IrradianceCache {
+    fn illumination(&self, scene: &Scene, light_list: &[&Emitter], ray: &Ray,
+                    hit: &Intersection, sampler: &mut Sampler, rng: &mut StdRng,
+                    alloc: &Allocator) -> Colorf {
+        let bsdf = hit.material.bsdf(hit, alloc);
+        let w_o = -ray.d;
+        let mut illum = Colorf::black();
+        if ray.depth == 0 {
+            if let Instance::Emitter(ref e) = *hit.instance {
+                illum = illum + e.radiance(&w_o, &hit.dg.p, &hit.dg.ng, ray.time);
+            }
+        }
+        illum = illum + hit.material.emission(&w_o, hit);
+
+        let mut sample_2d = [(0.0, 0.0), (0.0, 0.0)];
+        let mut sample_1d = [0.0, 0.0, 0.0];
+        sampler.get_samples_2d(&mut sample_2d[..], rng);
+        sampler.get_samples_1d(&mut sample_1d[..], rng);
+        let light_sample = Sample::new(&sample_2d[0], sample_1d[0]);
+        let bsdf_sample = Sample::new(&sample_2d[1], sample_1d[1]);
+        illum = illum + self.sample_one_light(scene, light_list, &w_o, &hit.dg.p, &bsdf,
+                                              &light_sample, &bsdf_sample, sample_1d[2], ray.time);
+
+        let mut diffuse = EnumSet::new();
+        diffuse.insert(BxDFType::Diffuse);
+        diffuse.insert(BxDFType::Reflection);
+        if bsdf.num_matching(diffuse) > 0 {
+            // Records are computed on the side of the surface the ray arrived from
+            let flip = linalg::dot(&w_o, &bsdf.n) < 0.0;
+            let n = if flip { -bsdf.n } else { bsdf.n };
+            let irradiance = match self.interpolate(&bsdf.p, &n) {
+                Some(e) => e,
+                None => {
+                    let record = self.compute_record(scene, light_list, &bsdf, &n, flip, ray.time,
+                                                     rng, alloc);
+                    let reach = self.max_error * record.radius;
+                    self.cache.write().unwrap().insert(record, reach);
+                    record.irradiance
+                }
+            };
+            let w_n = Vector::new(n.x, n.y, n.z);
+            illum = illum + bsdf.eval(&w_o, &w_n, diffuse) * irradiance;
+        }
+        if ray.depth < self.max_depth {
+            illum = illum + self.specular_reflection(scene, light_list, ray, &bsdf, sampler, rng, alloc);
+            illum = illum + self.specular_transmission(scene, light_list, ray, &bsdf, sampler, rng, alloc);
+        }
+        illum
+    }
+    fn begin_frame(&self) {
+        self.cache.write().unwrap().clear();
+    }
+}
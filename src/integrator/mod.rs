@@ -13,9 +13,29 @@
 //!     ...
 //! }
 //! ```
+//!
+//! # Ray Limit
+//! As a safety valve against pathological scenes, e.g. a very large `max_depth` or deeply nested
+//! glass, the path tracer and Whitted integrators stop tracing once `ray_limit` rays have been
+//! traced for a camera ray, including the camera ray itself. The limit is well above what
+//! reasonable settings need and defaults to 4096. Shadow rays also stop after passing through
+//! `ray_limit` transmissive surfaces and are treated as blocked, and rays stepping past the
+//! parts of an object cut away by its clip planes stop after the default limit and miss the
+//! object. A warning is logged the first time a ray is cut short by the limit, if you see it
+//! check the scene's depth settings.
+//!
+//! ```json
+//! "integrator": {
+//!     "type": "pathtracer",
+//!     "min_depth": 3,
+//!     "max_depth": 8,
+//!     "ray_limit": 512
+//! }
+//! ```
 
 use std::f32;
 use std::cmp;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use enum_set::EnumSet;
 use rand::StdRng;
 use light_arena::Allocator;
@@ -32,10 +52,27 @@ use mc;
 pub use self::whitted::Whitted;
 pub use self::path::Path;
 pub use self::normals_debug::NormalsDebug;
+pub use self::irradiance_cache::IrradianceCache;
+pub use self::fog::Fog;
+pub use self::shadow_pass::ShadowPass;
 
 pub mod whitted;
 pub mod path;
 pub mod normals_debug;
+pub mod irradiance_cache;
+pub mod fog;
+pub mod shadow_pass;
+
+/// Number of candidate lights resampled to pick the light to sample in `sample_one_light`
+const RIS_CANDIDATES: usize = 8;
+/// Fraction of a light's approximate irradiance kept as its resampling target, so lights
+/// whose sampled point doesn't light the surface can still be picked
+const RIS_MIN_TARGET: f32 = 1e-3;
+/// Default limit on the number of rays traced for each camera ray, see the Ray Limit section
+pub const DEFAULT_RAY_LIMIT: u32 = 4096;
+
+/// Set once a camera ray has been cut short by the ray limit so we only warn about it once
+static RAY_LIMIT_REACHED: AtomicBool = ATOMIC_BOOL_INIT;
 
 /// Trait implemented by the various integration methods that can be used to render
 /// the scene. For scene usage information see whitted and path to get information
@@ -45,6 +82,19 @@ pub trait Integrator {
     fn illumination(&self, scene: &Scene, light_list: &[&Emitter], ray: &Ray,
                     hit: &Intersection, sampler: &mut Sampler, rng: &mut StdRng,
                     alloc: &Allocator) -> Colorf;
+    /// Called before rendering each frame, integrators which cache data about the
+    /// scene while rendering should discard it here since the scene may have changed
+    fn begin_frame(&self) {}
+    /// Compute the color seen along a camera ray which escaped the scene without hitting
+    /// anything, by default this is the radiance from any lights surrounding the scene
+    fn escaped(&self, light_list: &[&Emitter], ray: &Ray) -> Colorf {
+        escaped_radiance(light_list, ray)
+    }
+    /// Get the limit on the number of rays traced for each camera ray, see the Ray Limit section.
+    /// This also limits the number of surfaces a shadow ray can pass through
+    fn ray_limit(&self) -> u32 {
+        DEFAULT_RAY_LIMIT
+    }
     /// Compute the color of specularly reflecting light off the intersection
     fn specular_reflection(&self, scene: &Scene, light_list: &[&Emitter], ray: &Ray,
                            bsdf: &BSDF, sampler: &mut Sampler, rng: &mut StdRng,
@@ -63,10 +113,11 @@ pub trait Integrator {
         if pdf > 0.0 && !f.is_black() && f32::abs(linalg::dot(&w_i, &bsdf.n)) != 0.0 {
             let mut refl_ray = ray.child(&bsdf.p, &w_i);
             refl_ray.min_t = 0.001;
-            if let Some(hit) = scene.intersect(&mut refl_ray) {
-                let li = self.illumination(scene, light_list, &refl_ray, &hit, sampler, rng, alloc);
-                refl = f * li * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
-            }
+            let li = match scene.intersect(&mut refl_ray) {
+                Some(hit) => self.illumination(scene, light_list, &refl_ray, &hit, sampler, rng, alloc),
+                None => escaped_radiance(light_list, &refl_ray),
+            };
+            refl = f * scene.apply_fog(&refl_ray, &li) * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
         }
         refl
     }
@@ -88,26 +139,68 @@ pub trait Integrator {
         if pdf > 0.0 && !f.is_black() && f32::abs(linalg::dot(&w_i, &bsdf.n)) != 0.0 {
             let mut trans_ray = ray.child(&bsdf.p, &w_i);
             trans_ray.min_t = 0.001;
-            if let Some(hit) = scene.intersect(&mut trans_ray) {
-                let li = self.illumination(scene, light_list, &trans_ray, &hit, sampler, rng, alloc);
-                transmit = f * li * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
-            }
+            let li = match scene.intersect(&mut trans_ray) {
+                Some(hit) => self.illumination(scene, light_list, &trans_ray, &hit, sampler, rng, alloc),
+                None => escaped_radiance(light_list, &trans_ray),
+            };
+            transmit = f * scene.apply_fog(&trans_ray, &li) * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
         }
         transmit
     }
-    /// Uniformly sample the contribution of a randomly chosen light in the scene
-    /// to the illumination of this BSDF at the point
+    /// Sample the contribution of a randomly chosen light in the scene to the illumination
+    /// of this BSDF at the point. The light is chosen with resampled importance sampling (RIS):
+    /// a few candidate lights are picked uniformly and one of them is resampled in proportion
+    /// to its unshadowed contribution, so only the chosen light is shadow tested. See
+    /// [Talbot et al. 05](https://dl.acm.org/citation.cfm?id=2383654)
     ///
     /// - `w_o` outgoing direction of the light that is incident from the light being
     ///         sampled and reflecting off the surface
     /// - `bsdf` surface properties of the surface being illuminated
     /// - `light_sample` 3 random samples for the light
     /// - `bsdf_sample` 3 random samples for the bsdf
+    /// - `select_sample` random sample for resampling the candidate lights
     fn sample_one_light(&self, scene: &Scene, light_list: &[&Emitter], w_o: &Vector, p: &Point,
-                        bsdf: &BSDF, light_sample: &Sample, bsdf_sample: &Sample, time: f32) -> Colorf {
-        let l = cmp::min((light_sample.one_d * light_list.len() as f32) as usize, light_list.len() - 1);
-        self.estimate_direct(scene, w_o, p, bsdf, light_sample, bsdf_sample, light_list[l],
-                             BxDFType::non_specular(), time)
+                        bsdf: &BSDF, light_sample: &Sample, bsdf_sample: &Sample, select_sample: f32,
+                        time: f32) -> Colorf {
+        let n = light_list.len();
+        if n == 1 {
+            return self.estimate_direct(scene, w_o, p, bsdf, light_sample, bsdf_sample, light_list[0],
+                                        BxDFType::non_specular(), time);
+        }
+        // The candidates are spread evenly through the light list from a random offset, so each
+        // is picked uniformly and with as many candidates as lights each light is considered once
+        let m = cmp::min(RIS_CANDIDATES, n);
+        let mut candidates = [(0, 0.0); RIS_CANDIDATES];
+        let mut target_sum = 0.0;
+        for (i, c) in candidates.iter_mut().take(m).enumerate() {
+            let l = cmp::min(((i as f32 + light_sample.one_d) / m as f32 * n as f32) as usize, n - 1);
+            *c = (l, ris_target(w_o, bsdf, light_list[l], &light_sample.two_d, time));
+            target_sum += c.1;
+        }
+        // None of the candidates light the surface when sampled but the BSDF may still find
+        // them, so fall back to picking one uniformly. The candidate is picked uniformly and
+        // offset by the light sample, so its light is picked uniformly among all the lights
+        if target_sum == 0.0 {
+            let c = candidates[cmp::min((select_sample * m as f32) as usize, m - 1)];
+            return self.estimate_direct(scene, w_o, p, bsdf, light_sample, bsdf_sample, light_list[c.0],
+                                        BxDFType::non_specular(), time) * n as f32;
+        }
+        // Resample a candidate in proportion to its target
+        let u = select_sample * target_sum;
+        let mut chosen = candidates[0];
+        let mut cdf = 0.0;
+        for c in candidates.iter().take(m).filter(|c| c.1 > 0.0) {
+            chosen = *c;
+            cdf += c.1;
+            if u < cdf {
+                break;
+            }
+        }
+        // The candidates' resampling weights are target * n since each is picked with probability
+        // 1 / n, giving the RIS weight (1 / m) * sum(target * n) / target_chosen
+        let ris_weight = n as f32 * target_sum / (m as f32 * chosen.1);
+        self.estimate_direct(scene, w_o, p, bsdf, light_sample, bsdf_sample, light_list[chosen.0],
+                             BxDFType::non_specular(), time) * ris_weight
     }
     /// Estimate the direct light contribution to the surface being shaded by the light
     /// using multiple importance sampling
@@ -124,15 +217,17 @@ pub trait Integrator {
         let mut direct_light = Colorf::black();
         // Sample the light first
         let (li, w_i, pdf_light, occlusion) = light.sample_incident(&bsdf.p, &light_sample.two_d, time);
-        if pdf_light > 0.0 && !li.is_black() && !occlusion.occluded(scene) {
+        if pdf_light > 0.0 && !li.is_black() {
             let f = bsdf.eval(w_o, &w_i, flags);
-            if !f.is_black() {
+            // Light passing through transmissive surfaces on the way is tinted by them
+            let tr = if f.is_black() { f } else { occlusion.transmittance(scene) };
+            if !tr.is_black() {
                 if light.delta_light() {
-                    direct_light = f * li * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf_light;
+                    direct_light = f * li * tr * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf_light;
                 } else {
                     let pdf_bsdf = bsdf.pdf(w_o, &w_i, flags);
                     let w = mc::power_heuristic(1.0, pdf_light, 1.0, pdf_bsdf);
-                    direct_light = f * li * f32::abs(linalg::dot(&w_i, &bsdf.n)) * w / pdf_light;
+                    direct_light = f * li * tr * f32::abs(linalg::dot(&w_i, &bsdf.n)) * w / pdf_light;
                 }
             }
         }
@@ -150,14 +245,37 @@ pub trait Integrator {
                 } else {
                     1.0
                 };
-                // Find out if the ray along w_i actually hits the light source
+                // Find out if the ray along w_i actually hits the light source, passing
+                // through any transmissive surfaces on the way like the light sample did
                 let mut ray = Ray::segment(p, &w_i, 0.001, f32::INFINITY, time);
+                let mut tr = Colorf::broadcast(1.0);
                 let mut li = Colorf::black();
-                if let Some(h) = scene.intersect(&mut ray) {
-                    if let Instance::Emitter(ref e) = *h.instance {
-                        if e as *const Light == light as *const Light {
-                            li = e.radiance(&-w_i, &h.dg.p, &h.dg.ng, time)
-                        }
+                let mut surfaces = 0;
+                loop {
+                    if surfaces == self.ray_limit() {
+                        ray_limit_reached(surfaces);
+                        break;
+                    }
+                    surfaces += 1;
+                    let mut test = ray;
+                    match scene.intersect(&mut test) {
+                        Some(h) => {
+                            if let Instance::Emitter(ref e) = *h.instance {
+                                if e as *const Light == light as *const Light {
+                                    li = tr * e.radiance(&-w_i, &h.dg.p, &h.dg.ng, time);
+                                    break;
+                                }
+                            }
+                            tr = tr * h.material.transmittance(&h);
+                            if tr.is_black() {
+                                break;
+                            }
+                            ray.min_t = test.max_t + f32::max(0.001, test.max_t * 1e-5);
+                        },
+                        None => {
+                            li = tr * light.escaped_radiance(&w_i, time);
+                            break;
+                        },
                     }
                 }
                 if !li.is_black() {
@@ -169,3 +287,138 @@ pub trait Integrator {
     }
 }
 
+
+/// Compute the unshadowed contribution of the light to the surface, used as the target
+/// distribution when resampling the light to sample in `Integrator::sample_one_light`
+fn ris_target(w_o: &Vector, bsdf: &BSDF, light: &Emitter, samples: &(f32, f32), time: f32) -> f32 {
+    // Lights other than point lights keep a small target even if the sampled point doesn't light
+    // the surface, since other points on them or directions the BSDF samples may still light it.
+    // A target of 0 would never pick them and bias the estimate
+    let floor = if light.delta_light() { 0.0 } else { RIS_MIN_TARGET * light.approx_irradiance(&bsdf.p, time) };
+    let (li, w_i, pdf, _) = light.sample_incident(&bsdf.p, samples, time);
+    if pdf == 0.0 || li.is_black() {
+        return floor;
+    }
+    let f = bsdf.eval(w_o, &w_i, BxDFType::non_specular());
+    let contrib = (f * li).luminance() * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
+    f32::max(contrib, floor)
+}
+
+/// Compute the radiance arriving along the ray from the lights in `light_list` if the
+/// ray escaped the scene without hitting anything, e.g. from a dome light
+pub fn escaped_radiance(light_list: &[&Emitter], ray: &Ray) -> Colorf {
+    light_list.iter().fold(Colorf::black(), |c, l| c + l.escaped_radiance(&ray.d, ray.time))
+}
+
+/// Report that a ray was cut short by the ray limit `limit`, only the first
+/// report logs a warning so renders hitting the limit don't flood the log
+pub fn ray_limit_reached(limit: u32) {
+    if !RAY_LIMIT_REACHED.swap(true, Ordering::Relaxed) {
+        warn!("A ray reached the limit of {} rays traced or surfaces passed through and was cut short, \
+              the scene's ray depth settings may be too high", limit);
+    }
+}
+
+#[test]
+fn test_ris_light_selection() {
+    use std::sync::Arc;
+    use light_arena::MemoryArena;
+    use geometry::Sphere;
+    use integrator::Path;
+    use film::Camera;
+    use test_util::{gray_matte, translate, constant_color, scene_builder};
+    let mut builder = scene_builder((8, 8), 1);
+    builder.add_camera(Camera::new(translate(-10.0, 0.0, 0.0), 60.0, (8, 8), 0.5, 0))
+        .add_receiver(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 0.0), "sphere");
+    // More lights than RIS candidates with a range of strengths, some behind the sphere
+    for i in 0..12 {
+        let a = i as f32 * 0.55;
+        builder.add_point_light(translate(-3.0 * f32::cos(a), 3.0 * f32::sin(a), 1.0),
+                                constant_color(Colorf::broadcast(1.0 + i as f32)), &format!("light{}", i));
+    }
+    let (scene, _, _, _) = builder.build();
+    let light_list: Vec<_> = scene.accel.iter().filter_map(|x| {
+        match *x {
+            Instance::Emitter(ref e) => Some(e),
+            _ => None,
+        }
+    }).collect();
+    let mut ray = Ray::new(&Point::new(-5.0, 0.0, 0.0), &Vector::new(1.0, 0.0, 0.0), 0.0);
+    let hit = scene.intersect(&mut ray).expect("Ray should hit the sphere");
+    let mut arena = MemoryArena::new(1);
+    let alloc = arena.allocator();
+    let bsdf = hit.material.bsdf(&hit, &alloc);
+    let integrator = Path::new(1, 1);
+    let w_o = -ray.d;
+    let bsdf_sample = Sample::new(&(0.5, 0.5), 0.5);
+    // Uniformly selecting a light and scaling by the number of lights converges to the sum of
+    // all the lights' contributions, resampling the light should converge to the same result
+    let uniform = light_list.iter().fold(Colorf::black(), |c, l| {
+        c + integrator.estimate_direct(&scene, &w_o, &hit.dg.p, &bsdf, &Sample::new(&(0.5, 0.5), 0.5),
+                                       &bsdf_sample, *l, BxDFType::non_specular(), 0.0)
+    });
+    let n = 64;
+    let mut ris = Colorf::black();
+    for i in 0..n {
+        for j in 0..n {
+            let light_sample = Sample::new(&(0.5, 0.5), (i as f32 + 0.5) / n as f32);
+            let select = (j as f32 + 0.5) / n as f32;
+            ris = ris + integrator.sample_one_light(&scene, &light_list, &w_o, &hit.dg.p, &bsdf, &light_sample,
+                                                    &bsdf_sample, select, 0.0) / (n * n) as f32;
+        }
+    }
+    assert!(uniform.r > 0.0);
+    assert!(f32::abs(ris.r - uniform.r) < 0.02 * uniform.r, "RIS {} uniform {}", ris.r, uniform.r);
+}
+
+#[test]
+fn test_ris_black_light_sample() {
+    use std::sync::Arc;
+    use light_arena::MemoryArena;
+    use geometry::{Emitter, Sphere};
+    use integrator::Path;
+    use film::Camera;
+    use test_util::{gray_matte, translate, constant_color, scene_builder};
+    // The dome is black at the zenith and white at the horizon, so the light sample at the center
+    // of the disk samples the zenith and sees nothing even though the rest of the dome lights the
+    // top of the sphere
+    let mut builder = scene_builder((8, 8), 1);
+    builder.add_camera(Camera::new(translate(0.0, 10.0, 0.0), 60.0, (8, 8), 0.5, 0))
+        .add_receiver(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 0.0), "sphere")
+        .add_point_light(translate(2.0, 3.0, 0.0), constant_color(Colorf::broadcast(0.1)), "point")
+        .add_instance(Instance::Emitter(Emitter::dome(translate(0.0, 0.0, 0.0), constant_color(Colorf::black()),
+                                                      constant_color(Colorf::broadcast(1.0)), "dome".to_owned())));
+    let (scene, _, _, _) = builder.build();
+    let light_list: Vec<_> = scene.accel.iter().filter_map(|x| {
+        match *x {
+            Instance::Emitter(ref e) => Some(e),
+            _ => None,
+        }
+    }).collect();
+    let mut ray = Ray::new(&Point::new(0.0, 5.0, 0.0), &Vector::new(0.0, -1.0, 0.0), 0.0);
+    let hit = scene.intersect(&mut ray).expect("Ray should hit the sphere");
+    let mut arena = MemoryArena::new(1);
+    let alloc = arena.allocator();
+    let bsdf = hit.material.bsdf(&hit, &alloc);
+    let integrator = Path::new(1, 1);
+    let w_o = -ray.d;
+    let light_sample = Sample::new(&(0.5, 0.5), 0.5);
+    let bsdf_sample = Sample::new(&(0.2, 0.7), 0.5);
+    // Both lights contribute, the dome only through the BSDF sample
+    let direct: Vec<_> = light_list.iter().map(|l| {
+        integrator.estimate_direct(&scene, &w_o, &hit.dg.p, &bsdf, &light_sample, &bsdf_sample, *l,
+                                   BxDFType::non_specular(), 0.0)
+    }).collect();
+    assert!(direct.iter().all(|c| c.r > 0.0));
+    let uniform = direct.iter().fold(Colorf::black(), |c, d| c + *d);
+    // With as many candidates as lights each light is a candidate, so averaging over the
+    // resampling must give the sum of the lights' contributions
+    let n = 4096;
+    let mut ris = Colorf::black();
+    for i in 0..n {
+        let select = (i as f32 + 0.5) / n as f32;
+        ris = ris + integrator.sample_one_light(&scene, &light_list, &w_o, &hit.dg.p, &bsdf, &light_sample,
+                                                &bsdf_sample, select, 0.0) / n as f32;
+    }
+    assert!(f32::abs(ris.r - uniform.r) < 0.02 * uniform.r, "RIS {} uniform {}", ris.r, uniform.r);
+}
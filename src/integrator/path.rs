@@ -15,7 +15,7 @@
 //! }
 //! ```
 
-use std::f32;
+use std::{cmp, f32};
 use rand::{StdRng, Rng};
 use light_arena::Allocator;
 
@@ -23,7 +23,7 @@ use scene::Scene;
 use linalg::{self, Ray};
 use geometry::{Intersection, Emitter, Instance};
 use film::Colorf;
-use integrator::Integrator;
+use integrator::{self, fog, Integrator};
 use bxdf::BxDFType;
 use sampler::{Sampler, Sample};
 
@@ -32,12 +32,21 @@ use sampler::{Sampler, Sample};
 pub struct Path {
     min_depth: usize,
     max_depth: usize,
+    /// The most rays traced for a camera ray, paths are cut short at this length
+    /// even if it's below `max_depth`
+    ray_limit: usize,
 }
 
 impl Path {
     /// Create a new path integrator with the min and max length desired for paths
     pub fn new(min_depth: u32, max_depth: u32) -> Path {
-        Path { min_depth: min_depth as usize, max_depth: max_depth as usize }
+        Path::with_ray_limit(min_depth, max_depth, integrator::DEFAULT_RAY_LIMIT)
+    }
+    /// Create a new path integrator with the min and max length desired for paths and
+    /// the limit on the rays traced for each camera ray, see the integrator module docs
+    pub fn with_ray_limit(min_depth: u32, max_depth: u32, ray_limit: u32) -> Path {
+        assert!(ray_limit > 0, "The ray limit must be at least 1");
+        Path { min_depth: min_depth as usize, max_depth: max_depth as usize, ray_limit: ray_limit as usize }
     }
 }
 
@@ -45,9 +54,12 @@ impl Integrator for Path {
     fn illumination(&self, scene: &Scene, light_list: &[&Emitter], r: &Ray,
                     hit: &Intersection, sampler: &mut Sampler, rng: &mut StdRng,
                     alloc: &Allocator) -> Colorf {
-        let num_samples = self.max_depth as usize + 1;
+        // The camera ray counts towards the limit so we can bounce one less time than it
+        let max_depth = cmp::min(self.max_depth, self.ray_limit - 1);
+        let num_samples = max_depth + 1;
         let l_samples = alloc.alloc_slice::<(f32, f32)>(num_samples);
         let l_samples_comp = alloc.alloc_slice::<f32>(num_samples);
+        let l_samples_select = alloc.alloc_slice::<f32>(num_samples);
         let bsdf_samples = alloc.alloc_slice::<(f32, f32)>(num_samples);
         let bsdf_samples_comp = alloc.alloc_slice::<f32>(num_samples);
         let path_samples = alloc.alloc_slice::<(f32, f32)>(num_samples);
@@ -58,6 +70,7 @@ impl Integrator for Path {
         sampler.get_samples_1d(l_samples_comp, rng);
         sampler.get_samples_1d(bsdf_samples_comp, rng);
         sampler.get_samples_1d(path_samples_comp, rng);
+        sampler.get_samples_1d(l_samples_select, rng);
 
         let mut illum = Colorf::black();
         let mut path_throughput = Colorf::broadcast(1.0);
@@ -73,12 +86,14 @@ impl Integrator for Path {
                     illum = illum + path_throughput * e.radiance(&w, &hit.dg.p, &hit.dg.ng, ray.time);
                 }
             }
+            // Emissive materials aren't sampled as lights so their emission is added at every hit
+            illum = illum + path_throughput * current_hit.material.emission(&-ray.d, &current_hit);
             let bsdf = current_hit.material.bsdf(&current_hit, alloc);
             let w_o = -ray.d;
             let light_sample = Sample::new(&l_samples[bounce], l_samples_comp[bounce]);
             let bsdf_sample = Sample::new(&bsdf_samples[bounce], bsdf_samples_comp[bounce]);
             let li = self.sample_one_light(scene, light_list, &w_o, &current_hit.dg.p, &bsdf,
-                                           &light_sample, &bsdf_sample, ray.time);
+                                           &light_sample, &bsdf_sample, l_samples_select[bounce], ray.time);
             illum = illum + path_throughput * li;
 
             // Determine the next direction to take the path by sampling the BSDF
@@ -102,20 +117,39 @@ impl Integrator for Path {
                 // Re-weight the sum terms accordingly with the Russian roulette weight
                 path_throughput = path_throughput / cont_prob;
             }
-            if bounce == self.max_depth {
+            if bounce == max_depth {
+                if max_depth < self.max_depth {
+                    integrator::ray_limit_reached(self.ray_limit as u32);
+                }
                 break;
             }
 
             ray = ray.child(&bsdf.p, &w_i.normalized());
             ray.min_t = 0.001;
-            // Find the next vertex on the path
-            match scene.intersect(&mut ray) {
+            // Find the next vertex on the path, attenuating the light along the
+            // segment by the scene's fog
+            let hit = scene.intersect(&mut ray);
+            if let Some(ref fog) = scene.fog {
+                illum = illum + path_throughput * fog.apply(&ray, &Colorf::black());
+                path_throughput = path_throughput * fog.transmittance(fog::ray_distance(&ray));
+            }
+            match hit {
                 Some(h) => current_hit = h,
-                None => break,
+                None => {
+                    // Light escaping the scene after a specular bounce isn't accounted for
+                    // by sampling the lights at the previous vertex
+                    if specular_bounce {
+                        illum = illum + path_throughput * integrator::escaped_radiance(light_list, &ray);
+                    }
+                    break;
+                },
             }
             bounce += 1;
         }
         illum
     }
+    fn ray_limit(&self) -> u32 {
+        self.ray_limit as u32
+    }
 }
 
@@ -0,0 +1,136 @@
+//! Defines the `ShadowPass` integrator which renders the shadow and ambient occlusion
+//! a single object casts onto the rest of the scene, for compositing a CG object over
+//! a photographed plate. The object itself is invisible, camera rays pass through it to
+//! the surfaces behind, and the surfaces it shadows only show the shadow. The pass is a
+//! grayscale matte of the shadow's opacity: white where the object blocks all the light
+//! reaching a surface and black where it has no effect or nothing is seen, to be used as
+//! the alpha of a black shadow layer over the plate.
+//!
+//! The shadow is the fraction of the light arriving at the surface from the scene's
+//! lights that's blocked by the object, and the ambient occlusion the fraction of
+//! cosine weighted rays leaving the surface that hit the object within `ao_distance`.
+//! Other objects in the scene are ignored when computing both, so only the object's
+//! contribution ends up in the pass. The two are combined like layered mattes.
+//!
+//! The easiest way to render a shadow pass is by passing `--shadow-pass <object>`,
+//! which picks an AO distance from the size of the object, see the usage of tray_rust.
+//!
+//! # Scene Usage Example
+//! The shadow pass takes the name of the object to render the shadow of, and optionally
+//! the distance to look for occlusion by the object and the number of rays to trace for
+//! the ambient occlusion, which default to 1 and 16 respectively.
+//!
+//! ```json
+//! "integrator": {
+//!     "type": "shadow_pass",
+//!     "target": "car",
+//!     "ao_distance": 2.0,
+//!     "ao_samples": 16
+//! }
+//! ```
+
+use std::f32;
+use rand::{StdRng, Rng};
+use light_arena::Allocator;
+
+use scene::Scene;
+use linalg::{self, Ray, Vector};
+use geometry::{Intersection, Emitter, Instance};
+use film::Colorf;
+use integrator::Integrator;
+use bxdf::BSDF;
+use light::{Light, OcclusionTester};
+use sampler::Sampler;
+use mc;
+
+/// The `ShadowPass` integrator renders the shadow a single object casts onto the scene
+#[derive(Clone, Debug)]
+pub struct ShadowPass {
+    /// The name of the object casting the shadow
+    target: String,
+    /// Maximum distance from the surface the object is considered to occlude it for AO
+    ao_distance: f32,
+    /// Number of ambient occlusion rays to trace at each hit
+    ao_samples: usize,
+}
+
+impl ShadowPass {
+    /// Create a shadow pass integrator for the object named `target`
+    pub fn new(target: String, ao_distance: f32, ao_samples: usize) -> ShadowPass {
+        assert!(ao_distance > 0.0, "The shadow pass AO distance must be greater than 0");
+        ShadowPass { target: target, ao_distance: ao_distance, ao_samples: ao_samples }
+    }
+    /// Compute the fraction of the light arriving at the surface which is blocked by the target
+    fn shadow(&self, scene: &Scene, light_list: &[&Emitter], w_o: &Vector, bsdf: &BSDF,
+              sample: &(f32, f32), time: f32) -> f32 {
+        let mut total = 0.0;
+        let mut blocked = 0.0;
+        for light in light_list {
+            let (li, w_i, pdf, occlusion) = light.sample_incident(&bsdf.p, sample, time);
+            // Only light arriving on the side of the surface we're looking at can be shadowed
+            let cos_i = linalg::dot(&w_i, &bsdf.n);
+            if pdf == 0.0 || li.is_black() || cos_i * linalg::dot(w_o, &bsdf.n) <= 0.0 {
+                continue;
+            }
+            let l = li.luminance() * f32::abs(cos_i) / pdf;
+            total += l;
+            if occlusion.occluded_by(scene, &self.target) {
+                blocked += l;
+            }
+        }
+        if total > 0.0 { blocked / total } else { 0.0 }
+    }
+    /// Compute the fraction of the hemisphere above the surface on the side of `w_o`
+    /// occluded by the target
+    fn ambient_occlusion(&self, scene: &Scene, w_o: &Vector, bsdf: &BSDF, time: f32,
+                         rng: &mut StdRng) -> f32 {
+        if self.ao_samples == 0 {
+            return 0.0;
+        }
+        let flip = linalg::dot(w_o, &bsdf.n) < 0.0;
+        let mut occluded = 0;
+        for _ in 0..self.ao_samples {
+            let mut dir = mc::cos_sample_hemisphere(&(rng.next_f32(), rng.next_f32()));
+            if flip {
+                dir.z = -dir.z;
+            }
+            let w_i = bsdf.from_shading(&dir);
+            let occlusion = OcclusionTester { ray: Ray::segment(&bsdf.p, &w_i, 0.001, self.ao_distance, time) };
+            if occlusion.occluded_by(scene, &self.target) {
+                occluded += 1;
+            }
+        }
+        occluded as f32 / self.ao_samples as f32
+    }
+}
+
+impl Integrator for ShadowPass {
+    fn illumination(&self, scene: &Scene, light_list: &[&Emitter], ray: &Ray,
+                    hit: &Intersection, sampler: &mut Sampler, rng: &mut StdRng,
+                    alloc: &Allocator) -> Colorf {
+        // Rays pass through the target to the surfaces behind it
+        if hit.instance.tag() == self.target {
+            let mut behind = *ray;
+            behind.min_t = ray.max_t + 0.001;
+            behind.max_t = f32::INFINITY;
+            return match scene.intersect(&mut behind) {
+                Some(h) => self.illumination(scene, light_list, &behind, &h, sampler, rng, alloc),
+                None => Colorf::black(),
+            };
+        }
+        // Lights aren't shadowed, they're already in the plate
+        if let Instance::Emitter(_) = *hit.instance {
+            return Colorf::black();
+        }
+        let bsdf = hit.material.bsdf(hit, alloc);
+        let w_o = -ray.d;
+        let mut sample_2d = [(0.0, 0.0)];
+        sampler.get_samples_2d(&mut sample_2d[..], rng);
+        let shadow = self.shadow(scene, light_list, &w_o, &bsdf, &sample_2d[0], ray.time);
+        let ao = self.ambient_occlusion(scene, &w_o, &bsdf, ray.time, rng);
+        Colorf::broadcast(1.0 - (1.0 - shadow) * (1.0 - ao))
+    }
+    fn escaped(&self, _: &[&Emitter], _: &Ray) -> Colorf {
+        Colorf::black()
+    }
+}
@@ -13,6 +13,7 @@
 //! ```
 
 use std::f32;
+use std::cell::Cell;
 use rand::StdRng;
 use light_arena::Allocator;
 
@@ -20,21 +21,38 @@ use scene::Scene;
 use linalg::{self, Ray};
 use geometry::{Intersection, Emitter, Instance};
 use film::Colorf;
-use integrator::Integrator;
+use integrator::{self, Integrator};
 use bxdf::BxDFType;
 use light::Light;
 use sampler::Sampler;
 
+thread_local!(
+    /// Number of rays which have hit something while tracing the current camera ray on this thread
+    static RAYS_TRACED: Cell<u32> = Cell::new(0)
+);
+
 /// The Whitted integrator implementing the Whitted recursive ray tracing algorithm
 #[derive(Clone, Copy, Debug)]
 pub struct Whitted {
-    /// The maximum recursion depth for rays
+    /// The maximum recursion depth for rays, specular rays are not spawned from
+    /// hits at this depth so nested reflective or refractive objects can't recurse forever
     max_depth: u32,
+    /// The most rays traced for a camera ray. Each hit can spawn both a reflected and
+    /// transmitted ray so the number of rays can grow exponentially with the depth
+    ray_limit: u32,
 }
 
 impl Whitted {
     /// Create a new Whitted integrator with the desired maximum recursion depth for rays
-    pub fn new(max_depth: u32) -> Whitted { Whitted { max_depth: max_depth } }
+    pub fn new(max_depth: u32) -> Whitted {
+        Whitted::with_ray_limit(max_depth, integrator::DEFAULT_RAY_LIMIT)
+    }
+    /// Create a new Whitted integrator with the desired maximum recursion depth for rays and
+    /// the limit on the rays traced for each camera ray, see the integrator module docs
+    pub fn with_ray_limit(max_depth: u32, ray_limit: u32) -> Whitted {
+        assert!(ray_limit > 0, "The ray limit must be at least 1");
+        Whitted { max_depth: max_depth, ray_limit: ray_limit }
+    }
 }
 
 impl Integrator for Whitted {
@@ -52,19 +70,36 @@ impl Integrator for Whitted {
                 illum = illum + e.radiance(&w, &hit.dg.p, &hit.dg.ng, ray.time);
             }
         }
+        illum = illum + hit.material.emission(&w_o, hit);
 
         for light in light_list {
             let (li, w_i, pdf, occlusion) = light.sample_incident(&hit.dg.p, &sample_2d[0], ray.time);
             let f = bsdf.eval(&w_o, &w_i, BxDFType::all());
-            if !li.is_black() && !f.is_black() && !occlusion.occluded(scene) {
-                illum = illum + f * li * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
+            if !li.is_black() && !f.is_black() {
+                let tr = occlusion.transmittance(scene);
+                if !tr.is_black() {
+                    illum = illum + f * li * tr * f32::abs(linalg::dot(&w_i, &bsdf.n)) / pdf;
+                }
             }
         }
+        // Count this ray towards the limit, the count restarts with each camera ray
+        let rays_traced = RAYS_TRACED.with(|c| {
+            let n = if ray.depth == 0 { 1 } else { c.get() + 1 };
+            c.set(n);
+            n
+        });
         if ray.depth < self.max_depth {
-            illum = illum + self.specular_reflection(scene, light_list, ray, &bsdf, sampler, rng, alloc);
-            illum = illum + self.specular_transmission(scene, light_list, ray, &bsdf, sampler, rng, alloc);
+            if rays_traced >= self.ray_limit {
+                integrator::ray_limit_reached(self.ray_limit);
+            } else {
+                illum = illum + self.specular_reflection(scene, light_list, ray, &bsdf, sampler, rng, alloc);
+                illum = illum + self.specular_transmission(scene, light_list, ray, &bsdf, sampler, rng, alloc);
+            }
         }
         illum
     }
+    fn ray_limit(&self) -> u32 {
+        self.ray_limit
+    }
 }
 
@@ -17,6 +17,8 @@
 //! Running and passing `--help` or `-h` will print out options you can pass to the renderer which are documented in the help.
 //! For the more complicated use cases I hope to do some write ups and guides on how to use them (e.g. distributed rendering,
 //! animation) but this may take a while. I strongly recommend running the release build as the debug version will be very slow.
+//! Progress, warnings and errors are reported through the [log](https://crates.io/crates/log) crate, so applications
+//! using tray\_rust as a library can install their own logger to capture them or control their verbosity.
 //! 
 //! ## Building Your Own Scenes
 //! 
@@ -94,6 +96,8 @@ extern crate bincode;
 extern crate mio;
 extern crate la;
 extern crate light_arena;
+#[macro_use]
+extern crate log;
 
 pub mod linalg;
 pub mod film;
@@ -108,4 +112,6 @@ pub mod mc;
 pub mod partition;
 pub mod exec;
 pub mod texture;
+#[cfg(test)]
+pub mod test_util;
 
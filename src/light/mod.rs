@@ -7,6 +7,11 @@ use std::f32;
 use linalg::{Point, Vector, Ray};
 use film::Colorf;
 use scene::Scene;
+use integrator;
+
+pub use self::sky::Sky;
+
+pub mod sky;
 
 /// The `OcclusionTester` provides a simple interface for setting up and executing
 /// occlusion queries in the scene
@@ -35,6 +40,52 @@ impl OcclusionTester {
             false
         }
     }
+    /// Compute the color of light transmitted along the test ray, which is tinted by
+    /// the transmittance of each transmissive surface it passes through. Refraction is
+    /// ignored so shadows through glass are tinted but not distorted. Returns black if
+    /// the ray is blocked by an opaque surface or passes through more surfaces than the
+    /// scene's integrator's ray limit
+    pub fn transmittance(&self, scene: &Scene) -> Colorf {
+        let mut r = self.ray;
+        let mut tr = Colorf::broadcast(1.0);
+        let step = 0.001 / r.d.length();
+        let limit = scene.integrator.ray_limit();
+        for _ in 0..limit {
+            let mut test = r;
+            match scene.intersect(&mut test) {
+                Some(hit) => {
+                    tr = tr * hit.material.transmittance(&hit);
+                    if tr.is_black() {
+                        return tr;
+                    }
+                    r.min_t = test.max_t + f32::max(step, test.max_t * 1e-5);
+                },
+                None => return tr,
+            }
+        }
+        integrator::ray_limit_reached(limit);
+        Colorf::black()
+    }
+    /// Perform the occlusion test considering only the object tagged `tag`, any other
+    /// objects along the ray are ignored. If the ray passes through more surfaces than the
+    /// scene's integrator's ray limit before finding the object it's treated as not occluded
+    pub fn occluded_by(&self, scene: &Scene, tag: &str) -> bool {
+        let mut r = self.ray;
+        // Step past other objects along the ray until we hit the object or leave the segment
+        let step = 0.001 / r.d.length();
+        let limit = scene.integrator.ray_limit();
+        for _ in 0..limit {
+            let mut test = r;
+            match scene.intersect(&mut test) {
+                Some(ref hit) if hit.instance.tag() == tag => return true,
+                // Make sure the step isn't lost to rounding far along the ray
+                Some(_) => r.min_t = test.max_t + f32::max(step, test.max_t * 1e-5),
+                None => return false,
+            }
+        }
+        integrator::ray_limit_reached(limit);
+        false
+    }
 }
 
 /// Trait implemented by all lights in `tray_rust`. Provides methods for sampling
@@ -50,5 +101,11 @@ pub trait Light {
     fn delta_light(&self) -> bool;
     /// Compute the PDF for sampling the point with incident direction `w_i`
     fn pdf(&self, p: &Point, w_i: &Vector, time: f32) -> f32;
+    /// Compute the radiance from the light arriving along a ray in direction `d` which
+    /// escaped the scene without hitting anything. Only lights infinitely far away, like
+    /// the dome and sky lights, emit light along these rays
+    fn escaped_radiance(&self, _: &Vector, _: f32) -> Colorf {
+        Colorf::black()
+    }
 }
 
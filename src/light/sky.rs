@@ -0,0 +1,196 @@
+//! Provides the analytic daylight sky model from Preetham, Shirley and Smits,
+//! [A Practical Analytic Model for Daylight](https://www2.cs.duke.edu/courses/cps124/spring08/assign/07_papers/p91-preetham.pdf),
+//! used by the sky light for quick outdoor lighting without an HDR environment map. The
+//! sky's radiance is computed from the direction of the sun and the turbidity of the
+//! atmosphere, and is tabulated over the hemisphere to importance sample the bright
+//! regions around the sun and the horizon. The model only describes the sky, the sun
+//! itself isn't included and can be added with a distant light or large far away area light.
+//!
+//! The model works in the sky's local space which has +Y up, the sky light rotates it into the scene.
+
+use std::f32;
+
+use linalg::{self, Vector};
+use film::Colorf;
+use mc::Distribution2D;
+
+/// Number of azimuthal and polar bins the sky is tabulated in for sampling
+const SAMPLE_PHI_BINS: usize = 64;
+const SAMPLE_THETA_BINS: usize = 32;
+
+/// The Perez sky distribution coefficients for some quantity of the sky
+#[derive(Clone, Copy, Debug)]
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    /// Evaluate the Perez function for the direction at angle `theta` from the zenith,
+    /// passed as its cosine, and `gamma` from the sun
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let cos_gamma = f32::cos(gamma);
+        (1.0 + self.a * f32::exp(self.b / f32::max(cos_theta, 1e-4)))
+            * (1.0 + self.c * f32::exp(self.d * gamma) + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The Preetham sky model for a sun direction and turbidity
+#[derive(Clone, Debug)]
+pub struct Sky {
+    /// Direction towards the sun in the sky's space
+    sun: Vector,
+    /// The zenith luminance (in kcd/m^2) and chromaticity
+    zenith: (f32, f32, f32),
+    /// Perez coefficients for the luminance and chromaticity
+    perez: (Perez, Perez, Perez),
+    /// The value of each Perez function at the zenith, to normalize by
+    zenith_perez: (f32, f32, f32),
+    /// Distribution of the sky's luminance over the hemisphere, in (phi, theta)
+    distribution: Distribution2D,
+}
+
+impl Sky {
+    /// Create the sky for the sun in direction `sun` and atmospheric `turbidity`. The sun
+    /// must not be below the horizon and the model is only valid for turbidities in [2, 10]
+    pub fn new(sun: &Vector, turbidity: f32) -> Sky {
+        if turbidity < 2.0 || turbidity > 10.0 {
+            panic!("Sky turbidity must be in [2, 10], got {}", turbidity);
+        }
+        let sun = sun.normalized();
+        if sun.y < 0.0 {
+            panic!("The sun must be above the horizon for the sky model, got direction {:?}", sun);
+        }
+        let t = turbidity;
+        let theta_s = f32::acos(linalg::clamp(sun.y, 0.0, 1.0));
+        let chi = (4.0 / 9.0 - t / 120.0) * (f32::consts::PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * f32::tan(chi) - 0.2155 * t + 2.4192;
+        let (t2, th2, th3) = (t * t, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = (0.00166 * th3 - 0.00375 * th2 + 0.00209 * theta_s) * t2
+            + (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * theta_s + 0.00394) * t
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * theta_s + 0.25886);
+        let zenith_yc = (0.00275 * th3 - 0.00610 * th2 + 0.00317 * theta_s) * t2
+            + (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * theta_s + 0.00516) * t
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * theta_s + 0.26688);
+        let perez_y = Perez { a: 0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251,
+                              d: 0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 };
+        let perez_x = Perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125,
+                              d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 };
+        let perez_yc = Perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102,
+                               d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 };
+        let mut sky = Sky { sun: sun, zenith: (zenith_y, zenith_x, zenith_yc),
+                            perez: (perez_y, perez_x, perez_yc),
+                            zenith_perez: (perez_y.eval(1.0, theta_s), perez_x.eval(1.0, theta_s),
+                                           perez_yc.eval(1.0, theta_s)),
+                            distribution: Distribution2D::new(&[1.0], 1, 1) };
+        // Tabulate the luminance over the hemisphere, weighted by the sin theta
+        // of the mapping from (phi, theta) to directions
+        let mut f = Vec::with_capacity(SAMPLE_PHI_BINS * SAMPLE_THETA_BINS);
+        for j in 0..SAMPLE_THETA_BINS {
+            let theta = (j as f32 + 0.5) / SAMPLE_THETA_BINS as f32 * f32::consts::FRAC_PI_2;
+            for i in 0..SAMPLE_PHI_BINS {
+                let phi = (i as f32 + 0.5) / SAMPLE_PHI_BINS as f32 * 2.0 * f32::consts::PI;
+                let w = spherical_direction(theta, phi);
+                f.push(sky.luminance(&w) * f32::sin(theta));
+            }
+        }
+        sky.distribution = Distribution2D::new(&f[..], SAMPLE_PHI_BINS, SAMPLE_THETA_BINS);
+        sky
+    }
+    /// Get the direction towards the sun in the sky's space
+    pub fn sun_direction(&self) -> Vector {
+        self.sun
+    }
+    /// Compute the radiance of the sky seen along the direction `w` pointing out of the
+    /// scene, in the sky's space. The sky is black below the horizon
+    pub fn radiance(&self, w: &Vector) -> Colorf {
+        let w = w.normalized();
+        if w.y <= 0.0 {
+            return Colorf::black();
+        }
+        let gamma = f32::acos(linalg::clamp(linalg::dot(&w, &self.sun), -1.0, 1.0));
+        let y = self.zenith.0 * self.perez.0.eval(w.y, gamma) / self.zenith_perez.0;
+        let x = self.zenith.1 * self.perez.1.eval(w.y, gamma) / self.zenith_perez.1;
+        let yc = self.zenith.2 * self.perez.2.eval(w.y, gamma) / self.zenith_perez.2;
+        // Convert from xyY to XYZ and then to RGB
+        let xyz = Colorf::new(x / yc * y, y, (1.0 - x - yc) / yc * y);
+        let c = Colorf::from_xyz(&xyz);
+        Colorf::new(f32::max(c.r, 0.0), f32::max(c.g, 0.0), f32::max(c.b, 0.0))
+    }
+    /// Sample a direction in the sky's space proportional to its luminance using
+    /// the samples `u`. Returns the direction and its pdf with respect to solid angle
+    pub fn sample(&self, u: &(f32, f32)) -> (Vector, f32) {
+        let ((su, sv), pdf) = self.distribution.sample_continuous(u);
+        let theta = sv * f32::consts::FRAC_PI_2;
+        let phi = su * 2.0 * f32::consts::PI;
+        let sin_theta = f32::sin(theta);
+        let w = spherical_direction(theta, phi);
+        if sin_theta == 0.0 {
+            (w, 0.0)
+        } else {
+            (w, pdf / (f32::consts::PI * f32::consts::PI * sin_theta))
+        }
+    }
+    /// Compute the pdf with respect to solid angle of sampling the direction `w` in the sky's space
+    pub fn pdf(&self, w: &Vector) -> f32 {
+        let w = w.normalized();
+        if w.y <= 0.0 {
+            return 0.0;
+        }
+        let theta = f32::acos(linalg::clamp(w.y, -1.0, 1.0));
+        let sin_theta = f32::sin(theta);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        let mut phi = f32::atan2(w.z, w.x);
+        if phi < 0.0 {
+            phi += 2.0 * f32::consts::PI;
+        }
+        let p = (phi / (2.0 * f32::consts::PI), theta / f32::consts::FRAC_PI_2);
+        self.distribution.pdf(&p) / (f32::consts::PI * f32::consts::PI * sin_theta)
+    }
+    fn luminance(&self, w: &Vector) -> f32 {
+        self.radiance(w).luminance()
+    }
+}
+
+/// Get the direction at angle `theta` from the +Y zenith and `phi` around it from +X
+fn spherical_direction(theta: f32, phi: f32) -> Vector {
+    let sin_theta = f32::sin(theta);
+    Vector::new(sin_theta * f32::cos(phi), f32::cos(theta), sin_theta * f32::sin(phi))
+}
+
+#[test]
+fn test_sky() {
+    let sun = Vector::new(1.0, 1.0, 0.0).normalized();
+    let sky = Sky::new(&sun, 3.0);
+    // The sky is brightest around the sun and dark below the horizon
+    let near_sun = sky.radiance(&Vector::new(1.0, 0.9, 0.0));
+    let away = sky.radiance(&Vector::new(-1.0, 0.9, 0.0));
+    assert!(near_sun.luminance() > away.luminance());
+    assert!(away.luminance() > 0.0 && away.is_finite());
+    assert!(sky.radiance(&Vector::new(0.0, -1.0, 0.0)).is_black());
+    // A clear sky is bluer than it is red away from the sun
+    assert!(away.b > away.r);
+    // Sampled directions are above the horizon, match the pdf and favor the sun
+    let n = 32;
+    let mut towards_sun = 0;
+    for i in 0..n {
+        for j in 0..n {
+            let u = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+            let (w, pdf) = sky.sample(&u);
+            assert!(w.y >= 0.0);
+            if pdf > 0.0 {
+                assert!(f32::abs(pdf - sky.pdf(&w)) / pdf < 1e-2);
+            }
+            if linalg::dot(&w, &sun) > 0.0 {
+                towards_sun += 1;
+            }
+        }
+    }
+    assert!(towards_sun > n * n / 2);
+    assert_eq!(sky.pdf(&Vector::new(0.0, -1.0, 0.0)), 0.0);
+}
@@ -36,7 +36,7 @@ impl AnimatedTransform {
         AnimatedTransform { keyframes: vec![BSpline::new(0, vec![key], vec![0.0, 1.0])] }
     }
     /// Compute the transformation matrix for the animation at some time point using B-Spline
-    /// interpolation.
+    /// interpolation. Times outside the keyframes hold the first or last keyframe's transform.
     pub fn transform(&self, time: f32) -> Transform {
         let mut transform = Transform::identity();
         // Step through the transform stack, applying each animation transform at this
@@ -69,9 +69,20 @@ impl AnimatedTransform {
             ret
         }
     }
-    /// Check if the transform is actually animated
+    /// Check if the transform is actually animated, the transform is animated if any
+    /// transform in the hierarchy is, e.g. a moving object in a static group
     pub fn is_animated(&self) -> bool {
-        self.keyframes.is_empty() || self.keyframes.iter().fold(true, |b, spline| b && spline.control_points().count() > 1)
+        self.keyframes.iter().any(|spline| spline.control_points().count() > 1)
+    }
+    /// Check if any keyframe in the hierarchy has NaNs in its transformation or inverse,
+    /// e.g. from a scaling of 0 or invalid values in the scene file
+    pub fn has_nans(&self) -> bool {
+        self.keyframes.iter().any(|spline| {
+            spline.control_points().any(|k| {
+                let t = k.transform();
+                t.mat.has_nans() || t.inv.has_nans()
+            })
+        })
     }
 }
 
@@ -86,3 +97,37 @@ impl Mul for AnimatedTransform {
     }
 }
 
+
+#[test]
+fn test_composed_animation_bounds() {
+    use linalg::{Point, Vector};
+    // An object moving along +X placed in a static group translated along +Y
+    let moving = AnimatedTransform::with_keyframes(
+        vec![Keyframe::new(&Transform::identity()),
+             Keyframe::new(&Transform::translate(&Vector::new(4.0, 0.0, 0.0)))],
+        vec![0.0, 0.0, 1.0, 1.0], 1);
+    let group = AnimatedTransform::unanimated(&Transform::translate(&Vector::new(0.0, 2.0, 0.0)));
+    assert!(!group.is_animated());
+    let composed = group * moving;
+    assert!(composed.is_animated());
+    let p = composed.transform(0.5) * Point::broadcast(0.0);
+    assert!(p.distance(&Point::new(2.0, 2.0, 0.0)) < 1e-4);
+    // The bounds must cover the object's motion over the entire shutter interval
+    let b = BBox::span(Point::broadcast(-1.0), Point::broadcast(1.0));
+    let bounds = composed.animation_bounds(&b, 0.0, 1.0);
+    assert!(bounds.min.distance(&Point::new(-1.0, 1.0, -1.0)) < 1e-4);
+    assert!(bounds.max.distance(&Point::new(5.0, 3.0, 1.0)) < 1e-4);
+}
+
+#[test]
+fn test_has_nans() {
+    use std::f32;
+    use linalg::Vector;
+    let valid = AnimatedTransform::unanimated(&Transform::translate(&Vector::new(1.0, 2.0, 3.0)));
+    let invalid = AnimatedTransform::unanimated(&Transform::translate(&Vector::new(f32::NAN, 0.0, 0.0)));
+    assert!(!valid.has_nans());
+    assert!(invalid.has_nans());
+    // A NaN anywhere in the hierarchy makes the composed transform invalid
+    assert!((invalid * valid.clone()).has_nans());
+    assert!(!(valid.clone() * valid).has_nans());
+}
@@ -4,7 +4,7 @@ use linalg::{Point, Vector};
 
 /// Ray is a standard 3D ray, starting at origin `o` and heading in direction `d`
 /// The min and max points along the ray can be specified with `min_t` and `max_t`
-/// `depth` is the recursion depth of the ray
+/// `depth` is the recursion depth of the ray and `primary` marks rays coming directly from the camera
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Ray {
     /// Origin of the ray
@@ -19,24 +19,28 @@ pub struct Ray {
     pub depth: u32,
     /// Time point sampled by this ray
     pub time: f32,
+    /// Whether this ray was generated by the camera
+    pub primary: bool,
 }
 
 impl Ray {
     /// Create a new ray from `o` heading in `d` with infinite length
     pub fn new(o: &Point, d: &Vector, time: f32) -> Ray {
-        Ray { o: *o, d: *d, min_t: 0f32, max_t: f32::INFINITY, depth: 0, time: time }
+        Ray { o: *o, d: *d, min_t: 0f32, max_t: f32::INFINITY, depth: 0, time: time, primary: false }
     }
     /// Create a new segment ray from `o + min_t * d` to `o + max_t * d`
     pub fn segment(o: &Point, d: &Vector, min_t: f32, max_t: f32, time: f32) -> Ray {
-        Ray { o: *o, d: *d, min_t: min_t, max_t: max_t, depth: 0, time: time }
+        Ray { o: *o, d: *d, min_t: min_t, max_t: max_t, depth: 0, time: time, primary: false }
     }
     /// Create a child ray from the parent starting at `o` and heading in `d`
     pub fn child(&self, o: &Point, d: &Vector) -> Ray {
-        Ray { o: *o, d: *d, min_t: 0f32, max_t: f32::INFINITY, depth: self.depth + 1, time: self.time }
+        Ray { o: *o, d: *d, min_t: 0f32, max_t: f32::INFINITY, depth: self.depth + 1, time: self.time,
+              primary: false }
     }
     /// Create a child ray segment from `o + min_t * d` to `o + max_t * d`
     pub fn child_segment(&self, o: &Point, d: &Vector, min_t: f32, max_t: f32) -> Ray {
-        Ray { o: *o, d: *d, min_t: min_t, max_t: max_t, depth: self.depth + 1, time: self.time }
+        Ray { o: *o, d: *d, min_t: min_t, max_t: max_t, depth: self.depth + 1, time: self.time,
+              primary: false }
     }
     /// Evaulate the ray equation at some t value and return the point
     /// returns result of `self.o + t * self.d`
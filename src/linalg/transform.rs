@@ -108,10 +108,28 @@ impl Transform {
         Transform { mat: m, inv: m.transpose() }
     }
     /// Construct the look at transform for a camera at `pos` looking at
-    /// the point `center` oriented with up vector `up`
+    /// the point `center` oriented with up vector `up`. If the camera position
+    /// and target are the same point or the up vector is zero or parallel to the
+    /// view direction a warning is printed and a valid basis is picked instead
     pub fn look_at(pos: &Point, center: &Point, up: &Vector) -> Transform {
-        let dir = (*center - *pos).normalized();
-        let left = linalg::cross(up, &dir).normalized();
+        let mut dir = *center - *pos;
+        if dir.length_sqr() < f32::EPSILON {
+            warn!("look_at position and target are the same point, looking along +Z instead");
+            dir = Vector::new(0.0, 0.0, 1.0);
+        }
+        let dir = dir.normalized();
+        let mut left = linalg::cross(up, &dir);
+        if left.length_sqr() < f32::EPSILON {
+            warn!("look_at up vector {:?} is zero or parallel to the view direction {:?}, \
+                     picking a new up vector", up, dir);
+            let fallback_up = if f32::abs(dir.y) < 0.9 {
+                Vector::new(0.0, 1.0, 0.0)
+            } else {
+                Vector::new(0.0, 0.0, 1.0)
+            };
+            left = linalg::cross(&fallback_up, &dir);
+        }
+        let left = left.normalized();
         let u = linalg::cross(&dir, &left).normalized();
         let mut m = Matrix4::identity();
         for i in 0..3 {
@@ -377,4 +395,19 @@ fn test_rotate() {
     assert_eq!(Transform::rotate(&Vector::new(0.0, 0.0, 1.0), 243.0),
                 Transform::rotate_z(243.0));
 }
-
+#[test]
+fn test_look_at_degenerate() {
+    let pos = Point::new(0.0, 0.0, 0.0);
+    let parallel = Transform::look_at(&pos, &Point::new(0.0, 5.0, 0.0), &Vector::new(0.0, 1.0, 0.0));
+    let same = Transform::look_at(&pos, &pos, &Vector::new(0.0, 1.0, 0.0));
+    let zero_up = Transform::look_at(&pos, &Point::new(0.0, 0.0, 1.0), &Vector::new(0.0, 0.0, 0.0));
+    for t in &[parallel, same, zero_up] {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!(!f32::is_nan(*t.mat.at(i, j)) && !f32::is_nan(*t.inv.at(i, j)));
+            }
+        }
+    }
+    let d = parallel * Vector::new(0.0, 0.0, 1.0);
+    assert!(f32::abs(d.y - 1.0) < 0.0001);
+}
@@ -1,81 +1,210 @@
-extern crate image;
 extern crate rand;
 extern crate docopt;
 #[macro_use]
 extern crate serde_derive;
 extern crate num_cpus;
 extern crate scoped_threadpool;
+#[macro_use]
+extern crate log;
 extern crate tray_rust;
 
-use std::path::PathBuf;
-use std::io::ErrorKind;
+use std::cmp;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind};
 use std::time::SystemTime;
 
 use docopt::Docopt;
+use log::{Log, Level, LevelFilter, Metadata, Record};
 
 use tray_rust::scene;
+use tray_rust::film::{output, tonemap, RenderTarget, Denoiser, Eye, Grade, Output, Tonemap};
 use tray_rust::exec::{self, Exec};
 use tray_rust::exec::distrib;
 
+/// The thumbnail is rendered at 1 / `THUMBNAIL_SCALE` of the film resolution
+const THUMBNAIL_SCALE: usize = 8;
+/// Maximum number of samples per pixel to take when rendering the thumbnail
+const THUMBNAIL_SPP: usize = 4;
+
+// Each usage pattern must stay on a single line, docopt doesn't support wrapping them
 static USAGE: &'static str = "
 Usage:
-    tray_rust <scenefile> [-o <path>] [-n <number>] [--start-frame <number>] [--end-frame <number>]
-    tray_rust <scenefile> --master <workers>... [-o <path>] [--start-frame <number>] [--end-frame <number>]
-    tray_rust --worker [-n <number>]
+    tray_rust <scenefile>... [-o <path>] [-n <number>] [--start-frame <number>] [--end-frame <number>] [--thumbnail] [--integrator <type>] [--frame-step <n>] [--clay] [--dump-bvh <path>] [--denoise] [--shadow-pass <object>] [--mattes] [--slices <n>] [-v | --quiet]
+    tray_rust <scenefile> --bake <instance> <resolution> [-o <path>] [-n <number>] [--spp <n>] [-v | --quiet]
+    tray_rust <scenefile> --master <workers>... [-o <path>] [--start-frame <number>] [--end-frame <number>] [--spp <n>] [--resolution-scale <s>] [-v | --quiet]
+    tray_rust --worker [-n <number>] [-v | --quiet]
     tray_rust (-h | --help)
 
 
 Options:
+  <scenefile>...          The scene file(s) to render. Multiple scenes are rendered one after another in the
+                          same process, each is written to a subdirectory of the output directory named after
+                          the scene file.
   -o <path>               Specify the output file or directory to save the image or frames. Supported formats are
-                          PNG, JPG and PPM. Default is 'frame<#>.png'.
+                          PNG, JPG and PPM. Default is 'frame<#>.png'. If the film specifies a list of outputs
+                          they're written to the output directory instead.
   -n <number>             Specify the number of threads to use for rendering. Defaults to the number of cores
                           on the system.
   --start-frame <number>  Specify frame to start rendering at, specifies an inclusive range [start, end]
   --end-frame <number>    Specify frame to stop rendering at, specifies an inclusive range [start, end]
+  --thumbnail             Render a quick low resolution, low sample count preview of the first frame and
+                          save it before starting the full render.
+  --integrator <type>     Override the scene's integrator with one of the type passed using its default
+                          parameters, e.g. normals_debug to check the scene's normals.
+  --frame-step <n>        Only render every nth frame in [start, end], useful for quickly previewing the
+                          motion of a long animation. Defaults to 1.
+  --clay                  Render all objects with a uniform gray matte material instead of their own
+                          materials, to review the lighting and composition. Lights are unchanged.
+  --dump-bvh <path>       Write the nodes of the scene's BVH to the file at <path> for debugging, each node's
+                          bounds and structure are written on a line of text. See BVH::write_nodes for the format.
+  --denoise               Denoise each frame before saving it, guided by the albedo and normals of the surfaces
+                          seen through each pixel. Useful for cleaning up low sample count previews.
+  --shadow-pass <object>  Render only the shadow and ambient occlusion the object casts onto the rest of the
+                          scene as a grayscale matte, for compositing the object over a photographed plate.
+                          The object itself and the surfaces it shadows are otherwise invisible.
+  --mattes                Save a grayscale coverage matte for each object seen in each frame alongside it, named
+                          '<object>_matte<#>.png', for isolating objects when compositing.
+  --slices <n>            Split the camera's clip range into <n> slices of equal depth and save an image of
+                          each, named 'frame<#>_slice<#>.png'. The camera must have a finite clip range, see
+                          the camera docs. Only camera rays are clipped, geometry outside the slice still
+                          casts shadows onto it. Can't be combined with --mattes.
+  --bake                  Bake the lighting on the mesh tagged <instance> into a <resolution> x <resolution>
+                          texture indexed by the mesh's texture coordinates instead of rendering the scene.
+                          The lighting is computed with the scene's integrator at the start of the first
+                          frame and saved to -o, or '<instance>_bake.png' by default.
+  <instance>              The name of the object to bake lighting for.
+  <resolution>            The width and height of the baked texture.
   --master                Start a master process to manage the worker nodes in <workers>... for distributed
                           rendering. The master collects results from workers and saves the image(s).
   <workers>...            Specify the list of worker nodes the master will connect too.
+  --spp <n>               Override the scene's samples per pixel when rendering with --master or baking
+                          with --bake. When rendering with --master the workers are instructed to use the
+                          new sample count.
+  --resolution-scale <s>  Scale the scene's image resolution when rendering with --master, e.g. 0.5 renders
+                          a quick half resolution preview on the workers.
   --worker                Start a worker process that will listen for a master process to contact it and
                           instruct on what to start rendering. The worker will report its results back to
                           the master.
+  -v, --verbose           Print extra debugging information while loading and rendering the scene.
+  -q, --quiet             Only print warnings and errors.
   -h, --help              Show this message.
 ";
 
 #[derive(Deserialize, Debug)]
 struct Args {
-    arg_scenefile: String,
+    arg_scenefile: Vec<String>,
     flag_o: Option<String>,
     flag_n: Option<u32>,
     flag_start_frame: Option<usize>,
     flag_end_frame: Option<usize>,
+    flag_thumbnail: Option<bool>,
+    flag_integrator: Option<String>,
+    flag_frame_step: Option<usize>,
+    flag_clay: Option<bool>,
+    flag_dump_bvh: Option<String>,
+    flag_denoise: Option<bool>,
+    flag_shadow_pass: Option<String>,
+    flag_mattes: Option<bool>,
+    flag_slices: Option<usize>,
+    flag_bake: Option<bool>,
+    arg_instance: Option<String>,
+    arg_resolution: Option<usize>,
     flag_master: Option<bool>,
+    flag_spp: Option<usize>,
+    flag_resolution_scale: Option<f32>,
     arg_workers: Vec<String>,
     flag_worker: Option<bool>,
+    flag_verbose: Option<bool>,
+    flag_quiet: Option<bool>,
+}
+
+/// Prints the messages logged by tray_rust, informational messages are printed to stdout
+/// and warnings and errors to stderr
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error! {}", record.args()),
+            Level::Warn => eprintln!("Warning! {}", record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
+    fn flush(&self) {}
 }
 
+static LOGGER: Logger = Logger;
+
 fn single_node_render(args: Args) {
     let num_threads = match args.flag_n {
         Some(n) => n,
         None => num_cpus::get() as u32,
     };
     let out_path = match args.flag_o {
-        Some(ref f) => {
-            let p = PathBuf::from(f);
-            // If we're writing to a directory make sure it exists
-            if p.extension() == None {
-                if let Err(e) = std::fs::create_dir(p.as_path()) {
-                    if e.kind() != ErrorKind::AlreadyExists {
-                        panic!("Failed to create output directory");
-                    }
-                }
-            }
-            p
-        },
+        Some(ref f) => PathBuf::from(f),
         None => PathBuf::from("./"),
     };
+    if args.arg_scenefile.len() > 1 && out_path.extension() != None {
+        panic!("An output directory must be passed with -o when rendering multiple scenes");
+    }
+    // The thread pool is shared by all the scenes we're rendering
+    let mut exec = exec::MultiThreaded::new(num_threads);
+    let batch_start = SystemTime::now();
+    for scene_file in &args.arg_scenefile {
+        // When rendering a batch of scenes each gets its own output directory
+        let scene_out = if args.arg_scenefile.len() > 1 {
+            let stem = Path::new(scene_file).file_stem().expect("Invalid scene file name");
+            info!("Rendering scene '{}'\n--------------------", scene_file);
+            out_path.join(stem)
+        } else {
+            out_path.clone()
+        };
+        create_output_dir(&scene_out);
+        render_scene(&args, scene_file, scene_out, num_threads, &mut exec);
+    }
+    if args.arg_scenefile.len() > 1 {
+        let time = batch_start.elapsed().expect("Failed to get render time?");
+        info!("Rendering all {} scenes took {:4}s", args.arg_scenefile.len(),
+                 time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
+    }
+}
+
+/// If we're writing to a directory make sure it exists
+fn create_output_dir(out_path: &Path) {
+    if out_path.extension() == None {
+        if let Err(e) = std::fs::create_dir_all(out_path) {
+            if e.kind() != ErrorKind::AlreadyExists {
+                panic!("Failed to create output directory");
+            }
+        }
+    }
+}
 
-    let (mut scene, mut rt, spp, mut frame_info) = scene::Scene::load_file(&args.arg_scenefile[..]);
+/// Render all the frames of the scene file passed using the executor `exec`
+fn render_scene(args: &Args, scene_file: &str, out_path: PathBuf, num_threads: u32,
+                exec: &mut exec::MultiThreaded) {
+    let (mut scene, mut rt, spp, mut frame_info) = scene::Scene::load_file(scene_file);
     let dim = rt.dimensions();
+    if let Some(ref ty) = args.flag_integrator {
+        scene.integrator = scene::default_integrator(ty);
+    }
+    if Some(true) == args.flag_clay {
+        scene.set_clay();
+    }
+    if let Some(ref target) = args.flag_shadow_pass {
+        scene.set_shadow_pass(target);
+    }
+    if let Some(ref path) = args.flag_dump_bvh {
+        dump_bvh(&scene, path);
+    }
+    print_warnings(&scene);
 
     frame_info.start = match args.flag_start_frame {
         Some(x) => x,
@@ -85,47 +214,213 @@ fn single_node_render(args: Args) {
         Some(x) => x,
         _ => frame_info.end,
     };
+    let frame_step = match args.flag_frame_step {
+        Some(0) => panic!("--frame-step must be at least 1"),
+        Some(x) => x,
+        _ => 1,
+    };
+    let denoise = Some(true) == args.flag_denoise;
+    let aov_outputs = output::uses_aovs(&scene.outputs);
+    if denoise || aov_outputs {
+        rt.set_aovs(true);
+    }
+    let mattes = Some(true) == args.flag_mattes;
+    rt.set_id_mattes(mattes);
+    // Each slice is rendered as its own image of the frame, or the whole clip range if not slicing
+    let slices: Vec<_> = match args.flag_slices {
+        Some(0) => panic!("--slices must be at least 1"),
+        Some(_) if mattes => panic!("--slices can't be combined with --mattes"),
+        Some(n) => (0..n).map(|s| Some((s, n))).collect(),
+        None => vec![None],
+    };
+    // Check the cameras we'll slice up front instead of failing partway through the render
+    if args.flag_slices.is_some() {
+        for i in (frame_info.start..frame_info.end + 1).step_by(frame_step) {
+            if !scene.camera_at(i).clip().1.is_finite() {
+                panic!("--slices requires a finite camera clip range but the camera rendering frame {} \
+                        doesn't set one, see the camera docs", i);
+            }
+        }
+    }
+    let tags = scene.object_tags();
     let scene_start = SystemTime::now();
-    let mut config = exec::Config::new(out_path, args.arg_scenefile, spp, num_threads, frame_info, (0, 0));
-    let mut exec = exec::MultiThreaded::new(num_threads);
-    for i in frame_info.start..frame_info.end + 1 {
+    let mut config = exec::Config::new(out_path, scene_file.to_owned(), spp, num_threads, frame_info, (0, 0));
+    config.outputs = scene.outputs.clone();
+    config.grade = rt.grade();
+    config.tonemap = rt.tonemap();
+    config.auto_exposure = rt.auto_exposure();
+    if Some(true) == args.flag_thumbnail {
+        render_thumbnail(&mut scene, exec, &mut rt, &config, dim);
+    }
+    let eyes = if scene.is_stereo() { vec![Eye::Left, Eye::Right] } else { vec![Eye::Center] };
+    // Frames keep their true number so animation is sampled at the right time
+    for i in (frame_info.start..frame_info.end + 1).step_by(frame_step) {
         config.current_frame = i;
-        exec.render(&mut scene, &mut rt, &config);
+        // The eyes and slices of a frame are all exposed the same, using the first image's exposure
+        let mut frame_exposure = None;
+        for eye in &eyes {
+            scene.set_eye(*eye);
+            for slice in &slices {
+                scene.set_slice(*slice);
+                exec.render(&mut scene, &mut rt, &config);
 
-        let img = rt.get_render();
-        let out_file = match config.out_path.extension() {
-            Some(_) => config.out_path.clone(),
-            None => config.out_path.join(PathBuf::from(format!("frame{:05}.png", i))),
-        };
-        match image::save_buffer(&out_file.as_path(), &img[..], dim.0 as u32, dim.1 as u32, image::RGB(8)) {
-            Ok(_) => {},
-            Err(e) => println!("Error saving image, {}", e),
-        };
-        rt.clear();
-        println!("Frame {}: rendered to '{}'\n--------------------", i, out_file.display());
+                let mut render = exec.get_renderf32(&rt);
+                if denoise {
+                    let (albedo, normals) = rt.get_aovs();
+                    render = Denoiser::default().denoise(&render, &albedo, &normals, dim);
+                }
+                if let Some(ref mut exposure) = config.auto_exposure {
+                    let scale = match frame_exposure {
+                        Some(s) => s,
+                        None => exposure.update(&render),
+                    };
+                    tonemap::expose(&mut render, scale);
+                    frame_exposure = Some(scale);
+                }
+                let suffix = match *slice {
+                    Some((s, _)) => format!("{}_slice{:03}", eye.suffix(), s),
+                    None => eye.suffix().to_owned(),
+                };
+                let aovs = if aov_outputs { rt.get_aov_image() } else { Vec::new() };
+                let mut out_files = output::save_suffixed_frame(&config.outputs, &config.out_path, i, &suffix, dim,
+                                                                &render, &aovs, config.grade, config.tonemap);
+                if mattes {
+                    out_files.extend(output::save_eye_mattes(&rt.get_id_mattes(), &tags, &config.out_path, i,
+                                                             *eye, dim));
+                }
+                rt.clear();
+                for f in &out_files {
+                    info!("Frame {}: rendered to '{}'", i, f.display());
+                }
+            }
+        }
+        info!("--------------------");
     }
     let time = scene_start.elapsed().expect("Failed to get render time?");
-    println!("Rendering entire sequence took {:4}s", time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
+    info!("Rendering entire sequence took {:4}s", time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
+}
+
+/// Print any likely mistakes found in the scene before we start rendering it
+fn print_warnings(scene: &scene::Scene) {
+    for w in scene.validate() {
+        warn!("{}", w);
+    }
+}
+
+/// Write the scene's BVH to the file at `path`
+fn dump_bvh(scene: &scene::Scene, path: &str) {
+    let mut f = match File::create(path) {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => panic!("Failed to create BVH dump file {}: {}", path, e),
+    };
+    let bvh = match scene.accel.bvh() {
+        Some(b) => b,
+        None => {
+            warn!("The scene's objects aren't stored in a BVH, there's no BVH to dump");
+            return;
+        },
+    };
+    match bvh.write_nodes(&mut f) {
+        Ok(_) => info!("Wrote BVH to '{}'", path),
+        Err(e) => error!("Failed to write BVH to '{}', {}", path, e),
+    }
+}
+
+/// Render a low resolution preview of the first frame and save it out so obvious
+/// mistakes in the scene can be caught before waiting on the full render. The preview is
+/// rendered with the film's render target, so it's filtered, graded, exposed and tonemapped
+/// like the final frames
+fn render_thumbnail(scene: &mut scene::Scene, exec: &mut exec::MultiThreaded, rt: &mut RenderTarget,
+                    config: &exec::Config, dim: (usize, usize)) {
+    let thumb_dim = (cmp::max(dim.0 / THUMBNAIL_SCALE, 1), cmp::max(dim.1 / THUMBNAIL_SCALE, 1));
+    let mut thumb_config = config.clone();
+    thumb_config.spp = cmp::min(config.spp, THUMBNAIL_SPP);
+    thumb_config.current_frame = config.frame_info.start;
+
+    scene.set_image_dimensions(thumb_dim);
+    rt.resize(thumb_dim);
+    exec.render(scene, rt, &thumb_config);
+    let mut render = exec.get_renderf32(rt);
+    scene.set_image_dimensions(dim);
+    rt.resize(dim);
+
+    // The thumbnail is exposed on its own so it doesn't affect the smoothing of the frames' exposure
+    if let Some(mut exposure) = config.auto_exposure {
+        exposure.apply(&mut render[..]);
+    }
+    let out_file = match config.out_path.extension() {
+        Some(_) => config.out_path.with_extension("thumb.png"),
+        None => config.out_path.join(PathBuf::from("thumbnail.png")),
+    };
+    if let Err(e) = Output::new("thumbnail.png").save(&out_file, thumb_dim, &render, &[], config.grade,
+                                                      config.tonemap) {
+        error!("Failed to save thumbnail, {}", e);
+    }
+    info!("Thumbnail: rendered to '{}'\n--------------------", out_file.display());
+}
+
+/// Bake the lighting on the instance passed into a texture instead of rendering the scene
+fn bake_node(args: Args) {
+    let num_threads = match args.flag_n {
+        Some(n) => n,
+        None => num_cpus::get() as u32,
+    };
+    let tag = args.arg_instance.clone().expect("An instance to bake is required");
+    let res = match args.arg_resolution {
+        Some(0) | None => panic!("The bake resolution must be at least 1"),
+        Some(x) => x,
+    };
+    let out_path = match args.flag_o {
+        Some(ref f) => PathBuf::from(f),
+        None => PathBuf::from(format!("{}_bake.png", tag)),
+    };
+    let (mut scene, _, spp, frame_info) = scene::Scene::load_file(&args.arg_scenefile[0][..]);
+    let spp = match args.flag_spp {
+        Some(0) => panic!("--spp must be at least 1"),
+        Some(x) => x,
+        _ => spp,
+    };
+    let time_step = frame_info.time / frame_info.frames as f32;
+    let start_time = frame_info.start as f32 * time_step;
+    scene.update_frame(frame_info.start, start_time, start_time + time_step);
+
+    let mut exec = exec::MultiThreaded::new(num_threads);
+    let bake_start = SystemTime::now();
+    let dim = (res, res);
+    let texture = exec.bake(&scene, &tag, dim, spp, start_time);
+    let time = bake_start.elapsed().expect("Failed to get bake time?");
+    info!("Baking '{}' took {:4}s", tag, time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9);
+    let out_str = out_path.to_str().expect("Invalid output path");
+    match Output::new(out_str).save(&out_path, dim, &texture, &[], Grade::identity(), Tonemap::Clamp) {
+        Ok(_) => info!("Baked lighting to '{}'", out_path.display()),
+        Err(e) => error!("Failed to save baked lighting to '{}', {}", out_path.display(), e),
+    }
 }
 
 fn master_node(args: Args) {
     let out_path = match args.flag_o {
-        Some(ref f) => {
-            let p = PathBuf::from(f);
-            // If we're writing to a directory make sure it exists
-            if p.extension() == None {
-                if let Err(e) = std::fs::create_dir(p.as_path()) {
-                    if e.kind() != ErrorKind::AlreadyExists {
-                        panic!("Failed to create output directory");
-                    }
-                }
-            }
-            p
-        },
+        Some(ref f) => PathBuf::from(f),
         None => PathBuf::from("./"),
     };
+    create_output_dir(&out_path);
 
-    let (_, rt, spp, mut frame_info) = scene::Scene::load_file(&args.arg_scenefile[..]);
+    let scene_file = args.arg_scenefile[0].clone();
+    let (scene, rt, spp, mut frame_info, assets) = scene::Scene::load_file_with_assets(&scene_file[..]);
+    print_warnings(&scene);
+    let spp = match args.flag_spp {
+        Some(0) => panic!("--spp must be at least 1"),
+        Some(x) => x,
+        _ => spp,
+    };
+    let dim = match args.flag_resolution_scale {
+        Some(s) if s > 0.0 => {
+            let dim = rt.dimensions();
+            (cmp::max((dim.0 as f32 * s).round() as usize, 1),
+             cmp::max((dim.1 as f32 * s).round() as usize, 1))
+        },
+        Some(_) => panic!("--resolution-scale must be greater than 0"),
+        _ => rt.dimensions(),
+    };
 
     frame_info.start = match args.flag_start_frame {
         Some(x) => x,
@@ -136,13 +431,17 @@ fn master_node(args: Args) {
         _ => frame_info.end,
     };
     let scene_start = SystemTime::now();
-    let config = exec::Config::new(out_path, args.arg_scenefile, spp, 0, frame_info, (0, 0));
+    let mut config = exec::Config::new(out_path, scene_file, spp, 0, frame_info, (0, 0));
+    config.outputs = scene.outputs.clone();
+    config.grade = rt.grade();
+    config.tonemap = rt.tonemap();
+    config.auto_exposure = rt.auto_exposure();
     // Connect to all the workers and prepare to send/receive data from/to them
-    let (mut master, mut event_loop) = distrib::Master::start_workers(args.arg_workers, config, rt.dimensions());
+    let (mut master, mut event_loop) = distrib::Master::start_workers(args.arg_workers, config, dim, assets);
     // Start the event loop to wait for and read results from each worker. No
     event_loop.run(&mut master).unwrap();
     let time = scene_start.elapsed().expect("Failed to get render time?");
-    println!("Rendering entire sequence took {:4}s", time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
+    info!("Rendering entire sequence took {:4}s", time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
 }
 
 fn worker_node(args: Args) {
@@ -159,18 +458,28 @@ fn worker_node(args: Args) {
         exec.render(&mut worker.scene, &mut worker.render_target, &worker.config);
         worker.send_results();
         worker.render_target.clear();
-        println!("--------------------");
+        info!("--------------------");
     }
     let time = scene_start.elapsed().expect("Failed to get render time?");
-    println!("Rendering entire sequence took {:4}s", time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
+    info!("Rendering entire sequence took {:4}s", time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9)
 }
 
 fn main() {
     let args: Args = Docopt::new(USAGE).and_then(|d| d.deserialize()).unwrap_or_else(|e| e.exit());
+    log::set_logger(&LOGGER).expect("Failed to install the logger");
+    log::set_max_level(if Some(true) == args.flag_quiet {
+        LevelFilter::Warn
+    } else if Some(true) == args.flag_verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
     if Some(true) == args.flag_master {
         master_node(args);
     } else if Some(true) == args.flag_worker {
         worker_node(args);
+    } else if Some(true) == args.flag_bake {
+        bake_node(args);
     } else {
         single_node_render(args);
     }
@@ -0,0 +1,139 @@
+//! Defines an emissive material, which makes the surface of any other material glow with
+//! a constant Lambertian emission, e.g. for screens, LEDs or other self-illuminated details
+//! on textured meshes. Unlike area lights the emission isn't sampled when computing direct
+//! lighting, it's only seen by rays which happen to hit the surface, so surfaces lit mostly
+//! by emissive materials will be noisy. Use an area light for surfaces that light the scene.
+//! Like area lights only the front side of the surface, which its geometric normal faces, emits,
+//! unless a separate back emission is given.
+//!
+//! # Scene Usage Example
+//! Any material can be made emissive by giving it an `emission` color or texture name, along
+//! with an optional `emission_strength` scaling it, which defaults to 1.
+//!
+//! ```json
+//! "materials": [
+//!     {
+//!         "name": "tv_screen",
+//!         "type": "plastic",
+//!         "diffuse": [0.02, 0.02, 0.02],
+//!         "gloss": [1, 1, 1],
+//!         "roughness": 0.01,
+//!         "emission": "screen_image",
+//!         "emission_strength": 4.0
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! Like area lights the `emission` can also be an object with `front` and `back` colors or
+//! texture names to emit from both sides of the surface, both are scaled by the strength.
+//! If no `back` is given the back doesn't emit.
+//!
+//! ```json
+//! "materials": [
+//!     {
+//!         "name": "neon_sign",
+//!         "type": "matte",
+//!         "diffuse": [0.1, 0.1, 0.1],
+//!         "roughness": 0.0,
+//!         "emission": {
+//!             "front": [1, 0.1, 0.4],
+//!             "back": [0.1, 0.4, 1]
+//!         },
+//!         "emission_strength": 8.0
+//!     },
+//!     ...
+//! ]
+//! ```
+
+use std::sync::Arc;
+
+use light_arena::Allocator;
+
+use geometry::Intersection;
+use bxdf::BSDF;
+use film::Colorf;
+use linalg::{self, Vector};
+use material::Material;
+use texture::Texture;
+
+/// The Emissive material adds a Lambertian emission to the surface of another material
+pub struct Emissive {
+    /// The material describing how the surface reflects light
+    material: Arc<Material + Send + Sync>,
+    emission: Arc<Texture + Send + Sync>,
+    /// The emission from the back of the surface, if None the back doesn't emit
+    back_emission: Option<Arc<Texture + Send + Sync>>,
+    strength: f32,
+}
+
+impl Emissive {
+    /// Create an emissive material which reflects light like `material` and emits the
+    /// radiance `emission` scaled by `strength`
+    pub fn new(material: Arc<Material + Send + Sync>, emission: Arc<Texture + Send + Sync>,
+               strength: f32) -> Emissive {
+        assert!(strength >= 0.0, "Emission strength must be positive");
+        Emissive { material: material, emission: emission, back_emission: None, strength: strength }
+    }
+    /// Create an emissive material which reflects light like `material` and emits the radiance
+    /// `front` from the front of the surface and `back` from the back, both scaled by `strength`
+    pub fn two_sided(material: Arc<Material + Send + Sync>, front: Arc<Texture + Send + Sync>,
+                     back: Arc<Texture + Send + Sync>, strength: f32) -> Emissive {
+        let mut e = Emissive::new(material, front, strength);
+        e.back_emission = Some(back);
+        e
+    }
+}
+
+impl Material for Emissive {
+    fn bsdf<'a, 'b, 'c>(&'a self, hit: &Intersection<'a, 'b>,
+                        alloc: &'c Allocator) -> BSDF<'c> where 'a: 'c
+    {
+        self.material.bsdf(hit, alloc)
+    }
+    fn transmittance(&self, hit: &Intersection) -> Colorf {
+        self.material.transmittance(hit)
+    }
+    fn emission(&self, w_o: &Vector, hit: &Intersection) -> Colorf {
+        let emission = if linalg::dot(w_o, &hit.dg.ng) > 0.0 {
+            &self.emission
+        } else {
+            match self.back_emission {
+                Some(ref b) => b,
+                None => return Colorf::black(),
+            }
+        };
+        emission.sample_color(hit.dg.u, hit.dg.v, hit.dg.time) * self.strength
+    }
+}
+
+#[test]
+fn test_front_and_back_emission() {
+    use geometry::{Instance, Sphere};
+    use linalg::{Point, Ray};
+    use texture;
+    use test_util::{gray_matte, translate};
+    let color = |r, g, b| -> Arc<Texture + Send + Sync> {
+        Arc::new(texture::ConstantColor::new(Colorf::new(r, g, b)))
+    };
+    let one_sided = Arc::new(Emissive::new(gray_matte(), color(1.0, 0.5, 0.25), 2.0));
+    let two_sided = Arc::new(Emissive::two_sided(gray_matte(), color(1.0, 0.5, 0.25), color(0.1, 0.2, 0.3), 2.0));
+    // Rays hitting the sphere from outside see its front and rays from inside see its back
+    let emission = |material: Arc<Material + Send + Sync>, origin: Point| {
+        let sphere = Instance::receiver(Arc::new(Sphere::new(1.0)), material, translate(0.0, 0.0, 0.0),
+                                        "sphere".to_owned());
+        let mut ray = Ray::new(&origin, &Vector::new(1.0, 0.0, 0.0), 0.0);
+        let hit = sphere.intersect(&mut ray).expect("Ray should hit the sphere");
+        hit.material.emission(&-ray.d, &hit)
+    };
+    let matches = |c: Colorf, r: f32, g: f32, b: f32| {
+        f32::abs(c.r - r) < 1e-6 && f32::abs(c.g - g) < 1e-6 && f32::abs(c.b - b) < 1e-6
+    };
+    let (outside, inside) = (Point::new(-5.0, 0.0, 0.0), Point::broadcast(0.0));
+    assert!(matches(emission(one_sided.clone(), outside), 2.0, 1.0, 0.5));
+    assert!(emission(one_sided, inside).is_black());
+    assert!(matches(emission(two_sided.clone(), outside), 2.0, 1.0, 0.5));
+    assert!(matches(emission(two_sided, inside), 0.2, 0.4, 0.6));
+    // Materials which aren't emissive don't emit from either side
+    assert!(emission(gray_matte(), outside).is_black() && emission(gray_matte(), inside).is_black());
+}
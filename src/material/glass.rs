@@ -4,6 +4,7 @@
 //! The specular glass material describes a thin glass surface type of material,
 //! not a solid block of glass (there is no absorption of light). The glass requires
 //! a reflective and emissive color along with a refrective index, eta.
+//! Shadows cast by the glass are tinted by its transmit color.
 //!
 //! ```json
 //! "materials": [
@@ -23,6 +24,7 @@ use std::sync::Arc;
 use light_arena::Allocator;
 
 use geometry::Intersection;
+use film::Colorf;
 use bxdf::{BxDF, BSDF, SpecularReflection, SpecularTransmission};
 use bxdf::fresnel::Dielectric;
 use material::Material;
@@ -76,6 +78,9 @@ impl Material for Glass {
         }
         BSDF::new(bxdfs, eta, &hit.dg)
     }
+    fn transmittance(&self, hit: &Intersection) -> Colorf {
+        self.transmit.sample_color(hit.dg.u, hit.dg.v, hit.dg.time)
+    }
 }
 
 
@@ -5,7 +5,31 @@
 //! will select a [Lambertian](https://en.wikipedia.org/wiki/Lambertian_reflectance) model
 //! while a roughness > 0 will select an
 //! [Oren-Nayar](https://en.wikipedia.org/wiki/Oren%E2%80%93Nayar_reflectance_model)
-//! reflectance model.
+//! reflectance model. The roughness is Oren-Nayar's sigma, the standard deviation in degrees
+//! of the angle of the microfacet slopes, so reasonable values are in [0, 45].
+//!
+//! The model can also be chosen explicitly with `"model"`, either `"lambertian"`, which takes
+//! no roughness, or `"oren_nayar"`, which takes the `"sigma"` in degrees in place of the
+//! roughness. Oren-Nayar with a sigma of 0 is the same as Lambertian.
+//!
+//! ```json
+//! "materials": [
+//!     {
+//!         "name": "chalk",
+//!         "type": "matte",
+//!         "diffuse": [0.9, 0.9, 0.9],
+//!         "model": "oren_nayar",
+//!         "sigma": 20
+//!     },
+//!     ...
+//! ]
+//! ```
+//!
+//! Real rough dielectrics like paint or plaster show some Fresnel reflection at grazing
+//! angles, the optional `specular` weight adds a rough dielectric reflection lobe on top of
+//! the diffuse one to give this subtle edge brightening. It defaults to 0, a purely diffuse
+//! material. The diffuse reflection is reduced by the light the specular lobe reflects so
+//! the material doesn't reflect more light than it receives.
 //!
 //! ```json
 //! "materials": [
@@ -13,7 +37,8 @@
 //!         "name": "purple_matte",
 //!         "type": "matte",
 //!         "diffuse": [1, 0, 1],
-//!         "roughness" 0.5
+//!         "roughness": 10,
+//!         "specular": 0.2
 //!     },
 //!     ...
 //! ]
@@ -24,26 +49,44 @@ use std::sync::Arc;
 use light_arena::Allocator;
 
 use geometry::Intersection;
-use bxdf::{BxDF, BSDF, Lambertian, OrenNayar};
+use bxdf::{BxDF, BSDF, Lambertian, OrenNayar, TorranceSparrow, FresnelWeighted};
+use bxdf::microfacet::Beckmann;
+use bxdf::fresnel::Dielectric;
+use film::Colorf;
 use material::Material;
 use texture::Texture;
 
+/// Roughness of the microfacet distribution used for the specular edge reflection
+const SPECULAR_ROUGHNESS: f32 = 0.3;
+
 /// The Matte material describes diffuse materials with either a Lambertian or
 /// Oren-Nayar BRDF. The Lambertian BRDF is used for materials with no roughness
 /// while Oren-Nayar is used for those with some roughness.
 pub struct Matte {
     diffuse: Arc<Texture + Send + Sync>,
     roughness: Arc<Texture + Send + Sync>,
+    /// Weight of the rough dielectric reflection lobe, 0 for a purely diffuse material
+    specular: f32,
 }
 
 impl Matte {
-    /// Create a new Matte material with the desired diffuse color and roughness
+    /// Create a new Matte material with the desired diffuse color and roughness, the roughness
+    /// is the Oren-Nayar sigma in degrees
     pub fn new(diffuse: Arc<Texture + Send + Sync>,
                roughness: Arc<Texture + Send + Sync>) -> Matte
     {
+        Matte::with_specular(diffuse, roughness, 0.0)
+    }
+    /// Create a new Matte material with the desired diffuse color and roughness, along with
+    /// a rough dielectric reflection lobe with weight `specular` for Fresnel edge brightening
+    pub fn with_specular(diffuse: Arc<Texture + Send + Sync>,
+                         roughness: Arc<Texture + Send + Sync>, specular: f32) -> Matte
+    {
+        assert!(specular >= 0.0 && specular <= 1.0, "Matte specular weight must be in [0, 1]");
         Matte {
             diffuse: diffuse.clone(),
-            roughness: roughness.clone()
+            roughness: roughness.clone(),
+            specular: specular,
         }
     }
 }
@@ -55,13 +98,62 @@ impl Material for Matte {
         let diffuse = self.diffuse.sample_color(hit.dg.u, hit.dg.v, hit.dg.time);
         let roughness = self.roughness.sample_f32(hit.dg.u, hit.dg.v, hit.dg.time);
 
-        let bsdfs = alloc.alloc_slice::<&'c BxDF>(1);
-        if roughness == 0.0 {
-            bsdfs[0] = alloc.alloc(Lambertian::new(&diffuse));
+        let num_bxdfs = if self.specular > 0.0 { 2 } else { 1 };
+        let bsdfs = alloc.alloc_slice::<&'c BxDF>(num_bxdfs);
+        let diffuse_bxdf: &'c BxDF = if roughness == 0.0 {
+            alloc.alloc(Lambertian::new(&diffuse))
         } else {
-            bsdfs[0] = alloc.alloc(OrenNayar::new(&diffuse, roughness));
+            alloc.alloc(OrenNayar::new(&diffuse, roughness))
+        };
+        if self.specular > 0.0 {
+            let fresnel = alloc.alloc(Dielectric::new(1.0, 1.5));
+            let microfacet = alloc.alloc(Beckmann::new(SPECULAR_ROUGHNESS));
+            // The diffuse lobe only gets the light which isn't reflected by the specular one
+            bsdfs[0] = alloc.alloc(FresnelWeighted::new(diffuse_bxdf, fresnel, self.specular));
+            bsdfs[1] = alloc.alloc(TorranceSparrow::new(&Colorf::broadcast(self.specular), fresnel, microfacet));
+        } else {
+            bsdfs[0] = diffuse_bxdf;
         }
         BSDF::new(bsdfs, 1.0, &hit.dg)
     }
 }
 
+#[test]
+fn test_specular_energy() {
+    use std::f32;
+    use light_arena::MemoryArena;
+    use geometry::{Instance, Sphere};
+    use linalg::{Point, Ray, Vector};
+    use bxdf::BxDFType;
+    use texture;
+    use mc;
+    use test_util::translate;
+    let white = Arc::new(texture::ConstantColor::new(Colorf::broadcast(1.0)));
+    let mut arena = MemoryArena::new(1);
+    for &roughness in &[0.0, 20.0] {
+        let matte = Matte::with_specular(white.clone(), Arc::new(texture::ConstantScalar::new(roughness)), 1.0);
+        let sphere = Instance::receiver(Arc::new(Sphere::new(1.0)), Arc::new(matte), translate(0.0, 0.0, 0.0),
+                                        "sphere".to_owned());
+        let mut ray = Ray::new(&Point::new(-5.0, 0.0, 0.0), &Vector::new(1.0, 0.0, 0.0), 0.0);
+        let hit = sphere.intersect(&mut ray).expect("Ray should hit the sphere");
+        let alloc = arena.allocator();
+        let bsdf = hit.material.bsdf(&hit, &alloc);
+        // A white matte with full strength specular should reflect at most all the incident light
+        // at any angle, integrate its albedo numerically over the hemisphere above the surface
+        let n = 256;
+        for &cos_o in &[1.0, 0.7, 0.4, 0.1] {
+            let w_o = bsdf.from_shading(&Vector::new(f32::sqrt(1.0 - cos_o * cos_o), 0.0, cos_o));
+            let mut albedo = Colorf::black();
+            for i in 0..n {
+                for j in 0..n {
+                    let u = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                    let d = mc::uniform_sample_hemisphere(&u);
+                    albedo = albedo + bsdf.eval(&w_o, &bsdf.from_shading(&d), BxDFType::all()) * d.z
+                        / (mc::uniform_hemisphere_pdf() * (n * n) as f32);
+                }
+            }
+            assert!(albedo.r <= 1.0 && albedo.r > 0.5, "Albedo {} at cos {} with roughness {}",
+                    albedo.r, cos_o, roughness);
+        }
+    }
+}
@@ -5,14 +5,18 @@
 //!
 //! # Scene Usage Example
 //! The MERL material requires a filepath to a BRDF table downloaded from the
-//! [MERL BRDF Database](http://www.merl.com/brdf/).
+//! [MERL BRDF Database](http://www.merl.com/brdf/). The measured values can optionally
+//! be adjusted when they're loaded for artistic control: `scale` multiplies the values to
+//! brighten or darken the material (default 1) and `clamp` clips them to a maximum value
+//! to tame strong highlights (default no clamping).
 //!
 //! ```json
 //! "materials": [
 //!     {
 //!         "name": "oxidized_steel",
 //!         "type": "merl",
-//!         "file": "./black-oxidized-steel.binary"
+//!         "file": "./black-oxidized-steel.binary",
+//!         "scale": 1.2
 //!     },
 //!     ...
 //! ]
@@ -49,6 +53,12 @@ impl Merl {
     /// Create a new MERL BRDF by loading the refletance data from a MERL BRDF
     /// database file
     pub fn load_file(path: &Path) -> Merl {
+        Merl::load_remapped(path, 1.0, None)
+    }
+    /// Create a new MERL BRDF by loading the reflectance data from a MERL BRDF database
+    /// file, multiplying the values by `scale` and clipping them to `clamp` if passed
+    pub fn load_remapped(path: &Path, scale: f32, clamp: Option<f32>) -> Merl {
+        assert!(scale >= 0.0, "material::Merl::load_remapped - scale must not be negative");
         let file = match File::open(path) {
             Ok(f) => f,
             Err(e) => {
@@ -77,13 +87,23 @@ impl Merl {
                 // The BRDF data is stored in double precision with these odd scaling factors
                 // so decode the value
                 let x = (reader.read_f64::<LittleEndian>().unwrap() * s) as f32;
-                brdf[3 * i + c] = f32::max(0.0, x);
+                brdf[3 * i + c] = remap(x, scale, clamp);
             }
         }
         Merl { brdf: brdf, n_theta_h: n_theta_h, n_theta_d: n_theta_d, n_phi_d: n_phi_d }
     }
 }
 
+/// Apply the scale and clamp to the decoded BRDF value, negative values mark missing
+/// measurements and are set to 0
+fn remap(x: f32, scale: f32, clamp: Option<f32>) -> f32 {
+    let x = f32::max(0.0, x * scale);
+    match clamp {
+        Some(c) => f32::min(x, c),
+        None => x,
+    }
+}
+
 impl Material for Merl {
     fn bsdf<'a, 'b, 'c>(&'a self, hit: &Intersection<'a, 'b>,
                         alloc: &'c Allocator) -> BSDF<'c> where 'a: 'c {
@@ -93,3 +113,12 @@ impl Material for Merl {
     }
 }
 
+
+#[test]
+fn test_remap() {
+    assert_eq!(remap(0.5, 1.0, None), 0.5);
+    assert_eq!(remap(0.5, 2.0, None), 1.0);
+    assert_eq!(remap(0.5, 2.0, Some(0.8)), 0.8);
+    // Missing measurements stay at 0
+    assert_eq!(remap(-1.0, 2.0, Some(0.8)), 0.0);
+}
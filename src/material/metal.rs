@@ -1,5 +1,7 @@
 //! Provides a material for modelling metal surfaces of varying roughness
-//! using the Torrance Sparrow BRDF and a Blinn microfacet distribution
+//! using the Torrance Sparrow BRDF and a Blinn microfacet distribution. The energy
+//! lost by the single scattering BRDF at high roughness is restored with a multiple
+//! scattering lobe, see `bxdf::multiple_scattering`.
 //! TODO: Add Ashikman-Shirley (spelling?) anisotropic microfacet model
 //!
 //! # Scene Usage Example
@@ -26,8 +28,9 @@ use light_arena::Allocator;
 
 use film::Colorf;
 use geometry::Intersection;
-use bxdf::{BxDF, BSDF, TorranceSparrow};
+use bxdf::{BxDF, BSDF, TorranceSparrow, MultipleScattering};
 use bxdf::microfacet::Beckmann;
+use bxdf::multiple_scattering::{self, AlbedoTable};
 use bxdf::fresnel::Conductor;
 use material::Material;
 use texture::Texture;
@@ -37,6 +40,8 @@ pub struct Metal {
     eta: Arc<Texture + Send + Sync>,
     k: Arc<Texture + Send + Sync>,
     roughness: Arc<Texture + Send + Sync>,
+    /// Albedo of the Beckmann distribution used to compute the multiple scattering lobe
+    albedo: Arc<AlbedoTable>,
 }
 
 impl Metal {
@@ -47,7 +52,8 @@ impl Metal {
     {
         Metal { eta: eta.clone(),
                 k: k.clone(),
-                roughness: roughness.clone()
+                roughness: roughness.clone(),
+                albedo: Arc::new(AlbedoTable::new(Beckmann::new)),
         }
     }
 }
@@ -59,10 +65,12 @@ impl Material for Metal {
         let k = self.k.sample_color(hit.dg.u, hit.dg.v, hit.dg.time);
         let roughness = self.roughness.sample_f32(hit.dg.u, hit.dg.v, hit.dg.time);
 
-        let bxdfs = alloc.alloc_slice::<&BxDF>(1);
+        let bxdfs = alloc.alloc_slice::<&BxDF>(2);
         let fresnel = alloc.alloc(Conductor::new(&eta, &k));
         let microfacet = alloc.alloc(Beckmann::new(roughness));
         bxdfs[0] = alloc.alloc(TorranceSparrow::new(&Colorf::broadcast(1.0), fresnel, microfacet));
+        let avg_fresnel = multiple_scattering::average_fresnel(fresnel);
+        bxdfs[1] = alloc.alloc(MultipleScattering::new(&self.albedo, roughness, &avg_fresnel));
         BSDF::new(bxdfs, 1.0, &hit.dg)
     }
 }
@@ -17,11 +17,40 @@
 //!     ...
 //! ]
 //! ```
+//!
+//! Any material can also emit light by giving it an `emission` color or texture, see the
+//! `emissive` module for details.
+//!
+//! # Animated Parameters
+//! Material parameters taking a color or scalar can also be animated over time by passing a list
+//! of keyframes instead of a single value. The parameter is linearly interpolated between the
+//! keyframes and evaluated at the time of each hit. For example a matte material becoming rougher
+//! and darker over two seconds:
+//!
+//! ```json
+//! "materials": [
+//!     {
+//!         "name": "drying_clay",
+//!         "type": "matte",
+//!         "diffuse": [
+//!             { "time": 0.0, "color": [0.4, 0.25, 0.2] },
+//!             { "time": 2.0, "color": [0.7, 0.55, 0.45] }
+//!         ],
+//!         "roughness": [
+//!             { "time": 0.0, "value": 0.0 },
+//!             { "time": 2.0, "value": 0.8 }
+//!         ]
+//!     },
+//!     ...
+//! ]
+//! ```
 
 use light_arena::Allocator;
 
 use geometry::Intersection;
 use bxdf::BSDF;
+use film::Colorf;
+use linalg::Vector;
 
 pub use self::matte::Matte;
 pub use self::specular_metal::SpecularMetal;
@@ -30,6 +59,7 @@ pub use self::merl::Merl;
 pub use self::plastic::Plastic;
 pub use self::metal::Metal;
 pub use self::rough_glass::RoughGlass;
+pub use self::emissive::Emissive;
 
 pub mod matte;
 pub mod specular_metal;
@@ -38,6 +68,7 @@ pub mod merl;
 pub mod plastic;
 pub mod metal;
 pub mod rough_glass;
+pub mod emissive;
 
 /// Trait implemented by materials. Provides method to get the BSDF describing
 /// the material properties at the intersection
@@ -49,5 +80,17 @@ pub trait Material {
     /// the parent material in the BxDFs making up the BSDF.
     fn bsdf<'a, 'b, 'c>(&'a self, hit: &Intersection<'a, 'b>,
                         alloc: &'c Allocator) -> BSDF<'c> where 'a: 'c;
+    /// Get the color of light passing straight through the surface at the hit point,
+    /// used to tint shadow rays passing through transmissive materials. Opaque materials
+    /// block all light
+    fn transmittance(&self, _: &Intersection) -> Colorf {
+        Colorf::black()
+    }
+    /// Get the radiance emitted by the surface at the hit point along `w_o`. Emission from
+    /// materials isn't importance sampled, it's only seen by rays hitting the surface.
+    /// Most materials don't emit any light
+    fn emission(&self, _: &Vector, _: &Intersection) -> Colorf {
+        Colorf::black()
+    }
 }
 
@@ -4,6 +4,7 @@
 //! The rough glass material describes a thin glass surface material,
 //! not a solid block of glass (there is no absorption of light). The glass requires
 //! a reflective and emissive color along with a refrective index, eta and roughness.
+//! Shadows cast by the glass are tinted by its transmit color.
 //!
 //! ```json
 //! "materials": [
@@ -24,6 +25,7 @@ use std::sync::Arc;
 use light_arena::Allocator;
 
 use geometry::Intersection;
+use film::Colorf;
 use bxdf::{BxDF, BSDF, MicrofacetTransmission, TorranceSparrow};
 use bxdf::microfacet::Beckmann;
 use bxdf::fresnel::Dielectric;
@@ -82,6 +84,9 @@ impl Material for RoughGlass {
         }
         BSDF::new(bxdfs, eta, &hit.dg)
     }
+    fn transmittance(&self, hit: &Intersection) -> Colorf {
+        self.transmit.sample_color(hit.dg.u, hit.dg.v, hit.dg.time)
+    }
 }
 
 
@@ -1,7 +1,13 @@
 //! Defines various Monte Carlo sampling functions for sampling
 //! points/directions on objects and computing the corresponding pdfs
+//!
+//! Each sampling function takes uniform random samples in [0, 1) and has a matching
+//! function computing the pdf of the samples it returns with respect to solid angle
+//! (or area for the disk). The heuristics for weighting samples from multiple
+//! sampling strategies with multiple importance sampling are also provided, along
+//! with the piecewise-constant 1D and 2D distributions for sampling tabulated functions.
 
-use std::f32;
+use std::{f32, cmp};
 
 use linalg::{self, Vector};
 
@@ -49,6 +55,17 @@ pub fn concentric_sample_disk(u: &(f32, f32)) -> (f32, f32) {
     let theta = theta * f32::consts::FRAC_PI_4;
     (radius * f32::cos(theta), radius * f32::sin(theta))
 }
+/// Compute the PDF of the concentric disk sampling with respect to area, the samples
+/// are uniformly distributed over the unit disk
+pub fn concentric_disk_pdf() -> f32 { f32::consts::FRAC_1_PI }
+/// Balance heuristic for multiple importance sampling for two functions being sampled, f & g
+/// - `n_f`, `n_g` number of samples taken of each
+/// - `pdf_f`, `pdf_g` pdf of each function
+pub fn balance_heuristic(n_f: f32, pdf_f: f32, n_g: f32, pdf_g: f32) -> f32 {
+    let f = n_f * pdf_f;
+    let g = n_g * pdf_g;
+    f / (f + g)
+}
 /// Power heuristic for multiple importance sampling for two functions being sampled, f & g
 /// where beta is hard-coded to be two following PBR & Veach
 /// - `n_f`, `n_g` number of samples taken of each
@@ -87,4 +104,221 @@ pub fn uniform_sample_sphere(samples: &(f32, f32)) -> Vector {
     let phi = f32::consts::PI * 2.0 * samples.1;
     Vector::new(f32::cos(phi) * r, f32::sin(phi) * r, z)
 }
+/// Return the PDF for uniformly sampling a direction on the unit sphere
+pub fn uniform_sphere_pdf() -> f32 { 1.0 / (4.0 * f32::consts::PI) }
+/// Uniformly sample a direction in the hemisphere around (0, 0, 1)
+pub fn uniform_sample_hemisphere(samples: &(f32, f32)) -> Vector {
+    let z = samples.0;
+    let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+    let phi = f32::consts::PI * 2.0 * samples.1;
+    Vector::new(f32::cos(phi) * r, f32::sin(phi) * r, z)
+}
+/// Return the PDF for uniformly sampling a direction in the hemisphere
+pub fn uniform_hemisphere_pdf() -> f32 { 1.0 / (2.0 * f32::consts::PI) }
+
 
+/// A piecewise-constant 1D distribution over [0, 1) built from a tabulated function,
+/// used to draw samples proportional to the function
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    func_int: f32,
+}
+
+impl Distribution1D {
+    /// Create the distribution for the function with the values `f` at evenly spaced
+    /// intervals over [0, 1). The function should not be negative, if it's zero everywhere
+    /// samples are drawn uniformly
+    pub fn new(f: &[f32]) -> Distribution1D {
+        assert!(!f.is_empty(), "A distribution requires at least one function value");
+        let n = f.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            assert!(f[i] >= 0.0, "Distribution function values must not be negative");
+            let prev = cdf[i];
+            cdf.push(prev + f[i] / n as f32);
+        }
+        let func_int = cdf[n];
+        if func_int == 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        } else {
+            for c in &mut cdf {
+                *c /= func_int;
+            }
+        }
+        Distribution1D { func: f.to_vec(), cdf: cdf, func_int: func_int }
+    }
+    /// Get the number of function values in the distribution
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+    /// Get the integral of the function over [0, 1)
+    pub fn integral(&self) -> f32 {
+        self.func_int
+    }
+    /// Find the interval containing `u`, the last one whose cdf value is <= u
+    fn find_interval(&self, u: f32) -> usize {
+        let (mut lo, mut hi) = (0, self.cdf.len() - 1);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        cmp::min(lo, self.count() - 1)
+    }
+    /// Sample a value in [0, 1) proportional to the function with the random sample `u`.
+    /// Returns the value, its pdf and the index of the interval it fell in
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let i = self.find_interval(u);
+        let mut du = u - self.cdf[i];
+        if self.cdf[i + 1] - self.cdf[i] > 0.0 {
+            du /= self.cdf[i + 1] - self.cdf[i];
+        }
+        let x = f32::min((i as f32 + du) / self.count() as f32, 1.0 - f32::EPSILON);
+        (x, self.pdf_at(i), i)
+    }
+    /// Sample one of the intervals proportional to the function with the random sample `u`.
+    /// Returns the index of the interval and the probability of choosing it
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32) {
+        let i = self.find_interval(u);
+        (i, self.cdf[i + 1] - self.cdf[i])
+    }
+    /// Compute the pdf of sampling the value `x` in [0, 1)
+    pub fn pdf(&self, x: f32) -> f32 {
+        let i = cmp::min(f32::max(x * self.count() as f32, 0.0) as usize, self.count() - 1);
+        self.pdf_at(i)
+    }
+    fn pdf_at(&self, i: usize) -> f32 {
+        if self.func_int > 0.0 {
+            self.func[i] / self.func_int
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A piecewise-constant 2D distribution over [0, 1)^2 built from a tabulated function,
+/// sampled by first choosing a row with the marginal distribution and then a position
+/// in the row with its conditional distribution
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Create the distribution for the `nu * nv` function values in `f`, stored in
+    /// row major order with `nu` values along u in each of the `nv` rows
+    pub fn new(f: &[f32], nu: usize, nv: usize) -> Distribution2D {
+        assert_eq!(f.len(), nu * nv, "Distribution2D requires nu * nv function values");
+        let conditional: Vec<_> = f.chunks(nu).map(Distribution1D::new).collect();
+        let marginal_func: Vec<_> = conditional.iter().map(|c| c.integral()).collect();
+        Distribution2D { conditional: conditional, marginal: Distribution1D::new(&marginal_func[..]) }
+    }
+    /// Sample a point in [0, 1)^2 proportional to the function with the random samples `u`.
+    /// Returns the point and its pdf
+    pub fn sample_continuous(&self, u: &(f32, f32)) -> ((f32, f32), f32) {
+        let (y, pdf_v, v) = self.marginal.sample_continuous(u.1);
+        let (x, pdf_u, _) = self.conditional[v].sample_continuous(u.0);
+        ((x, y), pdf_u * pdf_v)
+    }
+    /// Compute the pdf of sampling the point `p` in [0, 1)^2
+    pub fn pdf(&self, p: &(f32, f32)) -> f32 {
+        let v = cmp::min(f32::max(p.1 * self.marginal.count() as f32, 0.0) as usize,
+                         self.marginal.count() - 1);
+        self.marginal.pdf(p.1) * self.conditional[v].pdf(p.0)
+    }
+}
+
+#[test]
+fn test_distribution_2d() {
+    // Sample a histogram of the distribution on a regular grid of samples,
+    // each cell should be hit in proportion to its function value
+    let (nu, nv) = (4, 3);
+    let f = [1.0, 2.0, 0.0, 4.0,
+             0.5, 0.5, 0.5, 0.5,
+             0.0, 0.0, 8.0, 1.0];
+    let dist = Distribution2D::new(&f, nu, nv);
+    let total: f32 = f.iter().sum();
+    let n = 256;
+    let mut hist = [0; 12];
+    for i in 0..n {
+        for j in 0..n {
+            let u = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+            let (p, pdf) = dist.sample_continuous(&u);
+            assert!(p.0 >= 0.0 && p.0 < 1.0 && p.1 >= 0.0 && p.1 < 1.0);
+            assert!(f32::abs(pdf - dist.pdf(&p)) < 1e-4);
+            let cell = (p.1 * nv as f32) as usize * nu + (p.0 * nu as f32) as usize;
+            // The pdf is the function value normalized over the domain
+            assert!(f32::abs(pdf - f[cell] * (nu * nv) as f32 / total) < 1e-4);
+            hist[cell] += 1;
+        }
+    }
+    for (h, x) in hist.iter().zip(f.iter()) {
+        let expected = x / total;
+        let observed = *h as f32 / (n * n) as f32;
+        assert!(f32::abs(observed - expected) < 0.01);
+    }
+}
+
+#[test]
+fn test_sampling_pdfs() {
+    let n = 256;
+    let samples: Vec<_> = (0..n * n).map(|i| {
+        (((i % n) as f32 + 0.5) / n as f32, ((i / n) as f32 + 0.5) / n as f32)
+    }).collect();
+    let inv_n = 1.0 / samples.len() as f32;
+    // Integrate each direction sampler's pdf over the sphere by uniformly sampling it,
+    // then check the estimate of the domain's solid angle from the sampler's own samples
+    let integrate_pdf = |pdf: &Fn(&Vector) -> f32| {
+        let sphere = 1.0 / uniform_sphere_pdf();
+        samples.iter().map(|u| pdf(&uniform_sample_sphere(u))).sum::<f32>() * sphere * inv_n
+    };
+    let cos_theta_max = f32::cos(0.4);
+    let cone_pdf = |w: &Vector| if w.z >= cos_theta_max { uniform_cone_pdf(cos_theta_max) } else { 0.0 };
+    let cos_pdf = |w: &Vector| if w.z > 0.0 { cos_hemisphere_pdf(w.z) } else { 0.0 };
+    let hemi_pdf = |w: &Vector| if w.z > 0.0 { uniform_hemisphere_pdf() } else { 0.0 };
+    assert!(f32::abs(integrate_pdf(&|_| uniform_sphere_pdf()) - 1.0) < 1e-3);
+    assert!(f32::abs(integrate_pdf(&hemi_pdf) - 1.0) < 1e-2);
+    assert!(f32::abs(integrate_pdf(&cos_pdf) - 1.0) < 1e-2);
+    assert!(f32::abs(integrate_pdf(&cone_pdf) - 1.0) < 2e-2);
+
+    let mut hemi = 0.0;
+    let mut cos = 0.0;
+    let mut cone = 0.0;
+    let mut disk = 0.0;
+    for u in &samples {
+        let w = cos_sample_hemisphere(u);
+        assert!(w.z >= 0.0 && f32::abs(w.length() - 1.0) < 1e-4);
+        if w.z > 0.0 {
+            cos += inv_n * w.z * w.z / cos_hemisphere_pdf(w.z);
+        }
+        let w = uniform_sample_hemisphere(u);
+        assert!(w.z >= 0.0 && f32::abs(w.length() - 1.0) < 1e-4);
+        hemi += inv_n / uniform_hemisphere_pdf();
+        let w = uniform_sample_cone(u, cos_theta_max);
+        assert!(w.z >= cos_theta_max - 1e-5 && f32::abs(w.length() - 1.0) < 1e-4);
+        cone += inv_n / uniform_cone_pdf(cos_theta_max);
+        let d = concentric_sample_disk(u);
+        assert!(d.0 * d.0 + d.1 * d.1 <= 1.0 + 1e-5);
+        disk += inv_n * (d.0 * d.0 + d.1 * d.1);
+    }
+    // The sums are compared relative to the expected value since summing this many
+    // samples in f32 loses some precision
+    let two_pi = 2.0 * f32::consts::PI;
+    assert!(f32::abs(hemi / two_pi - 1.0) < 1e-3);
+    // Integral of cos^2 over the hemisphere is 2pi/3
+    assert!(f32::abs(cos / (two_pi / 3.0) - 1.0) < 1e-3);
+    assert!(f32::abs(cone / (two_pi * (1.0 - cos_theta_max)) - 1.0) < 1e-3);
+    // Uniform samples on the unit disk have a mean squared radius of 1/2
+    assert!(f32::abs(disk - 0.5) < 1e-3);
+    assert!(f32::abs(balance_heuristic(1.0, 0.2, 1.0, 0.6) + balance_heuristic(1.0, 0.6, 1.0, 0.2) - 1.0) < 1e-6);
+    assert!(f32::abs(power_heuristic(1.0, 0.2, 1.0, 0.6) + power_heuristic(1.0, 0.6, 1.0, 0.2) - 1.0) < 1e-6);
+}
@@ -4,8 +4,11 @@ use std::mem;
 
 /// Re-orders elements in the range yielded by `it` based on `pred`. All elements
 /// that the predicate returns true for will be placed before all elements
-/// that the predicate returned false for. Also returns the index of the
-/// first element in the false group
+/// that the predicate returned false for. Returns the index of the first element
+/// in the false group, which is also the number of elements the predicate is true for.
+/// If the predicate is true for every element the length of the range is returned,
+/// and 0 is returned if it's false for every element or the range is empty. The
+/// relative order of the elements within each group is not preserved.
 pub fn partition<'a, T: 'a, I, F>(mut it: I, pred: F) -> usize
         where I: DoubleEndedIterator<Item = &'a mut T>,
         F: Fn(&T) -> bool {
@@ -51,3 +54,38 @@ fn test_partition() {
     assert!(vals.iter().skip(3).fold(true, |f, x| *x % 2 != 0 && f));
 }
 
+#[test]
+fn test_partition_edge_cases() {
+    let mut empty: Vec<u32> = Vec::new();
+    assert_eq!(partition(empty.iter_mut(), |x| *x % 2 == 0), 0);
+
+    let mut all_true = vec![2u32, 4, 6, 8];
+    assert_eq!(partition(all_true.iter_mut(), |x| *x % 2 == 0), 4);
+    assert_eq!(all_true, vec![2, 4, 6, 8]);
+
+    let mut all_false = vec![1u32, 3, 5, 7];
+    assert_eq!(partition(all_false.iter_mut(), |x| *x % 2 == 0), 0);
+    assert_eq!(all_false, vec![1, 3, 5, 7]);
+
+    let mut single = vec![1u32];
+    assert_eq!(partition(single.iter_mut(), |x| *x % 2 == 0), 0);
+    assert_eq!(partition(single.iter_mut(), |x| *x % 2 != 0), 1);
+}
+
+#[test]
+fn test_partition_mixed() {
+    // Check every pattern of true and false elements up to 10 long, the returned index
+    // must split the elements into the true and false groups and keep all the elements
+    for len in 0..11 {
+        for pattern in 0..1u32 << len {
+            let mut vals: Vec<u32> = (0..len).map(|i| (i << 1) | ((pattern >> i) & 1)).collect();
+            let idx = partition(vals.iter_mut(), |x| *x & 1 == 1);
+            assert_eq!(idx, pattern.count_ones() as usize);
+            assert!(vals[..idx].iter().all(|x| *x & 1 == 1));
+            assert!(vals[idx..].iter().all(|x| *x & 1 == 0));
+            vals.sort();
+            assert!(vals.iter().enumerate().all(|(i, x)| *x >> 1 == i as u32));
+        }
+    }
+}
+
@@ -3,13 +3,83 @@
 //! are necessary for the pixel. The samples generated are the
 //! same as those from the Low Discrepancy sampler but the
 //! number of samples taken per pixel will vary.
+//!
+//! # Edge Antialiasing
+//! Measuring the contrast of the samples alone misses edges between objects of similar
+//! color, e.g. a gray object in front of a gray wall, which still need more samples to
+//! be antialiased. The sampler can also be given `EdgeDetection` thresholds to look at the
+//! geometry seen by each sample's camera ray and take more samples in pixels where the
+//! samples hit different objects, or the depth or normal of the surfaces hit changes sharply.
+//!
+//! # Scene Usage Example
+//! The adaptive sampler is selected by setting the `"sampler"` in the root object of the
+//! scene, the film's `"samples"` are ignored when it's used. The `"edges"` are optional,
+//! `"depth"` is the relative change in depth between samples and `"normal_angle"` the
+//! angle in degrees between their normals above which the pixel is taken to be on an edge.
+//! Each defaults to the values shown below if not specified. Samples hitting different
+//! objects or some hitting the background always mark the pixel as an edge.
+//!
+//! ```json
+//! "sampler": {
+//!     "type": "adaptive",
+//!     "min_spp": 4,
+//!     "max_spp": 64,
+//!     "edges": {
+//!         "depth": 0.1,
+//!         "normal_angle": 30
+//!     }
+//! }
+//! ```
 
 use std::{u32, f32, iter};
 use rand::{Rng, StdRng};
 use rand::distributions::{IndependentSample, Range};
 
-use sampler::{Sampler, Region, ld};
+use sampler::{Sampler, Region, GeometrySample, ld};
 use film::ImageSample;
+use linalg;
+
+/// Thresholds on the geometry seen by the samples in a pixel above which the
+/// pixel is on an object edge and should be supersampled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EdgeDetection {
+    /// Relative difference in depth between samples marking an edge
+    pub depth: f32,
+    /// Cosine of the angle between sample normals below which they're on an edge
+    pub cos_normal: f32,
+}
+
+impl EdgeDetection {
+    /// Create edge detection thresholds for a relative change in `depth` and
+    /// angle in degrees between normals
+    pub fn new(depth: f32, normal_angle: f32) -> EdgeDetection {
+        EdgeDetection { depth: depth, cos_normal: f32::cos(linalg::to_radians(normal_angle)) }
+    }
+    /// Check if the geometry seen by the samples of the pixel crosses an edge
+    pub fn on_edge(&self, geometry: &[GeometrySample]) -> bool {
+        let first = match geometry.first() {
+            Some(g) => g,
+            None => return false,
+        };
+        geometry.iter().skip(1).any(|g| {
+            if g.object != first.object {
+                return true;
+            }
+            // Both samples missed the scene
+            if g.object.is_none() {
+                return false;
+            }
+            f32::abs(g.depth - first.depth) / f32::min(g.depth, first.depth) > self.depth
+                || linalg::dot(&g.normal, &first.normal) < self.cos_normal
+        })
+    }
+}
+
+impl Default for EdgeDetection {
+    fn default() -> EdgeDetection {
+        EdgeDetection::new(0.1, 30.0)
+    }
+}
 
 /// Adaptive sampler that makes use of the (0, 2) sequence to generate
 /// well distributed samples and takes `min_spp` to `max_spp` samples per pixel
@@ -27,6 +97,9 @@ pub struct Adaptive {
     /// number of samples taken so far
     avg_luminance: f32,
     scramble_range: Range<u32>,
+    /// Thresholds for finding pixels on object edges, if None only the sample
+    /// contrast is used to decide if more samples are needed
+    edges: Option<EdgeDetection>,
 }
 
 impl Adaptive {
@@ -34,24 +107,30 @@ impl Adaptive {
     pub fn new(dim: (u32, u32), mut min_spp: usize, mut max_spp: usize) -> Adaptive {
         if !min_spp.is_power_of_two() {
             min_spp = min_spp.next_power_of_two();
-            print!("Warning: Adaptive sampler requires power of two samples per pixel, ");
-            println!("rounding min_spp up to {}", min_spp);
+            warn!("Adaptive sampler requires power of two samples per pixel, rounding min_spp up to {}", min_spp);
         }
         if !max_spp.is_power_of_two() {
             max_spp = max_spp.next_power_of_two();
-            print!("Warning: Adaptive sampler requires power of two samples per pixel, ");
-            println!("rounding max_spp up to {}", max_spp);
+            warn!("Adaptive sampler requires power of two samples per pixel, rounding max_spp up to {}", max_spp);
         }
         let step_size = ((max_spp - min_spp) / 5).next_power_of_two();
         Adaptive { region: Region::new((0, 0), dim), min_spp: min_spp, max_spp: max_spp,
                    step_size: step_size, samples_taken: 0, avg_luminance: 0.0,
-                   scramble_range: Range::new(0, u32::MAX) }
+                   scramble_range: Range::new(0, u32::MAX), edges: None }
+    }
+    /// Create an adaptive sampler which also takes more samples in pixels on object edges
+    /// found using the `edges` thresholds
+    pub fn with_edges(dim: (u32, u32), min_spp: usize, max_spp: usize, edges: EdgeDetection) -> Adaptive {
+        let mut sampler = Adaptive::new(dim, min_spp, max_spp);
+        sampler.edges = Some(edges);
+        sampler
     }
     /// Determine if more samples need to be taken for the pixel currently sampled with the
     /// set of samples passed. This is done by simply looking at the contrast difference
-    /// between the samples. TODO: What are some better strategies for estimating
+    /// between the samples and, if edge detection is enabled, checking if the samples
+    /// cross an object edge. TODO: What are some better strategies for estimating
     /// if we need more samples?
-    fn needs_supersampling(&mut self, samples: &[ImageSample]) -> bool {
+    fn needs_supersampling(&mut self, samples: &[ImageSample], geometry: &[GeometrySample]) -> bool {
         let max_contrast = 0.5;
         // First sampling pass, compute the initial average luminance
         if self.samples_taken == self.min_spp {
@@ -74,7 +153,7 @@ impl Adaptive {
                 return true;
             }
         }
-        false
+        self.edges.map_or(false, |e| e.on_edge(geometry))
     }
 }
 
@@ -124,10 +203,10 @@ impl Sampler for Adaptive {
     fn get_region(&self) -> &Region {
         &self.region
     }
-    fn report_results(&mut self, samples: &[ImageSample]) -> bool {
+    fn report_results(&mut self, samples: &[ImageSample], geometry: &[GeometrySample]) -> bool {
         // If we've hit taken the max samples per pixel or don't need to super sample
         // this pixel advance to the next one
-        if self.samples_taken >= self.max_spp || !self.needs_supersampling(samples) {
+        if self.samples_taken >= self.max_spp || !self.needs_supersampling(samples, geometry) {
             self.samples_taken = 0;
             self.region.current.0 += 1;
             if self.region.current.0 == self.region.end.0 {
@@ -139,5 +218,36 @@ impl Sampler for Adaptive {
             false
         }
     }
+    fn uses_geometry(&self) -> bool { self.edges.is_some() }
+}
+
+#[test]
+fn test_edge_supersampling() {
+    use film::Colorf;
+    use linalg::Normal;
+    let mut rng = StdRng::new().expect("Failed to get StdRng");
+    let mut sampler = Adaptive::with_edges((1, 1), 4, 16, EdgeDetection::default());
+    assert!(sampler.uses_geometry());
+    let mut pos = Vec::new();
+    sampler.get_samples(&mut pos, &mut rng);
+    // Samples of the same color on two different objects are an edge, even with no contrast
+    let samples: Vec<_> = pos.iter().map(|p| ImageSample::new(p.0, p.1, Colorf::broadcast(0.5))).collect();
+    let n = Normal::new(0.0, 0.0, -1.0);
+    let mut geometry: Vec<_> = (0..pos.len()).map(|i| GeometrySample::new(2.0, n, (i % 2) as u32)).collect();
+    assert!(!sampler.report_results(&samples, &geometry));
+    // The same object seen at a sharply different depth is also an edge
+    for g in &mut geometry {
+        g.object = Some(0);
+    }
+    geometry[1].depth = 4.0;
+    assert!(EdgeDetection::default().on_edge(&geometry));
+    // But a smooth surface isn't, so the pixel is done
+    geometry[1].depth = 2.05;
+    assert!(!EdgeDetection::default().on_edge(&geometry));
+    geometry[1].normal = Normal::new(0.0, 1.0, 0.0);
+    assert!(EdgeDetection::default().on_edge(&geometry));
+    geometry[1].normal = n;
+    assert!(sampler.report_results(&samples, &geometry));
+    assert!(!sampler.has_samples());
 }
 
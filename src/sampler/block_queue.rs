@@ -23,13 +23,11 @@ pub struct BlockQueueIterator<'a> {
 }
 
 impl BlockQueue {
-    /// Create a block queue for the image with dimensions `img`.
-    /// Panics if the image is not evenly broken into blocks of dimension `dim`
+    /// Create a block queue for the image with dimensions `img`. If the image is not evenly
+    /// broken into blocks of dimension `dim` the blocks on the right and bottom edges will
+    /// extend past the image
     pub fn new(img: (u32, u32), dim: (u32, u32), select_blocks: (usize, usize)) -> BlockQueue {
-        if img.0 % dim.0 != 0 || img.1 % dim.1 != 0 {
-            panic!("Image with dimension {:?} not evenly divided by blocks of {:?}", img, dim);
-        }
-        let num_blocks = (img.0 / dim.0, img.1 / dim.1);
+        let num_blocks = ((img.0 + dim.0 - 1) / dim.0, (img.1 + dim.1 - 1) / dim.1);
         // TODO: the .. operator precedence is very low so we need this paren here at the moment
         // once (hopefully) it's raised we can remove the parens
         let mut blocks: Vec<(u32, u32)> = (0..num_blocks.0 * num_blocks.1)
@@ -40,7 +38,7 @@ impl BlockQueue {
             blocks = blocks.into_iter().skip(select_blocks.0).take(select_blocks.1).collect();
         }
         if blocks.is_empty() {
-            println!("Warning: This block queue is empty!");
+            warn!("This block queue is empty!");
         }
         BlockQueue { blocks: blocks, dimensions: dim, next: AtomicUsize::new(0) }
     }
@@ -21,8 +21,7 @@ impl LowDiscrepancy {
     pub fn new(dim: (u32, u32), mut spp: usize) -> LowDiscrepancy {
         if !spp.is_power_of_two() {
             spp = spp.next_power_of_two();
-            print!("Warning: LowDiscrepancy sampler requires power of two samples per pixel, ");
-            println!("rounding up to {}", spp);
+            warn!("LowDiscrepancy sampler requires power of two samples per pixel, rounding up to {}", spp);
         }
         LowDiscrepancy { region: Region::new((0, 0), dim), spp: spp,
                          scramble_range: Range::new(0, u32::MAX) }
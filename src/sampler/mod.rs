@@ -2,12 +2,14 @@
 //! to provide stratified, low-discrepancy, adaptive sampling methods and so
 //! on through a simple trait interface
 
+use std::f32;
 use rand::StdRng;
 use film::ImageSample;
+use linalg::Normal;
 
 pub use self::uniform::Uniform;
 pub use self::ld::LowDiscrepancy;
-pub use self::adaptive::Adaptive;
+pub use self::adaptive::{Adaptive, EdgeDetection};
 pub use self::block_queue::BlockQueue;
 
 pub mod morton;
@@ -43,9 +45,46 @@ pub trait Sampler {
     fn get_region(&self) -> &Region;
     /// Let the sampler inspect the results of sampling the pixel so it can
     /// determine if more samples should be taken. Returns true if these samples
-    /// are ok to use, false if more need to be taken. The default implementation
-    /// just returns true.
-    fn report_results(&mut self, _samples: &[ImageSample]) -> bool { true }
+    /// are ok to use, false if more need to be taken. If the sampler uses geometry
+    /// `geometry` holds the first hit of each sample's camera ray, otherwise it's empty.
+    /// The default implementation just returns true.
+    fn report_results(&mut self, _samples: &[ImageSample], _geometry: &[GeometrySample]) -> bool { true }
+    /// Check if the sampler inspects the geometry seen by the samples when deciding
+    /// whether to take more, if not the geometry samples don't need to be computed
+    fn uses_geometry(&self) -> bool { false }
+}
+
+/// The sampler used to pick the samples taken in each pixel of the image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerType {
+    /// Take the film's samples per pixel in each pixel with the low discrepancy sampler
+    LowDiscrepancy,
+    /// Take between `min_spp` and `max_spp` samples in each pixel with the adaptive sampler,
+    /// optionally taking extra samples at the edges of objects
+    Adaptive { min_spp: usize, max_spp: usize, edges: Option<EdgeDetection> },
+}
+
+/// The geometry seen by the camera ray of an image sample, used by samplers
+/// to find pixels on object edges which need more samples
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GeometrySample {
+    /// Distance along the camera ray to the first hit, infinite if it missed
+    pub depth: f32,
+    /// Shading normal at the first hit, zero if the ray missed
+    pub normal: Normal,
+    /// ID of the object hit, see `film::object_id`, None if the ray missed
+    pub object: Option<u32>,
+}
+
+impl GeometrySample {
+    /// Create a geometry sample for a camera ray which hit `object` at `depth`
+    pub fn new(depth: f32, normal: Normal, object: u32) -> GeometrySample {
+        GeometrySample { depth: depth, normal: normal, object: Some(object) }
+    }
+    /// Create a geometry sample for a camera ray which missed the scene
+    pub fn miss() -> GeometrySample {
+        GeometrySample { depth: f32::INFINITY, normal: Normal::broadcast(0.0), object: None }
+    }
 }
 
 /// Provides a simple way to pass around a 3 component sample consisting of one 2D and
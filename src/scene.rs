@@ -1,5 +1,6 @@
 //! Defines the scene struct which contains the various objects defining the scene.
 //! This includes the geometry, instances of the geometry, the camera and so on.
+//! Scenes are loaded from JSON scene files or can be built in code with the `SceneBuilder`.
 //!
 //! # Scene JSON Files
 //! The scene file format has four required sections: a camera, an integrator,
@@ -22,22 +23,120 @@
 //! - Materials: See materials
 //! - Objects: See geometry
 //!
+//! The scene can also optionally specify a `"fog"` filling the scene, see integrator/fog.
+//!
+//! Files referenced by the scene, such as image textures, OBJ meshes and MERL data, can be
+//! given by absolute paths or paths relative to the directory containing the scene file.
+//! Only files given by relative paths are sent to the workers when rendering distributed,
+//! files given by absolute paths must be at the same path on each worker, see exec/distrib.
+//!
+//! To make hand editing scenes easier the scene file can contain `//` line and `/* */` block
+//! comments and trailing commas in objects and arrays, which are removed before the file is
+//! parsed as JSON.
+//!
+//! ```json
+//! {
+//!     // The main light, was [1, 1, 1, 50]
+//!     "emission": [1, 0.9, 0.8, 60],
+//!     "materials": [
+//!         /* ... */
+//!     ],
+//! }
+//! ```
+//!
+//! # BVH Parameters
+//! The scene can optionally specify parameters for building the BVH over the objects in
+//! the scene. Any parameter left out uses its default, shown below. `max_geom` is the max number
+//! of objects per leaf node and `buckets` is the number of bins considered when searching for the
+//! SAH split. The costs are the relative costs of traversing a node and intersecting an object.
+//!
+//! ```json
+//! "bvh": {
+//!     "max_geom": 4,
+//!     "buckets": 12,
+//!     "traversal_cost": 0.125,
+//!     "intersection_cost": 1.0
+//! }
+//! ```
+//!
+//! # Acceleration Structure
+//! The objects are stored in a BVH by default, which works well for any scene. Scenes made
+//! of many objects of similar size spread evenly through the scene, e.g. particles, can use
+//! a uniform grid instead, which builds much faster and may trace faster, by setting the
+//! `"accelerator"` to `"grid"`. The grid picks its resolution from the number of objects
+//! and ignores the BVH parameters. See geometry/grid. A SAH KD-tree can also be used by
+//! setting it to `"kdtree"`, see geometry/kdtree.
+//!
+//! ```json
+//! "accelerator": "grid"
+//! ```
+//!
+//! The triangles of each mesh are stored in their own acceleration structure, which is a BVH
+//! by default. Scenes of large static meshes can set `"mesh_accelerator"` to `"kdtree"` or
+//! `"grid"` to change the default for all meshes, or pick one per mesh as described in
+//! geometry/mesh.
+//!
+//! ```json
+//! "mesh_accelerator": "kdtree"
+//! ```
+//!
+//! # Sampler
+//! Each pixel is sampled with the film's `"samples"` per pixel by default. Setting the
+//! `"sampler"` to the adaptive sampler instead takes a varying number of samples per pixel,
+//! taking more in pixels with high contrast or, optionally, on the edges of objects.
+//! See sampler/adaptive for its parameters.
+//!
+//! ```json
+//! "sampler": {
+//!     "type": "adaptive",
+//!     "min_spp": 4,
+//!     "max_spp": 64,
+//!     "edges": {}
+//! }
+//! ```
+//!
+//! # Up Axis
+//! tray_rust's world is +Y up, scenes exported from Z up tools can set `"up_axis": "z"` in the
+//! root object to have the scene's positions and directions interpreted with +Z up instead.
+//! The whole scene is rotated into tray_rust's world when it's loaded: the objects, lights,
+//! portals, clip planes and cameras are all placed in the world by the rotation, and look at
+//! keyframes and turntables can be given in the scene's own coordinates. Dome lights are also
+//! rotated so their zenith points along the scene's up axis. Note that the geometry and
+//! camera's local axes are unchanged, e.g. cameras still look along their local +Z axis.
+//! The default up axis is `"y"`.
+//!
+//! ```json
+//! {
+//!     "up_axis": "z",
+//!     ...
+//! }
+//! ```
+//!
 
+use std::{cmp, fmt, f32};
+use std::cell::RefCell;
 use std::io::prelude::*;
 use std::fs::File;
 use std::sync::Arc;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use image;
 use serde_json::{self, Value};
 
-use linalg::{Transform, Point, Vector, Ray, Keyframe, AnimatedTransform};
-use film::{filter, Camera, Colorf, RenderTarget, FrameInfo, AnimatedColor, ColorKeyframe};
-use geometry::{Sphere, Instance, Intersection, BVH, Mesh, Disk, Rectangle,
-               BoundableGeom, SampleableGeom};
-use material::{Material, Matte, Glass, Metal, Merl, Plastic, SpecularMetal, RoughGlass};
-use integrator::{self, Integrator};
+use linalg::{Transform, Point, Vector, Normal, Ray, Keyframe, AnimatedTransform};
+use film::{filter, Camera, Eye, Colorf, RenderTarget, FrameInfo, AnimatedColor, ColorKeyframe, Output,
+           Grade, Tonemap, AutoExposure};
+use film::object_id::object_id;
+use film::output::Aov;
+use geometry::{Sphere, Instance, Emitter, EmissionUnit, Receiver, Intersection, BVH, SAHParams, Mesh,
+               Disk, Cone, Rectangle, InfinitePlane, AnimatedMesh, Instanced, BoundableGeom, SampleableGeom,
+               Boundable, Portal, ClipPlane, Accelerator, AcceleratorType, Grid, KdTree};
+use geometry::animated_mesh;
+use material::{Material, Matte, Glass, Metal, Merl, Plastic, SpecularMetal, RoughGlass, Emissive};
+use integrator::{self, Integrator, Fog};
+use sampler::{SamplerType, EdgeDetection};
+use light::Sky;
 use texture::{self, Texture};
 
 /// This lets me enforce only certain types of textures are valid,
@@ -62,10 +161,18 @@ impl LoadedTextures {
                     None => None,
                 }
             },
-            Value::Array(_) => {
-                match load_color(e) {
-                    Some(c) => Some(Arc::new(texture::ConstantColor::new(c))),
-                    None => None,
+            Value::Array(ref a) => {
+                // A list of color keyframes gives a color animated over time
+                if a.first().map_or(false, |k| k.is_object()) {
+                    match load_animated_color(e) {
+                        Some(c) => Some(Arc::new(c)),
+                        None => None,
+                    }
+                } else {
+                    match load_color(e) {
+                        Some(c) => Some(Arc::new(texture::ConstantColor::new(c))),
+                        None => None,
+                    }
                 }
             },
             _ => panic!("Invalid JSON type for colorf texture"),
@@ -83,21 +190,321 @@ impl LoadedTextures {
                 }
             },
             Value::Number(ref n) => Some(Arc::new(texture::ConstantScalar::new(n.as_f64().unwrap() as f32))),
+            Value::Array(ref a) => {
+                // A list of scalar keyframes gives a value animated over time
+                if a.is_empty() {
+                    return None;
+                }
+                let mut keyframes = Vec::with_capacity(a.len());
+                for k in a {
+                    let time = k.get("time").expect("A time must be specified for a scalar keyframe")
+                        .as_f64().expect("Time for scalar keyframe must be a number") as f32;
+                    let value = k.get("value").expect("A value must be specified for a scalar keyframe")
+                        .as_f64().expect("Value for scalar keyframe must be a number") as f32;
+                    keyframes.push((time, value));
+                }
+                Some(Arc::new(texture::AnimatedScalar::new(keyframes)))
+            },
             _ => panic!("Invalid JSON type for scalar texture"),
         }
     }
 }
 
+/// Information about the object hit by a ray cast with `Scene::pick`, e.g.
+/// for selecting objects in an editor
+#[derive(Debug, Clone)]
+pub struct PickResult {
+    /// The distance along the ray to the hit point
+    pub t: f32,
+    /// The hit point in world space
+    pub p: Point,
+    /// The shading normal at the hit point
+    pub n: Normal,
+    /// The name of the object that was hit
+    pub tag: String,
+    /// The name of the object's material, None for objects without a named material
+    pub material: Option<String>,
+}
+
+/// Number of rays along each axis of the image cast by `Scene::validate` to check if
+/// a camera sees anything
+const VALIDATE_CAMERA_RAYS: usize = 16;
+
+/// A likely mistake in the scene found by `Scene::validate`, these don't prevent
+/// rendering but often result in black or otherwise unexpected images
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// The light with this name has no emission at any time
+    BlackLight(String),
+    /// The object with this name has empty geometry, e.g. a mesh whose triangles
+    /// are all collapsed to a point
+    EmptyGeometry(String),
+    /// The object with this name has NaNs in its transform
+    InvalidTransform(String),
+    /// The camera at this index in the scene doesn't see any objects
+    CameraSeesNothing(usize),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::BlackLight(ref tag) => write!(f, "light '{}' has no emission", tag),
+            Warning::EmptyGeometry(ref tag) => write!(f, "object '{}' has empty geometry", tag),
+            Warning::InvalidTransform(ref tag) => write!(f, "object '{}' has NaNs in its transform", tag),
+            Warning::CameraSeesNothing(i) => write!(f, "camera {} doesn't see any objects", i),
+        }
+    }
+}
+
+/// The axis pointing up in the scene's coordinate system, see the Up Axis section of the module docs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    /// Get the transform rotating the scene's coordinate system into tray_rust's +Y up world
+    pub fn to_world(&self) -> Transform {
+        match *self {
+            UpAxis::Y => Transform::identity(),
+            UpAxis::Z => Transform::rotate_x(-90.0),
+        }
+    }
+}
+
+/// Minimum number of keyframes placed along each revolution of a turntable camera
+const TURNTABLE_MIN_STEPS: usize = 16;
+
+/// Builds a scene programmatically instead of loading it from a scene file, producing the
+/// same scene, render target, samples per pixel and frame info returned by `Scene::load_file`.
+/// By default the scene is rendered as a single frame with the path tracer using a minimum
+/// depth of 4 and maximum of 8
+pub struct SceneBuilder {
+    rt: RenderTarget,
+    spp: usize,
+    frame_info: FrameInfo,
+    cameras: Vec<Camera>,
+    instances: Vec<Instance>,
+    portals: Vec<Portal>,
+    integrator: Box<Integrator + Send + Sync>,
+    outputs: Vec<Output>,
+    fog: Option<Fog>,
+    sampler: SamplerType,
+    max_geom: usize,
+    sah_params: SAHParams,
+    accelerator: AcceleratorType,
+    up_axis: UpAxis,
+}
+
+impl SceneBuilder {
+    /// Start building a scene rendered to the render target with `spp` samples per pixel
+    pub fn new(rt: RenderTarget, spp: usize) -> SceneBuilder {
+        SceneBuilder { rt: rt, spp: spp, frame_info: FrameInfo::new(1, 1.0, 0, 0),
+                       cameras: Vec::new(), instances: Vec::new(), portals: Vec::new(),
+                       integrator: Box::new(integrator::Path::new(4, 8)), outputs: Vec::new(), fog: None,
+                       sampler: SamplerType::LowDiscrepancy, max_geom: 4, sah_params: SAHParams::default(),
+                       accelerator: AcceleratorType::BVH, up_axis: UpAxis::Y }
+    }
+    /// Get the dimensions of the image being rendered
+    pub fn dimensions(&self) -> (usize, usize) {
+        self.rt.dimensions()
+    }
+    /// Set the frames to render and the scene time they span
+    pub fn set_frames(&mut self, frame_info: FrameInfo) -> &mut SceneBuilder {
+        assert!(frame_info.end >= frame_info.start, "End frame must be greater or equal to the starting frame");
+        self.frame_info = frame_info;
+        self
+    }
+    /// Set the only camera in the scene, replacing any previously added
+    pub fn set_camera(&mut self, camera: Camera) -> &mut SceneBuilder {
+        self.cameras.clear();
+        self.add_camera(camera)
+    }
+    /// Add a camera to the scene, the scene switches to it at the frame it's active at
+    pub fn add_camera(&mut self, mut camera: Camera) -> &mut SceneBuilder {
+        camera.set_dimensions(self.rt.dimensions());
+        self.cameras.push(camera);
+        self
+    }
+    /// Set the integrator to render the scene with
+    pub fn set_integrator(&mut self, integrator: Box<Integrator + Send + Sync>) -> &mut SceneBuilder {
+        self.integrator = integrator;
+        self
+    }
+    /// Add an instance of geometry or a light to the scene
+    pub fn add_instance(&mut self, instance: Instance) -> &mut SceneBuilder {
+        self.instances.push(instance);
+        self
+    }
+    /// Add an object named `tag` using the geometry and material passed, placed by `transform`
+    pub fn add_receiver(&mut self, geom: Arc<BoundableGeom + Send + Sync>, material: Arc<Material + Send + Sync>,
+                        transform: AnimatedTransform, tag: &str) -> &mut SceneBuilder {
+        self.add_instance(Instance::receiver(geom, material, transform, tag.to_owned()))
+    }
+    /// Add a point light named `tag` with the radiance `emission` placed by `transform`
+    pub fn add_point_light(&mut self, transform: AnimatedTransform, emission: AnimatedColor, tag: &str)
+                           -> &mut SceneBuilder {
+        self.add_instance(Instance::point_light(transform, emission, tag.to_owned()))
+    }
+    /// Add an area light named `tag` emitting the radiance `emission` from the geometry, placed
+    /// by `transform`. The material is used to shade the light's surface
+    pub fn add_area_light(&mut self, geom: Arc<SampleableGeom + Send + Sync>, material: Arc<Material + Send + Sync>,
+                          emission: AnimatedColor, transform: AnimatedTransform, tag: &str) -> &mut SceneBuilder {
+        self.add_instance(Instance::area_light(geom, material, emission, transform, tag.to_owned()))
+    }
+    /// Add a portal to sample the scene's dome lights through, see geometry/portal
+    pub fn add_portal(&mut self, portal: Portal) -> &mut SceneBuilder {
+        self.portals.push(portal);
+        self
+    }
+    /// Add an image file the film should save each frame to
+    pub fn add_output(&mut self, output: Output) -> &mut SceneBuilder {
+        self.outputs.push(output);
+        self
+    }
+    /// Fill the scene with homogeneous fog
+    pub fn set_fog(&mut self, fog: Fog) -> &mut SceneBuilder {
+        self.fog = Some(fog);
+        self
+    }
+    /// Set the sampler used to pick the samples taken in each pixel, by default the
+    /// low discrepancy sampler takes the scene's samples per pixel in every pixel
+    pub fn set_sampler(&mut self, sampler: SamplerType) -> &mut SceneBuilder {
+        self.sampler = sampler;
+        self
+    }
+    /// Set the parameters used to build the BVH over the objects in the scene, see
+    /// the BVH Parameters section of the module docs
+    pub fn set_bvh_params(&mut self, max_geom: usize, sah_params: SAHParams) -> &mut SceneBuilder {
+        self.max_geom = max_geom;
+        self.sah_params = sah_params;
+        self
+    }
+    /// Set the type of acceleration structure to store the objects in the scene in, see
+    /// the Acceleration Structure section of the module docs
+    pub fn set_accelerator(&mut self, accelerator: AcceleratorType) -> &mut SceneBuilder {
+        self.accelerator = accelerator;
+        self
+    }
+    /// Set the axis pointing up in the coordinates the scene's cameras, objects and portals
+    /// are given in, they're rotated into the +Y up world when the scene is built
+    pub fn set_up_axis(&mut self, up_axis: UpAxis) -> &mut SceneBuilder {
+        self.up_axis = up_axis;
+        self
+    }
+    /// Build the scene, returning it along with the render target, samples per pixel and
+    /// frame info to render it with. Panics if the scene has no camera or objects
+    pub fn build(mut self) -> (Scene, RenderTarget, usize, FrameInfo) {
+        if self.up_axis != UpAxis::Y {
+            self.rotate_to_world();
+        }
+        let mut cameras = self.cameras;
+        let mut instances = self.instances;
+        assert!(!cameras.is_empty(), "Error: A camera is required!");
+        assert!(!instances.is_empty(), "Aborting: the scene does not have any objects!");
+        cameras.sort_by(|a, b| a.active_at.cmp(&b.active_at));
+        if !self.portals.is_empty() {
+            let mut has_dome = false;
+            for i in &mut instances {
+                if let Instance::Emitter(ref mut e) = *i {
+                    if e.is_dome() {
+                        e.set_portals(self.portals.clone());
+                        has_dome = true;
+                    }
+                }
+            }
+            if !has_dome {
+                warn!("The scene has portals but no dome light to sample through them");
+            }
+        }
+        let scene = Scene {
+            cameras: cameras,
+            active_camera: None,
+            slice: None,
+            // TODO: Read time parameters from the scene file, update BVH every few frames
+            accel: match self.accelerator {
+                AcceleratorType::BVH => {
+                    Accelerator::BVH(BVH::with_params(self.max_geom, self.sah_params, instances, 0.0,
+                                                      self.frame_info.time))
+                },
+                AcceleratorType::Grid => Accelerator::Grid(Grid::new(instances, 0.0, self.frame_info.time)),
+                AcceleratorType::KdTree => {
+                    Accelerator::KdTree(KdTree::new(instances, 0.0, self.frame_info.time))
+                },
+            },
+            integrator: self.integrator,
+            outputs: self.outputs,
+            fog: self.fog,
+            sampler: self.sampler,
+        };
+        (scene, self.rt, self.spp, self.frame_info)
+    }
+    /// Rotate everything placed in the scene from the scene's up axis into the +Y up world
+    fn rotate_to_world(&mut self) {
+        let basis = self.up_axis.to_world();
+        let to_world = AnimatedTransform::unanimated(&basis);
+        let from_world = AnimatedTransform::unanimated(&basis.inverse());
+        for c in &mut self.cameras {
+            let t = c.get_transform().clone();
+            c.set_transform(to_world.clone() * t);
+        }
+        for i in &mut self.instances {
+            let t = i.get_transform().clone();
+            match *i {
+                Instance::Emitter(ref mut e) => {
+                    // The dome's zenith is its local +Y axis, which should stay pointing up
+                    if e.is_dome() {
+                        e.set_transform(to_world.clone() * t * from_world.clone());
+                    } else {
+                        e.set_transform(to_world.clone() * t);
+                    }
+                },
+                Instance::Receiver(ref mut r) => {
+                    r.set_transform(to_world.clone() * t);
+                    for c in &mut r.clip_planes {
+                        *c = ClipPlane::new(basis * c.point, basis * c.normal);
+                    }
+                },
+            }
+        }
+        for p in &mut self.portals {
+            let t = p.get_transform().clone();
+            p.set_transform(to_world.clone() * t);
+        }
+    }
+}
+
 /// The scene containing the objects and camera configuration we'd like to render,
 /// shared immutably among the ray tracing threads
 pub struct Scene {
     pub cameras: Vec<Camera>,
     active_camera: Option<usize>,
-    pub bvh: BVH<Instance>,
+    /// The slice of the active camera's clip range to render, see `set_slice`
+    slice: Option<(usize, usize)>,
+    /// The acceleration structure storing the objects in the scene
+    pub accel: Accelerator<Instance>,
     pub integrator: Box<Integrator + Send + Sync>,
+    /// The image files the film should save each frame to
+    pub outputs: Vec<Output>,
+    /// Optional homogeneous fog filling the scene
+    pub fog: Option<Fog>,
+    /// The sampler used to pick the samples taken in each pixel
+    pub sampler: SamplerType,
 }
 
 impl Scene {
+    /// Load the scene like `load_file`, also returning the files the scene references by paths
+    /// relative to the scene file which were read to load it, e.g. meshes and textures.
+    /// Used by the distributed renderer to find the files to send to the workers
+    pub fn load_file_with_assets(file: &str) -> (Scene, RenderTarget, usize, FrameInfo, Vec<PathBuf>) {
+        SCENE_ASSETS.with(|a| a.borrow_mut().clear());
+        let (scene, rt, spp, frame_info) = Scene::load_file(file);
+        let mut assets: Vec<_> = SCENE_ASSETS.with(|a| a.borrow_mut().drain(..).collect());
+        assets.sort();
+        assets.dedup();
+        (scene, rt, spp, frame_info, assets)
+    }
     pub fn load_file(file: &str) -> (Scene, RenderTarget, usize, FrameInfo) {
         let mut f = match File::open(file) {
             Ok(f) => f,
@@ -107,6 +514,7 @@ impl Scene {
         if let Err(e) = f.read_to_string(&mut content) {
             panic!("Failed to read scene file: {}", e);
         }
+        let content = strip_json_extensions(&content);
         // Why not use expect here?
         let data: Value = match serde_json::from_str(&content[..]) {
             Ok(d) => d,
@@ -118,42 +526,88 @@ impl Scene {
             None => Path::new(file),
         };
 
-        let (rt, spp, frame_info) = load_film(data.get("film").expect("The scene must specify a film to write to"));
-        let cameras = load_cameras(&data, rt.dimensions());
-        let integrator = load_integrator(data.get("integrator")
-                                         .expect("The scene must specify the integrator to render with"));
+        let (rt, spp, frame_info, outputs) = load_film(data.get("film").expect("The scene must specify a film to write to"));
+        let mut builder = SceneBuilder::new(rt, spp);
+        builder.set_frames(frame_info);
+        for c in load_cameras(&data, builder.dimensions(), &frame_info) {
+            builder.add_camera(c);
+        }
+        for o in outputs {
+            builder.add_output(o);
+        }
+        builder.set_integrator(load_integrator(data.get("integrator")
+                                               .expect("The scene must specify the integrator to render with")));
         let textures = match data.get("textures") {
             Some(e) => load_textures(path, e),
             None => LoadedTextures::none(),
         };
-        let materials = load_materials(path, data.get("materials").expect("An array of materials is required"),
-                                       &textures);
-        // mesh cache is a map of file_name -> (map of mesh name -> mesh)
+        let material_elem = data.get("materials").expect("An array of materials is required");
+        let object_elem = data.get("objects").expect("The scene must specify a list of objects");
+        validate_material_names(material_elem, object_elem);
+        let materials = load_materials(path, material_elem, &textures);
+        // mesh cache is a map of file_name:accelerator -> (map of mesh name -> mesh)
         let mut mesh_cache = HashMap::new();
-        let instances = load_objects(path, &materials, &mut mesh_cache,
-                                     data.get("objects").expect("The scene must specify a list of objects"));
-
-        assert!(!instances.is_empty(), "Aborting: the scene does not have any objects!");
-        let scene = Scene {
-            cameras: cameras,
-            active_camera: None,
-            // TODO: Read time parameters from the scene file, update BVH every few frames
-            bvh: BVH::new(4, instances, 0.0, frame_info.time),
-            integrator: integrator,
+        let mesh_accel = match data.get("mesh_accelerator") {
+            Some(e) => load_accelerator(e),
+            None => AcceleratorType::BVH,
         };
-        (scene, rt, spp, frame_info)
+        let mut portals = Vec::new();
+        for i in load_objects(path, &materials, &mut mesh_cache, mesh_accel, &mut portals, object_elem) {
+            builder.add_instance(i);
+        }
+        for p in portals {
+            builder.add_portal(p);
+        }
+        if let Some(e) = data.get("bvh") {
+            let (max_geom, sah_params) = load_bvh_params(e);
+            builder.set_bvh_params(max_geom, sah_params);
+        }
+        if let Some(e) = data.get("accelerator") {
+            builder.set_accelerator(load_accelerator(e));
+        }
+        if let Some(f) = data.get("fog") {
+            builder.set_fog(load_fog(f));
+        }
+        if let Some(s) = data.get("sampler") {
+            builder.set_sampler(load_sampler(s));
+        }
+        if let Some(u) = data.get("up_axis") {
+            builder.set_up_axis(load_up_axis(u));
+        }
+        builder.build()
     }
     /// Test the ray for intersections against the objects in the scene.
     /// Returns Some(Intersection) if an intersection was found and None if not.
     pub fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
-        self.bvh.intersect(ray, |r, i| i.intersect(r))
+        self.accel.intersect(ray, |r, i| i.intersect(r))
+    }
+    /// Apply the scene's fog, if any, to the radiance `li` arriving along the ray
+    /// from its hit point. `ray.max_t` should be infinite if the ray missed
+    pub fn apply_fog(&self, ray: &Ray, li: &Colorf) -> Colorf {
+        match self.fog {
+            Some(ref f) => f.apply(ray, li),
+            None => *li,
+        }
+    }
+    /// Find the closest object hit by the ray and report which object and material
+    /// it is along with the hit point and normal. Returns None if nothing was hit
+    pub fn pick(&self, ray: &Ray) -> Option<PickResult> {
+        let mut r = *ray;
+        match self.intersect(&mut r) {
+            Some(hit) => Some(PickResult { t: r.max_t,
+                                           p: hit.dg.p,
+                                           n: hit.dg.n,
+                                           tag: hit.instance.tag().to_owned(),
+                                           material: hit.instance.material_name().map(|m| m.to_owned()) }),
+            None => None,
+        }
     }
     /// Advance the time the scene is currently displaying to the time range passed
     pub fn update_frame(&mut self, frame: usize, start: f32, end: f32) {
         let cam = match self.active_camera {
             Some(c) => {
                 if c != self.cameras.len() - 1 && self.cameras[c + 1].active_at == frame {
-                    println!("Changing to camera {}", c + 1);
+                    info!("Changing to camera {}", c + 1);
                     c + 1
                 } else { c }
             },
@@ -162,27 +616,148 @@ impl Scene {
                 // based on what frame we're beginning the rendering at. e.g. if you have a
                 // camera become active at frame 5 and pass --start-frame 5, you should render
                 // from that camera.
-                let c = self.cameras.iter().take_while(|x| x.active_at <= frame).count() - 1;
-                println!("Selecting starting camera {}", c);
+                let c = self.camera_index(frame);
+                info!("Selecting starting camera {}", c);
                 c
             },
         };
         self.active_camera = Some(cam);
+        self.cameras[cam].set_slice(self.slice);
         self.cameras[cam].update_frame(start, end);
-        // TODO: How often to re-build the BVH?
-        let shutter_time = self.cameras[cam].shutter_time();
-        println!("Frame {}: re-building bvh for {} to {}", frame, shutter_time.0, shutter_time.1);
-        self.bvh.rebuild(shutter_time.0, shutter_time.1);
+        // The BVH built when loading the scene is valid for every frame if nothing moves
+        if !self.is_static() {
+            let shutter_time = self.cameras[cam].shutter_time();
+            info!("Frame {}: re-building the accelerator for {} to {}", frame, shutter_time.0, shutter_time.1);
+            self.accel.rebuild(shutter_time.0, shutter_time.1);
+        }
+        self.integrator.begin_frame();
+    }
+    /// Check if the scene is static, i.e. none of the objects have animated transforms. The
+    /// BVH doesn't need to be re-built between frames of a static scene. Deforming meshes
+    /// report their bounds over the entire animation so don't require re-building either
+    pub fn is_static(&self) -> bool {
+        self.accel.iter().all(|i| !i.get_transform().is_animated())
+    }
+    /// Replace the materials of all the objects in the scene with a uniform gray matte
+    /// "clay" material to review the lighting and composition without the materials.
+    /// Lights keep their emission
+    pub fn set_clay(&mut self) {
+        let gray = Arc::new(texture::ConstantColor::new(Colorf::broadcast(0.5)));
+        let roughness = Arc::new(texture::ConstantScalar::new(0.0));
+        let clay: Arc<Material + Send + Sync> = Arc::new(Matte::new(gray, roughness));
+        for i in self.accel.iter_mut() {
+            if let Instance::Receiver(ref mut r) = *i {
+                r.material = clay.clone();
+                r.material_name = "clay".to_owned();
+            }
+        }
+    }
+    /// Render the shadow and ambient occlusion the object named `target` casts onto the rest
+    /// of the scene instead of the scene's lighting, see `integrator::ShadowPass`. The AO
+    /// distance is half the size of the object. Panics if there's no object named `target`
+    pub fn set_shadow_pass(&mut self, target: &str) {
+        let bounds = self.accel.iter().find(|i| i.tag() == target)
+            .expect(&format!("No object named '{}' to render the shadow pass for", target)[..])
+            .bounds(0.0, 0.0);
+        let extent = bounds.max - bounds.min;
+        let ao_distance = f32::max(0.5 * f32::max(extent.x, f32::max(extent.y, extent.z)), 1e-3);
+        self.integrator = Box::new(integrator::ShadowPass::new(target.to_owned(), ao_distance, 16));
+        // The plate is already seen through any fog, we only want the shadow
+        self.fog = None;
+    }
+    /// Get the tags of the objects in the scene by their object ID, used to name the
+    /// object ID mattes. See `film::object_id`
+    pub fn object_tags(&self) -> HashMap<u32, String> {
+        self.accel.iter().map(|i| (object_id(i.tag()), i.tag().to_owned())).collect()
+    }
+    /// Change the image dimensions that the scene's cameras generate rays for,
+    /// used to render the scene at a different resolution than the film
+    pub fn set_image_dimensions(&mut self, dim: (usize, usize)) {
+        for c in &mut self.cameras {
+            c.set_dimensions(dim);
+        }
+    }
+    /// Check if any of the scene's cameras render stereo pairs
+    pub fn is_stereo(&self) -> bool {
+        self.cameras.iter().any(|c| c.is_stereo())
+    }
+    /// Set which eye the cameras should render for
+    pub fn set_eye(&mut self, eye: Eye) {
+        for c in &mut self.cameras {
+            c.set_eye(eye);
+        }
+    }
+    /// Set the slice of its clip range the camera should render, passed as (slice, number
+    /// of slices), or None to render the whole range. The slice is applied to the camera
+    /// active for each frame when the frame is started, so only that camera needs a finite
+    /// clip range. See film/camera
+    pub fn set_slice(&mut self, slice: Option<(usize, usize)>) {
+        self.slice = slice;
+    }
+    /// Check the scene for common mistakes which would otherwise show up as a black or
+    /// broken render, returning a warning for each problem found. Objects without a
+    /// material and meshes without any triangles are already rejected when loading the
+    /// scene. Cameras are checked at the time their shutter is currently open, which is
+    /// the start of the scene when called after loading it
+    pub fn validate(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        for i in self.accel.iter() {
+            if i.get_transform().has_nans() {
+                warnings.push(Warning::InvalidTransform(i.tag().to_owned()));
+                continue;
+            }
+            match *i {
+                Instance::Emitter(ref e) => {
+                    if e.is_black() {
+                        warnings.push(Warning::BlackLight(e.tag.clone()));
+                    }
+                },
+                Instance::Receiver(ref r) => {
+                    let b = r.bounds(0.0, 0.0);
+                    // Empty boxes have their min above their max, NaN bounds are also treated as empty
+                    let empty = (0..3).any(|a| b.min[a] > b.max[a] || b.min[a].is_nan() || b.max[a].is_nan());
+                    let point = (0..3).all(|a| b.min[a] == b.max[a]);
+                    if empty || point {
+                        warnings.push(Warning::EmptyGeometry(r.tag.clone()));
+                    }
+                },
+            }
+        }
+        for (i, c) in self.cameras.iter().enumerate() {
+            let dim = c.dimensions();
+            let mut sees_objects = false;
+            for y in 0..VALIDATE_CAMERA_RAYS {
+                for x in 0..VALIDATE_CAMERA_RAYS {
+                    let px = ((x as f32 + 0.5) / VALIDATE_CAMERA_RAYS as f32 * dim.0 as f32,
+                              (y as f32 + 0.5) / VALIDATE_CAMERA_RAYS as f32 * dim.1 as f32);
+                    let mut ray = c.generate_ray(&px, 0.0);
+                    sees_objects = sees_objects || self.intersect(&mut ray).is_some();
+                }
+            }
+            if !sees_objects {
+                warnings.push(Warning::CameraSeesNothing(i));
+            }
+        }
+        warnings
     }
     /// Get the active camera for the current frame
     pub fn active_camera(&self) -> &Camera {
         &self.cameras[self.active_camera.expect("Update frame must be called before active_camera")]
     }
+    /// Get the camera the frame passed is rendered from
+    pub fn camera_at(&self, frame: usize) -> &Camera {
+        &self.cameras[self.camera_index(frame)]
+    }
+    /// Get the index of the camera the frame passed is rendered from, which is the last
+    /// camera to become active at or before the frame
+    fn camera_index(&self, frame: usize) -> usize {
+        self.cameras.iter().take_while(|x| x.active_at <= frame).count() - 1
+    }
 }
 
 /// Load the film described by the JSON value passed. Returns the render target
 /// along with the image dimensions and samples per pixel
-fn load_film(elem: &Value) -> (RenderTarget, usize, FrameInfo) {
+fn load_film(elem: &Value) -> (RenderTarget, usize, FrameInfo, Vec<Output>) {
     let width = elem.get("width").expect("The film must specify the image width")
         .as_u64().expect("Image width must be a number") as usize;
     let height = elem.get("height").expect("The film must specify the image height")
@@ -202,7 +777,89 @@ fn load_film(elem: &Value) -> (RenderTarget, usize, FrameInfo) {
         .as_f64().expect("Scene time must be a number") as f32;
     let frame_info = FrameInfo::new(frames, scene_time, start_frame, end_frame);
     let filter = load_filter(elem.get("filter").expect("The film must specify a reconstruction filter"));
-    (RenderTarget::new((width, height), (2, 2), filter), spp, frame_info)
+    let tile_size = match elem.get("tile_size") {
+        Some(t) => {
+            let t = t.as_array().expect("Film tile_size must be an array of 2 unsigned ints");
+            if t.len() != 2 {
+                panic!("Film tile_size must be an array of 2 unsigned ints");
+            }
+            (t[0].as_u64().expect("Film tile_size must be an array of 2 unsigned ints") as usize,
+             t[1].as_u64().expect("Film tile_size must be an array of 2 unsigned ints") as usize)
+        },
+        None => (2, 2),
+    };
+    if tile_size.0 == 0 || tile_size.1 == 0 {
+        panic!("Film tile_size must be at least 1x1, got {:?}", tile_size);
+    }
+    let outputs = match elem.get("outputs") {
+        Some(o) => {
+            o.as_array().expect("Film outputs must be an array of outputs").iter().map(|e| {
+                let path = e.get("path").expect("A path is required for each film output")
+                    .as_str().expect("Film output path must be a string");
+                let aov = match e.get("aov") {
+                    Some(a) => {
+                        let a = a.as_str().expect("Film output aov must be a string");
+                        Aov::from_str(a).expect(&format!("Unrecognized film output aov '{}'", a))
+                    },
+                    None => Aov::Beauty,
+                };
+                Output::with_aov(path, aov)
+            }).collect()
+        },
+        None => Vec::new(),
+    };
+    let mut rt = RenderTarget::new((width, height), tile_size, filter);
+    if let Some(g) = elem.get("grade") {
+        rt.set_grade(load_grade(g));
+    }
+    if let Some(t) = elem.get("tonemap") {
+        rt.set_tonemap(load_tonemap(t));
+    }
+    if let Some(a) = elem.get("auto_exposure") {
+        rt.set_auto_exposure(Some(load_auto_exposure(a)));
+    }
+    if let Some(d) = elem.get("deterministic") {
+        rt.set_deterministic(d.as_bool().expect("Film deterministic must be a bool"));
+    }
+    (rt, spp, frame_info, outputs)
+}
+/// Load the color grade described by the JSON value passed, parameters which
+/// aren't specified leave the image unchanged
+fn load_grade(elem: &Value) -> Grade {
+    let param = |name: &str, default: f32| match elem.get(name) {
+        Some(x) => x.as_f64().expect(&format!("Grade {} must be a number", name)) as f32,
+        None => default,
+    };
+    Grade::new(param("exposure", 0.0), param("contrast", 1.0), param("saturation", 1.0))
+}
+/// Load the tonemapping operator described by the JSON value passed, panics if the type
+/// isn't "clamp" or "reinhard"
+fn load_tonemap(elem: &Value) -> Tonemap {
+    let ty = elem.get("type").expect("A type is required for the tonemap")
+        .as_str().expect("Tonemap type must be a string");
+    match ty {
+        "clamp" => Tonemap::Clamp,
+        "reinhard" => {
+            let white = match elem.get("white") {
+                Some(w) => w.as_f64().expect("Tonemap white must be a number") as f32,
+                None => f32::INFINITY,
+            };
+            if white <= 0.0 {
+                panic!("Tonemap white must be greater than 0, got {}", white);
+            }
+            Tonemap::Reinhard { white: white }
+        },
+        _ => panic!("Unrecognized tonemap type '{}', expected \"clamp\" or \"reinhard\"", ty),
+    }
+}
+/// Load the auto exposure described by the JSON value passed, parameters which aren't
+/// specified use their defaults
+fn load_auto_exposure(elem: &Value) -> AutoExposure {
+    let param = |name: &str, default: f32| match elem.get(name) {
+        Some(x) => x.as_f64().expect(&format!("Auto exposure {} must be a number", name)) as f32,
+        None => default,
+    };
+    AutoExposure::new(param("key", 0.18), param("smoothing", 0.8))
 }
 /// Load the reconstruction filter described by the JSON value passed
 fn load_filter(elem: &Value) -> Box<filter::Filter + Send + Sync> {
@@ -227,8 +884,100 @@ fn load_filter(elem: &Value) -> Box<filter::Filter + Send + Sync> {
     }
 }
 
+/// Load the scene's fog, see integrator/fog for details
+fn load_fog(elem: &Value) -> Fog {
+    let color = load_color(elem.get("color").expect("The fog must specify a color"))
+        .expect("Invalid color specified for the fog");
+    let density = elem.get("density").expect("The fog must specify a density")
+        .as_f64().expect("The fog density must be a number") as f32;
+    Fog::new(color, density)
+}
+
+/// Load the sampler used to pick the samples taken in each pixel, see sampler/adaptive
+/// for the adaptive sampler's parameters
+fn load_sampler(elem: &Value) -> SamplerType {
+    let ty = elem.get("type").expect("The sampler must specify its type")
+        .as_str().expect("The sampler type must be a string");
+    match ty {
+        "ld" | "low_discrepancy" => SamplerType::LowDiscrepancy,
+        "adaptive" => {
+            let min_spp = elem.get("min_spp").expect("The adaptive sampler must specify min_spp")
+                .as_u64().expect("The adaptive sampler min_spp must be an unsigned int") as usize;
+            let max_spp = elem.get("max_spp").expect("The adaptive sampler must specify max_spp")
+                .as_u64().expect("The adaptive sampler max_spp must be an unsigned int") as usize;
+            if min_spp == 0 || max_spp < min_spp {
+                panic!("The adaptive sampler needs 0 < min_spp <= max_spp, got {} and {}", min_spp, max_spp);
+            }
+            let edges = elem.get("edges").map(|e| {
+                let defaults = EdgeDetection::default();
+                let depth = match e.get("depth") {
+                    Some(d) => d.as_f64().expect("Edge depth threshold must be a number") as f32,
+                    None => defaults.depth,
+                };
+                match e.get("normal_angle") {
+                    Some(a) => {
+                        EdgeDetection::new(depth, a.as_f64().expect("Edge normal angle must be a number") as f32)
+                    },
+                    None => EdgeDetection { depth: depth, cos_normal: defaults.cos_normal },
+                }
+            });
+            SamplerType::Adaptive { min_spp: min_spp, max_spp: max_spp, edges: edges }
+        },
+        s => panic!("Unrecognized sampler type '{}', expected \"ld\" or \"adaptive\"", s),
+    }
+}
+
+/// Load the type of acceleration structure to use, panics if it isn't "bvh", "grid" or "kdtree"
+fn load_accelerator(elem: &Value) -> AcceleratorType {
+    match elem.as_str().expect("The accelerator must be a string") {
+        "bvh" => AcceleratorType::BVH,
+        "grid" => AcceleratorType::Grid,
+        "kdtree" => AcceleratorType::KdTree,
+        a => panic!("Unrecognized accelerator '{}', expected \"bvh\", \"grid\" or \"kdtree\"", a),
+    }
+}
+
+/// Load the scene's up axis, panics if it isn't "y" or "z"
+fn load_up_axis(elem: &Value) -> UpAxis {
+    match elem.as_str().expect("The up axis must be a string") {
+        "y" => UpAxis::Y,
+        "z" => UpAxis::Z,
+        a => panic!("Unrecognized up axis '{}', expected \"y\" or \"z\"", a),
+    }
+}
+
+/// Load the parameters used to build the scene BVH, returns the max number of
+/// objects per leaf and the SAH split parameters. Any parameters not specified
+/// keep their default values
+fn load_bvh_params(elem: &Value) -> (usize, SAHParams) {
+    let defaults = SAHParams::default();
+    let max_geom = match elem.get("max_geom") {
+        Some(m) => m.as_u64().expect("BVH max_geom must be an unsigned int") as usize,
+        None => 4,
+    };
+    let buckets = match elem.get("buckets") {
+        Some(b) => b.as_u64().expect("BVH buckets must be an unsigned int") as usize,
+        None => defaults.buckets,
+    };
+    let traversal_cost = match elem.get("traversal_cost") {
+        Some(c) => c.as_f64().expect("BVH traversal_cost must be a number") as f32,
+        None => defaults.traversal_cost,
+    };
+    let intersection_cost = match elem.get("intersection_cost") {
+        Some(c) => c.as_f64().expect("BVH intersection_cost must be a number") as f32,
+        None => defaults.intersection_cost,
+    };
+    if max_geom == 0 {
+        panic!("BVH max_geom must be at least 1");
+    }
+    if buckets < 2 {
+        panic!("BVH buckets must be at least 2");
+    }
+    (max_geom, SAHParams::new(buckets, traversal_cost, intersection_cost))
+}
+
 /// Load the cameras or single camera specified for this scene
-fn load_cameras(elem: &Value, dim: (usize, usize)) -> Vec<Camera> {
+fn load_cameras(elem: &Value, dim: (usize, usize), frame_info: &FrameInfo) -> Vec<Camera> {
     match elem.get("cameras") {
         Some(c) => {
             let cameras_json = match c.as_array() {
@@ -237,18 +986,18 @@ fn load_cameras(elem: &Value, dim: (usize, usize)) -> Vec<Camera> {
             };
             let mut cameras = Vec::new();
             for cam in cameras_json {
-                cameras.push(load_camera(cam, dim));
+                cameras.push(load_camera(cam, dim, frame_info));
             }
             cameras.sort_by(|a, b| a.active_at.cmp(&b.active_at));
             cameras
         },
-        None => vec![load_camera(elem.get("camera").expect("Error: A camera is required!"), dim)]
+        None => vec![load_camera(elem.get("camera").expect("Error: A camera is required!"), dim, frame_info)]
     }
 }
 /// Load the camera described by the JSON value passed.
 /// Returns the camera along with the number of samples to take per pixel
 /// and the scene dimensions. Panics if the camera is incorrectly specified
-fn load_camera(elem: &Value, dim: (usize, usize)) -> Camera {
+fn load_camera(elem: &Value, dim: (usize, usize), frame_info: &FrameInfo) -> Camera {
     let shutter_size = match elem.get("shutter_size") {
         Some(s) => s.as_f64().expect("Shutter size should be a float from 0 to 1") as f32,
         None => 0.5,
@@ -257,25 +1006,76 @@ fn load_camera(elem: &Value, dim: (usize, usize)) -> Camera {
         Some(s) => s.as_u64().expect("The camera activation frame 'active_at' must be an unsigned int") as usize,
         None => 0,
     };
-    let transform = match elem.get("keyframes") {
-        Some(t) => load_keyframes(t).expect("Invalid keyframes specified"),
-        None => {
-            let t = match elem.get("transform") {
-                Some(t) => load_transform(t).expect("Invalid transform specified"),
-                None => {
-                    println!("Warning! Specifying transforms with pos, target and up vectors is deprecated!");
-                    let pos = load_point(elem.get("position").expect("The camera must specify a position"))
-                        .expect("position must be an array of 3 floats");
-                    let target = load_point(elem.get("target").expect("The camera must specify a target"))
-                        .expect("target must be an array of 3 floats");
-                    let up = load_vector(elem.get("up").expect("The camera must specify an up vector"))
-                        .expect("up must be an array of 3 floats");
-                    Transform::look_at(&pos, &target, &up)
-                }
-            };
-            AnimatedTransform::unanimated(&t)
-        },
+    let keyframed = elem.get("keyframes").is_some() || elem.get("look_at_keyframes").is_some();
+    if elem.get("turntable").is_some() && keyframed {
+        panic!("A turntable camera can't also be animated with keyframes");
+    }
+    let transform = if let Some(t) = elem.get("keyframes") {
+        load_keyframes(t).expect("Invalid keyframes specified")
+    } else if let Some(t) = elem.get("look_at_keyframes") {
+        load_look_at_keyframes(t).expect("Invalid look_at_keyframes specified")
+    } else {
+        let t = match elem.get("transform") {
+            Some(t) => load_transform(t).expect("Invalid transform specified"),
+            None => {
+                warn!("Specifying transforms with pos, target and up vectors is deprecated!");
+                let pos = load_point(elem.get("position").expect("The camera must specify a position"))
+                    .expect("position must be an array of 3 floats");
+                let target = load_point(elem.get("target").expect("The camera must specify a target"))
+                    .expect("target must be an array of 3 floats");
+                let up = load_vector(elem.get("up").expect("The camera must specify an up vector"))
+                    .expect("up must be an array of 3 floats");
+                Transform::look_at(&pos, &target, &up)
+            }
+        };
+        match elem.get("turntable") {
+            Some(tt) => load_turntable(tt, &t, frame_info),
+            None => AnimatedTransform::unanimated(&t),
+        }
+    };
+    let mut camera = if let Some(o) = elem.get("orthographic") {
+        let size = o.get("size").expect("An orthographic camera must specify its size")
+            .as_f64().expect("Orthographic camera size must be a number") as f32;
+        if size <= 0.0 {
+            panic!("Orthographic camera size must be greater than 0");
+        }
+        Camera::orthographic(transform, size, dim, shutter_size, active_at)
+    } else {
+        load_perspective_camera(elem, transform, dim, shutter_size, active_at)
     };
+    if let Some(c) = elem.get("clip") {
+        let c = c.as_array().expect("Camera clip must be an array of [near, far]");
+        if c.len() != 2 {
+            panic!("Camera clip must be an array of [near, far]");
+        }
+        let near = c[0].as_f64().expect("Camera clip near must be a number") as f32;
+        let far = c[1].as_f64().expect("Camera clip far must be a number") as f32;
+        if near < 0.0 || far <= near {
+            panic!("Camera clip range must have 0 <= near < far, got [{}, {}]", near, far);
+        }
+        camera.set_clip(near, far);
+    }
+    if let Some(c) = elem.get("shutter_curve") {
+        let points = c.as_array().expect("The shutter curve must be an array of control points").iter()
+            .map(|x| x.as_f64().expect("Shutter curve control points must be numbers") as f32).collect();
+        let knots = elem.get("shutter_curve_knots").expect("The shutter curve must specify spline knots")
+            .as_array().expect("Shutter curve knots must be an array").iter()
+            .map(|x| x.as_f64().expect("Shutter curve knots must be numbers") as f32).collect();
+        let degree = elem.get("shutter_curve_degree").expect("The shutter curve must specify its degree")
+            .as_u64().expect("Shutter curve degree must be a u64") as usize;
+        camera.set_shutter_curve(points, knots, degree);
+    }
+    if let Some(s) = elem.get("stereo") {
+        let ipd = s.get("ipd").expect("A stereo camera must specify the interocular distance 'ipd'")
+            .as_f64().expect("Stereo ipd must be a number") as f32;
+        camera.set_stereo(ipd);
+    }
+    camera
+}
+
+/// Load the camera's perspective projection, with a fixed or animated field of view
+fn load_perspective_camera(elem: &Value, transform: AnimatedTransform, dim: (usize, usize), shutter_size: f32,
+                           active_at: usize) -> Camera {
     let fov_elem = elem.get("fov").expect("The camera must specify a field of view");
     if fov_elem.is_array() {
         let fovs_elems = fov_elem.as_array().expect("List of FOVs must be an array");
@@ -292,6 +1092,16 @@ fn load_camera(elem: &Value, dim: (usize, usize)) -> Camera {
     }
 }
 
+/// Create an integrator of the type passed with default parameters, e.g. to override
+/// the scene's integrator from the command line. Panics if the type is unrecognized
+pub fn default_integrator(ty: &str) -> Box<Integrator + Send + Sync> {
+    let mut elem = serde_json::Map::new();
+    elem.insert("type".to_owned(), Value::from(ty));
+    elem.insert("min_depth".to_owned(), Value::from(4));
+    elem.insert("max_depth".to_owned(), Value::from(8));
+    load_integrator(&Value::Object(elem))
+}
+
 /// Load the integrator described by the JSON value passed.
 /// Return the integrator or panics if it's incorrectly specified
 fn load_integrator(elem: &Value) -> Box<Integrator + Send + Sync> {
@@ -302,18 +1112,73 @@ fn load_integrator(elem: &Value) -> Box<Integrator + Send + Sync> {
             .as_u64().expect("min_depth must be a number") as u32;
         let max_depth = elem.get("max_depth").expect("The integrator must specify the maximum ray depth")
             .as_u64().expect("max_depth must be a number") as u32;
-        Box::new(integrator::Path::new(min_depth, max_depth))
+        Box::new(integrator::Path::with_ray_limit(min_depth, max_depth, load_ray_limit(elem)))
     } else if ty == "whitted" {
-        let min_depth = elem.get("min_depth").expect("The integrator must specify the minimum ray depth")
-            .as_u64().expect("min_depth must be a number") as u32;
-        Box::new(integrator::Whitted::new(min_depth))
+        // Older scenes passed the Whitted integrator's maximum depth as min_depth
+        let max_depth = match (elem.get("max_depth"), elem.get("min_depth")) {
+            (Some(d), _) => d,
+            (None, Some(d)) => {
+                warn!("The whitted integrator's min_depth is deprecated, use max_depth instead");
+                d
+            },
+            (None, None) => panic!("The integrator must specify the maximum ray depth"),
+        };
+        let max_depth = max_depth.as_u64().expect("max_depth must be a number") as u32;
+        Box::new(integrator::Whitted::with_ray_limit(max_depth, load_ray_limit(elem)))
     } else if ty == "normals_debug" {
         Box::new(integrator::NormalsDebug)
+    } else if ty == "irradiance_cache" {
+        let max_depth = elem.get("max_depth").expect("The integrator must specify the maximum ray depth")
+            .as_u64().expect("max_depth must be a number") as u32;
+        let samples = match elem.get("samples") {
+            Some(s) => s.as_u64().expect("samples must be a number") as usize,
+            None => 64,
+        };
+        let max_error = match elem.get("max_error") {
+            Some(e) => e.as_f64().expect("max_error must be a number") as f32,
+            None => 0.2,
+        };
+        let min_spacing = match elem.get("min_spacing") {
+            Some(s) => s.as_f64().expect("min_spacing must be a number") as f32,
+            None => 0.1,
+        };
+        let max_spacing = match elem.get("max_spacing") {
+            Some(s) => s.as_f64().expect("max_spacing must be a number") as f32,
+            None => 10.0,
+        };
+        Box::new(integrator::IrradianceCache::new(max_depth, samples, max_error, min_spacing, max_spacing))
+    } else if ty == "shadow_pass" {
+        let target = elem.get("target").expect("The shadow pass must specify the target object")
+            .as_str().expect("target must be a string").to_owned();
+        let ao_distance = match elem.get("ao_distance") {
+            Some(d) => d.as_f64().expect("ao_distance must be a number") as f32,
+            None => 1.0,
+        };
+        let ao_samples = match elem.get("ao_samples") {
+            Some(s) => s.as_u64().expect("ao_samples must be a number") as usize,
+            None => 16,
+        };
+        Box::new(integrator::ShadowPass::new(target, ao_distance, ao_samples))
     } else {
         panic!("Unrecognized integrator type '{}'", ty);
     }
 }
 
+/// Load the optional limit on the rays traced for each camera ray from the integrator,
+/// panics if it isn't at least 1
+fn load_ray_limit(elem: &Value) -> u32 {
+    match elem.get("ray_limit") {
+        Some(l) => {
+            let l = l.as_u64().expect("ray_limit must be a number");
+            if l == 0 || l > u32::max_value() as u64 {
+                panic!("ray_limit must be at least 1 and fit in 32 bits, got {}", l);
+            }
+            l as u32
+        },
+        None => integrator::DEFAULT_RAY_LIMIT,
+    }
+}
+
 fn load_textures(path: &Path, elem: &Value) -> LoadedTextures {
     let mut textures = LoadedTextures::none();
     let tex_vec = elem.as_array().expect("The 'textures' must be an array of textures to load");
@@ -328,13 +1193,8 @@ fn load_textures(path: &Path, elem: &Value) -> LoadedTextures {
             panic!("Error loading texture '{}': name conflicts with an existing entry", name);
         }
         if ty == "image" {
-            let mut file_path = PathBuf::new();
-            file_path.push(t.get("file").expect("Image textures must specify an image file")
-                      .as_str().expect("Image file name must be a string"));
-
-            if file_path.is_relative() {
-                file_path = path.join(file_path);
-            }
+            let file_path = resolve_path(path, t.get("file").expect("Image textures must specify an image file")
+                                         .as_str().expect("Image file name must be a string"));
             let img = image::open(file_path).expect("Failed to load image file");
 
             textures.textures.insert(name, Arc::new(texture::Image::new(img)));
@@ -345,13 +1205,8 @@ fn load_textures(path: &Path, elem: &Value) -> LoadedTextures {
                 panic!("animated_image must have at least 2 frames");
             }
             let frames: Vec<_> = frames_list.iter().map(|f| {
-                let mut file_path = PathBuf::new();
-                file_path.push(f.get("file").expect("Image textures must specify an image file")
-                               .as_str().expect("Image file name must be a string"));
-
-                if file_path.is_relative() {
-                    file_path = path.join(file_path);
-                }
+                let file_path = resolve_path(path, f.get("file").expect("Image textures must specify an image file")
+                                             .as_str().expect("Image file name must be a string"));
                 let time = f.get("time").expect("animated_image keyframe requires time")
                     .as_f64().expect("animated_image keyframe time must be a number") as f32;
                 let img = texture::Image::new(image::open(file_path).expect("Failed to load image file"));
@@ -373,13 +1228,9 @@ fn load_textures(path: &Path, elem: &Value) -> LoadedTextures {
                 .as_u64().expect("framerate for movie must be an int");
 
             let frames: Vec<_> = (0..total_frames).map(|frame| {
-                let mut file_path = PathBuf::new();
                 // There's no support for runtime-string formatting, maybe some lib out there for
                 // it but a lot of them seem targetted for web development and are too heavy.
-                file_path.push(format!("{}{:05}{}", file_prefix, frame, file_suffix));
-                if file_path.is_relative() {
-                    file_path = path.join(file_path);
-                }
+                let file_path = resolve_path(path, format!("{}{:05}{}", file_prefix, frame, file_suffix));
                 let time = frame as f32 / framerate as f32;
                 let img = texture::Image::new(image::open(file_path).expect("Failed to load image file"));
                 (time, img)
@@ -393,11 +1244,164 @@ fn load_textures(path: &Path, elem: &Value) -> LoadedTextures {
     textures
 }
 
+thread_local! {
+    /// The relative asset paths resolved while loading a scene on this thread,
+    /// see `Scene::load_file_with_assets`
+    static SCENE_ASSETS: RefCell<Vec<PathBuf>> = RefCell::new(Vec::new());
+}
+
+/// Resolve the path of an asset referenced by the scene, e.g. a texture, mesh or MERL file.
+/// Relative paths are relative to `scene_dir`, the directory containing the scene file,
+/// absolute paths are returned unchanged. All loaders should find their files through this
+/// so the distributed renderer knows which files to send to the workers
+fn resolve_path<P: AsRef<Path>>(scene_dir: &Path, file: P) -> PathBuf {
+    let file = file.as_ref();
+    if file.is_relative() {
+        let path = scene_dir.join(file);
+        SCENE_ASSETS.with(|a| a.borrow_mut().push(path.clone()));
+        path
+    } else {
+        file.to_path_buf()
+    }
+}
+
+/// Remove the comments and trailing commas allowed in scene files so they can be parsed by
+/// serde_json. Comments are replaced with whitespace, keeping their newlines, so the line
+/// numbers of parsing errors still match the scene file
+fn strip_json_extensions(json: &str) -> String {
+    let mut no_comments = String::with_capacity(json.len());
+    let mut chars = json.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            no_comments.push(c);
+            if c == '\\' {
+                if let Some(e) = chars.next() {
+                    no_comments.push(e);
+                }
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            while let Some(&n) = chars.peek() {
+                if n == '\n' {
+                    break;
+                }
+                chars.next();
+            }
+            no_comments.push(' ');
+        } else if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut prev = ' ';
+            loop {
+                match chars.next() {
+                    Some('/') if prev == '*' => break,
+                    Some(n) => {
+                        if n == '\n' {
+                            no_comments.push('\n');
+                        }
+                        prev = n;
+                    },
+                    None => panic!("Unterminated block comment in scene file"),
+                }
+            }
+            no_comments.push(' ');
+        } else {
+            in_string = c == '"';
+            no_comments.push(c);
+        }
+    }
+    // Drop commas which are followed only by whitespace before the end of an object or array
+    let chars: Vec<_> = no_comments.chars().collect();
+    let mut stripped = String::with_capacity(no_comments.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &c) in chars.iter().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let next = chars[i + 1..].iter().find(|n| !n.is_whitespace());
+            if next == Some(&'}') || next == Some(&']') {
+                stripped.push(' ');
+                continue;
+            }
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
 /// Generate a material loading error string
 fn mat_error(mat_name: &str, msg: &str) -> String {
     format!("Error loading material '{}': {}", mat_name, msg)
 }
 
+/// Check the material names defined and referenced by the scene before loading any of them.
+/// All duplicate material names and all materials referenced by objects but never defined are
+/// reported together, instead of panicking on the first problem found.
+fn validate_material_names(materials: &Value, objects: &Value) {
+    let mut defined = HashSet::new();
+    let mut duplicates = Vec::new();
+    if let Some(mat_vec) = materials.as_array() {
+        for m in mat_vec {
+            if let Some(name) = m.get("name").and_then(|n| n.as_str()) {
+                if !defined.insert(name) && !duplicates.contains(&name) {
+                    duplicates.push(name);
+                }
+            }
+        }
+    }
+    let mut references = Vec::new();
+    collect_material_references(objects, &mut references);
+    let missing: Vec<_> = references.iter().filter(|&&(mat, _)| !defined.contains(mat)).collect();
+    if duplicates.is_empty() && missing.is_empty() {
+        return;
+    }
+    let mut msg = String::from("Error loading materials:");
+    for d in &duplicates {
+        msg.push_str(&format!("\n\tmaterial '{}' is defined more than once", d));
+    }
+    for &&(mat, obj) in &missing {
+        msg.push_str(&format!("\n\tmaterial '{}' used by object '{}' was not found in the material list",
+                              mat, obj));
+    }
+    panic!("{}", msg);
+}
+
+/// Collect the (material, object) name pairs for each object referencing a material,
+/// recursing into groups. Objects missing names are skipped, `load_objects` will report them
+fn collect_material_references<'a>(elem: &'a Value, references: &mut Vec<(&'a str, &'a str)>) {
+    let objects = match elem.as_array() {
+        Some(o) => o,
+        None => return,
+    };
+    for o in objects {
+        if let Some(mat) = o.get("material").and_then(|m| m.as_str()) {
+            let name = o.get("name").and_then(|n| n.as_str()).unwrap_or("<unnamed>");
+            references.push((mat, name));
+        }
+        if let Some(inst_vec) = o.get("instances").and_then(|i| i.as_array()) {
+            let name = o.get("name").and_then(|n| n.as_str()).unwrap_or("<unnamed>");
+            for e in inst_vec {
+                if let Some(mat) = e.get("material").and_then(|m| m.as_str()) {
+                    references.push((mat, e.get("name").and_then(|n| n.as_str()).unwrap_or(name)));
+                }
+            }
+        }
+        if let Some(group_objects) = o.get("objects") {
+            collect_material_references(group_objects, references);
+        }
+    }
+}
+
 /// Load the array of materials used in the scene, panics if a material is specified
 /// incorrectly. The path to the directory containing the scene file is required to find
 /// referenced material data relative to the scene file.
@@ -416,6 +1420,23 @@ fn load_materials(path: &Path, elem: &Value, textures: &LoadedTextures)
         if materials.contains_key(&name) {
             panic!("Error loading material '{}': name conflicts with an existing entry", name);
         }
+        // Any type of material can emit light, it's wrapped in an emissive material once loaded
+        let emission = m.get("emission").map(|e| {
+            let find = |c: &Value, msg: &str| textures.find_color(c).expect(&mat_error(&name, msg)[..]);
+            let (front, back) = if e.is_object() {
+                let front = e.get("front").expect(&mat_error(&name, "A front color is required for emission")[..]);
+                (find(front, "Invalid color specified for front emission"),
+                 e.get("back").map(|b| find(b, "Invalid color specified for back emission")))
+            } else {
+                (find(e, "Invalid color specified for emission"), None)
+            };
+            let strength = match m.get("emission_strength") {
+                Some(s) => s.as_f64().expect(&mat_error(&name, "emission_strength must be a number")[..]) as f32,
+                None => 1.0,
+            };
+            (front, back, strength)
+        });
+        let key = name.clone();
         if ty == "glass" {
             let reflect = textures.find_color(m.get("reflect")
                                             .expect("reflect color/texture name is required for glass"))
@@ -449,21 +1470,42 @@ fn load_materials(path: &Path, elem: &Value, textures: &LoadedTextures)
                                             .expect("diffuse color/texture name is required for matte"))
                 .expect(&mat_error(&name, "Invalid color specified for diffuse of matte")[..]);
 
-            let roughness = textures.find_scalar(m.get("roughness")
-                                                 .expect("roughness color/texture is required for matte"))
-                .expect(&mat_error(&name, "Invalid roughness specified for roughness")[..]);
+            let roughness: Arc<Texture + Send + Sync> = match m.get("model").map(|x| x.as_str()) {
+                // Oren-Nayar with no roughness is Lambertian
+                Some(Some("lambertian")) => Arc::new(texture::ConstantScalar::new(0.0)),
+                Some(Some("oren_nayar")) => {
+                    textures.find_scalar(m.get("sigma")
+                                         .expect(&mat_error(&name, "sigma is required for oren_nayar matte")[..]))
+                        .expect(&mat_error(&name, "Invalid sigma specified for oren_nayar matte")[..])
+                },
+                Some(Some(model)) => panic!("{}", mat_error(&name, &format!("Unknown matte model '{}'", model))),
+                Some(None) => panic!("{}", mat_error(&name, "The matte model must be a string")),
+                None => {
+                    textures.find_scalar(m.get("roughness")
+                                         .expect("roughness color/texture is required for matte"))
+                        .expect(&mat_error(&name, "Invalid roughness specified for roughness")[..])
+                },
+            };
+            let specular = match m.get("specular") {
+                Some(s) => s.as_f64().expect(&mat_error(&name, "specular weight must be a number")[..]) as f32,
+                None => 0.0,
+            };
 
-            materials.insert(name, Arc::new(Matte::new(diffuse, roughness)));
+            materials.insert(name, Arc::new(Matte::with_specular(diffuse, roughness, specular)));
         } else if ty == "merl" {
-            let file_path = Path::new(m.get("file")
+            let file_path = resolve_path(path, m.get("file")
                       .expect(&mat_error(&name, "A filename containing the MERL material data is required")[..])
                       .as_str().expect(&mat_error(&name, "The MERL file must be a string")[..]));
-            if file_path.is_relative() {
-                materials.insert(name, Arc::new(Merl::load_file(path.join(file_path).as_path()))
-                                 as Arc<Material + Send + Sync>);
-            } else {
-                materials.insert(name, Arc::new(Merl::load_file(file_path)) as Arc<Material + Send + Sync>);
-            }
+            let scale = match m.get("scale") {
+                Some(s) => s.as_f64().expect(&mat_error(&name, "MERL scale must be a number")[..]) as f32,
+                None => 1.0,
+            };
+            let clamp = match m.get("clamp") {
+                Some(c) => Some(c.as_f64().expect(&mat_error(&name, "MERL clamp must be a number")[..]) as f32),
+                None => None,
+            };
+            materials.insert(name, Arc::new(Merl::load_remapped(&file_path, scale, clamp))
+                             as Arc<Material + Send + Sync>);
         } else if ty == "metal" {
             let refr_index = textures.find_color(m.get("refractive_index")
                                             .expect("refractive_index color/texture name is required for metal"))
@@ -506,15 +1548,25 @@ fn load_materials(path: &Path, elem: &Value, textures: &LoadedTextures)
         } else {
             panic!("Error parsing material '{}': unrecognized type '{}'", name, ty);
         }
+        if let Some((front, back, strength)) = emission {
+            let material = materials.remove(&key).unwrap();
+            let emissive = match back {
+                Some(b) => Emissive::two_sided(material, front, b, strength),
+                None => Emissive::new(material, front, strength),
+            };
+            materials.insert(key, Arc::new(emissive) as Arc<Material + Send + Sync>);
+        }
     }
     materials
 }
 
 /// Loads the array of objects in the scene, assigning them materials from the materials map. Will
-/// panic if an incorrectly specified object is found.
+/// panic if an incorrectly specified object is found. Portals aren't instances in the scene
+/// so they're collected into `portals` to be given to the dome lights. Meshes which don't
+/// pick an accelerator store their triangles in `mesh_accel`
 fn load_objects(path: &Path, materials: &HashMap<String, Arc<Material + Send + Sync>>,
-                mesh_cache: &mut HashMap<String, HashMap<String, Arc<Mesh>>>, elem: &Value)
-                -> Vec<Instance> {
+                mesh_cache: &mut HashMap<String, HashMap<String, Arc<Mesh>>>, mesh_accel: AcceleratorType,
+                portals: &mut Vec<Portal>, elem: &Value) -> Vec<Instance> {
     let mut instances = Vec::new();
     let objects = elem.as_array().expect("The objects must be an array of objects used");
     for o in objects {
@@ -528,6 +1580,10 @@ fn load_objects(path: &Path, materials: &HashMap<String, Arc<Material + Send + S
             None => {
                 let t = match o.get("transform") {
                     Some(t) => load_transform(t).expect("Invalid transform specified"),
+                    // Dome and sky lights surround the scene so don't need to be placed in it
+                    None if o.get("emitter").and_then(|e| e.as_str()).map_or(false, |e| e == "dome" || e == "sky") => {
+                        Transform::identity()
+                    },
                     None => panic!("No keyframes or transform specified for object {}", name),
                 };
                 AnimatedTransform::unanimated(&t)
@@ -536,11 +1592,22 @@ fn load_objects(path: &Path, materials: &HashMap<String, Arc<Material + Send + S
         if ty == "emitter" {
             let emit_ty = o.get("emitter").expect("An emitter type is required for emitters")
                 .as_str().expect("Emitter type must be a string");
-            let emission = load_animated_color(o.get("emission")
-                    .expect("An emission color is required for emitters"))
-                    .expect("Emitter emission must be a color");
+            let emission = || load_emission(o.get("emission").expect("An emission color is required for emitters"));
+            let emission_unit = match o.get("emission_unit") {
+                Some(u) => {
+                    let u = u.as_str().expect("Emission unit must be a string");
+                    EmissionUnit::from_str(u).expect(&format!("Unrecognized emission unit '{}'", u))
+                },
+                None => EmissionUnit::Radiance,
+            };
             if emit_ty == "point" {
-                instances.push(Instance::point_light(transform, emission, name));
+                let (front, back) = emission();
+                if back.is_some() {
+                    panic!("Point light {} can't have a back emission, only area lights have a back", name);
+                }
+                let mut emitter = Emitter::point(transform, front, name);
+                emitter.convert_emission(emission_unit);
+                instances.push(Instance::Emitter(emitter));
             } else if emit_ty == "area" {
                 let mat_name = o.get("material").expect("A material is required for an object")
                     .as_str().expect("Object material name must be a string");
@@ -549,22 +1616,134 @@ fn load_objects(path: &Path, materials: &HashMap<String, Arc<Material + Send + S
                 let geom = load_sampleable_geometry(o.get("geometry")
                                                     .expect("Geometry is required for area lights"));
 
-                instances.push(Instance::area_light(geom, mat, emission, transform, name));
+                let (front, back) = emission();
+                let mut emitter = Emitter::area(geom, mat, front, transform, name);
+                emitter.material_name = mat_name.to_owned();
+                if let Some(b) = back {
+                    emitter.set_back_emission(b);
+                }
+                if let Some(s) = o.get("spread") {
+                    emitter.set_spread(s.as_f64().expect("Emitter spread must be a number") as f32);
+                }
+                emitter.convert_emission(emission_unit);
+                instances.push(Instance::Emitter(emitter));
+            } else if emit_ty == "dome" {
+                let mut top = load_animated_color(o.get("top").expect("A top color is required for dome lights"))
+                    .expect("Dome light top must be a color");
+                let mut bottom = match o.get("bottom") {
+                    Some(b) => load_animated_color(b).expect("Dome light bottom must be a color"),
+                    None => top.clone(),
+                };
+                if let Some(s) = o.get("strength") {
+                    let s = s.as_f64().expect("Dome light strength must be a number") as f32;
+                    top.scale(s);
+                    bottom.scale(s);
+                }
+                let mut emitter = Emitter::dome(transform, top, bottom, name);
+                emitter.convert_emission(emission_unit);
+                instances.push(Instance::Emitter(emitter));
+            } else if emit_ty == "sky" {
+                let sun = load_vector(o.get("sun_direction").expect("A sun_direction is required for sky lights"))
+                    .expect("Sky light sun_direction must be a vector");
+                let turbidity = match o.get("turbidity") {
+                    Some(t) => t.as_f64().expect("Sky light turbidity must be a number") as f32,
+                    None => 3.0,
+                };
+                let strength = match o.get("strength") {
+                    Some(s) => s.as_f64().expect("Sky light strength must be a number") as f32,
+                    None => 1.0,
+                };
+                let mut emitter = Emitter::sky(transform, Sky::new(&sun, turbidity), strength, name);
+                emitter.convert_emission(emission_unit);
+                instances.push(Instance::Emitter(emitter));
             } else {
                 panic!("Invalid emitter type specified: {}", emit_ty);
             }
         } else if ty == "receiver" {
-            let mat_name = o.get("material").expect("A material is required for an object")
-                    .as_str().expect("Object material name must be a string");
-            let mat = materials.get(mat_name)
-                .expect(&format!("Material {} was not found in the material list", mat_name)).clone();
-            let geom = load_geometry(path, mesh_cache, o.get("geometry")
+            let geom = load_geometry(path, mesh_cache, mesh_accel, o.get("geometry")
                                      .expect("Geometry is required for receivers"));
-
-            instances.push(Instance::receiver(geom, mat, transform, name));
+            let primary_visible = match o.get("primary_visible") {
+                Some(v) => v.as_bool().expect("primary_visible must be a bool"),
+                None => true,
+            };
+            let clip_planes = match o.get("clip_planes") {
+                Some(c) => load_clip_planes(c),
+                None => Vec::new(),
+            };
+            let flip_normals = match o.get("flip_normals") {
+                Some(v) => v.as_bool().expect("flip_normals must be a bool"),
+                None => false,
+            };
+            let recompute_normals = match o.get("recompute_normals") {
+                Some(v) => v.as_bool().expect("recompute_normals must be a bool"),
+                None => false,
+            };
+            match o.get("instances") {
+                Some(inst) => {
+                    let inst_vec = inst.as_array().expect("The receiver instances must be an array");
+                    for (i, e) in inst_vec.iter().enumerate() {
+                        let inst_name = match e.get("name") {
+                            Some(n) => n.as_str().expect("Instance name must be a string").to_owned(),
+                            None => format!("{}_{}", name, i),
+                        };
+                        let mat_name = e.get("material").or(o.get("material"))
+                            .expect(&format!("A material is required for instance {}", inst_name))
+                            .as_str().expect("Instance material name must be a string");
+                        let mat = materials.get(mat_name)
+                            .expect(&format!("Material {} was not found in the material list", mat_name)).clone();
+                        let inst_transform = match e.get("keyframes") {
+                            Some(t) => load_keyframes(t).expect("Invalid keyframes specified"),
+                            None => {
+                                let t = match e.get("transform") {
+                                    Some(t) => load_transform(t).expect("Invalid transform specified"),
+                                    None => Transform::identity(),
+                                };
+                                AnimatedTransform::unanimated(&t)
+                            },
+                        };
+                        let mut r = Receiver::new(geom.clone(), mat, transform.clone() * inst_transform,
+                                                  inst_name);
+                        r.material_name = mat_name.to_owned();
+                        r.primary_visible = match e.get("primary_visible") {
+                            Some(v) => v.as_bool().expect("primary_visible must be a bool"),
+                            None => primary_visible,
+                        };
+                        r.clip_planes = clip_planes.clone();
+                        r.flip_normals = match e.get("flip_normals") {
+                            Some(v) => v.as_bool().expect("flip_normals must be a bool"),
+                            None => flip_normals,
+                        };
+                        r.recompute_normals = match e.get("recompute_normals") {
+                            Some(v) => v.as_bool().expect("recompute_normals must be a bool"),
+                            None => recompute_normals,
+                        };
+                        instances.push(Instance::Receiver(r));
+                    }
+                },
+                None => {
+                    let mat_name = o.get("material").expect("A material is required for an object")
+                        .as_str().expect("Object material name must be a string");
+                    let mat = materials.get(mat_name)
+                        .expect(&format!("Material {} was not found in the material list", mat_name)).clone();
+                    let mut r = Receiver::new(geom, mat, transform, name);
+                    r.material_name = mat_name.to_owned();
+                    r.primary_visible = primary_visible;
+                    r.clip_planes = clip_planes;
+                    r.flip_normals = flip_normals;
+                    r.recompute_normals = recompute_normals;
+                    instances.push(Instance::Receiver(r));
+                },
+            }
+        } else if ty == "portal" {
+            let width = o.get("width").expect("A width is required for a portal").as_f64()
+                .expect("width must be a number") as f32;
+            let height = o.get("height").expect("A height is required for a portal").as_f64()
+                .expect("height must be a number") as f32;
+            portals.push(Portal::new(width, height, transform));
         } else if ty == "group" {
             let group_objects = o.get("objects").expect("A group must specify an array of objects in the group");
-            let group_instances = load_objects(path, materials, mesh_cache, group_objects);
+            let first_portal = portals.len();
+            let group_instances = load_objects(path, materials, mesh_cache, mesh_accel, portals, group_objects);
             for mut gi in group_instances {
                 {
                     let t = gi.get_transform().clone();
@@ -572,6 +1751,10 @@ fn load_objects(path: &Path, materials: &HashMap<String, Arc<Material + Send + S
                 }
                 instances.push(gi);
             }
+            for p in &mut portals[first_portal..] {
+                let t = p.get_transform().clone();
+                p.set_transform(transform.clone() * t);
+            }
         } else {
             panic!("Error parsing object '{}': unrecognized type '{}'", name, ty);
         }
@@ -579,10 +1762,21 @@ fn load_objects(path: &Path, materials: &HashMap<String, Arc<Material + Send + S
     instances
 }
 
+/// Load the list of clip planes for a receiver, panics if they're incorrectly specified
+fn load_clip_planes(elem: &Value) -> Vec<ClipPlane> {
+    elem.as_array().expect("Clip planes must be an array of planes").iter().map(|c| {
+        let point = load_point(c.get("point").expect("A point is required for a clip plane"))
+            .expect("Clip plane point must be an array of 3 floats");
+        let n = load_vector(c.get("normal").expect("A normal is required for a clip plane"))
+            .expect("Clip plane normal must be an array of 3 floats");
+        ClipPlane::new(point, Normal::new(n.x, n.y, n.z))
+    }).collect()
+}
+
 /// Load the geometry specified by the JSON value. Will re-use any already loaded meshes
 /// and will place newly loaded meshees in the mesh cache.
-fn load_geometry(path: &Path, meshes: &mut HashMap<String, HashMap<String, Arc<Mesh>>>, elem: &Value)
-             -> Arc<BoundableGeom + Send + Sync> {
+fn load_geometry(path: &Path, meshes: &mut HashMap<String, HashMap<String, Arc<Mesh>>>,
+                 mesh_accel: AcceleratorType, elem: &Value) -> Arc<BoundableGeom + Send + Sync> {
     let ty = elem.get("type").expect("A type is required for geometry")
         .as_str().expect("Geometry type must be a string");
     if ty == "sphere" {
@@ -595,6 +1789,12 @@ fn load_geometry(path: &Path, meshes: &mut HashMap<String, HashMap<String, Arc<M
         let ir = elem.get("inner_radius").expect("An inner radius is required for a disk").as_f64()
             .expect("inner radius must be a number") as f32;
         Arc::new(Disk::new(r, ir))
+    } else if ty == "cone" {
+        let r = elem.get("radius").expect("A radius is required for a cone").as_f64()
+            .expect("radius must be a number") as f32;
+        let h = elem.get("height").expect("A height is required for a cone").as_f64()
+            .expect("height must be a number") as f32;
+        Arc::new(Cone::new(r, h))
     } else if ty == "plane" {
         // We just treat plane as a special case of Rectangle now
         Arc::new(Rectangle::new(2.0, 2.0))
@@ -604,29 +1804,147 @@ fn load_geometry(path: &Path, meshes: &mut HashMap<String, HashMap<String, Arc<M
         let height = elem.get("height").expect("A height is required for a rectangle").as_f64()
             .expect("height must be a number") as f32;
         Arc::new(Rectangle::new(width, height))
+    } else if ty == "infinite_plane" {
+        let height = match elem.get("height") {
+            Some(h) => h.as_f64().expect("height must be a number") as f32,
+            None => 0.0,
+        };
+        let uv_scale = match elem.get("uv_scale") {
+            Some(s) => s.as_f64().expect("uv_scale must be a number") as f32,
+            None => 1.0,
+        };
+        if uv_scale <= 0.0 {
+            panic!("uv_scale of an infinite plane must be greater than 0");
+        }
+        Arc::new(InfinitePlane::new(height, uv_scale))
     } else if ty == "mesh" {
-        let mut file = Path::new(elem.get("file").expect("An OBJ file is required for meshes")
-            .as_str().expect("OBJ filename must be a string")).to_path_buf();
+        let file = resolve_path(path, elem.get("file").expect("An OBJ file is required for meshes")
+            .as_str().expect("OBJ filename must be a string"));
         let model = elem.get("model").expect("A model name is required for geometry")
             .as_str().expect("Model name type must be a string");
 
-        if file.is_relative() {
-            file = path.join(file);
-        }
-        let file_string = file.to_str().expect("Invalid file name");
-        if meshes.get(file_string).is_none() {
-            meshes.insert(file_string.to_owned(), Mesh::load_obj(Path::new(&file)));
+        let use_cache = match elem.get("cache") {
+            Some(c) => c.as_bool().expect("Mesh cache flag must be a bool"),
+            None => false,
+        };
+        let accel = match elem.get("accelerator") {
+            Some(a) => load_accelerator(a),
+            None => mesh_accel,
+        };
+        // Meshes are cached per accelerator so the same file can be loaded with different ones
+        let cache_key = format!("{}:{:?}", file.to_str().expect("Invalid file name"), accel);
+        if meshes.get(&cache_key).is_none() {
+            let loaded = if use_cache {
+                Mesh::load_obj_cached_with_accelerator(Path::new(&file), accel)
+            } else {
+                Mesh::load_obj_with_accelerator(Path::new(&file), accel)
+            };
+            meshes.insert(cache_key.clone(), loaded);
         }
-        let file_meshes = &meshes[file_string];
+        let file_meshes = &meshes[&cache_key];
         match file_meshes.get(model) {
             Some(m) => m.clone(),
             None => panic!("Requested model '{}' was not found in '{:?}'", model, file),
         }
+    } else if ty == "animated_mesh" {
+        Arc::new(load_animated_mesh(path, elem))
+    } else if ty == "instanced" {
+        let inner = elem.get("geometry").expect("Instanced geometry must specify the geometry to place");
+        if inner.get("type").and_then(|t| t.as_str()) == Some("animated_mesh") {
+            panic!("Animated meshes can't be used as instanced geometry");
+        }
+        let geometry = load_geometry(path, meshes, mesh_accel, inner);
+        let transforms = elem.get("transforms").expect("Instanced geometry must specify a list of transforms")
+            .as_array().expect("Instanced geometry transforms must be an array of transforms").iter()
+            .map(|t| load_transform(t).expect("Invalid transform specified for instanced geometry"))
+            .collect();
+        Arc::new(Instanced::new(geometry, transforms))
     } else {
         panic!("Unrecognized geometry type '{}'", ty);
     }
 }
 
+/// Load the animated mesh specified by the JSON value, the keyframes can either be listed
+/// explicitly or be generated from a sequence of numbered OBJ files
+fn load_animated_mesh(path: &Path, elem: &Value) -> AnimatedMesh {
+    let model = elem.get("model").expect("A model name is required for geometry")
+        .as_str().expect("Model name type must be a string");
+    let mut keyframes = Vec::new();
+    if let Some(k) = elem.get("keyframes") {
+        let k = k.as_array().expect("Animated mesh keyframes must be an array");
+        for e in k {
+            let file = e.get("file").expect("An OBJ file is required for animated mesh keyframes")
+                .as_str().expect("OBJ filename must be a string");
+            let time = e.get("time").expect("A time is required for animated mesh keyframes")
+                .as_f64().expect("Animated mesh keyframe time must be a number") as f32;
+            keyframes.push((resolve_path(path, file), time));
+        }
+    } else if let Some(s) = elem.get("sequence") {
+        let pattern = s.get("file").expect("A file name pattern is required for an OBJ sequence")
+            .as_str().expect("OBJ sequence file name pattern must be a string");
+        let fps = s.get("fps").expect("A frame rate is required for an OBJ sequence")
+            .as_f64().expect("OBJ sequence frame rate must be a number") as f32;
+        if fps <= 0.0 {
+            panic!("OBJ sequence frame rate must be greater than 0");
+        }
+        let start = match s.get("start") {
+            Some(x) => x.as_u64().expect("OBJ sequence start must be an unsigned int"),
+            None => 0,
+        };
+        let end = s.get("end").map(|x| x.as_u64().expect("OBJ sequence end must be an unsigned int"));
+        let start_time = match s.get("start_time") {
+            Some(x) => x.as_f64().expect("OBJ sequence start time must be a number") as f32,
+            None => 0.0,
+        };
+        let hash_start = pattern.find('#').expect("OBJ sequence file pattern must contain #'s to number");
+        let hash_len = pattern[hash_start..].chars().take_while(|c| *c == '#').count();
+        let mut frame = start;
+        loop {
+            if end.map_or(false, |e| frame > e) {
+                break;
+            }
+            let file = resolve_path(path, format!("{}{:0width$}{}", &pattern[..hash_start], frame,
+                                                  &pattern[hash_start + hash_len..], width = hash_len));
+            if !file.exists() {
+                if end.is_some() {
+                    panic!("OBJ sequence file '{}' does not exist", file.display());
+                }
+                break;
+            }
+            keyframes.push((file, start_time + (frame - start) as f32 / fps));
+            frame += 1;
+        }
+    } else {
+        panic!("Keyframes or a sequence of OBJ files are required for an animated mesh");
+    }
+    if keyframes.len() < 2 {
+        panic!("At least two keyframes are required for an animated mesh, found {}", keyframes.len());
+    }
+    if keyframes.windows(2).any(|w| w[0].1 >= w[1].1) {
+        panic!("Animated mesh keyframe times must be strictly increasing");
+    }
+    let mut meshes: Vec<Arc<Mesh>> = Vec::with_capacity(keyframes.len());
+    let mut times = Vec::with_capacity(keyframes.len());
+    for (file, time) in keyframes {
+        let mut loaded = Mesh::load_obj(&file);
+        let m = match loaded.remove(model) {
+            Some(m) => m,
+            None => panic!("Requested model '{}' was not found in '{:?}'", model, file),
+        };
+        // Report topology changes here where we know the file, since it's an easy
+        // mistake to make when exporting deforming meshes
+        if let Some(first) = meshes.first() {
+            if let Some(e) = animated_mesh::topology_mismatch(first, &m) {
+                panic!("Animated mesh keyframe '{}' does not match the topology of the first keyframe: {}",
+                       file.display(), e);
+            }
+        }
+        meshes.push(m);
+        times.push(time);
+    }
+    AnimatedMesh::new(meshes, times)
+}
+
 /// Load the sampleable geometry specified by the JSON value. Will panic if the geometry specified
 /// is not sampleable.
 fn load_sampleable_geometry(elem: &Value) -> Arc<SampleableGeom + Send + Sync> {
@@ -746,6 +2064,46 @@ fn load_animated_color(elem: &Value) -> Option<AnimatedColor> {
     }
 }
 
+/// Load an emitter's emission, which is either a single animated color emitted from the front
+/// or an object with `front` and optional `back` colors. Returns the front and back emission
+fn load_emission(elem: &Value) -> (AnimatedColor, Option<AnimatedColor>) {
+    if elem.is_object() {
+        let front = elem.get("front").expect("A front color is required for front and back emission");
+        let front = load_animated_color(front).expect("Emitter front emission must be a color");
+        let back = elem.get("back").map(|b| load_animated_color(b).expect("Emitter back emission must be a color"));
+        (front, back)
+    } else {
+        (load_animated_color(elem).expect("Emitter emission must be a color"), None)
+    }
+}
+
+/// Load the scaling for a scale transform, which can be given under the key `scaling`
+/// or `scale` as a single float or an array of 3 floats. Panics if the scaling is
+/// malformed or not strictly positive, since a zero scale can't be inverted
+fn load_scaling(t: &Value) -> Vector {
+    let s = match (t.get("scaling"), t.get("scale")) {
+        (Some(_), Some(_)) => panic!("Only one of 'scaling' or 'scale' should be specified for scale"),
+        (Some(s), None) | (None, Some(s)) => s,
+        (None, None) => panic!("A scaling value or vector is required for scale"),
+    };
+    let v = if let Some(a) = s.as_array() {
+        if a.len() != 3 {
+            panic!("Scaling vector must have 3 components, got {}", a.len());
+        }
+        load_vector(s).expect("Invalid vector specified for scaling vector, all components must be numbers")
+    } else if s.is_number() {
+        Vector::broadcast(s.as_f64().expect("Invalid float specified for scale value") as f32)
+    } else {
+        panic!("Scaling value should be an array of 3 floats or a single float");
+    };
+    for i in 0..3 {
+        if v[i] <= 0.0 || !v[i].is_finite() {
+            panic!("Scaling must be positive and finite, got [{}, {}, {}]", v.x, v.y, v.z);
+        }
+    }
+    v
+}
+
 /// Load a transform stack specified by the element. Will panic on invalidly specified
 /// transforms and log the error.
 fn load_transform(elem: &Value) -> Option<Transform> {
@@ -762,17 +2120,8 @@ fn load_transform(elem: &Value) -> Option<Transform> {
                 .expect("Invalid vector specified for translation direction");
 
             transform = Transform::translate(&v) * transform;
-        } else if ty == "scale" {
-            let s = t.get("scaling").expect("A scaling value or vector is required for scale");
-            let v;
-            if s.is_array() {
-                v = load_vector(s).expect("Invalid vector specified for scaling vector");
-            } else if s.is_number() {
-                v = Vector::broadcast(s.as_f64().expect("Invalid float specified for scale value") as f32);
-            } else {
-                panic!("Scaling value should be an array of 3 floats or a single float");
-            }
-
+        } else if ty == "scale" || ty == "scaling" {
+            let v = load_scaling(t);
             transform = Transform::scale(&v) * transform;
         } else if ty == "rotate_x" {
             let r = t.get("rotation").expect("A rotation in degrees is required for rotate_x")
@@ -813,7 +2162,7 @@ fn load_transform(elem: &Value) -> Option<Transform> {
 
             transform = Transform::from_mat(&rows.iter().collect()) * transform;
         } else {
-            println!("Unrecognized transform type '{}'", ty);
+            error!("Unrecognized transform type '{}'", ty);
             return None;
         }
     }
@@ -849,3 +2198,302 @@ fn load_keyframes(elem: &Value) -> Option<AnimatedTransform> {
     Some(AnimatedTransform::with_keyframes(keyframes, knots, degree))
 }
 
+/// Load a list of look at keyframes specified by the element. Each keyframe is converted
+/// to a look at transform and the camera is linearly interpolated between them over time.
+/// Will panic on invalidly specified keyframes and log the error
+fn load_look_at_keyframes(elem: &Value) -> Option<AnimatedTransform> {
+    let array = match elem.as_array() {
+        Some(a) => a,
+        None => return None,
+    };
+    if array.is_empty() {
+        return None;
+    }
+    let mut transforms = Vec::with_capacity(array.len());
+    let mut times: Vec<f32> = Vec::with_capacity(array.len());
+    for k in array {
+        let time = k.get("time").expect("A time is required for a look at keyframe")
+            .as_f64().expect("Look at keyframe time must be a number") as f32;
+        let eye = load_point(k.get("eye").expect("An eye position is required for a look at keyframe"))
+            .expect("eye must be an array of 3 floats");
+        let target = load_point(k.get("target").expect("A target is required for a look at keyframe"))
+            .expect("target must be an array of 3 floats");
+        let up = load_vector(k.get("up").expect("An up vector is required for a look at keyframe"))
+            .expect("up must be an array of 3 floats");
+        if let Some(prev) = times.last() {
+            if time <= *prev {
+                panic!("Look at keyframes must be specified in increasing time order");
+            }
+        }
+        transforms.push(Transform::look_at(&eye, &target, &up));
+        times.push(time);
+    }
+    if transforms.len() == 1 {
+        return Some(AnimatedTransform::unanimated(&transforms[0]));
+    }
+    let keyframes = transforms.iter().map(|t| Keyframe::new(t)).collect();
+    // Repeat the first and last times so the linear spline passes through each keyframe
+    let mut knots = Vec::with_capacity(times.len() + 2);
+    knots.push(times[0]);
+    knots.extend(times.iter().cloned());
+    knots.push(times[times.len() - 1]);
+    Some(AnimatedTransform::with_keyframes(keyframes, knots, 1))
+}
+
+/// Load the turntable orbit described by the JSON value, moving the camera from its
+/// starting transform `start` around the turntable's axis through the target
+fn load_turntable(elem: &Value, start: &Transform, frame_info: &FrameInfo) -> AnimatedTransform {
+    let target = load_point(elem.get("target").expect("A target is required for a turntable"))
+        .expect("Turntable target must be an array of 3 floats");
+    let axis = load_vector(elem.get("axis").expect("An axis is required for a turntable"))
+        .expect("Turntable axis must be an array of 3 floats");
+    let frames = elem.get("frames").expect("The number of frames per revolution is required for a turntable")
+        .as_u64().expect("Turntable frames must be a number") as usize;
+    assert!(frames > 0, "A turntable must take at least one frame per revolution");
+    assert!(axis.length_sqr() > 0.0, "The turntable axis can't be zero");
+    assert!(frame_info.time > 0.0, "The scene time must be greater than 0 to animate a turntable");
+    turntable(&target, &axis, frames, start, frame_info)
+}
+
+/// Compute the animated transform rotating the `start` transform a full revolution about
+/// `axis` through `target` every `frames` frames, over the entire scene time
+fn turntable(target: &Point, axis: &Vector, frames: usize, start: &Transform, frame_info: &FrameInfo)
+             -> AnimatedTransform {
+    // Keyframes are linearly interpolated so we take enough steps per revolution to
+    // keep the camera close to the circular orbit between them
+    let steps_per_rev = cmp::max(frames, TURNTABLE_MIN_STEPS);
+    let frame_time = frame_info.time / frame_info.frames as f32;
+    let step_time = frame_time * frames as f32 / steps_per_rev as f32;
+    let steps = cmp::max(f32::ceil(frame_info.time / step_time) as usize, 1);
+    let to_target = Vector::new(target.x, target.y, target.z);
+    let mut keyframes = Vec::with_capacity(steps + 1);
+    let mut knots = Vec::with_capacity(steps + 3);
+    knots.push(0.0);
+    for i in 0..steps + 1 {
+        let angle = 360.0 * (i % steps_per_rev) as f32 / steps_per_rev as f32;
+        let orbit = Transform::translate(&to_target) * Transform::rotate(axis, angle)
+            * Transform::translate(&-to_target);
+        keyframes.push(Keyframe::new(&(orbit * *start)));
+        knots.push(i as f32 * step_time);
+    }
+    knots.push(steps as f32 * step_time);
+    AnimatedTransform::with_keyframes(keyframes, knots, 1)
+}
+
+#[test]
+fn test_validate() {
+    use test_util::{gray_matte, translate, constant_color, scene_builder};
+    let mut builder = scene_builder((32, 32), 1);
+    // The first camera looks at the sphere while the second is behind it looking away
+    builder.add_camera(Camera::new(translate(0.0, 0.0, -10.0), 60.0, (32, 32), 0.5, 0))
+        .add_camera(Camera::new(translate(0.0, 0.0, 10.0), 60.0, (32, 32), 0.5, 0))
+        .add_receiver(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 0.0), "sphere")
+        .add_point_light(translate(0.0, 5.0, 0.0), constant_color(Colorf::black()), "light");
+    let (scene, _, _, _) = builder.build();
+    let warnings = scene.validate();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings.contains(&Warning::BlackLight("light".to_owned())));
+    assert!(warnings.contains(&Warning::CameraSeesNothing(1)));
+}
+
+#[test]
+fn test_strip_json_extensions() {
+    let scene = r#"{
+        // A line comment with "quotes" and a trailing comma,
+        "name": "a // not a comment, /* nor this */",
+        /* A block comment
+           over two lines */
+        "color": [1, 0.5, 0.25,],
+        "escaped": "\\",
+        "nested": { "a": 1, },
+    }"#;
+    let stripped = strip_json_extensions(scene);
+    assert_eq!(stripped.lines().count(), scene.lines().count());
+    let data: Value = serde_json::from_str(&stripped).expect("Stripped scene should be valid JSON");
+    assert_eq!(data["name"], "a // not a comment, /* nor this */");
+    assert_eq!(data["color"].as_array().unwrap().len(), 3);
+    assert_eq!(data["escaped"], "\\");
+    assert_eq!(data["nested"]["a"], 1);
+    // Plain JSON is unchanged
+    let json = r#"{"a": [1, 2], "b": "c,]"}"#;
+    assert_eq!(strip_json_extensions(json), json);
+}
+
+#[test]
+fn test_occluded_by() {
+    use light::OcclusionTester;
+    use test_util::{gray_matte, translate, scene_builder};
+    let sphere = Arc::new(Sphere::new(1.0));
+    let mut builder = scene_builder((8, 8), 1);
+    // The target is behind another object along the ray to the light
+    builder.add_camera(Camera::new(translate(0.0, 0.0, -10.0), 60.0, (8, 8), 0.5, 0))
+        .add_receiver(sphere.clone(), gray_matte(), translate(0.0, 3.0, 0.0), "other")
+        .add_receiver(sphere, gray_matte(), translate(0.0, 6.0, 0.0), "target");
+    let (mut scene, _, _, _) = builder.build();
+    let p = Point::broadcast(0.0);
+    let blocked = OcclusionTester::test_points(&p, &Point::new(0.0, 10.0, 0.0), 0.0);
+    assert!(blocked.occluded_by(&scene, "target") && blocked.occluded_by(&scene, "other"));
+    // The segment stops before reaching the target
+    let short = OcclusionTester::test_points(&p, &Point::new(0.0, 4.5, 0.0), 0.0);
+    assert!(!short.occluded_by(&scene, "target") && short.occluded_by(&scene, "other"));
+    let clear = OcclusionTester::test_ray(&p, &Vector::new(1.0, 1.0, 0.0), 0.0);
+    assert!(!clear.occluded_by(&scene, "target"));
+    scene.set_shadow_pass("target");
+}
+
+#[test]
+fn test_transmittance() {
+    use light::OcclusionTester;
+    use test_util::{gray_matte, translate, scene_builder};
+    let color = |c| -> Arc<Texture + Send + Sync> { Arc::new(texture::ConstantColor::new(Colorf::broadcast(c))) };
+    let glass = Arc::new(Glass::new(color(1.0), color(0.5), Arc::new(texture::ConstantScalar::new(1.5))));
+    let sphere = Arc::new(Sphere::new(1.0));
+    let mut builder = scene_builder((8, 8), 1);
+    builder.add_camera(Camera::new(translate(0.0, 0.0, -10.0), 60.0, (8, 8), 0.5, 0))
+        .add_receiver(sphere.clone(), glass, translate(0.0, 3.0, 0.0), "glass")
+        .add_receiver(sphere, gray_matte(), translate(3.0, 3.0, 0.0), "matte");
+    let (scene, _, _, _) = builder.build();
+    let p = Point::broadcast(0.0);
+    // Light entering and leaving the glass sphere is tinted by both surfaces
+    let tr = OcclusionTester::test_points(&p, &Point::new(0.0, 10.0, 0.0), 0.0).transmittance(&scene);
+    assert!((0..3).all(|i| f32::abs(tr[i] - 0.25) < 1e-5));
+    let tr = OcclusionTester::test_points(&p, &Point::new(6.0, 6.0, 0.0), 0.0).transmittance(&scene);
+    assert!(tr.is_black());
+    let tr = OcclusionTester::test_points(&p, &Point::new(-10.0, 10.0, 0.0), 0.0).transmittance(&scene);
+    assert_eq!(tr, Colorf::broadcast(1.0));
+}
+
+#[test]
+fn test_transmittance_ray_limit() {
+    use light::OcclusionTester;
+    use test_util::{translate, scene_builder};
+    let color = |c| -> Arc<Texture + Send + Sync> { Arc::new(texture::ConstantColor::new(Colorf::broadcast(c))) };
+    let glass = Arc::new(Glass::new(color(1.0), color(0.5), Arc::new(texture::ConstantScalar::new(1.5))));
+    let sphere = Arc::new(Sphere::new(1.0));
+    let mut builder = scene_builder((8, 8), 1);
+    builder.add_camera(Camera::new(translate(0.0, 0.0, -10.0), 60.0, (8, 8), 0.5, 0));
+    // The shadow ray passes through the 6 surfaces of a stack of 3 glass spheres
+    for i in 0..3 {
+        builder.add_receiver(sphere.clone(), glass.clone(), translate(0.0, 3.0 * (i + 1) as f32, 0.0),
+                             &format!("glass{}", i));
+    }
+    let (mut scene, _, _, _) = builder.build();
+    let shadow = OcclusionTester::test_points(&Point::broadcast(0.0), &Point::new(0.0, 20.0, 0.0), 0.0);
+    // Passing through takes 7 intersection tests, one per surface and one finding the rest clear
+    scene.integrator = Box::new(integrator::Path::with_ray_limit(1, 1, 7));
+    let tr = shadow.transmittance(&scene);
+    assert!((0..3).all(|i| f32::abs(tr[i] - f32::powi(0.5, 6)) < 1e-5));
+    scene.integrator = Box::new(integrator::Path::with_ray_limit(1, 1, 6));
+    assert!(shadow.transmittance(&scene).is_black());
+    // The last sphere is first hit by the 5th intersection test, it's missed with a lower limit
+    scene.integrator = Box::new(integrator::Path::with_ray_limit(1, 1, 5));
+    assert!(shadow.occluded_by(&scene, "glass2"));
+    scene.integrator = Box::new(integrator::Path::with_ray_limit(1, 1, 4));
+    assert!(!shadow.occluded_by(&scene, "glass2") && shadow.occluded_by(&scene, "glass1"));
+}
+
+#[test]
+fn test_scene_builder() {
+    use test_util::{gray_matte, translate, constant_color, scene_builder};
+    let mut builder = scene_builder((32, 16), 4);
+    // The second camera takes over at frame 2, and is given the render target's dimensions
+    builder.add_camera(Camera::new(translate(0.0, 0.0, -12.0), 60.0, (8, 8), 0.5, 2))
+        .add_camera(Camera::new(translate(0.0, 0.0, -10.0), 60.0, (8, 8), 0.5, 0))
+        .add_receiver(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 0.0), "sphere")
+        .add_point_light(translate(0.0, 5.0, 0.0), constant_color(Colorf::broadcast(1.0)), "light")
+        .set_frames(FrameInfo::new(4, 2.0, 0, 3));
+    let (scene, rt, spp, frame_info) = builder.build();
+    assert_eq!(rt.dimensions(), (32, 16));
+    assert_eq!(spp, 4);
+    assert_eq!(frame_info.frames, 4);
+    assert_eq!(scene.accel.iter().count(), 2);
+    assert_eq!(scene.cameras[0].active_at, 0);
+    assert!(scene.cameras.iter().all(|c| c.dimensions() == (32, 16)));
+    assert!(scene.validate().is_empty());
+    let hit = scene.intersect(&mut Ray::new(&Point::new(0.0, 0.0, -10.0), &Vector::new(0.0, 0.0, 1.0), 0.0));
+    assert_eq!(hit.map(|h| h.instance.tag().to_owned()), Some("sphere".to_owned()));
+}
+
+#[test]
+fn test_up_axis() {
+    use test_util::{gray_matte, translate, constant_color, scene_builder};
+    let white = constant_color(Colorf::broadcast(1.0));
+    let mut builder = scene_builder((8, 8), 1);
+    // A Z up scene with a sphere 5 units above the origin, clipped below its center
+    let mut sphere = Receiver::new(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 5.0),
+                                   "sphere".to_owned());
+    sphere.clip_planes.push(ClipPlane::new(Point::new(0.0, 0.0, 5.0), Normal::new(0.0, 0.0, 1.0)));
+    builder.set_up_axis(UpAxis::Z)
+        .add_camera(Camera::new(translate(0.0, -10.0, 0.0), 60.0, (8, 8), 0.5, 0))
+        .add_instance(Instance::Receiver(sphere))
+        .add_instance(Instance::Emitter(Emitter::dome(translate(0.0, 0.0, 0.0), white.clone(), white,
+                                                      "dome".to_owned())));
+    let (scene, _, _, _) = builder.build();
+    // The sphere is now above the origin along +Y and the camera behind it along +Z
+    let cam_pos = scene.cameras[0].get_transform().transform(0.0) * Point::broadcast(0.0);
+    assert!(cam_pos.distance(&Point::new(0.0, 0.0, 10.0)) < 1e-4);
+    let mut up = Ray::new(&Point::broadcast(0.0), &Vector::new(0.0, 1.0, 0.0), 0.0);
+    let hit = scene.intersect(&mut up).expect("The ray up should hit the upper half of the sphere");
+    assert_eq!(hit.instance.tag(), "sphere");
+    assert!(f32::abs(hit.dg.p.y - 6.0) < 1e-4);
+    // The dome's zenith still points up
+    for i in scene.accel.iter() {
+        if let Instance::Emitter(ref e) = *i {
+            let zenith = e.get_transform().transform(0.0) * Vector::new(0.0, 1.0, 0.0);
+            assert!(f32::abs(zenith.y - 1.0) < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn test_turntable() {
+    let start = Transform::translate(&Vector::new(0.0, 2.0, -10.0));
+    let target = Point::new(0.0, 2.0, 0.0);
+    // The turntable makes a revolution every 4 frames of a film with 8 one second frames
+    let anim = turntable(&target, &Vector::new(0.0, 1.0, 0.0), 4, &start, &FrameInfo::new(8, 8.0, 0, 7));
+    let position = |t: f32| anim.transform(t) * Point::broadcast(0.0);
+    for &(t, x, z) in &[(0.0, 0.0, -10.0), (1.0, -10.0, 0.0), (2.0, 0.0, 10.0), (4.0, 0.0, -10.0),
+                        (7.0, 10.0, 0.0)] {
+        let p = position(t);
+        assert!(f32::abs(p.x - x) < 1e-3 && f32::abs(p.y - 2.0) < 1e-3 && f32::abs(p.z - z) < 1e-3,
+                "Camera at {:?} at time {}, expected [{}, 2, {}]", p, t, x, z);
+    }
+    // Between keyframes the camera stays close to the orbit
+    for i in 0..64 {
+        assert!(f32::abs(position(i as f32 / 8.0).distance(&target) - 10.0) < 0.25);
+    }
+}
+
+#[test]
+fn test_slice_active_camera() {
+    use test_util::{gray_matte, translate, scene_builder};
+    let mut sliced = Camera::new(translate(0.0, 0.0, -10.0), 60.0, (8, 8), 0.5, 0);
+    sliced.set_clip(2.0, 10.0);
+    let mut builder = scene_builder((8, 8), 1);
+    // Only the first camera is used for the frames being sliced, the second doesn't have a
+    // finite clip range and shouldn't be sliced
+    builder.add_camera(sliced)
+        .add_camera(Camera::new(translate(0.0, 0.0, -12.0), 60.0, (8, 8), 0.5, 4))
+        .add_receiver(Arc::new(Sphere::new(1.0)), gray_matte(), translate(0.0, 0.0, 0.0), "sphere");
+    let (mut scene, _, _, _) = builder.build();
+    assert!(scene.camera_at(3).clip().1.is_finite() && !scene.camera_at(4).clip().1.is_finite());
+    scene.set_slice(Some((1, 2)));
+    scene.update_frame(0, 0.0, 1.0);
+    assert_eq!(scene.active_camera().slice_range(), (6.0, 10.0));
+    assert_eq!(scene.cameras[1].slice_range(), (0.0, f32::INFINITY));
+    scene.set_slice(None);
+    scene.update_frame(1, 1.0, 2.0);
+    assert_eq!(scene.active_camera().slice_range(), (2.0, 10.0));
+}
+
+#[test]
+fn test_resolve_path() {
+    let scene_dir = Path::new("scenes/cornell");
+    assert_eq!(resolve_path(scene_dir, "textures/wood.png"), Path::new("scenes/cornell/textures/wood.png"));
+    assert_eq!(resolve_path(scene_dir, "../models/bunny.obj"), Path::new("scenes/cornell/../models/bunny.obj"));
+    // Absolute paths are left alone, regardless of where the scene is
+    let abs = ::std::env::current_dir().unwrap().join("blue-metallic-paint.binary");
+    assert_eq!(resolve_path(scene_dir, &abs), abs);
+    // Scenes loaded from the working directory have an empty parent path
+    assert_eq!(resolve_path(Path::new(""), "bunny.obj"), Path::new("bunny.obj"));
+}
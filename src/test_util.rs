@@ -0,0 +1,31 @@
+//! Materials, transforms and scenes shared by the tests building scenes to render or trace
+
+use std::sync::Arc;
+
+use film::{filter, Colorf, RenderTarget, AnimatedColor, ColorKeyframe};
+use linalg::{AnimatedTransform, Transform, Vector};
+use material::{Material, Matte};
+use scene::SceneBuilder;
+use texture;
+
+/// A diffuse gray material for objects whose appearance doesn't matter to the test
+pub fn gray_matte() -> Arc<Material + Send + Sync> {
+    Arc::new(Matte::new(Arc::new(texture::ConstantColor::new(Colorf::broadcast(0.5))),
+                        Arc::new(texture::ConstantScalar::new(0.0))))
+}
+
+/// An unanimated transform translating by `[x, y, z]`
+pub fn translate(x: f32, y: f32, z: f32) -> AnimatedTransform {
+    AnimatedTransform::unanimated(&Transform::translate(&Vector::new(x, y, z)))
+}
+
+/// A color which doesn't change over time
+pub fn constant_color(c: Colorf) -> AnimatedColor {
+    AnimatedColor::with_keyframes(vec![ColorKeyframe::new(&c, 0.0)])
+}
+
+/// Start building a scene rendered to a `dim` image with `spp` samples per pixel
+pub fn scene_builder(dim: (usize, usize), spp: usize) -> SceneBuilder {
+    let rt = RenderTarget::new(dim, (2, 2), Box::new(filter::Gaussian::new(1.0, 1.0, 2.0)));
+    SceneBuilder::new(rt, spp)
+}
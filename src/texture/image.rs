@@ -1,48 +1,87 @@
-use image::{self, GenericImage};
+use image::{GenericImage, DynamicImage};
 
 use linalg::clamp;
 use film::Colorf;
 use texture::{Texture, bilinear_interpolate};
 
 /// An `Image` texture is a `Texture` whose samples come
-/// from an image file.
+/// from an image file. Color samples are decoded from sRGB to linear
+/// before filtering so interpolating between texels doesn't darken the
+/// result, scalar samples are assumed to already be linear. The image is
+/// kept in the channel layout it was loaded with, so single channel roughness
+/// or mask maps don't take up the memory of an RGBA image.
 pub struct Image {
-    img: image::DynamicImage,
+    img: DynamicImage,
+    dims: (u32, u32),
+    /// Lookup table mapping the 8-bit sRGB values to linear
+    to_linear: Vec<f32>,
 }
 
 impl Image {
-    pub fn new(img: image::DynamicImage) -> Image {
-        Image { img: img }
+    pub fn new(img: DynamicImage) -> Image {
+        let dims = img.dimensions();
+        let to_linear = (0..256).map(|i| Colorf::broadcast(i as f32 / 255.0).to_linear().r).collect();
+        Image { img: img, dims: dims, to_linear: to_linear }
     }
     fn get_float(&self, x: u32, y: u32) -> f32 {
-        let dims = self.img.dimensions();
-        let x = clamp(x, 0, dims.0 - 1);
-        let y = clamp(y, 0, dims.1 - 1);
-        self.img.get_pixel(x, y).data[0] as f32 / 255.0
+        let x = clamp(x, 0, self.dims.0 - 1);
+        let y = clamp(y, 0, self.dims.1 - 1);
+        let v = match self.img {
+            DynamicImage::ImageLuma8(ref img) => img.get_pixel(x, y).data[0],
+            DynamicImage::ImageLumaA8(ref img) => img.get_pixel(x, y).data[0],
+            DynamicImage::ImageRgb8(ref img) => img.get_pixel(x, y).data[0],
+            DynamicImage::ImageRgba8(ref img) => img.get_pixel(x, y).data[0],
+        };
+        v as f32 / 255.0
     }
     fn get_color(&self, x: u32, y: u32) -> Colorf {
-        let dims = self.img.dimensions();
-        let x = clamp(x, 0, dims.0 - 1);
-        let y = clamp(y, 0, dims.1 - 1);
-        let px = self.img.get_pixel(x, y);
-        Colorf::with_alpha(px.data[0] as f32 / 255.0,
-                           px.data[1] as f32 / 255.0,
-                           px.data[2] as f32 / 255.0,
-                           px.data[3] as f32 / 255.0)
+        let x = clamp(x, 0, self.dims.0 - 1);
+        let y = clamp(y, 0, self.dims.1 - 1);
+        let (r, g, b, a) = match self.img {
+            DynamicImage::ImageLuma8(ref img) => {
+                let v = img.get_pixel(x, y).data[0];
+                (v, v, v, 255)
+            },
+            DynamicImage::ImageLumaA8(ref img) => {
+                let px = img.get_pixel(x, y);
+                (px.data[0], px.data[0], px.data[0], px.data[1])
+            },
+            DynamicImage::ImageRgb8(ref img) => {
+                let px = img.get_pixel(x, y);
+                (px.data[0], px.data[1], px.data[2], 255)
+            },
+            DynamicImage::ImageRgba8(ref img) => {
+                let px = img.get_pixel(x, y);
+                (px.data[0], px.data[1], px.data[2], px.data[3])
+            },
+        };
+        Colorf::with_alpha(self.to_linear[r as usize], self.to_linear[g as usize],
+                           self.to_linear[b as usize], a as f32 / 255.0)
     }
 }
 
 impl Texture for Image {
     fn sample_f32(&self, u: f32, v: f32, _: f32) -> f32 {
-        let dims = self.img.dimensions();
-        let x = u * dims.0 as f32;
-        let y = v * dims.1 as f32;
+        let x = u * self.dims.0 as f32;
+        let y = v * self.dims.1 as f32;
         bilinear_interpolate(x, y, |px, py| self.get_float(px, py))
     }
     fn sample_color(&self, u: f32, v: f32, _: f32) -> Colorf {
-        let x = u * self.img.dimensions().0 as f32;
-        let y = v * self.img.dimensions().1 as f32;
+        let x = u * self.dims.0 as f32;
+        let y = v * self.dims.1 as f32;
         bilinear_interpolate(x, y, |px, py| self.get_color(px, py))
     }
 }
 
+#[test]
+fn test_channel_layouts() {
+    use image::{GrayAlphaImage, RgbImage};
+    // Gray images with alpha keep their alpha and RGB images are opaque
+    let gray = Image::new(DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(1, 1, vec![255, 51]).unwrap()));
+    let c = gray.sample_color(0.5, 0.5, 0.0);
+    assert!(f32::abs(c.r - 1.0) < 1e-4 && f32::abs(c.b - 1.0) < 1e-4 && f32::abs(c.a - 0.2) < 1e-4);
+    assert!(f32::abs(gray.sample_f32(0.5, 0.5, 0.0) - 1.0) < 1e-4);
+    let rgb = Image::new(DynamicImage::ImageRgb8(RgbImage::from_raw(1, 1, vec![0, 255, 0]).unwrap()));
+    let c = rgb.sample_color(0.5, 0.5, 0.0);
+    assert!(c.r == 0.0 && f32::abs(c.g - 1.0) < 1e-4 && c.b == 0.0 && c.a == 1.0);
+}
@@ -2,7 +2,8 @@
 
 use std::ops::{Add, Mul};
 
-use film::Colorf;
+use linalg;
+use film::{Colorf, AnimatedColor};
 
 pub use self::image::Image;
 pub use self::animated_image::AnimatedImage;
@@ -75,6 +76,50 @@ impl Texture for ConstantColor {
     }
 }
 
+/// A scalar texture whose value is animated over time by linearly
+/// interpolating between a list of (time, value) keyframes
+pub struct AnimatedScalar {
+    /// Keyframes sorted by time
+    keyframes: Vec<(f32, f32)>,
+}
+impl AnimatedScalar {
+    /// Create an animated scalar interpolating between the (time, value) keyframes passed
+    pub fn new(mut keyframes: Vec<(f32, f32)>) -> AnimatedScalar {
+        assert!(!keyframes.is_empty());
+        keyframes.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("NaN time in scalar keyframes"));
+        AnimatedScalar { keyframes: keyframes }
+    }
+    /// Compute the value at the desired time
+    pub fn value(&self, time: f32) -> f32 {
+        match self.keyframes.iter().position(|k| k.0 >= time) {
+            Some(0) => self.keyframes[0].1,
+            Some(i) => {
+                let (lo, hi) = (self.keyframes[i - 1], self.keyframes[i]);
+                linalg::lerp((time - lo.0) / (hi.0 - lo.0), &lo.1, &hi.1)
+            },
+            None => self.keyframes[self.keyframes.len() - 1].1,
+        }
+    }
+}
+impl Texture for AnimatedScalar {
+    fn sample_f32(&self, _: f32, _: f32, time: f32) -> f32 {
+        self.value(time)
+    }
+    fn sample_color(&self, _: f32, _: f32, time: f32) -> Colorf {
+        Colorf::broadcast(self.value(time))
+    }
+}
+
+/// Animated colors can be used as a solid color texture which changes over time
+impl Texture for AnimatedColor {
+    fn sample_f32(&self, _: f32, _: f32, time: f32) -> f32 {
+        self.color(time).luminance()
+    }
+    fn sample_color(&self, _: f32, _: f32, time: f32) -> Colorf {
+        self.color(time)
+    }
+}
+
 pub struct UVColor;
 impl Texture for UVColor {
     fn sample_f32(&self, u: f32, v: f32, _: f32) -> f32 {